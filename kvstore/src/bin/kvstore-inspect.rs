@@ -0,0 +1,238 @@
+// Copyright 2018 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A command-line tool for inspecting the contents of a `RocksDB` database.
+//!
+//! The database is opened in the read-only mode, so it may be inspected while it is used
+//! by a running node.
+
+#[macro_use]
+extern crate failure;
+extern crate hex;
+extern crate kvstore;
+
+use failure::Error;
+
+use std::env;
+use std::process;
+
+use kvstore::storage::{list_indexes, IndexType, ProofListIndex, ProofMapIndex, RocksDB, Snapshot};
+use kvstore::crypto::Hash;
+
+const USAGE: &str = "\
+Usage: kvstore-inspect <DB_PATH> <COMMAND> [OPTIONS]
+
+Commands:
+    cfs                         List column families.
+    indexes                     List indexes from the indexes metadata.
+    scan <CF>                   Print entries of the column family.
+    get <CF> <KEY>              Print the value stored for the key.
+    count <CF>                  Count entries of the column family.
+    root <INDEX> [<INDEX_ID>]   Print the Merkle root of a proof map or a proof list.
+
+Options:
+    --prefix <KEY>      Only process keys starting with the prefix (scan, count).
+    --from <KEY>        Start iteration from the key (scan, count).
+    --limit <N>         Print at most N entries (scan).
+    --utf8              Print keys and values as UTF-8 strings where possible.
+
+Keys are given in hex, or as UTF-8 strings prefixed with `str:`.";
+
+#[derive(Debug, Default)]
+struct Options {
+    prefix: Vec<u8>,
+    from: Option<Vec<u8>>,
+    limit: Option<usize>,
+    utf8: bool,
+}
+
+fn main() {
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    if args.len() < 2 || args.iter().any(|arg| arg == "-h" || arg == "--help") {
+        println!("{}", USAGE);
+        process::exit(if args.len() < 2 { 1 } else { 0 });
+    }
+
+    if let Err(e) = run(&args[0], &args[1], &args[2..]) {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    }
+}
+
+fn run(path: &str, command: &str, args: &[String]) -> Result<(), Error> {
+    let (positional, options) = parse_options(args)?;
+    if command == "cfs" {
+        for name in RocksDB::column_families(path)? {
+            println!("{}", name);
+        }
+        return Ok(());
+    }
+
+    let db = RocksDB::open_read_only(path)?;
+    let snapshot = db.snapshot();
+    match (command, positional.as_slice()) {
+        ("indexes", &[]) => indexes(&*snapshot),
+        ("scan", &[ref cf]) => scan(&*snapshot, cf, &options),
+        ("get", &[ref cf, ref key]) => get(&*snapshot, cf, &parse_key(key)?, &options),
+        ("count", &[ref cf]) => count(&*snapshot, cf, &options),
+        ("root", &[ref name]) => root(&*snapshot, name, None),
+        ("root", &[ref name, ref index_id]) => root(&*snapshot, name, Some(parse_key(index_id)?)),
+        _ => bail!("Invalid arguments for command `{}`.\n\n{}", command, USAGE),
+    }
+}
+
+fn parse_options(args: &[String]) -> Result<(Vec<String>, Options), Error> {
+    let mut positional = Vec::new();
+    let mut options = Options::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format_err!("Missing value for `{}`", arg))
+        };
+        match arg.as_str() {
+            "--prefix" => options.prefix = parse_key(value()?)?,
+            "--from" => options.from = Some(parse_key(value()?)?),
+            "--limit" => options.limit = Some(value()?.parse()?),
+            "--utf8" => options.utf8 = true,
+            _ if arg.starts_with("--") => bail!("Unknown option `{}`", arg),
+            _ => positional.push(arg.clone()),
+        }
+    }
+    Ok((positional, options))
+}
+
+fn parse_key(key: &str) -> Result<Vec<u8>, Error> {
+    if key.starts_with("str:") {
+        Ok(key["str:".len()..].as_bytes().to_vec())
+    } else {
+        hex::decode(key).map_err(|e| format_err!("Invalid hex key `{}`: {}", key, e))
+    }
+}
+
+fn format_bytes(bytes: &[u8], options: &Options) -> String {
+    if options.utf8 {
+        if let Ok(s) = ::std::str::from_utf8(bytes) {
+            return format!("{:?}", s);
+        }
+    }
+    hex::encode(bytes)
+}
+
+fn indexes(snapshot: &Snapshot) -> Result<(), Error> {
+    for info in list_indexes(snapshot) {
+        println!(
+            "{}\t{:?}{}",
+            info.name,
+            info.index_type,
+            if info.is_family { " (family)" } else { "" }
+        );
+    }
+    Ok(())
+}
+
+fn for_each_entry<F>(snapshot: &Snapshot, cf: &str, options: &Options, mut f: F)
+where
+    F: FnMut(&[u8], &[u8]) -> bool,
+{
+    let from = match options.from {
+        Some(ref from) if from.as_slice() > options.prefix.as_slice() => from.as_slice(),
+        _ => options.prefix.as_slice(),
+    };
+    let mut iter = snapshot.iter(cf, from);
+    while let Some((key, value)) = iter.next() {
+        if !key.starts_with(&options.prefix) || !f(key, value) {
+            break;
+        }
+    }
+}
+
+fn scan(snapshot: &Snapshot, cf: &str, options: &Options) -> Result<(), Error> {
+    let mut printed = 0;
+    for_each_entry(snapshot, cf, options, |key, value| {
+        if options.limit.map_or(false, |limit| printed >= limit) {
+            return false;
+        }
+        println!(
+            "{}\t{}",
+            format_bytes(key, options),
+            format_bytes(value, options)
+        );
+        printed += 1;
+        true
+    });
+    Ok(())
+}
+
+fn get(snapshot: &Snapshot, cf: &str, key: &[u8], options: &Options) -> Result<(), Error> {
+    match snapshot.get(cf, key) {
+        Some(value) => {
+            println!("{}", format_bytes(&value, options));
+            Ok(())
+        }
+        None => bail!("Key `{}` is not found in `{}`", hex::encode(key), cf),
+    }
+}
+
+fn count(snapshot: &Snapshot, cf: &str, options: &Options) -> Result<(), Error> {
+    let mut count = 0_u64;
+    for_each_entry(snapshot, cf, options, |_, _| {
+        count += 1;
+        true
+    });
+    println!("{}", count);
+    Ok(())
+}
+
+fn root(snapshot: &Snapshot, name: &str, index_id: Option<Vec<u8>>) -> Result<(), Error> {
+    let info = match list_indexes(snapshot).into_iter().find(|info| info.name == name) {
+        Some(info) => info,
+        None => bail!("Index `{}` is not found", name),
+    };
+    if info.is_family != index_id.is_some() {
+        bail!(
+            "Index `{}` is {}",
+            name,
+            if info.is_family {
+                "a family, an index id is required"
+            } else {
+                "not a family, an index id is not allowed"
+            }
+        );
+    }
+
+    // Values are treated as raw bytes, which doesn't affect the hash as long as the hash
+    // of a value is the hash of its binary representation.
+    let hash: Hash = match (info.index_type, index_id) {
+        (IndexType::ProofMap, None) => {
+            ProofMapIndex::<_, Hash, Vec<u8>>::new(name, snapshot).merkle_root()
+        }
+        (IndexType::ProofMap, Some(id)) => {
+            ProofMapIndex::<_, Hash, Vec<u8>>::new_in_family(name, &id, snapshot).merkle_root()
+        }
+        (IndexType::ProofList, None) => {
+            ProofListIndex::<_, Vec<u8>>::new(name, snapshot).merkle_root()
+        }
+        (IndexType::ProofList, Some(id)) => {
+            ProofListIndex::<_, Vec<u8>>::new_in_family(name, &id, snapshot).merkle_root()
+        }
+        (index_type, _) => bail!(
+            "Index `{}` of type {:?} doesn't have a Merkle root",
+            name,
+            index_type
+        ),
+    };
+    println!("{}", hash.to_hex());
+    Ok(())
+}
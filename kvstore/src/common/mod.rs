@@ -6,9 +6,8 @@ pub fn to_hex<T: AsRef<[u8]>>(data: T) -> String{
 }
 
 
-pub fn to_sha3<T: AsRef<u8>>(data: T) -> Vec<u8> {
+pub fn to_sha3<T: AsRef<[u8]>>(data: T) -> Vec<u8> {
     let mut hasher = Sha3_256::default();
-    hasher.input(data);
-    hasher.result()
-//    format!("0x{:x}", out)
-}
\ No newline at end of file
+    hasher.input(data.as_ref());
+    hasher.result().to_vec()
+}
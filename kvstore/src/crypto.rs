@@ -1,23 +1,35 @@
 use sha3::{Sha3_256, Digest};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::de::Error as DeError;
+use chrono::{DateTime, Duration, Utc};
+use uuid::Uuid;
+
+use std::fmt;
+use std::str::FromStr;
 
 use common;
-use encoding::{ToHex, FromHexError};
+use encoding::{FromHex, FromHexError, ToHex};
 
 pub const HASH_SIZE: usize = 32;
 
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
 pub struct Hash([u8; HASH_SIZE]);
 
 impl Hash {
-    // Create a new instance from bytes array.
+    /// Create a new instance from bytes array.
     pub fn new(b: [u8; HASH_SIZE]) -> Self {
         Hash(b)
     }
 
-    /// Create a new instance from bytes slice
+    /// Create a new instance from bytes slice. Returns `None` if the slice length
+    /// differs from `HASH_SIZE`.
     pub fn from_slice(bs: &[u8]) -> Option<Self> {
-        assert_eq!(bs.len(), HASH_SIZE);
-        // TODO
-        None
+        if bs.len() != HASH_SIZE {
+            return None;
+        }
+        let mut b = [0; HASH_SIZE];
+        b.copy_from_slice(bs);
+        Some(Hash(b))
     }
 
     /// Create a new install with filled with zeros.
@@ -30,45 +42,49 @@ impl Hash {
     }
 }
 
-/// It is very good
 impl AsRef<[u8]> for Hash {
     fn as_ref(&self) -> &[u8] {
         self.0.as_ref()
     }
 }
 
-use std::str::FromStr;
+/// Parses a hash from its hex representation, optionally prefixed with `0x`.
 impl FromStr for Hash {
     type Err = FromHexError;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        if s.len() == HASH_SIZE {
-            let out = s.chars().map(|c| c as u8).collect();
-            return Ok(Hash::new(out));
-        }else if  s.len() == (HASH_SIZE + 2)  {
-            let out = s.chars().skip(2).map(|c| c as u8).collect();
-            return Ok(Hash::new(out));
-        } else {
-            return Err(FromHexError::new());
-        }
+        let s = if s.starts_with("0x") { &s[2..] } else { s };
+        let bytes = Vec::<u8>::from_hex(s)?;
+        Hash::from_slice(&bytes).ok_or(FromHexError::InvalidStringLength)
     }
 }
 
-use std::fmt;
 impl fmt::Debug for Hash {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "0x{}", self.to_hex())
     }
 }
 
-/// TODO use macro
+/// Serializes the hash as a hex string.
+impl Serialize for Hash {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_hex())
+    }
+}
+
+impl<'de> Deserialize<'de> for Hash {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let hex = String::deserialize(deserializer)?;
+        Hash::from_str(&hex).map_err(|e| D::Error::custom(format!("Invalid hash {}: {}", hex, e)))
+    }
+}
+
 impl ToHex for Hash {
-    // TODO
-    fn write_hex<W: ::std::fmt::Write>(&self, w: &mut W) -> ::std::fmt::Result {
-        Ok(())
+    fn write_hex<W: fmt::Write>(&self, w: &mut W) -> fmt::Result {
+        w.write_str(&common::to_hex(self).to_lowercase())
     }
-    // TODO
-    fn write_hex_upper<W: ::std::fmt::Write>(&self, w: &mut W) -> ::std::fmt::Result {
-        Ok(())
+
+    fn write_hex_upper<W: fmt::Write>(&self, w: &mut W) -> fmt::Result {
+        w.write_str(&common::to_hex(self))
     }
 }
 
@@ -78,7 +94,63 @@ pub trait CryptoHash {
 
 pub fn hash(data: &[u8]) -> Hash {
     let digest = common::to_sha3(data);
-    Hash::from_str(&format!("{:x}", digest)).unwrap()
+    Hash::from_slice(&digest).unwrap()
+}
+
+impl CryptoHash for () {
+    fn hash(&self) -> Hash {
+        hash(&[])
+    }
+}
+
+impl CryptoHash for bool {
+    fn hash(&self) -> Hash {
+        hash(&[*self as u8])
+    }
+}
+
+macro_rules! crypto_hash_for_ints {
+    ($($type:ty),*) => {
+        $(
+            impl CryptoHash for $type {
+                fn hash(&self) -> Hash {
+                    hash(&::storage::StorageValue::into_bytes(*self))
+                }
+            }
+        )*
+    }
+}
+
+crypto_hash_for_ints!{u8, i8, u16, i16, u32, i32, u64, i64}
+
+impl CryptoHash for Vec<u8> {
+    fn hash(&self) -> Hash {
+        hash(self)
+    }
+}
+
+impl CryptoHash for String {
+    fn hash(&self) -> Hash {
+        hash(self.as_bytes())
+    }
+}
+
+impl CryptoHash for DateTime<Utc> {
+    fn hash(&self) -> Hash {
+        hash(&::storage::StorageValue::into_bytes(*self))
+    }
+}
+
+impl CryptoHash for Duration {
+    fn hash(&self) -> Hash {
+        hash(&::storage::StorageValue::into_bytes(*self))
+    }
+}
+
+impl CryptoHash for Uuid {
+    fn hash(&self) -> Hash {
+        hash(self.as_bytes())
+    }
 }
 
 #[derive(Debug, Default)]
@@ -99,6 +171,6 @@ impl HashStream {
     /// Returns the hash of data supplied to the stream so far.
     pub fn hash(self) -> Hash {
         let dig = self.0.result();
-        Hash(dig)
+        Hash::from_slice(&dig).unwrap()
     }
-}
\ No newline at end of file
+}
@@ -10,12 +10,22 @@ extern crate serde_derive;
 extern crate serde_json;
 #[macro_use]
 extern crate failure;
-extern crate exonum_rocksdb as rocksdb;
+extern crate rocksdb;
+extern crate memmap;
 extern crate hex;
 extern crate sha3;
 extern crate byteorder;
 extern crate chrono;
 extern crate uuid;
+#[cfg(any(test, feature = "testing"))]
+extern crate rand;
+#[cfg(test)]
+extern crate tempdir;
+#[cfg(feature = "async")]
+#[macro_use]
+extern crate futures;
+#[cfg(feature = "async")]
+extern crate futures_cpupool;
 
 #[cfg(test)]
 mod tests {
@@ -0,0 +1,601 @@
+// Copyright 2018 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! An asynchronous facade over `Database`, `Snapshot` and the typed indices.
+//!
+//! The module is available with the `async` feature. All the blocking operations (merges,
+//! snapshot creation and reads) are executed on a dedicated thread pool, so the returned
+//! futures and streams can be used from an event loop (e.g., `tokio`) without blocking
+//! its threads.
+//!
+//! Iteration over indices is exposed as a [`Stream`] that reads entries from the snapshot
+//! in batches of [`BATCH_SIZE`] elements.
+//!
+//! [`Stream`]: https://docs.rs/futures/0.1/futures/stream/trait.Stream.html
+//! [`BATCH_SIZE`]: constant.BATCH_SIZE.html
+//!
+//! # Examples
+//!
+//! ```
+//! extern crate exonum;
+//! extern crate futures;
+//! extern crate futures_cpupool;
+//!
+//! use futures::{Future, Stream};
+//! use futures_cpupool::CpuPool;
+//! use exonum::storage::{Database, MapIndex, MemoryDB};
+//! use exonum::storage::async_db::AsyncDatabase;
+//!
+//! # fn main() {
+//! let db = AsyncDatabase::new(MemoryDB::new(), CpuPool::new(2));
+//! let mut fork = db.fork();
+//! MapIndex::new("map", &mut fork).put(&1_u8, 2_u8);
+//! db.merge(fork.into_patch()).wait().unwrap();
+//!
+//! let snapshot = db.snapshot().wait().unwrap();
+//! let index = snapshot.map_index::<u8, u8>("map");
+//! assert_eq!(index.get(1).wait().unwrap(), Some(2));
+//! assert_eq!(index.iter().collect().wait().unwrap(), vec![(1, 2)]);
+//! # }
+//! ```
+
+use futures::{Async, Future, Poll, Stream};
+use futures_cpupool::{CpuFuture, CpuPool};
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+use super::{Database, Entry, Error, Fork, ListIndex, MapIndex, Patch, Snapshot, StorageKey,
+            StorageValue};
+
+/// Maximal number of elements read from a snapshot at once by the streams.
+pub const BATCH_SIZE: usize = 256;
+
+/// A future resolved on the thread pool of an `AsyncDatabase`.
+pub type AsyncResult<T> = CpuFuture<T, Error>;
+
+/// Asynchronous wrapper around a `Database`.
+///
+/// Merges and snapshot creation are executed on the provided thread pool.
+#[derive(Clone)]
+pub struct AsyncDatabase {
+    db: Arc<Database>,
+    pool: CpuPool,
+}
+
+/// Asynchronous wrapper around a `Snapshot`.
+///
+/// The snapshot is shared between the thread pool tasks, so cloning an `AsyncSnapshot` is cheap.
+#[derive(Clone)]
+pub struct AsyncSnapshot {
+    snapshot: Arc<Snapshot>,
+    pool: CpuPool,
+}
+
+/// Asynchronous read-only representation of an `Entry`.
+pub struct AsyncEntry<V> {
+    snapshot: AsyncSnapshot,
+    name: String,
+    _v: PhantomData<V>,
+}
+
+/// Asynchronous read-only representation of a `MapIndex`.
+pub struct AsyncMapIndex<K, V> {
+    snapshot: AsyncSnapshot,
+    name: String,
+    _k: PhantomData<K>,
+    _v: PhantomData<V>,
+}
+
+/// Asynchronous read-only representation of a `ListIndex`.
+pub struct AsyncListIndex<V> {
+    snapshot: AsyncSnapshot,
+    name: String,
+    _v: PhantomData<V>,
+}
+
+type FetchBatch<C, T> = Fn(&Snapshot, Option<C>) -> (Vec<T>, Option<C>) + Send + Sync;
+
+/// A stream over the elements of a snapshot that reads them in batches on the thread pool.
+///
+/// The position of the stream between batches is tracked by a cursor of type `C`.
+pub struct AsyncStream<C, T> {
+    snapshot: AsyncSnapshot,
+    fetch: Arc<FetchBatch<C, T>>,
+    cursor: Option<C>,
+    buffer: VecDeque<T>,
+    pending: Option<AsyncResult<(Vec<T>, Option<C>)>>,
+    finished: bool,
+}
+
+/// A stream over the raw entries of a column family.
+pub type SnapshotStream = AsyncStream<Vec<u8>, (Vec<u8>, Vec<u8>)>;
+/// A stream over the entries of a `MapIndex`.
+pub type MapIndexStream<K, V> = AsyncStream<K, (K, V)>;
+/// A stream over the items of a `ListIndex`.
+pub type ListIndexStream<V> = AsyncStream<u64, V>;
+
+impl AsyncDatabase {
+    /// Wraps the database, executing blocking operations on the given thread pool.
+    pub fn new<D: Database>(db: D, pool: CpuPool) -> Self {
+        Self::from_shared(Arc::new(db), pool)
+    }
+
+    /// Wraps the shared database, executing blocking operations on the given thread pool.
+    pub fn from_shared(db: Arc<Database>, pool: CpuPool) -> Self {
+        AsyncDatabase { db, pool }
+    }
+
+    /// Returns a reference to the underlying database.
+    pub fn database(&self) -> &Arc<Database> {
+        &self.db
+    }
+
+    /// Creates a new snapshot of the database from its current state.
+    pub fn snapshot(&self) -> AsyncResult<AsyncSnapshot> {
+        let db = Arc::clone(&self.db);
+        let pool = self.pool.clone();
+        self.pool.spawn_fn(move || {
+            Ok(AsyncSnapshot {
+                snapshot: Arc::from(db.snapshot()),
+                pool,
+            })
+        })
+    }
+
+    /// Creates a new fork of the database from its current state.
+    ///
+    /// The fork is created synchronously; the changes are accumulated in memory
+    /// and do not touch the database until the patch is merged.
+    pub fn fork(&self) -> Fork {
+        self.db.fork()
+    }
+
+    /// Atomically applies a sequence of patch changes to the database.
+    pub fn merge(&self, patch: Patch) -> AsyncResult<()> {
+        let db = Arc::clone(&self.db);
+        self.pool.spawn_fn(move || db.merge(patch))
+    }
+
+    /// Atomically applies a sequence of patch changes to the database with fsync.
+    pub fn merge_sync(&self, patch: Patch) -> AsyncResult<()> {
+        let db = Arc::clone(&self.db);
+        self.pool.spawn_fn(move || db.merge_sync(patch))
+    }
+}
+
+impl AsyncSnapshot {
+    /// Wraps a snapshot, executing reads on the given thread pool.
+    pub fn new(snapshot: Box<Snapshot>, pool: CpuPool) -> Self {
+        AsyncSnapshot {
+            snapshot: Arc::from(snapshot),
+            pool,
+        }
+    }
+
+    /// Executes an arbitrary read-only function over the snapshot on the thread pool.
+    ///
+    /// This method allows to use any index type asynchronously:
+    ///
+    /// ```
+    /// # extern crate exonum;
+    /// # extern crate futures;
+    /// # extern crate futures_cpupool;
+    /// # use futures::Future;
+    /// # use futures_cpupool::CpuPool;
+    /// # use exonum::storage::{MemoryDB, SparseListIndex};
+    /// # use exonum::storage::async_db::AsyncDatabase;
+    /// # fn main() {
+    /// let db = AsyncDatabase::new(MemoryDB::new(), CpuPool::new(1));
+    /// let snapshot = db.snapshot().wait().unwrap();
+    /// let len = snapshot.read(|view| {
+    ///     let index: SparseListIndex<_, u8> = SparseListIndex::new("list", view);
+    ///     index.len()
+    /// });
+    /// assert_eq!(len.wait().unwrap(), 0);
+    /// # }
+    /// ```
+    pub fn read<F, R>(&self, f: F) -> AsyncResult<R>
+    where
+        F: FnOnce(&Snapshot) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let snapshot = Arc::clone(&self.snapshot);
+        self.pool.spawn_fn(move || Ok(f(&*snapshot)))
+    }
+
+    /// Returns a value corresponding to the specified key as a raw vector of bytes,
+    /// or `None` if it does not exist.
+    pub fn get(&self, name: &str, key: &[u8]) -> AsyncResult<Option<Vec<u8>>> {
+        let (name, key) = (name.to_owned(), key.to_vec());
+        self.read(move |view| view.get(&name, &key))
+    }
+
+    /// Returns `true` if the snapshot contains a value for the specified key.
+    pub fn contains(&self, name: &str, key: &[u8]) -> AsyncResult<bool> {
+        let (name, key) = (name.to_owned(), key.to_vec());
+        self.read(move |view| view.contains(&name, &key))
+    }
+
+    /// Returns a stream over the entries of the column family in ascending order
+    /// starting from the specified key.
+    pub fn iter(&self, name: &str, from: &[u8]) -> SnapshotStream {
+        let name = name.to_owned();
+        let from = from.to_vec();
+        self.stream(move |view, last: Option<Vec<u8>>| {
+            let start = last.as_ref().unwrap_or(&from).clone();
+            let skip = if last.is_some() { 1 } else { 0 };
+            let mut entries = Vec::with_capacity(BATCH_SIZE);
+            let mut iter = view.iter(&name, &start);
+            while let Some((k, v)) = iter.next() {
+                if entries.len() == BATCH_SIZE + skip {
+                    break;
+                }
+                entries.push((k.to_vec(), v.to_vec()));
+            }
+            let entries = entries.into_iter().skip(skip).collect::<Vec<_>>();
+            let next = next_cursor(&entries, |&(ref k, _)| k.clone());
+            (entries, next)
+        })
+    }
+
+    /// Returns an asynchronous representation of the `Entry` with the given name.
+    pub fn entry<V>(&self, name: &str) -> AsyncEntry<V>
+    where
+        V: StorageValue + Send + 'static,
+    {
+        AsyncEntry {
+            snapshot: self.clone(),
+            name: name.to_owned(),
+            _v: PhantomData,
+        }
+    }
+
+    /// Returns an asynchronous representation of the `MapIndex` with the given name.
+    pub fn map_index<K, V>(&self, name: &str) -> AsyncMapIndex<K, V>
+    where
+        K: StorageKey + ToOwned<Owned = K> + Clone + Send + Sync + 'static,
+        V: StorageValue + Send + 'static,
+    {
+        AsyncMapIndex {
+            snapshot: self.clone(),
+            name: name.to_owned(),
+            _k: PhantomData,
+            _v: PhantomData,
+        }
+    }
+
+    /// Returns an asynchronous representation of the `ListIndex` with the given name.
+    pub fn list_index<V>(&self, name: &str) -> AsyncListIndex<V>
+    where
+        V: StorageValue + Send + 'static,
+    {
+        AsyncListIndex {
+            snapshot: self.clone(),
+            name: name.to_owned(),
+            _v: PhantomData,
+        }
+    }
+
+    fn stream<C, T, F>(&self, fetch: F) -> AsyncStream<C, T>
+    where
+        C: Send + 'static,
+        T: Send + 'static,
+        F: Fn(&Snapshot, Option<C>) -> (Vec<T>, Option<C>) + Send + Sync + 'static,
+    {
+        AsyncStream {
+            snapshot: self.clone(),
+            fetch: Arc::new(fetch),
+            cursor: None,
+            buffer: VecDeque::new(),
+            pending: None,
+            finished: false,
+        }
+    }
+}
+
+/// Returns the cursor pointing to the last element of a full batch, or `None` if the batch
+/// is the last one.
+fn next_cursor<T, C, F>(batch: &[T], f: F) -> Option<C>
+where
+    F: Fn(&T) -> C,
+{
+    if batch.len() < BATCH_SIZE {
+        None
+    } else {
+        batch.last().map(f)
+    }
+}
+
+impl<V> AsyncEntry<V>
+where
+    V: StorageValue + Send + 'static,
+{
+    /// Returns a value of the entry or `None` if does not exist.
+    pub fn get(&self) -> AsyncResult<Option<V>> {
+        let name = self.name.clone();
+        self.snapshot
+            .read(move |view| Entry::new(name, view).get())
+    }
+
+    /// Returns `true` if a value of the entry exists.
+    pub fn exists(&self) -> AsyncResult<bool> {
+        let name = self.name.clone();
+        self.snapshot
+            .read(move |view| Entry::<_, V>::new(name, view).exists())
+    }
+}
+
+impl<K, V> AsyncMapIndex<K, V>
+where
+    K: StorageKey + ToOwned<Owned = K> + Clone + Send + Sync + 'static,
+    V: StorageValue + Send + 'static,
+{
+    /// Returns a value corresponding to the key.
+    pub fn get(&self, key: K) -> AsyncResult<Option<V>> {
+        let name = self.name.clone();
+        self.snapshot.read(move |view| {
+            let index: MapIndex<_, K, V> = MapIndex::new(name, view);
+            index.get(&key)
+        })
+    }
+
+    /// Returns `true` if the map contains a value for the specified key.
+    pub fn contains(&self, key: K) -> AsyncResult<bool> {
+        let name = self.name.clone();
+        self.snapshot.read(move |view| {
+            let index: MapIndex<_, K, V> = MapIndex::new(name, view);
+            index.contains(&key)
+        })
+    }
+
+    /// Returns a stream over the entries of the map in ascending order.
+    pub fn iter(&self) -> MapIndexStream<K, V> {
+        self.stream(None)
+    }
+
+    /// Returns a stream over the entries of the map in ascending order starting from the
+    /// specified key.
+    pub fn iter_from(&self, from: K) -> MapIndexStream<K, V> {
+        self.stream(Some(from))
+    }
+
+    fn stream(&self, from: Option<K>) -> MapIndexStream<K, V> {
+        let name = self.name.clone();
+        self.snapshot.stream(move |view, last: Option<K>| {
+            let index: MapIndex<_, K, V> = MapIndex::new(&name, view);
+            // The cursor points to the last returned key, which is skipped.
+            let entries: Vec<(K, V)> = match (last.as_ref(), from.as_ref()) {
+                (Some(last), _) => index.iter_from(last).skip(1).take(BATCH_SIZE).collect(),
+                (None, Some(from)) => index.iter_from(from).take(BATCH_SIZE).collect(),
+                (None, None) => index.iter().take(BATCH_SIZE).collect(),
+            };
+            let next = next_cursor(&entries, |&(ref k, _)| k.clone());
+            (entries, next)
+        })
+    }
+}
+
+impl<V> AsyncListIndex<V>
+where
+    V: StorageValue + Send + 'static,
+{
+    /// Returns an element at the specified position or `None` if out of bounds.
+    pub fn get(&self, index: u64) -> AsyncResult<Option<V>> {
+        let name = self.name.clone();
+        self.snapshot
+            .read(move |view| ListIndex::new(name, view).get(index))
+    }
+
+    /// Returns the number of elements in the list.
+    pub fn len(&self) -> AsyncResult<u64> {
+        let name = self.name.clone();
+        self.snapshot
+            .read(move |view| ListIndex::<_, V>::new(name, view).len())
+    }
+
+    /// Returns `true` if the list contains no elements.
+    pub fn is_empty(&self) -> AsyncResult<bool> {
+        let name = self.name.clone();
+        self.snapshot
+            .read(move |view| ListIndex::<_, V>::new(name, view).is_empty())
+    }
+
+    /// Returns a stream over the list items in ascending order of their positions.
+    pub fn iter(&self) -> ListIndexStream<V> {
+        self.iter_from(0)
+    }
+
+    /// Returns a stream over the list items starting from the specified position.
+    pub fn iter_from(&self, from: u64) -> ListIndexStream<V> {
+        let name = self.name.clone();
+        self.snapshot.stream(move |view, next: Option<u64>| {
+            let start = next.unwrap_or(from);
+            let index: ListIndex<_, V> = ListIndex::new(&name, view);
+            let items = index
+                .iter_from(start)
+                .take(BATCH_SIZE)
+                .collect::<Vec<_>>();
+            let next = if items.len() < BATCH_SIZE {
+                None
+            } else {
+                Some(start + BATCH_SIZE as u64)
+            };
+            (items, next)
+        })
+    }
+}
+
+impl<C, T> Stream for AsyncStream<C, T>
+where
+    C: Send + 'static,
+    T: Send + 'static,
+{
+    type Item = T;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<T>, Error> {
+        loop {
+            if let Some(item) = self.buffer.pop_front() {
+                return Ok(Async::Ready(Some(item)));
+            }
+            if self.finished {
+                return Ok(Async::Ready(None));
+            }
+
+            if self.pending.is_none() {
+                let fetch = Arc::clone(&self.fetch);
+                let cursor = self.cursor.take();
+                self.pending = Some(self.snapshot.read(move |view| fetch(view, cursor)));
+            }
+            let (batch, next) = try_ready!(self.pending.as_mut().unwrap().poll());
+            self.pending = None;
+            self.finished = next.is_none();
+            self.cursor = next;
+            self.buffer.extend(batch);
+        }
+    }
+}
+
+impl fmt::Debug for AsyncDatabase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "AsyncDatabase(..)")
+    }
+}
+
+impl fmt::Debug for AsyncSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "AsyncSnapshot(..)")
+    }
+}
+
+impl<C, T> fmt::Debug for AsyncStream<C, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "AsyncStream(..)")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{Future, Stream};
+    use futures_cpupool::CpuPool;
+
+    use storage::{Entry, ListIndex, MapIndex, MemoryDB};
+    use super::*;
+
+    fn create_database() -> AsyncDatabase {
+        AsyncDatabase::new(MemoryDB::new(), CpuPool::new(2))
+    }
+
+    #[test]
+    fn merge_and_read() {
+        let db = create_database();
+        let mut fork = db.fork();
+        Entry::new("entry", &mut fork).set(42_u64);
+        fork.put("raw", vec![1], vec![2]);
+        db.merge_sync(fork.into_patch()).wait().unwrap();
+
+        let snapshot = db.snapshot().wait().unwrap();
+        assert_eq!(snapshot.entry::<u64>("entry").get().wait().unwrap(), Some(42));
+        assert!(!snapshot.entry::<u64>("other").exists().wait().unwrap());
+        assert_eq!(snapshot.get("raw", &[1]).wait().unwrap(), Some(vec![2]));
+        assert!(!snapshot.contains("raw", &[2]).wait().unwrap());
+    }
+
+    #[test]
+    fn snapshot_isolation() {
+        let db = create_database();
+        let snapshot = db.snapshot().wait().unwrap();
+
+        let mut fork = db.fork();
+        fork.put("raw", vec![1], vec![2]);
+        db.merge(fork.into_patch()).wait().unwrap();
+
+        assert_eq!(snapshot.get("raw", &[1]).wait().unwrap(), None);
+        let snapshot = db.snapshot().wait().unwrap();
+        assert_eq!(snapshot.get("raw", &[1]).wait().unwrap(), Some(vec![2]));
+    }
+
+    #[test]
+    fn map_index_stream() {
+        let db = create_database();
+        let count = BATCH_SIZE as u64 * 2 + 10;
+        let mut fork = db.fork();
+        {
+            let mut index = MapIndex::new("map", &mut fork);
+            for i in 0..count {
+                index.put(&i, i * 2);
+            }
+        }
+        db.merge(fork.into_patch()).wait().unwrap();
+
+        let snapshot = db.snapshot().wait().unwrap();
+        let index = snapshot.map_index::<u64, u64>("map");
+        assert_eq!(index.get(5).wait().unwrap(), Some(10));
+        assert!(!index.contains(count).wait().unwrap());
+
+        let entries = index.iter().collect().wait().unwrap();
+        assert_eq!(entries, (0..count).map(|i| (i, i * 2)).collect::<Vec<_>>());
+
+        let entries = index.iter_from(count - 3).collect().wait().unwrap();
+        assert_eq!(entries.len(), 3);
+
+        let entries = snapshot.iter("map", &[]).collect().wait().unwrap();
+        assert_eq!(entries.len() as u64, count);
+    }
+
+    #[test]
+    fn list_index_stream() {
+        let db = create_database();
+        let count = BATCH_SIZE as u64 + 1;
+        let mut fork = db.fork();
+        ListIndex::new("list", &mut fork).extend(0..count);
+        db.merge(fork.into_patch()).wait().unwrap();
+
+        let snapshot = db.snapshot().wait().unwrap();
+        let index = snapshot.list_index::<u64>("list");
+        assert_eq!(index.len().wait().unwrap(), count);
+        assert_eq!(index.get(3).wait().unwrap(), Some(3));
+        assert_eq!(
+            index.iter().collect().wait().unwrap(),
+            (0..count).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            index.iter_from(count - 2).collect().wait().unwrap(),
+            vec![count - 2, count - 1]
+        );
+    }
+
+    #[test]
+    fn empty_streams() {
+        let db = create_database();
+        let snapshot = db.snapshot().wait().unwrap();
+        assert!(snapshot
+            .map_index::<u8, u8>("map")
+            .iter()
+            .collect()
+            .wait()
+            .unwrap()
+            .is_empty());
+        assert!(snapshot
+            .list_index::<u8>("list")
+            .iter()
+            .collect()
+            .wait()
+            .unwrap()
+            .is_empty());
+    }
+}
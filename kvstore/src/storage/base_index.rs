@@ -18,9 +18,15 @@
 use std::borrow::Cow;
 use std::marker::PhantomData;
 
-use super::{Fork, Iter, Snapshot, StorageKey, StorageValue};
+use super::{IndexAccess, IndexAccessMut, Iter, StorageKey, StorageKeyRef, StorageValue,
+            StorageValueRef};
 use storage::indexes_metadata::{self, IndexType, INDEXES_METADATA_TABLE_NAME};
 
+/// Subprefix of the entries of an index which has auxiliary data.
+const ENTRIES_SUBPREFIX: u8 = 0;
+/// Subprefix of the auxiliary data of an index, such as a bloom filter.
+const AUXILIARY_SUBPREFIX: u8 = 1;
+
 /// Basic struct for all indices that implements common features.
 ///
 /// This structure is not intended for direct use, rather it is the basis for building other types
@@ -37,6 +43,7 @@ pub struct BaseIndex<T> {
     name: String,
     is_family: bool,
     index_id: Option<Vec<u8>>,
+    auxiliary_id: Option<Vec<u8>>,
     is_mutable: bool,
     index_type: IndexType,
     view: T,
@@ -59,16 +66,34 @@ pub struct BaseIndexIter<'a, K, V> {
     _v: PhantomData<V>,
 }
 
+/// An iterator over the keys of a `BaseIndex`, which does not read the values.
+///
+/// This struct is created by the [`iter_keys`] or
+/// [`iter_keys_from`] methods on [`BaseIndex`]. See its documentation for more.
+///
+/// [`iter_keys`]: struct.BaseIndex.html#method.iter_keys
+/// [`iter_keys_from`]: struct.BaseIndex.html#method.iter_keys_from
+/// [`BaseIndex`]: struct.BaseIndex.html
+pub struct BaseIndexKeysIter<'a, K> {
+    base_iter: Iter<'a>,
+    base_prefix_len: usize,
+    index_id: Vec<u8>,
+    ended: bool,
+    _k: PhantomData<K>,
+}
+
 impl<T> BaseIndex<T>
 where
-    T: AsRef<Snapshot>,
+    T: IndexAccess,
 {
     /// Creates a new index representation based on the name and storage view.
     ///
-    /// Storage view can be specified as [`&Snapshot`] or [`&mut Fork`]. In the first case only
-    /// immutable methods are available. In the second case both immutable and mutable methods are
-    /// available.
+    /// Storage view can be any type implementing [`IndexAccess`], such as [`&Snapshot`] or
+    /// [`&mut Fork`]. If the view also implements [`IndexAccessMut`], both immutable and mutable
+    /// methods are available; otherwise, only immutable methods are available.
     ///
+    /// [`IndexAccess`]: ../trait.IndexAccess.html
+    /// [`IndexAccessMut`]: ../trait.IndexAccessMut.html
     /// [`&Snapshot`]: ../trait.Snapshot.html
     /// [`&mut Fork`]: ../struct.Fork.html
     pub fn new<S: AsRef<str>>(index_name: S, index_type: IndexType, view: T) -> Self {
@@ -79,13 +104,14 @@ where
             index_name.as_ref(),
             index_type,
             is_family,
-            view.as_ref(),
+            view.snapshot(),
         );
 
         BaseIndex {
             name: index_name.as_ref().to_string(),
             is_family,
             index_id: None,
+            auxiliary_id: None,
             is_mutable: false,
             index_type,
             view,
@@ -114,7 +140,7 @@ where
             family_name.as_ref(),
             index_type,
             is_family,
-            view.as_ref(),
+            view.snapshot(),
         );
 
         BaseIndex {
@@ -125,17 +151,39 @@ where
                 index_id.write(&mut buf);
                 Some(buf)
             },
+            auxiliary_id: None,
             is_mutable: false,
             index_type,
             view,
         }
     }
 
+    /// Returns the storage view of the index, consuming the index.
+    pub fn into_view(self) -> T {
+        self.view
+    }
+
+    /// Splits the keys of the index into the entries and the auxiliary data, each under its own
+    /// subprefix. The auxiliary data is stored in the column family of the index, so it is
+    /// copied, dumped and removed together with the entries. All the methods, except for
+    /// the `*_auxiliary` ones, access the entries.
+    pub(crate) fn with_auxiliary_data(mut self) -> Self {
+        let index_id = self.index_id.take().unwrap_or_default();
+        let mut entries_id = index_id.clone();
+        entries_id.push(ENTRIES_SUBPREFIX);
+        let mut auxiliary_id = index_id;
+        auxiliary_id.push(AUXILIARY_SUBPREFIX);
+        self.index_id = Some(entries_id);
+        self.auxiliary_id = Some(auxiliary_id);
+        self
+    }
+
     pub(crate) fn indexes_metadata(view: T) -> Self {
         BaseIndex {
             name: INDEXES_METADATA_TABLE_NAME.to_string(),
             is_family: false,
             index_id: None,
+            auxiliary_id: None,
             is_mutable: true,
             index_type: IndexType::Map,
             view,
@@ -165,7 +213,7 @@ where
         V: StorageValue,
     {
         self.view
-            .as_ref()
+            .snapshot()
             .get(&self.name, &self.prefixed_key(key))
             .map(|v| StorageValue::from_bytes(Cow::Owned(v)))
     }
@@ -177,7 +225,7 @@ where
         K: StorageKey + ?Sized,
     {
         self.view
-            .as_ref()
+            .snapshot()
             .contains(&self.name, &self.prefixed_key(key))
     }
 
@@ -192,7 +240,7 @@ where
     {
         let iter_prefix = self.prefixed_key(subprefix);
         BaseIndexIter {
-            base_iter: self.view.as_ref().iter(&self.name, &iter_prefix),
+            base_iter: self.view.snapshot().iter(&self.name, &iter_prefix),
             base_prefix_len: self.index_id.as_ref().map_or(0, |p| p.len()),
             index_id: iter_prefix,
             ended: false,
@@ -214,29 +262,139 @@ where
         let iter_prefix = self.prefixed_key(subprefix);
         let iter_from = self.prefixed_key(from);
         BaseIndexIter {
-            base_iter: self.view.as_ref().iter(&self.name, &iter_from),
+            base_iter: self.view.snapshot().iter(&self.name, &iter_from),
+            base_prefix_len: self.index_id.as_ref().map_or(0, |p| p.len()),
+            index_id: iter_prefix,
+            ended: false,
+            _k: PhantomData,
+            _v: PhantomData,
+        }
+    }
+
+    /// Returns an iterator over the keys of the index in ascending order. The iterator element
+    /// type is *any* key. An argument `subprefix` allows to specify a subset of keys
+    /// for iteration.
+    ///
+    /// Unlike [`iter`](#method.iter), the values are not read from the storage.
+    pub fn iter_keys<P, K>(&self, subprefix: &P) -> BaseIndexKeysIter<K>
+    where
+        P: StorageKey,
+        K: StorageKey,
+    {
+        let iter_prefix = self.prefixed_key(subprefix);
+        BaseIndexKeysIter {
+            base_iter: self.view.snapshot().iter_keys(&self.name, &iter_prefix),
+            base_prefix_len: self.index_id.as_ref().map_or(0, |p| p.len()),
+            index_id: iter_prefix,
+            ended: false,
+            _k: PhantomData,
+        }
+    }
+
+    /// Returns an iterator over the keys of the index in ascending order starting from the
+    /// specified key. The iterator element type is *any* key. An argument `subprefix`
+    /// allows to specify a subset of iteration.
+    ///
+    /// Unlike [`iter_from`](#method.iter_from), the values are not read from the storage.
+    pub fn iter_keys_from<P, F, K>(&self, subprefix: &P, from: &F) -> BaseIndexKeysIter<K>
+    where
+        P: StorageKey,
+        F: StorageKey + ?Sized,
+        K: StorageKey,
+    {
+        let iter_prefix = self.prefixed_key(subprefix);
+        let iter_from = self.prefixed_key(from);
+        BaseIndexKeysIter {
+            base_iter: self.view.snapshot().iter_keys(&self.name, &iter_from),
             base_prefix_len: self.index_id.as_ref().map_or(0, |p| p.len()),
             index_id: iter_prefix,
             ended: false,
             _k: PhantomData,
+        }
+    }
+
+    fn auxiliary_key<K: StorageKey + ?Sized>(&self, key: &K) -> Vec<u8> {
+        let prefix = self.auxiliary_id
+            .as_ref()
+            .expect("Index is created without auxiliary data");
+        let mut v = vec![0; prefix.len() + key.size()];
+        v[..prefix.len()].copy_from_slice(prefix);
+        key.write(&mut v[prefix.len()..]);
+        v
+    }
+
+    /// Returns a value corresponding to the key from the auxiliary data of the index.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the index is created without [`with_auxiliary_data`].
+    ///
+    /// [`with_auxiliary_data`]: #method.with_auxiliary_data
+    pub(crate) fn get_auxiliary<K, V>(&self, key: &K) -> Option<V>
+    where
+        K: StorageKey + ?Sized,
+        V: StorageValue,
+    {
+        self.view
+            .snapshot()
+            .get(&self.name, &self.auxiliary_key(key))
+            .map(|v| StorageValue::from_bytes(Cow::Owned(v)))
+    }
+
+    /// Returns an iterator over the auxiliary data of the index in ascending order starting from
+    /// the specified key.
+    pub(crate) fn iter_auxiliary_from<F, K, V>(&self, from: &F) -> BaseIndexIter<K, V>
+    where
+        F: StorageKey + ?Sized,
+        K: StorageKey,
+        V: StorageValue,
+    {
+        let iter_prefix = self.auxiliary_key(&());
+        let iter_from = self.auxiliary_key(from);
+        BaseIndexIter {
+            base_iter: self.view.snapshot().iter(&self.name, &iter_from),
+            base_prefix_len: iter_prefix.len(),
+            index_id: iter_prefix,
+            ended: false,
+            _k: PhantomData,
             _v: PhantomData,
         }
     }
 }
 
-impl<'a> BaseIndex<&'a mut Fork> {
+impl<T> BaseIndex<T>
+where
+    T: IndexAccessMut,
+{
     fn set_index_type(&mut self) {
         if !self.is_mutable {
             indexes_metadata::set_index_type(
                 &self.name,
                 self.index_type,
                 self.is_family,
-                &mut self.view,
+                self.view.fork(),
             );
             self.is_mutable = true;
         }
     }
 
+    /// Returns a value of *any* type corresponding to the key of *any* type and records the read
+    /// in the fork, so that merging the fork fails if the value is changed concurrently.
+    /// See [`Fork::get_for_update`] for details.
+    ///
+    /// [`Fork::get_for_update`]: ../struct.Fork.html#method.get_for_update
+    pub fn get_for_update<K, V>(&mut self, key: &K) -> Option<V>
+    where
+        K: StorageKey + ?Sized,
+        V: StorageValue,
+    {
+        let key = self.prefixed_key(key);
+        self.view
+            .fork()
+            .get_for_update(&self.name, &key)
+            .map(|v| StorageValue::from_bytes(Cow::Owned(v)))
+    }
+
     /// Inserts the key-value pair into the index. Both key and value may be of *any* types.
     pub fn put<K, V>(&mut self, key: &K, value: V)
     where
@@ -245,7 +403,7 @@ impl<'a> BaseIndex<&'a mut Fork> {
     {
         self.set_index_type();
         let key = self.prefixed_key(key);
-        self.view.put(&self.name, key, value.into_bytes());
+        self.view.fork().put(&self.name, key, value.into_bytes());
     }
 
     /// Removes the key of *any* type from the index.
@@ -255,7 +413,7 @@ impl<'a> BaseIndex<&'a mut Fork> {
     {
         self.set_index_type();
         let key = self.prefixed_key(key);
-        self.view.remove(&self.name, key);
+        self.view.fork().remove(&self.name, key);
     }
 
     /// Clears the index, removing entries with keys that starts with a prefix or all entries
@@ -269,8 +427,43 @@ impl<'a> BaseIndex<&'a mut Fork> {
     pub fn clear(&mut self) {
         self.set_index_type();
         self.view
+            .fork()
             .remove_by_prefix(&self.name, self.index_id.as_ref());
     }
+
+    /// Returns a value corresponding to the key from the auxiliary data of the index and records
+    /// the read in the fork, like [`get_for_update`].
+    ///
+    /// [`get_for_update`]: #method.get_for_update
+    pub(crate) fn get_auxiliary_for_update<K, V>(&mut self, key: &K) -> Option<V>
+    where
+        K: StorageKey + ?Sized,
+        V: StorageValue,
+    {
+        let key = self.auxiliary_key(key);
+        self.view
+            .fork()
+            .get_for_update(&self.name, &key)
+            .map(|v| StorageValue::from_bytes(Cow::Owned(v)))
+    }
+
+    /// Inserts the key-value pair into the auxiliary data of the index.
+    pub(crate) fn put_auxiliary<K, V>(&mut self, key: &K, value: V)
+    where
+        K: StorageKey,
+        V: StorageValue,
+    {
+        self.set_index_type();
+        let key = self.auxiliary_key(key);
+        self.view.fork().put(&self.name, key, value.into_bytes());
+    }
+
+    /// Removes all the auxiliary data of the index.
+    pub(crate) fn clear_auxiliary(&mut self) {
+        self.set_index_type();
+        let prefix = self.auxiliary_key(&());
+        self.view.fork().remove_by_prefix(&self.name, Some(&prefix));
+    }
 }
 
 impl<'a, K, V> Iterator for BaseIndexIter<'a, K, V>
@@ -297,6 +490,73 @@ where
     }
 }
 
+impl<'a, K, V> BaseIndexIter<'a, K, V> {
+    /// Advances the iterator and returns the next entry decoded from the data borrowed
+    /// from the storage, without allocating owned keys and values.
+    ///
+    /// The types of the returned key and value may differ from the iterator element type,
+    /// provided that they are decoded from the same bytes (e.g., `&str` for `String`).
+    pub fn next_ref<'b, RK, RV>(&'b mut self) -> Option<(RK, RV)>
+    where
+        RK: StorageKeyRef<'b>,
+        RV: StorageValueRef<'b>,
+    {
+        if self.ended {
+            return None;
+        }
+        if let Some((k, v)) = self.base_iter.next() {
+            if k.starts_with(&self.index_id) {
+                return Some((
+                    RK::read_ref(&k[self.base_prefix_len..]),
+                    RV::from_slice(v),
+                ));
+            }
+        }
+        self.ended = true;
+        None
+    }
+}
+
+impl<'a, K> Iterator for BaseIndexKeysIter<'a, K>
+where
+    K: StorageKey,
+{
+    type Item = K::Owned;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.ended {
+            return None;
+        }
+        if let Some((k, _)) = self.base_iter.next() {
+            if k.starts_with(&self.index_id) {
+                return Some(K::read(&k[self.base_prefix_len..]));
+            }
+        }
+        self.ended = true;
+        None
+    }
+}
+
+impl<'a, K> BaseIndexKeysIter<'a, K> {
+    /// Advances the iterator and returns the next key decoded from the data borrowed
+    /// from the storage, without allocating an owned key.
+    pub fn next_ref<'b, RK>(&'b mut self) -> Option<RK>
+    where
+        RK: StorageKeyRef<'b>,
+    {
+        if self.ended {
+            return None;
+        }
+        if let Some((k, _)) = self.base_iter.next() {
+            if k.starts_with(&self.index_id) {
+                return Some(RK::read_ref(&k[self.base_prefix_len..]));
+            }
+        }
+        self.ended = true;
+        None
+    }
+}
+
 impl<'a, K, V> ::std::fmt::Debug for BaseIndexIter<'a, K, V> {
     fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
         write!(f, "BaseIndexIter(..)")
@@ -319,6 +579,12 @@ fn assert_valid_name<S: AsRef<str>>(name: S) {
     }
 }
 
+impl<'a, K> ::std::fmt::Debug for BaseIndexKeysIter<'a, K> {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        write!(f, "BaseIndexKeysIter(..)")
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,441 @@
+// Copyright 2018 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A persisted bloom filter used by the set indexes to answer negative membership queries
+//! without reading the index.
+//!
+//! The filter is a blocked bloom filter: the bits of each key are set within a single block
+//! of 512 bits, so an insertion rewrites only one block in the storage. The filter is stored
+//! in the auxiliary data of the index, with the header under the empty key and the non-zero
+//! blocks under their numbers. The auxiliary data shares the column family with the elements
+//! of the set, so the filter is always dumped, copied and restored together with them.
+//!
+//! The header contains a version of the filter contents, which changes with every
+//! modification. Loaded filters are cached per thread by their headers, so the filter stored
+//! in a snapshot is loaded once, regardless of the number of index instances created for it.
+
+use byteorder::{BigEndian, ByteOrder};
+
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::f64::consts::LN_2;
+use std::fmt;
+use std::sync::Arc;
+
+use crypto::{hash, CryptoHash, Hash, HashStream};
+use super::{BaseIndex, IndexAccess, IndexAccessMut, StorageKey, StorageValue};
+
+/// Number of `u64` words in a block.
+const BLOCK_WORDS: usize = 8;
+/// Number of bits in a block.
+const BLOCK_BITS: u64 = 64 * BLOCK_WORDS as u64;
+/// Maximal number of bits set for a key.
+const MAX_HASHES: u64 = 16;
+/// Maximal number of filters cached by a thread.
+const MAX_LOADED_FILTERS: usize = 16;
+
+thread_local! {
+    // Filters loaded by the thread, keyed by their encoded headers.
+    static LOADED_FILTERS: RefCell<HashMap<Vec<u8>, Arc<BloomFilter>>> =
+        RefCell::new(HashMap::new());
+}
+
+#[derive(Debug, Clone, Copy)]
+struct FilterHeader {
+    /// Number of blocks of the filter.
+    num_blocks: u64,
+    /// Number of bits set for a key.
+    num_hashes: u64,
+    /// Number of keys the filter was sized for.
+    capacity: u64,
+    /// False positive rate the filter was sized for.
+    false_positive_rate: f64,
+    /// Number of keys inserted since the filter was built, including removed keys.
+    insertions: u64,
+    /// Version of the filter contents.
+    version: u64,
+}
+
+impl FilterHeader {
+    fn new(capacity: u64, false_positive_rate: f64) -> Self {
+        assert!(capacity > 0, "Bloom filter capacity must be positive");
+        assert!(
+            false_positive_rate > 0.0 && false_positive_rate < 1.0,
+            "Bloom filter false positive rate must be between 0 and 1"
+        );
+        let num_bits = -(capacity as f64) * false_positive_rate.ln() / (LN_2 * LN_2);
+        let num_blocks = (num_bits / BLOCK_BITS as f64).ceil().max(1.0) as u64;
+        let num_hashes = (-false_positive_rate.log2()).round() as u64;
+        FilterHeader {
+            num_blocks,
+            num_hashes: ::std::cmp::min(::std::cmp::max(num_hashes, 1), MAX_HASHES),
+            capacity,
+            false_positive_rate,
+            insertions: 0,
+            version: 0,
+        }
+    }
+
+    /// Returns the expected false positive rate for the current number of insertions.
+    fn expected_false_positive_rate(&self) -> f64 {
+        let num_bits = (self.num_blocks * BLOCK_BITS) as f64;
+        let k = self.num_hashes as f64;
+        let n = self.insertions as f64;
+        (1.0 - (-k * n / num_bits).exp()).powf(k)
+    }
+
+    fn to_array(&self) -> [u8; 48] {
+        let mut buf = [0; 48];
+        BigEndian::write_u64(&mut buf[0..8], self.num_blocks);
+        BigEndian::write_u64(&mut buf[8..16], self.num_hashes);
+        BigEndian::write_u64(&mut buf[16..24], self.capacity);
+        BigEndian::write_f64(&mut buf[24..32], self.false_positive_rate);
+        BigEndian::write_u64(&mut buf[32..40], self.insertions);
+        BigEndian::write_u64(&mut buf[40..48], self.version);
+        buf
+    }
+}
+
+impl CryptoHash for FilterHeader {
+    fn hash(&self) -> Hash {
+        hash(&self.to_array())
+    }
+}
+
+impl StorageValue for FilterHeader {
+    fn into_bytes(self) -> Vec<u8> {
+        self.to_array().to_vec()
+    }
+
+    fn from_bytes(value: Cow<[u8]>) -> Self {
+        let buf = value.as_ref();
+        FilterHeader {
+            num_blocks: BigEndian::read_u64(&buf[0..8]),
+            num_hashes: BigEndian::read_u64(&buf[8..16]),
+            capacity: BigEndian::read_u64(&buf[16..24]),
+            false_positive_rate: BigEndian::read_f64(&buf[24..32]),
+            insertions: BigEndian::read_u64(&buf[32..40]),
+            version: BigEndian::read_u64(&buf[40..48]),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct BloomFilter {
+    header: FilterHeader,
+    words: Vec<u64>,
+}
+
+impl BloomFilter {
+    fn new(header: FilterHeader) -> Self {
+        BloomFilter {
+            header,
+            words: vec![0; header.num_blocks as usize * BLOCK_WORDS],
+        }
+    }
+
+    /// Builds a filter from the keys read from the index.
+    pub fn build<I>(capacity: u64, false_positive_rate: f64, keys: I) -> Self
+    where
+        I: Iterator<Item = Vec<u8>>,
+    {
+        let mut filter = BloomFilter::new(FilterHeader::new(capacity, false_positive_rate));
+        for key in keys {
+            filter.set(&key);
+            filter.header.insertions += 1;
+        }
+        filter.header.version = filter.content_version();
+        filter
+    }
+
+    /// Returns the version derived from the parameters and the bits of the filter.
+    fn content_version(&self) -> u64 {
+        let mut header = self.header;
+        header.version = 0;
+        let mut stream = HashStream::new().update(&header.to_array());
+        for block in 0..self.header.num_blocks {
+            stream = stream.update(&self.block_bytes(block));
+        }
+        BigEndian::read_u64(&stream.hash().as_ref()[..8])
+    }
+
+    /// Returns the version of the filter after the insertion of the key, which is derived
+    /// from the previous version, so that the same insertions lead to the same version.
+    fn next_version(&self, key: &[u8]) -> u64 {
+        let mut buf = vec![0; 8];
+        BigEndian::write_u64(&mut buf, self.header.version);
+        buf.extend_from_slice(key);
+        BigEndian::read_u64(&hash(&buf).as_ref()[..8])
+    }
+
+    /// Returns the block of the key and the positions of its bits within the block.
+    fn positions(&self, key: &[u8]) -> (u64, Vec<u64>) {
+        let digest = hash(key);
+        let bytes = digest.as_ref();
+        let block = BigEndian::read_u64(&bytes[0..8]) % self.header.num_blocks;
+        let h1 = BigEndian::read_u64(&bytes[8..16]);
+        let h2 = BigEndian::read_u64(&bytes[16..24]) | 1;
+        let bits = (0..self.header.num_hashes)
+            .map(|i| h1.wrapping_add(i.wrapping_mul(h2)) % BLOCK_BITS)
+            .collect();
+        (block, bits)
+    }
+
+    fn may_contain(&self, key: &[u8]) -> bool {
+        let (block, bits) = self.positions(key);
+        let words = &self.words[block as usize * BLOCK_WORDS..];
+        bits.iter()
+            .all(|bit| words[(bit / 64) as usize] & (1 << (bit % 64)) != 0)
+    }
+
+    /// Sets the bits of the key and returns the number of the block if it has changed.
+    fn set(&mut self, key: &[u8]) -> Option<u64> {
+        let (block, bits) = self.positions(key);
+        let words = &mut self.words[block as usize * BLOCK_WORDS..];
+        let mut changed = false;
+        for bit in bits {
+            let word = &mut words[(bit / 64) as usize];
+            let mask = 1 << (bit % 64);
+            changed |= *word & mask == 0;
+            *word |= mask;
+        }
+        if changed {
+            Some(block)
+        } else {
+            None
+        }
+    }
+
+    fn block(&self, block: u64) -> &[u64] {
+        let start = block as usize * BLOCK_WORDS;
+        &self.words[start..start + BLOCK_WORDS]
+    }
+
+    fn block_bytes(&self, block: u64) -> Vec<u8> {
+        let mut bytes = vec![0; BLOCK_WORDS * 8];
+        for (chunk, word) in bytes.chunks_mut(8).zip(self.block(block)) {
+            BigEndian::write_u64(chunk, *word);
+        }
+        bytes
+    }
+
+    /// Loads the filter stored in the index, reusing the filter with the same header
+    /// loaded by the thread before.
+    fn load<T: IndexAccess>(base: &BaseIndex<T>) -> Option<Arc<Self>> {
+        let header = match base.get_auxiliary::<_, FilterHeader>(&()) {
+            Some(header) => header,
+            None => return None,
+        };
+        let cache_key = header.to_array().to_vec();
+        let loaded = LOADED_FILTERS.with(|filters| filters.borrow().get(&cache_key).cloned());
+        if loaded.is_some() {
+            return loaded;
+        }
+
+        let filter = Arc::new(BloomFilter::read(base, header));
+        LOADED_FILTERS.with(|filters| {
+            let mut filters = filters.borrow_mut();
+            if filters.len() >= MAX_LOADED_FILTERS {
+                filters.clear();
+            }
+            filters.insert(cache_key, Arc::clone(&filter));
+        });
+        Some(filter)
+    }
+
+    fn read<T: IndexAccess>(base: &BaseIndex<T>, header: FilterHeader) -> Self {
+        let mut filter = BloomFilter::new(header);
+        for (block, bytes) in base.iter_auxiliary_from::<_, u64, Vec<u8>>(&0_u64) {
+            let start = block as usize * BLOCK_WORDS;
+            let words = &mut filter.words[start..start + BLOCK_WORDS];
+            for (word, chunk) in words.iter_mut().zip(bytes.chunks(8)) {
+                *word = BigEndian::read_u64(chunk);
+            }
+        }
+        filter
+    }
+
+    /// Replaces the filter stored in the index with this one.
+    fn store<T: IndexAccessMut>(&self, base: &mut BaseIndex<T>) {
+        base.clear_auxiliary();
+        for block in 0..self.header.num_blocks {
+            if self.block(block).iter().any(|word| *word != 0) {
+                base.put_auxiliary(&block, self.block_bytes(block));
+            }
+        }
+        base.put_auxiliary(&(), self.header);
+    }
+}
+
+impl fmt::Debug for BloomFilter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BloomFilter")
+            .field("header", &self.header)
+            .finish()
+    }
+}
+
+/// The bloom filter of a set index, loaded into memory on the first use.
+///
+/// An index instance reads the filter header on the first use, and takes the filter from
+/// the cache of the thread if the filter with the same header has been loaded before.
+/// The filter is copied on the first modification.
+#[derive(Debug, Default)]
+pub(crate) struct BloomFilterCache {
+    /// `None` if the filter is not loaded yet, `Some(None)` if the index has no filter.
+    filter: RefCell<Option<Option<Arc<BloomFilter>>>>,
+}
+
+impl BloomFilterCache {
+    fn ensure_loaded<T: IndexAccess>(&self, base: &BaseIndex<T>) {
+        let mut filter = self.filter.borrow_mut();
+        if filter.is_none() {
+            *filter = Some(BloomFilter::load(base));
+        }
+    }
+
+    /// Returns `false` if the index definitely doesn't contain the key, and `true` if it
+    /// may contain the key or there is no filter.
+    pub fn may_contain<T, K>(&self, base: &BaseIndex<T>, key: &K) -> bool
+    where
+        T: IndexAccess,
+        K: StorageKey + ?Sized,
+    {
+        self.ensure_loaded(base);
+        match *self.filter.borrow() {
+            Some(Some(ref filter)) => filter.may_contain(&key_bytes(key)),
+            _ => true,
+        }
+    }
+
+    /// Returns `true` if the index has a filter.
+    pub fn is_enabled<T: IndexAccess>(&self, base: &BaseIndex<T>) -> bool {
+        self.ensure_loaded(base);
+        match *self.filter.borrow() {
+            Some(Some(_)) => true,
+            _ => false,
+        }
+    }
+
+    /// Returns `true` if the expected false positive rate of the filter is more than twice
+    /// the rate the filter was sized for.
+    pub fn is_stale<T: IndexAccess>(&self, base: &BaseIndex<T>) -> bool {
+        self.ensure_loaded(base);
+        match *self.filter.borrow() {
+            Some(Some(ref filter)) => {
+                let header = &filter.header;
+                header.expected_false_positive_rate() > 2.0 * header.false_positive_rate
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns the capacity and the false positive rate of the filter, if any.
+    pub fn parameters<T: IndexAccess>(&self, base: &BaseIndex<T>) -> Option<(u64, f64)> {
+        self.ensure_loaded(base);
+        match *self.filter.borrow() {
+            Some(Some(ref filter)) => {
+                Some((filter.header.capacity, filter.header.false_positive_rate))
+            }
+            _ => None,
+        }
+    }
+
+    /// Adds the key to the filter, if the index has one.
+    pub fn insert<T, K>(&self, base: &mut BaseIndex<T>, key: &K)
+    where
+        T: IndexAccessMut,
+        K: StorageKey + ?Sized,
+    {
+        self.ensure_loaded(base);
+        if let Some(Some(ref mut filter)) = *self.filter.borrow_mut() {
+            let filter = Arc::make_mut(filter);
+            let key = key_bytes(key);
+            // The block and the header are read for update, so that merging the fork fails
+            // if another fork has changed them, rather than discarding the changes of that fork.
+            let (block, _) = filter.positions(&key);
+            base.get_auxiliary_for_update::<_, Vec<u8>>(&block);
+            base.get_auxiliary_for_update::<_, FilterHeader>(&());
+
+            if let Some(block) = filter.set(&key) {
+                base.put_auxiliary(&block, filter.block_bytes(block));
+            }
+            filter.header.insertions += 1;
+            filter.header.version = filter.next_version(&key);
+            base.put_auxiliary(&(), filter.header);
+        }
+    }
+
+    /// Stores the filter in the index, replacing the existing one.
+    pub fn replace<T: IndexAccessMut>(&self, base: &mut BaseIndex<T>, filter: BloomFilter) {
+        filter.store(base);
+        *self.filter.borrow_mut() = Some(Some(Arc::new(filter)));
+    }
+
+    /// Removes all the keys from the filter, if the index has one.
+    pub fn clear<T: IndexAccessMut>(&self, base: &mut BaseIndex<T>) {
+        self.ensure_loaded(base);
+        if let Some(Some(ref mut filter)) = *self.filter.borrow_mut() {
+            let header = filter.header;
+            let empty = BloomFilter::build(
+                header.capacity,
+                header.false_positive_rate,
+                ::std::iter::empty(),
+            );
+            empty.store(base);
+            *filter = Arc::new(empty);
+        }
+    }
+
+    /// Removes the filter from the index.
+    pub fn remove<T: IndexAccessMut>(&self, base: &mut BaseIndex<T>) {
+        base.clear_auxiliary();
+        *self.filter.borrow_mut() = Some(None);
+    }
+}
+
+fn key_bytes<K: StorageKey + ?Sized>(key: &K) -> Vec<u8> {
+    let mut buffer = vec![0; key.size()];
+    key.write(&mut buffer);
+    buffer
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BloomFilter, FilterHeader};
+
+    #[test]
+    fn no_false_negatives() {
+        let keys = (0..1000_u32).map(|i| format!("key{}", i).into_bytes());
+        let filter = BloomFilter::build(1000, 0.01, keys);
+        for i in 0..1000_u32 {
+            assert!(filter.may_contain(format!("key{}", i).as_bytes()));
+        }
+        let false_positives = (0..10_000_u32)
+            .filter(|i| filter.may_contain(format!("other{}", i).as_bytes()))
+            .count();
+        assert!(false_positives < 300, "{} false positives", false_positives);
+    }
+
+    #[test]
+    fn filter_becomes_stale_when_overfilled() {
+        let mut header = FilterHeader::new(1000, 0.01);
+        assert_eq!(header.num_hashes, 7);
+        header.insertions = 1000;
+        assert!(header.expected_false_positive_rate() < 0.02);
+        header.insertions = 2000;
+        assert!(header.expected_false_positive_rate() > 0.02);
+    }
+}
@@ -0,0 +1,450 @@
+// Copyright 2018 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! An implementation of a read cache on top of an arbitrary `Database`.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::{Arc, Mutex, RwLock};
+
+use super::{Database, Iter, Patch, Result, Snapshot};
+
+type CacheKey = (String, Vec<u8>);
+
+/// Statistics of a `CachedDatabase`.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct CacheStats {
+    /// Number of reads served from the cache.
+    pub hits: u64,
+    /// Number of reads that went to the underlying database.
+    pub misses: u64,
+    /// Number of entries evicted to keep the cache within its capacity.
+    pub evictions: u64,
+    /// Number of entries dropped because the corresponding keys were changed by `merge`.
+    pub invalidations: u64,
+    /// Number of entries currently held by the cache.
+    pub entries: usize,
+    /// Total size of the cached entries in bytes.
+    pub size: usize,
+}
+
+impl CacheStats {
+    /// Returns the ratio of cache hits to the total number of reads, or `0.0` if there were
+    /// no reads yet.
+    pub fn hit_ratio(&self) -> f64 {
+        let total = self.hits + self.misses;
+        if total == 0 {
+            0.0
+        } else {
+            self.hits as f64 / total as f64
+        }
+    }
+}
+
+/// Database wrapper that keeps recently read values in a bounded LRU cache.
+///
+/// Values are cached per full key, i.e. a column family name together with a key within
+/// the family. Absent keys are cached as well, so repeated lookups of missing entries
+/// do not hit the underlying database either. The capacity of the cache is given in bytes
+/// and accounts for the column family name, the key and the value of each entry.
+///
+/// Cached entries are invalidated exactly for the keys touched by a [`merge`]. Each entry
+/// remembers the database version it was read at, and a snapshot never gets a value read
+/// after it was created, so snapshots observe the same state as snapshots of the underlying
+/// database.
+///
+/// [`merge`]: ../trait.Database.html#tymethod.merge
+///
+/// # Examples
+///
+/// ```
+/// use exonum::storage::{CachedDatabase, Database, Entry, MemoryDB};
+///
+/// let db = CachedDatabase::new(MemoryDB::new(), 1024 * 1024);
+/// let mut fork = db.fork();
+/// Entry::new("entry", &mut fork).set(10_u64);
+/// db.merge(fork.into_patch()).unwrap();
+///
+/// let snapshot = db.snapshot();
+/// let entry: Entry<_, u64> = Entry::new("entry", &snapshot);
+/// assert_eq!(entry.get(), Some(10));
+/// assert_eq!(entry.get(), Some(10));
+/// assert!(db.stats().hits > 0);
+/// ```
+pub struct CachedDatabase<D> {
+    db: D,
+    cache: Arc<Cache>,
+}
+
+/// A snapshot of a `CachedDatabase`.
+pub struct CachedSnapshot {
+    snapshot: Box<Snapshot>,
+    version: u64,
+    cache: Arc<Cache>,
+}
+
+struct Cache {
+    /// Serializes merges with respect to snapshot creation, so that the version of a snapshot
+    /// always corresponds to the state of the underlying database it was taken from.
+    commit_lock: RwLock<()>,
+    state: Mutex<CacheState>,
+}
+
+struct CacheState {
+    capacity: usize,
+    version: u64,
+    tick: u64,
+    entries: HashMap<CacheKey, CacheEntry>,
+    lru: BTreeMap<u64, CacheKey>,
+    stats: CacheStats,
+}
+
+struct CacheEntry {
+    value: Option<Vec<u8>>,
+    version: u64,
+    tick: u64,
+    size: usize,
+}
+
+impl<D: Database> CachedDatabase<D> {
+    /// Wraps the database with a cache holding at most `capacity` bytes.
+    pub fn new(db: D, capacity: usize) -> Self {
+        CachedDatabase {
+            db,
+            cache: Arc::new(Cache {
+                commit_lock: RwLock::new(()),
+                state: Mutex::new(CacheState::new(capacity)),
+            }),
+        }
+    }
+
+    /// Returns a reference to the underlying database.
+    pub fn inner(&self) -> &D {
+        &self.db
+    }
+
+    /// Returns the current statistics of the cache.
+    pub fn stats(&self) -> CacheStats {
+        self.cache.state.lock().unwrap().stats
+    }
+
+    /// Resets hit, miss, eviction and invalidation counters of the cache.
+    pub fn reset_stats(&self) {
+        let mut state = self.cache.state.lock().unwrap();
+        state.stats = CacheStats {
+            entries: state.stats.entries,
+            size: state.stats.size,
+            ..CacheStats::default()
+        };
+    }
+
+    /// Removes all entries from the cache.
+    pub fn clear(&self) {
+        self.cache.state.lock().unwrap().clear();
+    }
+
+    fn do_merge<F>(&self, patch: Patch, merge: F) -> Result<()>
+    where
+        F: FnOnce(&D, Patch) -> Result<()>,
+    {
+        let keys = patch
+            .iter()
+            .flat_map(|(name, changes)| {
+                changes
+                    .iter()
+                    .map(move |(key, _)| (name.clone(), key.clone()))
+            })
+            .collect::<Vec<_>>();
+
+        let _guard = self.cache.commit_lock.write().unwrap();
+        merge(&self.db, patch)?;
+        let mut state = self.cache.state.lock().unwrap();
+        for key in &keys {
+            if state.remove(key) {
+                state.stats.invalidations += 1;
+            }
+        }
+        state.version += 1;
+        Ok(())
+    }
+}
+
+impl<D: Database> Database for CachedDatabase<D> {
+    fn snapshot(&self) -> Box<Snapshot> {
+        let _guard = self.cache.commit_lock.read().unwrap();
+        let snapshot = self.db.snapshot();
+        let version = self.cache.state.lock().unwrap().version;
+        Box::new(CachedSnapshot {
+            snapshot,
+            version,
+            cache: Arc::clone(&self.cache),
+        })
+    }
+
+    fn merge(&self, patch: Patch) -> Result<()> {
+        self.do_merge(patch, |db, patch| db.merge(patch))
+    }
+
+    fn merge_sync(&self, patch: Patch) -> Result<()> {
+        self.do_merge(patch, |db, patch| db.merge_sync(patch))
+    }
+}
+
+impl Snapshot for CachedSnapshot {
+    fn get(&self, name: &str, key: &[u8]) -> Option<Vec<u8>> {
+        let cache_key = (name.to_string(), key.to_vec());
+        let mut state = self.cache.state.lock().unwrap();
+        if let Some(value) = state.lookup(&cache_key, self.version) {
+            state.stats.hits += 1;
+            return value;
+        }
+        state.stats.misses += 1;
+
+        // The lock is held while reading the underlying snapshot, so the cache state is
+        // only locked once per read.
+        let value = self.snapshot.get(name, key);
+        // Only snapshots of the latest database state may populate the cache, otherwise
+        // a stale value could be served to the snapshots created later.
+        if state.version == self.version {
+            state.insert(cache_key, value.clone(), self.version);
+        }
+        value
+    }
+
+    fn iter<'a>(&'a self, name: &str, from: &[u8]) -> Iter<'a> {
+        self.snapshot.iter(name, from)
+    }
+
+    fn iter_keys<'a>(&'a self, name: &str, from: &[u8]) -> Iter<'a> {
+        self.snapshot.iter_keys(name, from)
+    }
+
+    fn column_families(&self) -> Vec<String> {
+        self.snapshot.column_families()
+    }
+}
+
+impl CacheState {
+    fn new(capacity: usize) -> Self {
+        CacheState {
+            capacity,
+            version: 0,
+            tick: 0,
+            entries: HashMap::new(),
+            lru: BTreeMap::new(),
+            stats: CacheStats::default(),
+        }
+    }
+
+    fn lookup(&mut self, key: &CacheKey, version: u64) -> Option<Option<Vec<u8>>> {
+        self.tick += 1;
+        let tick = self.tick;
+        let (old_tick, value) = match self.entries.get_mut(key) {
+            Some(entry) => {
+                if entry.version > version {
+                    return None;
+                }
+                let old_tick = entry.tick;
+                entry.tick = tick;
+                (old_tick, entry.value.clone())
+            }
+            None => return None,
+        };
+        let key = self.lru.remove(&old_tick).unwrap();
+        self.lru.insert(tick, key);
+        Some(value)
+    }
+
+    fn insert(&mut self, key: CacheKey, value: Option<Vec<u8>>, version: u64) {
+        let size = key.0.len() + key.1.len() + value.as_ref().map_or(0, |v| v.len());
+        if size > self.capacity {
+            return;
+        }
+        self.remove(&key);
+        while self.stats.size + size > self.capacity {
+            self.evict();
+        }
+
+        self.tick += 1;
+        self.lru.insert(self.tick, key.clone());
+        self.entries.insert(
+            key,
+            CacheEntry {
+                value,
+                version,
+                tick: self.tick,
+                size,
+            },
+        );
+        self.stats.size += size;
+        self.stats.entries += 1;
+    }
+
+    fn remove(&mut self, key: &CacheKey) -> bool {
+        match self.entries.remove(key) {
+            Some(entry) => {
+                self.lru.remove(&entry.tick);
+                self.stats.size -= entry.size;
+                self.stats.entries -= 1;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn evict(&mut self) {
+        let key = match self.lru.iter().next() {
+            Some((_, key)) => key.clone(),
+            None => return,
+        };
+        self.remove(&key);
+        self.stats.evictions += 1;
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.lru.clear();
+        self.stats.entries = 0;
+        self.stats.size = 0;
+    }
+}
+
+impl<D> fmt::Debug for CachedDatabase<D> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "CachedDatabase(..)")
+    }
+}
+
+impl fmt::Debug for CachedSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "CachedSnapshot(..)")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use storage::{Entry, MapIndex, MemoryDB};
+
+    const IDX_NAME: &str = "idx_name";
+
+    #[test]
+    fn hits_and_misses() {
+        let db = CachedDatabase::new(MemoryDB::new(), 1024);
+        let mut fork = db.fork();
+        fork.put(IDX_NAME, vec![1], vec![10]);
+        db.merge(fork.into_patch()).unwrap();
+
+        let snapshot = db.snapshot();
+        assert_eq!(snapshot.get(IDX_NAME, &[1]), Some(vec![10]));
+        assert_eq!(snapshot.get(IDX_NAME, &[1]), Some(vec![10]));
+        assert_eq!(snapshot.get(IDX_NAME, &[2]), None);
+        assert!(!snapshot.contains(IDX_NAME, &[2]));
+
+        let stats = db.stats();
+        assert_eq!(stats.hits, 2);
+        assert_eq!(stats.misses, 2);
+        assert_eq!(stats.entries, 2);
+        assert_eq!(stats.hit_ratio(), 0.5);
+    }
+
+    #[test]
+    fn invalidation_on_merge() {
+        let db = CachedDatabase::new(MemoryDB::new(), 1024);
+        let mut fork = db.fork();
+        {
+            let mut index = MapIndex::new(IDX_NAME, &mut fork);
+            index.put(&1_u8, 1_u8);
+            index.put(&2_u8, 2_u8);
+        }
+        db.merge(fork.into_patch()).unwrap();
+
+        {
+            let snapshot = db.snapshot();
+            let index: MapIndex<_, u8, u8> = MapIndex::new(IDX_NAME, &snapshot);
+            assert_eq!(index.get(&1), Some(1));
+            assert_eq!(index.get(&2), Some(2));
+        }
+
+        // The first merge also invalidates the cached lookup of the index in the indexes
+        // metadata, so only the invalidations of the second merge are counted.
+        db.reset_stats();
+        let mut fork = db.fork();
+        MapIndex::new(IDX_NAME, &mut fork).put(&1_u8, 10_u8);
+        db.merge(fork.into_patch()).unwrap();
+        assert_eq!(db.stats().invalidations, 1);
+
+        let snapshot = db.snapshot();
+        let index: MapIndex<_, u8, u8> = MapIndex::new(IDX_NAME, &snapshot);
+        db.reset_stats();
+        assert_eq!(index.get(&1), Some(10));
+        assert_eq!(index.get(&2), Some(2));
+        assert_eq!(db.stats().misses, 1);
+    }
+
+    #[test]
+    fn snapshot_isolation() {
+        let db = CachedDatabase::new(MemoryDB::new(), 1024);
+        let mut fork = db.fork();
+        Entry::new(IDX_NAME, &mut fork).set(1_u64);
+        db.merge(fork.into_patch()).unwrap();
+
+        let old_snapshot = db.snapshot();
+
+        let mut fork = db.fork();
+        Entry::new(IDX_NAME, &mut fork).set(2_u64);
+        db.merge(fork.into_patch()).unwrap();
+
+        // A newer value cached by a fresh snapshot must not leak into the old one.
+        let new_snapshot = db.snapshot();
+        assert_eq!(Entry::new(IDX_NAME, &new_snapshot).get(), Some(2_u64));
+        assert_eq!(Entry::new(IDX_NAME, &old_snapshot).get(), Some(1_u64));
+
+        // An old value read by the old snapshot must not be served to the newer ones.
+        db.clear();
+        assert_eq!(Entry::new(IDX_NAME, &old_snapshot).get(), Some(1_u64));
+        assert_eq!(Entry::new(IDX_NAME, &new_snapshot).get(), Some(2_u64));
+        assert_eq!(Entry::new(IDX_NAME, &db.snapshot()).get(), Some(2_u64));
+    }
+
+    #[test]
+    fn eviction_by_size() {
+        // Every entry takes `IDX_NAME.len() + 1 + 4` bytes.
+        let entry_size = IDX_NAME.len() + 5;
+        let db = CachedDatabase::new(MemoryDB::new(), entry_size * 2);
+        let mut fork = db.fork();
+        for i in 0..3 {
+            fork.put(IDX_NAME, vec![i], vec![i; 4]);
+        }
+        db.merge(fork.into_patch()).unwrap();
+
+        let snapshot = db.snapshot();
+        snapshot.get(IDX_NAME, &[0]);
+        snapshot.get(IDX_NAME, &[1]);
+        // Touch the first key so that the second one becomes the least recently used.
+        snapshot.get(IDX_NAME, &[0]);
+        snapshot.get(IDX_NAME, &[2]);
+
+        let stats = db.stats();
+        assert_eq!(stats.evictions, 1);
+        assert_eq!(stats.entries, 2);
+        assert_eq!(stats.size, entry_size * 2);
+
+        db.reset_stats();
+        snapshot.get(IDX_NAME, &[0]);
+        snapshot.get(IDX_NAME, &[1]);
+        assert_eq!(db.stats().hits, 1);
+        assert_eq!(db.stats().misses, 1);
+    }
+}
@@ -19,7 +19,8 @@ use std::collections::Bound::*;
 use std::cmp::Ordering::*;
 use std::iter::{Iterator as StdIterator, Peekable};
 
-use super::Result;
+use super::{state_hash, Error, Result};
+use super::indexes_metadata::INDEXES_METADATA_TABLE_NAME;
 use self::NextIterValue::*;
 
 /// Map containing changes with corresponding key.
@@ -67,17 +68,29 @@ impl IntoIterator for Changes {
     }
 }
 
+/// Values read from the database by a fork, which must remain unchanged for the fork
+/// changes to be merged.
+type Reads = HashMap<String, BTreeMap<Vec<u8>, Option<Vec<u8>>>>;
+
 /// A set of serial changes that should be applied to a storage atomically.
+///
+/// Besides the changes, a patch contains the values read with [`Fork::get_for_update`].
+/// A database refuses to merge the patch if any of these values has been changed since
+/// the fork was created; see [`check_reads`](#method.check_reads).
+///
+/// [`Fork::get_for_update`]: struct.Fork.html#method.get_for_update
 #[derive(Debug, Clone)]
 pub struct Patch {
     changes: HashMap<String, Changes>,
+    reads: Reads,
 }
 
 impl Patch {
     /// Creates a new empty `Patch` instance.
-    fn new() -> Self {
+    pub(crate) fn new() -> Self {
         Self {
             changes: HashMap::new(),
+            reads: HashMap::new(),
         }
     }
 
@@ -101,11 +114,63 @@ impl Patch {
         self.changes.insert(name, changes);
     }
 
+    /// Records a change of the key in the column family with the given name.
+    pub(crate) fn insert_change(&mut self, name: &str, key: Vec<u8>, change: Change) {
+        self.changes_entry(name.to_string())
+            .or_insert_with(Changes::new)
+            .data
+            .insert(key, change);
+    }
+
+    /// Records the value of the key in the column family with the given name, which the changes
+    /// depend on. If the key has already been read, the earlier value is kept.
+    pub(crate) fn insert_read(&mut self, name: &str, key: Vec<u8>, value: Option<Vec<u8>>) {
+        self.reads
+            .entry(name.to_string())
+            .or_insert_with(BTreeMap::new)
+            .entry(key)
+            .or_insert(value);
+    }
+
+    /// Forgets the recorded reads, so that the patch is merged unconditionally.
+    pub(crate) fn clear_reads(&mut self) {
+        self.reads.clear();
+    }
+
     /// Returns iterator over changes.
     pub fn iter(&self) -> HmIter<String, Changes> {
         self.changes.iter()
     }
 
+    /// Returns `true` if the changes of this patch depend on values read from the database.
+    pub fn has_reads(&self) -> bool {
+        self.reads.values().any(|reads| !reads.is_empty())
+    }
+
+    /// Checks that the values read by the fork of this patch are the same in the given snapshot.
+    ///
+    /// `Database` implementations call this method with the current state of the database
+    /// before applying the patch, ensuring that no other patch is merged in between.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error for which [`is_conflict`] is `true` if any of the values is different.
+    ///
+    /// [`is_conflict`]: struct.Error.html#method.is_conflict
+    pub fn check_reads(&self, snapshot: &Snapshot) -> Result<()> {
+        for (name, reads) in &self.reads {
+            for (key, value) in reads {
+                if snapshot.get(name, key) != *value {
+                    return Err(Error::conflict(format!(
+                        "Value of key {:?} in column family {} has been changed concurrently",
+                        key, name
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
     /// Returns the number of changes.
     pub fn len(&self) -> usize {
         self.changes
@@ -167,7 +232,9 @@ pub enum Change {
 /// [`into_patch`] and then atomically [`merge`] it into the database. If two
 /// conflicting forks are merged into a database, this can lead to an inconsistent state. If you
 /// need to consistently apply several sets of changes for the same data, the next fork should be
-/// created after the previous fork has been merged.
+/// created after the previous fork has been merged, or the data should be read with
+/// [`get_for_update`]: then merging the patch fails if the values read have been changed
+/// by a patch merged after the fork was created.
 ///
 /// `Fork` also supports checkpoints ([`checkpoint`], [`commit`] and
 /// [`rollback`] methods), which allows to rollback some of the latest changes (e.g., after
@@ -187,6 +254,7 @@ pub enum Change {
 /// [`Patch`]: struct.Patch.html
 /// [`into_patch`]: #method.into_patch
 /// [`merge`]: trait.Database.html#tymethod.merge
+/// [`get_for_update`]: #method.get_for_update
 /// [`checkpoint`]: #method.checkpoint
 /// [`commit`]: #method.commit
 /// [`rollback`]: #method.rollback
@@ -264,6 +332,11 @@ pub trait Database: Send + Sync + 'static {
     /// If this method encounters any form of I/O or other error during merging, an error variant
     /// will be returned. In case of an error the method guarantees no changes were applied to
     /// the database.
+    ///
+    /// If the values read by the fork of the patch have been changed, a conflict error is
+    /// returned (see [`Patch::check_reads`]).
+    ///
+    /// [`Patch::check_reads`]: struct.Patch.html#method.check_reads
     fn merge(&self, patch: Patch) -> Result<()>;
 
     /// Atomically applies a sequence of patch changes to the database with fsync.
@@ -276,18 +349,48 @@ pub trait Database: Send + Sync + 'static {
     /// If this method encounters any form of I/O or other error during merging, an error variant
     /// will be returned. In case of an error the method guarantees no changes were applied to
     /// the database.
+    ///
+    /// If the values read by the fork of the patch have been changed, a conflict error is
+    /// returned (see [`Patch::check_reads`]).
+    ///
+    /// [`Patch::check_reads`]: struct.Patch.html#method.check_reads
     fn merge_sync(&self, patch: Patch) -> Result<()>;
 }
 
+/// Bounds required from every `Snapshot` implementation.
+///
+/// The trait is implemented automatically. Without the `async` feature only the `'static` bound
+/// is required, with the feature snapshots must be `Send` and `Sync` as well.
+#[cfg(not(feature = "async"))]
+pub trait SnapshotBounds: 'static {}
+
+#[cfg(not(feature = "async"))]
+impl<T: 'static + ?Sized> SnapshotBounds for T {}
+
+/// Bounds required from every `Snapshot` implementation.
+///
+/// The trait is implemented automatically. Without the `async` feature only the `'static` bound
+/// is required, with the feature snapshots must be `Send` and `Sync` as well.
+#[cfg(feature = "async")]
+pub trait SnapshotBounds: Send + Sync + 'static {}
+
+#[cfg(feature = "async")]
+impl<T: Send + Sync + 'static + ?Sized> SnapshotBounds for T {}
+
 /// A read-only snapshot of a storage backend.
 ///
 /// A `Snapshot` instance is an immutable representation of a certain storage state.
 /// It provides read isolation, so consistency is guaranteed even if the data in
 /// the database changes between reads.
 ///
+/// With the `async` feature enabled, a snapshot is shared between the threads of a thread pool,
+/// so it must be `Send` and `Sync` (see [`SnapshotBounds`]).
+///
 /// **Note.** Unless stated otherwise, "key" in the method descriptions below refers
 /// to a full key (a string column family name + key as an array of bytes within the family).
-pub trait Snapshot: 'static {
+///
+/// [`SnapshotBounds`]: trait.SnapshotBounds.html
+pub trait Snapshot: SnapshotBounds {
     /// Returns a value corresponding to the specified key as a raw vector of bytes,
     /// or `None` if it does not exist.
     fn get(&self, name: &str, key: &[u8]) -> Option<Vec<u8>>;
@@ -302,6 +405,34 @@ pub trait Snapshot: 'static {
     /// Returns an iterator over the entries of the snapshot in ascending order starting from
     /// the specified key. The iterator element type is `(&[u8], &[u8])`.
     fn iter<'a>(&'a self, name: &str, from: &[u8]) -> Iter<'a>;
+
+    /// Returns an iterator over the keys of the snapshot in ascending order starting from
+    /// the specified key.
+    ///
+    /// The iterator yields the same keys as [`iter`](#tymethod.iter), but the values it yields
+    /// are unspecified and must be ignored; backends may skip reading or copying them.
+    /// Default implementation falls back to `iter`.
+    fn iter_keys<'a>(&'a self, name: &str, from: &[u8]) -> Iter<'a> {
+        self.iter(name, from)
+    }
+
+    /// Returns the names of the column families of the snapshot in ascending order.
+    ///
+    /// The list may include column families without entries. Default implementation
+    /// returns the indexes metadata table and the indexes registered in it, so it misses
+    /// the data written with [`Fork::put`] to other column families; backends should
+    /// override it.
+    ///
+    /// [`Fork::put`]: struct.Fork.html#method.put
+    fn column_families(&self) -> Vec<String> {
+        let mut names = vec![INDEXES_METADATA_TABLE_NAME.to_string()];
+        let mut iter = self.iter_keys(INDEXES_METADATA_TABLE_NAME, &[]);
+        while let Some((name, _)) = iter.next() {
+            names.push(String::from_utf8_lossy(name).into_owned());
+        }
+        names.sort();
+        names
+    }
 }
 
 /// A trait that defines streaming iterator over storage view entries.
@@ -339,20 +470,34 @@ impl Snapshot for Fork {
     }
 
     fn iter<'a>(&'a self, name: &str, from: &[u8]) -> Iter<'a> {
+        self.fork_iter(name, from, self.snapshot.iter(name, from))
+    }
+
+    fn iter_keys<'a>(&'a self, name: &str, from: &[u8]) -> Iter<'a> {
+        self.fork_iter(name, from, self.snapshot.iter_keys(name, from))
+    }
+
+    fn column_families(&self) -> Vec<String> {
+        let mut names = self.snapshot.column_families();
+        names.extend(self.patch.iter().map(|(name, _)| name.clone()));
+        names.sort();
+        names.dedup();
+        names
+    }
+}
+
+impl Fork {
+    /// Combines the changes of the fork with the snapshot iterator.
+    fn fork_iter<'a>(&'a self, name: &str, from: &[u8], snapshot: Iter<'a>) -> Iter<'a> {
         let range = (Included(from), Unbounded);
         let changes = match self.patch.changes(name) {
             Some(changes) => Some(changes.data.range::<[u8], _>(range).peekable()),
             None => None,
         };
 
-        Box::new(ForkIter {
-            snapshot: self.snapshot.iter(name, from),
-            changes,
-        })
+        Box::new(ForkIter { snapshot, changes })
     }
-}
 
-impl Fork {
     /// Creates a new checkpoint.
     ///
     /// # Panics
@@ -400,6 +545,28 @@ impl Fork {
         self.logged = false;
     }
 
+    /// Returns a value corresponding to the specified key, like [`get`], and records the value
+    /// in the patch if it is read from the database rather than from the fork changes.
+    ///
+    /// Merging the patch fails with a conflict if the recorded value has been changed in
+    /// the database after the fork was created. Values stay recorded even if the changes
+    /// made after a checkpoint are rolled back.
+    ///
+    /// [`get`]: trait.Snapshot.html#tymethod.get
+    pub fn get_for_update(&mut self, name: &str, key: &[u8]) -> Option<Vec<u8>> {
+        if let Some(changes) = self.patch.changes(name) {
+            if let Some(change) = changes.data.get(key) {
+                match *change {
+                    Change::Put(ref v) => return Some(v.clone()),
+                    Change::Delete => return None,
+                }
+            }
+        }
+        let value = self.snapshot.get(name, key);
+        self.patch.insert_read(name, key.to_vec(), value.clone());
+        value
+    }
+
     /// Inserts a key-value pair into the fork.
     pub fn put(&mut self, name: &str, key: Vec<u8>, value: Vec<u8>) {
         let changes = self.patch
@@ -439,23 +606,31 @@ impl Fork {
             .changes_entry(name.to_string())
             .or_insert_with(Changes::new);
         // Remove changes
-        if let Some(prefix) = prefix {
-            let keys = changes
+        let keys = if let Some(prefix) = prefix {
+            changes
                 .data
                 .range::<Vec<u8>, _>((Included(prefix), Unbounded))
                 .map(|(k, _)| k.to_vec())
                 .take_while(|k| k.starts_with(prefix))
-                .collect::<Vec<_>>();
-            for k in keys {
-                changes.data.remove(&k);
-            }
+                .collect::<Vec<_>>()
         } else {
-            changes.data.clear();
+            changes.data.keys().cloned().collect::<Vec<_>>()
+        };
+        for k in keys {
+            let change = changes.data.remove(&k);
+            if self.logged {
+                self.changelog.push((name.to_string(), k, change));
+            }
         }
         // Remove from storage
         let mut iter = self.snapshot
             .iter(name, prefix.map_or(&[], |k| k.as_slice()));
         while let Some((k, ..)) = iter.next() {
+            if let Some(prefix) = prefix {
+                if !k.starts_with(prefix) {
+                    break;
+                }
+            }
             let change = changes.data.insert(k.to_vec(), Change::Delete);
             if self.logged {
                 self.changelog.push((name.to_string(), k.to_vec(), change));
@@ -464,7 +639,22 @@ impl Fork {
     }
 
     /// Converts the fork into `Patch`.
-    pub fn into_patch(self) -> Patch {
+    ///
+    /// The Merkle roots of the indexes contributing to the state hash are updated in
+    /// the state aggregator before that, so the merged patch always keeps the state hash
+    /// up to date. See the [`state_hash`] module for details.
+    ///
+    /// [`state_hash`]: state_hash/index.html
+    pub fn into_patch(mut self) -> Patch {
+        state_hash::update_aggregator(&mut self);
+        self.patch
+    }
+
+    /// Converts the fork into `Patch` without updating the state aggregator.
+    ///
+    /// Used by the database wrappers which store the data of the wrapped database
+    /// in their own format, or copy it as is.
+    pub(crate) fn into_raw_patch(self) -> Patch {
         self.patch
     }
 
@@ -477,6 +667,7 @@ impl Fork {
     ///
     /// If both forks have changed the same data, this can lead to an inconsistent state. Hence,
     /// this method is useful only if you are sure that forks interacted with different indices.
+    /// The values read by the other fork are recorded in this fork as well.
     ///
     /// # Panics
     ///
@@ -486,7 +677,13 @@ impl Fork {
             panic!("call merge before commit or rollback");
         }
 
-        for (name, changes) in patch {
+        let Patch { changes, reads } = patch;
+        for (name, reads) in reads {
+            for (key, value) in reads {
+                self.patch.insert_read(&name, key, value);
+            }
+        }
+        for (name, changes) in changes {
             if let Some(in_changes) = self.patch.changes_mut(&name) {
                 in_changes.data.extend(changes.into_iter());
                 continue;
@@ -0,0 +1,640 @@
+// Copyright 2018 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! An implementation of double-ended queue of items.
+
+use byteorder::{BigEndian, ByteOrder};
+
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::marker::PhantomData;
+
+use crypto::{hash, CryptoHash, Hash};
+use super::{BaseIndex, BaseIndexIter, IndexAccess, IndexAccessMut, StorageKey, StorageValue};
+use super::indexes_metadata::IndexType;
+
+/// Position of the first element of an empty deque. Starting in the middle of the `u64` range
+/// lets the deque grow in both directions.
+const INITIAL_POSITION: u64 = 1 << 63;
+
+#[derive(Debug, Clone, Copy)]
+struct DequeBounds {
+    /// Position of the front element.
+    head: u64,
+    /// Position following the back element.
+    tail: u64,
+}
+
+impl Default for DequeBounds {
+    fn default() -> Self {
+        DequeBounds {
+            head: INITIAL_POSITION,
+            tail: INITIAL_POSITION,
+        }
+    }
+}
+
+impl DequeBounds {
+    fn len(&self) -> u64 {
+        self.tail - self.head
+    }
+
+    fn to_array(&self) -> [u8; 16] {
+        let mut buf = [0; 16];
+        BigEndian::write_u64(&mut buf[0..8], self.head);
+        BigEndian::write_u64(&mut buf[8..16], self.tail);
+        buf
+    }
+}
+
+impl CryptoHash for DequeBounds {
+    fn hash(&self) -> Hash {
+        hash(&self.to_array())
+    }
+}
+
+impl StorageValue for DequeBounds {
+    fn into_bytes(self) -> Vec<u8> {
+        self.to_array().to_vec()
+    }
+
+    fn from_bytes(value: Cow<[u8]>) -> Self {
+        let buf = value.as_ref();
+        let head = BigEndian::read_u64(&buf[0..8]);
+        let tail = BigEndian::read_u64(&buf[8..16]);
+        DequeBounds { head, tail }
+    }
+}
+
+/// A double-ended queue of items.
+///
+/// `DequeIndex` is similar to [`ListIndex`], but allows to insert and remove elements at both
+/// ends in constant time. Elements are stored under consecutive `u64` positions between the
+/// head and the tail counters, which are moved by the push and pop operations, so the elements
+/// are never shifted. Elements are accessed by their index relative to the front of the deque.
+/// `DequeIndex` requires that the elements implement the [`StorageValue`] trait.
+///
+/// [`StorageValue`]: ../trait.StorageValue.html
+/// [`ListIndex`]: <../list_index/struct.ListIndex.html>
+#[derive(Debug)]
+pub struct DequeIndex<T, V> {
+    base: BaseIndex<T>,
+    bounds: Cell<Option<DequeBounds>>,
+    _v: PhantomData<V>,
+}
+
+/// An iterator over the items of a `DequeIndex`.
+///
+/// This struct is created by the [`iter`] or
+/// [`iter_from`] methods on [`DequeIndex`]. See its documentation for more.
+///
+/// [`iter`]: struct.DequeIndex.html#method.iter
+/// [`iter_from`]: struct.DequeIndex.html#method.iter_from
+/// [`DequeIndex`]: struct.DequeIndex.html
+#[derive(Debug)]
+pub struct DequeIndexIter<'a, V> {
+    base_iter: BaseIndexIter<'a, u64, V>,
+    remaining: u64,
+}
+
+impl<T, V> DequeIndex<T, V>
+where
+    T: IndexAccess,
+    V: StorageValue,
+{
+    /// Creates a new index representation based on the name and storage view.
+    ///
+    /// Storage view can be specified as [`&Snapshot`] or [`&mut Fork`]. In the first case only
+    /// immutable methods are available. In the second case both immutable and mutable methods are
+    /// available.
+    ///
+    /// [`&Snapshot`]: ../trait.Snapshot.html
+    /// [`&mut Fork`]: ../struct.Fork.html
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, DequeIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let snapshot = db.snapshot();
+    /// let name = "name";
+    /// let index: DequeIndex<_, u8> = DequeIndex::new(name, &snapshot);
+    /// ```
+    pub fn new<S: AsRef<str>>(index_name: S, view: T) -> Self {
+        DequeIndex {
+            base: BaseIndex::new(index_name, IndexType::Deque, view),
+            bounds: Cell::new(None),
+            _v: PhantomData,
+        }
+    }
+
+    /// Creates a new index representation based on the name, index id in family
+    /// and storage view.
+    ///
+    /// Storage view can be specified as [`&Snapshot`] or [`&mut Fork`]. In the first case only
+    /// immutable methods are available. In the second case both immutable and mutable methods are
+    /// available.
+    ///
+    /// [`&Snapshot`]: ../trait.Snapshot.html
+    /// [`&mut Fork`]: ../struct.Fork.html
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, DequeIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let snapshot = db.snapshot();
+    /// let name = "name";
+    /// let index_id = vec![123];
+    /// let index: DequeIndex<_, u8> = DequeIndex::new_in_family(
+    ///     name,
+    ///     &index_id,
+    ///     &snapshot,
+    ///  );
+    /// ```
+    pub fn new_in_family<S: AsRef<str>, I: StorageKey>(
+        family_name: S,
+        index_id: &I,
+        view: T,
+    ) -> Self {
+        DequeIndex {
+            base: BaseIndex::new_in_family(family_name, index_id, IndexType::Deque, view),
+            bounds: Cell::new(None),
+            _v: PhantomData,
+        }
+    }
+
+    /// Returns the storage view of the index, consuming the index.
+    pub fn into_view(self) -> T {
+        self.base.into_view()
+    }
+
+    fn bounds(&self) -> DequeBounds {
+        if let Some(bounds) = self.bounds.get() {
+            return bounds;
+        }
+        let bounds = self.base.get(&()).unwrap_or_default();
+        self.bounds.set(Some(bounds));
+        bounds
+    }
+
+    /// Returns the element at the specified index counting from the front of the deque,
+    /// or `None` if the index is out of bounds.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, DequeIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let mut fork = db.fork();
+    /// let mut index = DequeIndex::new("name", &mut fork);
+    /// assert_eq!(None, index.get(0));
+    ///
+    /// index.push_back(2);
+    /// index.push_front(1);
+    /// assert_eq!(Some(1), index.get(0));
+    /// assert_eq!(Some(2), index.get(1));
+    /// ```
+    pub fn get(&self, index: u64) -> Option<V> {
+        let bounds = self.bounds();
+        if index >= bounds.len() {
+            return None;
+        }
+        self.base.get(&(bounds.head + index))
+    }
+
+    /// Returns the front element of the deque, or `None` if it is empty.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, DequeIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let mut fork = db.fork();
+    /// let mut index = DequeIndex::new("name", &mut fork);
+    /// assert_eq!(None, index.front());
+    ///
+    /// index.push_back(1);
+    /// index.push_back(2);
+    /// assert_eq!(Some(1), index.front());
+    /// ```
+    pub fn front(&self) -> Option<V> {
+        self.get(0)
+    }
+
+    /// Returns the back element of the deque, or `None` if it is empty.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, DequeIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let mut fork = db.fork();
+    /// let mut index = DequeIndex::new("name", &mut fork);
+    /// assert_eq!(None, index.back());
+    ///
+    /// index.push_back(1);
+    /// index.push_back(2);
+    /// assert_eq!(Some(2), index.back());
+    /// ```
+    pub fn back(&self) -> Option<V> {
+        let bounds = self.bounds();
+        if bounds.len() == 0 {
+            return None;
+        }
+        self.base.get(&(bounds.tail - 1))
+    }
+
+    /// Returns `true` if the deque contains no elements.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, DequeIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let mut fork = db.fork();
+    /// let mut index = DequeIndex::new("name", &mut fork);
+    /// assert!(index.is_empty());
+    ///
+    /// index.push_front(1);
+    /// assert!(!index.is_empty());
+    /// ```
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the number of elements in the deque.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, DequeIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let mut fork = db.fork();
+    /// let mut index = DequeIndex::new("name", &mut fork);
+    /// assert_eq!(0, index.len());
+    ///
+    /// index.push_front(1);
+    /// index.push_back(2);
+    /// assert_eq!(2, index.len());
+    /// ```
+    pub fn len(&self) -> u64 {
+        self.bounds().len()
+    }
+
+    /// Returns an iterator over the deque, from the front to the back. The iterator element type
+    /// is V.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, DequeIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let mut fork = db.fork();
+    /// let mut index = DequeIndex::new("name", &mut fork);
+    ///
+    /// index.push_back(2);
+    /// index.push_front(1);
+    /// assert_eq!(vec![1, 2], index.iter().collect::<Vec<u8>>());
+    /// ```
+    pub fn iter(&self) -> DequeIndexIter<V> {
+        self.iter_from(0)
+    }
+
+    /// Returns an iterator over the deque starting from the specified index counting from
+    /// the front. The iterator element type is V.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, DequeIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let mut fork = db.fork();
+    /// let mut index = DequeIndex::new("name", &mut fork);
+    ///
+    /// index.push_back(2);
+    /// index.push_back(3);
+    /// index.push_front(1);
+    /// assert_eq!(vec![2, 3], index.iter_from(1).collect::<Vec<u8>>());
+    /// ```
+    pub fn iter_from(&self, from: u64) -> DequeIndexIter<V> {
+        let bounds = self.bounds();
+        let from = ::std::cmp::min(from, bounds.len());
+        DequeIndexIter {
+            base_iter: self.base.iter_from(&(), &(bounds.head + from)),
+            remaining: bounds.len() - from,
+        }
+    }
+}
+
+impl<T, V> DequeIndex<T, V>
+where
+    T: IndexAccessMut,
+    V: StorageValue,
+{
+    fn set_bounds(&mut self, bounds: DequeBounds) {
+        self.base.put(&(), bounds);
+        self.bounds.set(Some(bounds));
+    }
+
+    /// Prepends an element to the front of the deque.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the deque has been grown from the front more than `2^63` times since it was
+    /// last empty.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, DequeIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let mut fork = db.fork();
+    /// let mut index = DequeIndex::new("name", &mut fork);
+    ///
+    /// index.push_front(2);
+    /// index.push_front(1);
+    /// assert_eq!(Some(1), index.front());
+    /// ```
+    pub fn push_front(&mut self, value: V) {
+        let mut bounds = self.bounds();
+        bounds.head = bounds
+            .head
+            .checked_sub(1)
+            .expect("DequeIndex has no room at the front");
+        self.base.put(&bounds.head, value);
+        self.set_bounds(bounds);
+    }
+
+    /// Appends an element to the back of the deque.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the deque has been grown from the back more than `2^63` times since it was
+    /// last empty.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, DequeIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let mut fork = db.fork();
+    /// let mut index = DequeIndex::new("name", &mut fork);
+    ///
+    /// index.push_back(1);
+    /// index.push_back(2);
+    /// assert_eq!(Some(2), index.back());
+    /// ```
+    pub fn push_back(&mut self, value: V) {
+        let mut bounds = self.bounds();
+        self.base.put(&bounds.tail, value);
+        bounds.tail = bounds
+            .tail
+            .checked_add(1)
+            .expect("DequeIndex has no room at the back");
+        self.set_bounds(bounds);
+    }
+
+    /// Removes the front element from the deque and returns it, or `None` if it is empty.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, DequeIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let mut fork = db.fork();
+    /// let mut index = DequeIndex::new("name", &mut fork);
+    /// assert_eq!(None, index.pop_front());
+    ///
+    /// index.push_back(1);
+    /// index.push_back(2);
+    /// assert_eq!(Some(1), index.pop_front());
+    /// ```
+    pub fn pop_front(&mut self) -> Option<V> {
+        let mut bounds = self.bounds();
+        if bounds.len() == 0 {
+            return None;
+        }
+        let value = self.base.get(&bounds.head);
+        self.base.remove(&bounds.head);
+        bounds.head += 1;
+        self.update_bounds(bounds);
+        value
+    }
+
+    /// Removes the back element from the deque and returns it, or `None` if it is empty.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, DequeIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let mut fork = db.fork();
+    /// let mut index = DequeIndex::new("name", &mut fork);
+    /// assert_eq!(None, index.pop_back());
+    ///
+    /// index.push_back(1);
+    /// index.push_back(2);
+    /// assert_eq!(Some(2), index.pop_back());
+    /// ```
+    pub fn pop_back(&mut self) -> Option<V> {
+        let mut bounds = self.bounds();
+        if bounds.len() == 0 {
+            return None;
+        }
+        bounds.tail -= 1;
+        let value = self.base.get(&bounds.tail);
+        self.base.remove(&bounds.tail);
+        self.update_bounds(bounds);
+        value
+    }
+
+    /// Extends the deque at the back with the contents of an iterator.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, DequeIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let mut fork = db.fork();
+    /// let mut index = DequeIndex::new("name", &mut fork);
+    ///
+    /// index.extend([1, 2, 3].iter().cloned());
+    /// assert_eq!(3, index.len());
+    /// ```
+    pub fn extend<I>(&mut self, iter: I)
+    where
+        I: IntoIterator<Item = V>,
+    {
+        let mut bounds = self.bounds();
+        for value in iter {
+            self.base.put(&bounds.tail, value);
+            bounds.tail = bounds
+                .tail
+                .checked_add(1)
+                .expect("DequeIndex has no room at the back");
+        }
+        self.set_bounds(bounds);
+    }
+
+    /// Clears the deque, removing all values.
+    ///
+    /// # Notes
+    ///
+    /// Currently this method is not optimized to delete large set of data. During the execution of
+    /// this method the amount of allocated memory is linearly dependent on the number of elements
+    /// in the index.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, DequeIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let mut fork = db.fork();
+    /// let mut index = DequeIndex::new("name", &mut fork);
+    ///
+    /// index.push_back(1);
+    /// assert!(!index.is_empty());
+    ///
+    /// index.clear();
+    /// assert!(index.is_empty());
+    /// ```
+    pub fn clear(&mut self) {
+        self.bounds.set(Some(DequeBounds::default()));
+        self.base.clear()
+    }
+
+    /// Stores the bounds after an element has been popped, moving the counters back to
+    /// the initial position once the deque becomes empty.
+    fn update_bounds(&mut self, bounds: DequeBounds) {
+        if bounds.len() == 0 {
+            self.base.remove(&());
+            self.bounds.set(Some(DequeBounds::default()));
+        } else {
+            self.set_bounds(bounds);
+        }
+    }
+}
+
+impl<'a, T, V> ::std::iter::IntoIterator for &'a DequeIndex<T, V>
+where
+    T: IndexAccess,
+    V: StorageValue,
+{
+    type Item = V;
+    type IntoIter = DequeIndexIter<'a, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a, V> Iterator for DequeIndexIter<'a, V>
+where
+    V: StorageValue,
+{
+    type Item = V;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        self.base_iter.next().map(|(_, value)| value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use storage::{Database, MemoryDB};
+    use super::{DequeIndex, INITIAL_POSITION};
+
+    #[test]
+    fn push_and_pop_at_both_ends() {
+        let db = MemoryDB::new();
+        let mut fork = db.fork();
+        let mut index = DequeIndex::new("deque", &mut fork);
+
+        index.push_back(3_u32);
+        index.push_front(2);
+        index.push_back(4);
+        index.push_front(1);
+        assert_eq!(index.len(), 4);
+        assert_eq!(index.iter().collect::<Vec<_>>(), vec![1, 2, 3, 4]);
+        assert_eq!(index.get(2), Some(3));
+        assert_eq!(index.get(4), None);
+
+        assert_eq!(index.pop_back(), Some(4));
+        assert_eq!(index.pop_front(), Some(1));
+        assert_eq!(index.iter().collect::<Vec<_>>(), vec![2, 3]);
+        assert_eq!(index.front(), Some(2));
+        assert_eq!(index.back(), Some(3));
+
+        assert_eq!(index.pop_front(), Some(2));
+        assert_eq!(index.pop_front(), Some(3));
+        assert_eq!(index.pop_front(), None);
+        assert_eq!(index.pop_back(), None);
+        assert!(index.is_empty());
+    }
+
+    #[test]
+    fn bounds_are_persisted() {
+        let db = MemoryDB::new();
+        let mut fork = db.fork();
+        {
+            let mut index = DequeIndex::new("deque", &mut fork);
+            index.extend(vec![2_u32, 3]);
+            index.push_front(1);
+        }
+        db.merge(fork.into_patch()).unwrap();
+
+        let snapshot = db.snapshot();
+        let index: DequeIndex<_, u32> = DequeIndex::new("deque", &snapshot);
+        assert_eq!(index.len(), 3);
+        assert_eq!(index.iter_from(1).collect::<Vec<_>>(), vec![2, 3]);
+        assert_eq!(index.iter_from(5).count(), 0);
+    }
+
+    #[test]
+    fn emptied_deque_starts_over() {
+        let db = MemoryDB::new();
+        let mut fork = db.fork();
+        let mut index = DequeIndex::new("deque", &mut fork);
+
+        for i in 0..10_u32 {
+            index.push_front(i);
+        }
+        while index.pop_back().is_some() {}
+        assert_eq!(index.bounds().head, INITIAL_POSITION);
+        assert_eq!(index.bounds().tail, INITIAL_POSITION);
+
+        index.push_back(1);
+        assert_eq!(index.iter().collect::<Vec<_>>(), vec![1]);
+    }
+}
@@ -0,0 +1,201 @@
+// Copyright 2018 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Computation of the changes between two database states.
+
+use std::cmp::Ordering;
+use std::collections::BTreeSet;
+
+use super::{Patch, Snapshot};
+use super::db::Change;
+
+/// Computes the patch which transforms the state of the `old` snapshot into the state
+/// of the `new` one.
+///
+/// Applying the patch with [`Database::merge`] to a database with the same state as `old`
+/// brings it to the state of `new`. The column families are compared by merge-joining
+/// their iterators, so the entries are read once and in order.
+///
+/// All the column families of either snapshot (as listed by [`Snapshot::column_families`])
+/// are compared, including the ones not registered in the indexes metadata.
+///
+/// [`Database::merge`]: trait.Database.html#tymethod.merge
+/// [`Snapshot::column_families`]: trait.Snapshot.html#method.column_families
+///
+/// # Examples
+///
+/// ```
+/// use exonum::storage::{self, Database, MapIndex, MemoryDB};
+///
+/// let db = MemoryDB::new();
+/// let old = db.snapshot();
+/// let mut fork = db.fork();
+/// MapIndex::new("map", &mut fork).put(&1_u8, 2_u8);
+/// db.merge(fork.into_patch()).unwrap();
+///
+/// let patch = storage::diff(&*old, &*db.snapshot());
+/// let other_db = MemoryDB::new();
+/// other_db.merge(patch).unwrap();
+/// let snapshot = other_db.snapshot();
+/// let index: MapIndex<_, u8, u8> = MapIndex::new("map", &snapshot);
+/// assert_eq!(index.get(&1), Some(2));
+/// ```
+pub fn diff(old: &Snapshot, new: &Snapshot) -> Patch {
+    let names = old.column_families()
+        .into_iter()
+        .chain(new.column_families())
+        .collect::<BTreeSet<_>>();
+    let mut patch = Patch::new();
+    for name in &names {
+        diff_column_family(name, old, new, &mut patch);
+    }
+    patch
+}
+
+fn diff_column_family(name: &str, old: &Snapshot, new: &Snapshot, patch: &mut Patch) {
+    let mut old_iter = old.iter(name, &[]);
+    let mut new_iter = new.iter(name, &[]);
+    loop {
+        let (key, change, ordering) = match (old_iter.peek(), new_iter.peek()) {
+            (None, None) => break,
+            (Some((old_key, _)), None) => (old_key.to_vec(), Some(Change::Delete), Ordering::Less),
+            (None, Some((new_key, new_value))) => (
+                new_key.to_vec(),
+                Some(Change::Put(new_value.to_vec())),
+                Ordering::Greater,
+            ),
+            (Some((old_key, old_value)), Some((new_key, new_value))) => {
+                match old_key.cmp(new_key) {
+                    Ordering::Less => (old_key.to_vec(), Some(Change::Delete), Ordering::Less),
+                    Ordering::Greater => (
+                        new_key.to_vec(),
+                        Some(Change::Put(new_value.to_vec())),
+                        Ordering::Greater,
+                    ),
+                    Ordering::Equal if old_value == new_value => {
+                        (old_key.to_vec(), None, Ordering::Equal)
+                    }
+                    Ordering::Equal => (
+                        old_key.to_vec(),
+                        Some(Change::Put(new_value.to_vec())),
+                        Ordering::Equal,
+                    ),
+                }
+            }
+        };
+
+        if ordering != Ordering::Greater {
+            old_iter.next();
+        }
+        if ordering != Ordering::Less {
+            new_iter.next();
+        }
+        if let Some(change) = change {
+            patch.insert_change(name, key, change);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use storage::{Database, Entry, ListIndex, MapIndex, MemoryDB, VersionedDatabase};
+
+    fn state(snapshot: &Snapshot) -> Vec<(String, Vec<(Vec<u8>, Vec<u8>)>)> {
+        snapshot
+            .column_families()
+            .into_iter()
+            .map(|name| {
+                let mut entries = Vec::new();
+                let mut iter = snapshot.iter(&name, &[]);
+                while let Some((k, v)) = iter.next() {
+                    entries.push((k.to_vec(), v.to_vec()));
+                }
+                (name, entries)
+            })
+            .filter(|&(_, ref entries)| !entries.is_empty())
+            .collect()
+    }
+
+    #[test]
+    fn diff_applies_cleanly() {
+        let db = VersionedDatabase::new(MemoryDB::new());
+        let mut fork = db.fork();
+        {
+            let mut map = MapIndex::new("map", &mut fork);
+            for i in 0..10_u8 {
+                map.put(&i, u64::from(i));
+            }
+            ListIndex::new("list", &mut fork).extend(vec![1_u8, 2, 3]);
+        }
+        db.merge(fork.into_patch()).unwrap();
+
+        let mut fork = db.fork();
+        {
+            let mut map = MapIndex::new("map", &mut fork);
+            map.remove(&0_u8);
+            map.put(&5, 50_u64);
+            map.put(&20, 20_u64);
+            ListIndex::<_, u8>::new("list", &mut fork).clear();
+            Entry::new("entry", &mut fork).set(1_u8);
+        }
+        db.merge(fork.into_patch()).unwrap();
+
+        let other_db = MemoryDB::new();
+        let patch = diff(&*other_db.snapshot(), &*db.snapshot_at(1).unwrap());
+        other_db.merge(patch).unwrap();
+        assert_eq!(
+            state(&*other_db.snapshot()),
+            state(&*db.snapshot_at(1).unwrap())
+        );
+
+        let patch = diff(&*db.snapshot_at(1).unwrap(), &*db.snapshot_at(2).unwrap());
+        other_db.merge(patch).unwrap();
+        assert_eq!(state(&*other_db.snapshot()), state(&*db.snapshot()));
+        let map: MapIndex<_, u8, u64> = MapIndex::new("map", other_db.snapshot());
+        assert_eq!(map.get(&0), None);
+        assert_eq!(map.get(&5), Some(50));
+        assert_eq!(map.get(&20), Some(20));
+    }
+
+    #[test]
+    fn diff_includes_unregistered_column_families() {
+        let db = MemoryDB::new();
+        let old = db.snapshot();
+        let mut fork = db.fork();
+        fork.put("raw", vec![1], vec![2]);
+        MapIndex::new("map", &mut fork).put(&1_u8, 1_u8);
+        db.merge(fork.into_patch()).unwrap();
+
+        let other_db = MemoryDB::new();
+        other_db.merge(diff(&*old, &*db.snapshot())).unwrap();
+        assert_eq!(other_db.snapshot().get("raw", &[1]), Some(vec![2]));
+        assert_eq!(state(&*other_db.snapshot()), state(&*db.snapshot()));
+
+        let mut fork = db.fork();
+        fork.remove("raw", vec![1]);
+        db.merge(fork.into_patch()).unwrap();
+        other_db.merge(diff(&*other_db.snapshot(), &*db.snapshot())).unwrap();
+        assert_eq!(other_db.snapshot().get("raw", &[1]), None);
+    }
+
+    #[test]
+    fn diff_of_equal_snapshots_is_empty() {
+        let db = MemoryDB::new();
+        let mut fork = db.fork();
+        MapIndex::new("map", &mut fork).put(&1_u8, 1_u8);
+        db.merge(fork.into_patch()).unwrap();
+        assert!(diff(&*db.snapshot(), &*db.snapshot()).is_empty());
+    }
+}
@@ -0,0 +1,512 @@
+// Copyright 2018 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Export and import of the whole database contents in a portable line-oriented format.
+//!
+//! A dump is a sequence of JSON objects, one per line:
+//!
+//! - a header with the format version;
+//! - index records with the name, type and family flag of every index in the
+//!   indexes metadata;
+//! - entry records with hex-encoded keys and values, grouped by column families,
+//!   with keys in ascending order within each family;
+//! - a trailing checksum record with the number of preceding records and the hash
+//!   of all preceding lines.
+//!
+//! Only the column families registered in the indexes metadata are exported. This covers all
+//! the data written through indexes; raw changes made with [`Fork::put`] to other column
+//! families are not included into a dump.
+//!
+//! [`Fork::put`]: ../struct.Fork.html#method.put
+
+use byteorder::{BigEndian, ByteOrder};
+use serde_json;
+use hex;
+
+use std::collections::BTreeSet;
+use std::io::{BufRead, BufReader, Read, Write};
+
+use crypto::HashStream;
+use super::{Database, Error, Result, Snapshot};
+use super::indexes_metadata::{self, IndexType};
+
+/// Version of the dump format produced by [`export`](fn.export.html).
+pub const DUMP_FORMAT_VERSION: u32 = 1;
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Record {
+    Header {
+        version: u32,
+    },
+    Index {
+        name: String,
+        index_type: IndexType,
+        is_family: bool,
+    },
+    Entry {
+        cf: String,
+        key: String,
+        value: String,
+    },
+    Checksum {
+        records: u64,
+        hash: String,
+    },
+}
+
+struct DumpWriter<W> {
+    writer: W,
+    hasher: HashStream,
+    records: u64,
+}
+
+impl<W: Write> DumpWriter<W> {
+    fn write(&mut self, record: &Record) -> Result<()> {
+        let line = serde_json::to_string(record).map_err(|e| Error::new(e.to_string()))?;
+        writeln!(self.writer, "{}", line).map_err(|e| Error::new(e.to_string()))?;
+        let hasher = ::std::mem::replace(&mut self.hasher, HashStream::new());
+        self.hasher = hasher.update(line.as_bytes()).update(b"\n");
+        self.records += 1;
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<()> {
+        let checksum = Record::Checksum {
+            records: self.records,
+            hash: self.hasher.hash().to_hex(),
+        };
+        let line = serde_json::to_string(&checksum).map_err(|e| Error::new(e.to_string()))?;
+        writeln!(self.writer, "{}", line).map_err(|e| Error::new(e.to_string()))?;
+        self.writer.flush().map_err(|e| Error::new(e.to_string()))
+    }
+}
+
+/// Writes the contents of the snapshot to `writer` in the dump format.
+///
+/// # Examples
+///
+/// ```
+/// use exonum::storage::{Database, MapIndex, MemoryDB};
+/// use exonum::storage::dump;
+///
+/// let db = MemoryDB::new();
+/// let mut fork = db.fork();
+/// MapIndex::new("map", &mut fork).put(&1_u8, 2_u8);
+/// db.merge(fork.into_patch()).unwrap();
+///
+/// let mut buffer = Vec::new();
+/// dump::export(&*db.snapshot(), &mut buffer).unwrap();
+///
+/// let other_db = MemoryDB::new();
+/// dump::import(&other_db, buffer.as_slice()).unwrap();
+/// let snapshot = other_db.snapshot();
+/// let index: MapIndex<_, u8, u8> = MapIndex::new("map", &snapshot);
+/// assert_eq!(index.get(&1), Some(2));
+/// ```
+pub fn export<W: Write>(snapshot: &Snapshot, writer: W) -> Result<()> {
+    let mut writer = DumpWriter {
+        writer,
+        hasher: HashStream::new(),
+        records: 0,
+    };
+    writer.write(&Record::Header {
+        version: DUMP_FORMAT_VERSION,
+    })?;
+
+    let indexes = indexes_metadata::list_indexes(snapshot);
+    for info in &indexes {
+        writer.write(&Record::Index {
+            name: info.name.clone(),
+            index_type: info.index_type,
+            is_family: info.is_family,
+        })?;
+    }
+
+    let names = indexes
+        .into_iter()
+        .map(|info| info.name)
+        .collect::<BTreeSet<_>>();
+    for name in names {
+        let mut iter = snapshot.iter(&name, &[]);
+        while let Some((key, value)) = iter.next() {
+            writer.write(&Record::Entry {
+                cf: name.clone(),
+                key: hex::encode(key),
+                value: hex::encode(value),
+            })?;
+        }
+    }
+    writer.finish()
+}
+
+/// Maximum number of entries merged into the database at once by [`import`](fn.import.html).
+pub const IMPORT_BATCH_SIZE: usize = 10_000;
+
+/// Column family that keeps the entries of a dump until its checksum is verified.
+const STAGING_CF: &str = "__DUMP_IMPORT_STAGING__";
+
+/// Reads a dump produced by [`export`](fn.export.html) and merges its contents
+/// into the database.
+///
+/// To keep memory usage bounded, the entries are written to the database in batches of
+/// [`IMPORT_BATCH_SIZE`](constant.IMPORT_BATCH_SIZE.html) entries. Until the checksum
+/// of the dump is verified, the batches are kept in an internal staging column family,
+/// so a malformed or truncated dump leaves the indexes untouched. A dump that fits into
+/// a single batch is merged atomically. Larger dumps are moved from the staging area
+/// in batches, so if the database fails in the middle of this step, a part of the dump
+/// may be left in the database.
+///
+/// Imports into the same database must not run concurrently.
+///
+/// # Errors
+///
+/// Returns an error if the dump is malformed, its checksum doesn't match, it is truncated,
+/// it contains entries of a column family without an index record,
+/// or an index in the dump has a different type in the database.
+pub fn import<R: Read>(db: &Database, reader: R) -> Result<()> {
+    import_in_batches(db, reader, IMPORT_BATCH_SIZE)
+}
+
+/// Part of a verified dump that is not written to the staging column family.
+struct VerifiedDump {
+    indexes: Vec<(String, IndexType, bool)>,
+    entries: Vec<(String, Vec<u8>, Vec<u8>)>,
+}
+
+fn import_in_batches<R: Read>(db: &Database, reader: R, batch_size: usize) -> Result<()> {
+    // Leftovers of an interrupted import must not be committed along with this dump.
+    clear_staging(db, batch_size)?;
+    let result = read_dump(db, reader, batch_size)
+        .and_then(|dump| commit_dump(db, dump, batch_size));
+    if result.is_err() {
+        // If the cleanup fails, the staged entries are removed by the next import.
+        let _ = clear_staging(db, batch_size);
+    }
+    result
+}
+
+fn read_dump<R: Read>(db: &Database, reader: R, batch_size: usize) -> Result<VerifiedDump> {
+    let existing = indexes_metadata::list_indexes(&*db.snapshot());
+
+    let mut indexes = Vec::new();
+    let mut declared = BTreeSet::new();
+    let mut entries = Vec::new();
+    let mut hasher = HashStream::new();
+    let mut records = 0_u64;
+    let mut checksum = None;
+    for line in BufReader::new(reader).lines() {
+        let line = line.map_err(|e| Error::new(e.to_string()))?;
+        if checksum.is_some() {
+            return Err(Error::new("Unexpected record after the checksum"));
+        }
+        let record: Record =
+            serde_json::from_str(&line).map_err(|e| Error::new(format!("Invalid record: {}", e)))?;
+        match record {
+            Record::Header { version } => {
+                if records != 0 {
+                    return Err(Error::new("Unexpected header record"));
+                }
+                if version != DUMP_FORMAT_VERSION {
+                    return Err(Error::new(format!(
+                        "Unsupported dump format version {}",
+                        version
+                    )));
+                }
+            }
+            _ if records == 0 => return Err(Error::new("Missing header record")),
+            Record::Index {
+                name,
+                index_type,
+                is_family,
+            } => {
+                if name == STAGING_CF {
+                    return Err(Error::new(format!("Index name '{}' is reserved", name)));
+                }
+                if let Some(info) = existing.iter().find(|info| info.name == name) {
+                    if info.index_type != index_type || info.is_family != is_family {
+                        return Err(Error::new(format!(
+                            "Index '{}' already exists with type {:?}",
+                            name, info.index_type
+                        )));
+                    }
+                }
+                declared.insert(name.clone());
+                indexes.push((name, index_type, is_family));
+            }
+            Record::Entry { cf, key, value } => {
+                if !declared.contains(&cf) {
+                    return Err(Error::new(format!(
+                        "Entry of column family '{}' without an index record",
+                        cf
+                    )));
+                }
+                let key = hex::decode(&key).map_err(|e| Error::new(e.to_string()))?;
+                let value = hex::decode(&value).map_err(|e| Error::new(e.to_string()))?;
+                if entries.len() == batch_size {
+                    stage(db, &mut entries)?;
+                }
+                entries.push((cf, key, value));
+            }
+            Record::Checksum { records: count, hash } => {
+                checksum = Some((count, hash));
+                continue;
+            }
+        }
+        hasher = hasher.update(line.as_bytes()).update(b"\n");
+        records += 1;
+    }
+
+    let hash = hasher.hash().to_hex();
+    match checksum {
+        Some((count, ref expected)) if count == records && *expected == hash => {
+            Ok(VerifiedDump { indexes, entries })
+        }
+        Some(..) => Err(Error::new("Dump checksum mismatch")),
+        None => Err(Error::new("Missing checksum record, the dump is probably truncated")),
+    }
+}
+
+/// Writes the entries to the staging column family. The key of a staged entry is
+/// the length of the column family name as `u32`, the name and the key of the entry.
+fn stage(db: &Database, entries: &mut Vec<(String, Vec<u8>, Vec<u8>)>) -> Result<()> {
+    let mut fork = db.fork();
+    for (cf, key, value) in entries.drain(..) {
+        let mut staging_key = vec![0; 4];
+        BigEndian::write_u32(&mut staging_key, cf.len() as u32);
+        staging_key.extend_from_slice(cf.as_bytes());
+        staging_key.extend_from_slice(&key);
+        fork.put(STAGING_CF, staging_key, value);
+    }
+    db.merge(fork.into_raw_patch())
+}
+
+/// Moves the staged entries to their column families and merges the rest of the dump
+/// together with the index records.
+fn commit_dump(db: &Database, dump: VerifiedDump, batch_size: usize) -> Result<()> {
+    let snapshot = db.snapshot();
+    let mut fork = db.fork();
+    let mut pending = 0;
+    {
+        let mut iter = snapshot.iter(STAGING_CF, &[]);
+        while let Some((staging_key, value)) = iter.next() {
+            if pending == batch_size {
+                db.merge(fork.into_raw_patch())?;
+                fork = db.fork();
+                pending = 0;
+            }
+            let len = BigEndian::read_u32(&staging_key[..4]) as usize;
+            let cf = ::std::str::from_utf8(&staging_key[4..4 + len])
+                .map_err(|e| Error::new(e.to_string()))?;
+            fork.put(cf, staging_key[4 + len..].to_vec(), value.to_vec());
+            fork.remove(STAGING_CF, staging_key.to_vec());
+            pending += 1;
+        }
+    }
+    for (cf, key, value) in dump.entries {
+        fork.put(&cf, key, value);
+    }
+    for (name, index_type, is_family) in dump.indexes {
+        indexes_metadata::set_index_type(&name, index_type, is_family, &mut fork);
+    }
+    db.merge(fork.into_raw_patch())
+}
+
+fn clear_staging(db: &Database, batch_size: usize) -> Result<()> {
+    let snapshot = db.snapshot();
+    let mut fork = db.fork();
+    let mut pending = 0;
+    let mut iter = snapshot.iter_keys(STAGING_CF, &[]);
+    while let Some((key, _)) = iter.next() {
+        fork.remove(STAGING_CF, key.to_vec());
+        pending += 1;
+        if pending == batch_size {
+            db.merge(fork.into_raw_patch())?;
+            fork = db.fork();
+            pending = 0;
+        }
+    }
+    if pending > 0 {
+        db.merge(fork.into_raw_patch())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use storage::{Entry, ListIndex, MapIndex, MemoryDB};
+
+    fn create_dump() -> Vec<u8> {
+        let db = MemoryDB::new();
+        let mut fork = db.fork();
+        {
+            let mut index = MapIndex::new("map", &mut fork);
+            index.put(&"a".to_owned(), 1_u64);
+            index.put(&"b".to_owned(), 2_u64);
+        }
+        {
+            let mut index = ListIndex::new_in_family("lists", &3_u8, &mut fork);
+            index.extend(vec![1_u8, 2, 3]);
+        }
+        Entry::new("entry", &mut fork).set("value".to_owned());
+        db.merge(fork.into_patch()).unwrap();
+
+        let mut buffer = Vec::new();
+        export(&*db.snapshot(), &mut buffer).unwrap();
+        buffer
+    }
+
+    #[test]
+    fn export_import_roundtrip() {
+        let dump = create_dump();
+        let db = MemoryDB::new();
+        import(&db, dump.as_slice()).unwrap();
+
+        let snapshot = db.snapshot();
+        let map: MapIndex<_, String, u64> = MapIndex::new("map", &snapshot);
+        assert_eq!(
+            map.iter().collect::<Vec<_>>(),
+            vec![("a".to_owned(), 1), ("b".to_owned(), 2)]
+        );
+        let list: ListIndex<_, u8> = ListIndex::new_in_family("lists", &3_u8, &snapshot);
+        assert_eq!(list.iter().collect::<Vec<_>>(), vec![1, 2, 3]);
+        let entry: Entry<_, String> = Entry::new("entry", &snapshot);
+        assert_eq!(entry.get(), Some("value".to_owned()));
+
+        // Exporting the imported data yields the same dump.
+        let mut buffer = Vec::new();
+        export(&*snapshot, &mut buffer).unwrap();
+        assert_eq!(buffer, dump);
+    }
+
+    #[test]
+    fn dump_layout() {
+        let dump = String::from_utf8(create_dump()).unwrap();
+        let lines = dump.lines().collect::<Vec<_>>();
+        assert!(lines[0].contains("\"type\":\"header\""));
+        assert!(lines[1..4].iter().all(|line| line.contains("\"type\":\"index\"")));
+        assert!(lines[4..lines.len() - 1]
+            .iter()
+            .all(|line| line.contains("\"type\":\"entry\"")));
+        assert!(lines[lines.len() - 1].contains("\"type\":\"checksum\""));
+    }
+
+    #[test]
+    fn corrupted_dump() {
+        let dump = String::from_utf8(create_dump()).unwrap();
+        let corrupted = dump.replacen("\"cf\":\"map\"", "\"cf\":\"maq\"", 1);
+        assert_ne!(corrupted, dump);
+
+        let db = MemoryDB::new();
+        assert!(import(&db, corrupted.as_bytes()).is_err());
+        assert!(db.snapshot().get("maq", &[]).is_none());
+        assert!(indexes_metadata::list_indexes(&*db.snapshot()).is_empty());
+    }
+
+    #[test]
+    fn truncated_dump() {
+        let dump = String::from_utf8(create_dump()).unwrap();
+        let truncated = dump.lines()
+            .take(dump.lines().count() - 1)
+            .collect::<Vec<_>>()
+            .join("\n");
+        let db = MemoryDB::new();
+        assert!(import(&db, truncated.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn conflicting_index_type() {
+        let dump = create_dump();
+        let db = MemoryDB::new();
+        let mut fork = db.fork();
+        ListIndex::new("map", &mut fork).push(1_u8);
+        db.merge(fork.into_patch()).unwrap();
+
+        assert!(import(&db, dump.as_slice()).is_err());
+    }
+
+    #[test]
+    fn undeclared_column_family() {
+        let dump = String::from_utf8(create_dump()).unwrap();
+        let mut lines = dump.lines().map(str::to_owned).collect::<Vec<_>>();
+        // Drop the index record of the map and recompute the checksum, so that
+        // only the missing declaration makes the dump invalid.
+        lines.retain(|line| !line.contains("\"type\":\"index\",\"name\":\"map\""));
+        lines.pop();
+        let mut hasher = HashStream::new();
+        for line in &lines {
+            hasher = hasher.update(line.as_bytes()).update(b"\n");
+        }
+        let checksum = Record::Checksum {
+            records: lines.len() as u64,
+            hash: hasher.hash().to_hex(),
+        };
+        lines.push(serde_json::to_string(&checksum).unwrap());
+        let dump = lines.join("\n");
+
+        let db = MemoryDB::new();
+        assert!(import(&db, dump.as_bytes()).is_err());
+        assert!(db.snapshot().get("map", &[]).is_none());
+        assert!(indexes_metadata::list_indexes(&*db.snapshot()).is_empty());
+    }
+
+    #[test]
+    fn truncated_dump_in_small_batches() {
+        let dump = String::from_utf8(create_dump()).unwrap();
+        let truncated = dump.lines()
+            .take(dump.lines().count() - 2)
+            .collect::<Vec<_>>()
+            .join("\n");
+        let db = MemoryDB::new();
+        assert!(import_in_batches(&db, truncated.as_bytes(), 2).is_err());
+
+        let snapshot = db.snapshot();
+        let map: MapIndex<_, String, u64> = MapIndex::new("map", &snapshot);
+        assert_eq!(map.iter().count(), 0);
+        assert!(snapshot.iter(STAGING_CF, &[]).next().is_none());
+        assert!(indexes_metadata::list_indexes(&*snapshot).is_empty());
+    }
+
+    #[test]
+    fn stale_staged_entries_are_discarded() {
+        let db = MemoryDB::new();
+        let mut fork = db.fork();
+        let mut staging_key = vec![0, 0, 0, 3];
+        staging_key.extend_from_slice(b"map");
+        staging_key.extend_from_slice(b"c");
+        fork.put(STAGING_CF, staging_key, vec![0; 8]);
+        db.merge(fork.into_patch()).unwrap();
+
+        let dump = create_dump();
+        import_in_batches(&db, dump.as_slice(), 2).unwrap();
+
+        let snapshot = db.snapshot();
+        assert!(snapshot.iter(STAGING_CF, &[]).next().is_none());
+        let mut buffer = Vec::new();
+        export(&*snapshot, &mut buffer).unwrap();
+        assert_eq!(buffer, dump);
+    }
+
+    #[test]
+    fn import_in_small_batches() {
+        let dump = create_dump();
+        let db = MemoryDB::new();
+        import_in_batches(&db, dump.as_slice(), 2).unwrap();
+
+        let mut buffer = Vec::new();
+        export(&*db.snapshot(), &mut buffer).unwrap();
+        assert_eq!(buffer, dump);
+    }
+}
@@ -17,7 +17,7 @@
 use std::marker::PhantomData;
 
 use crypto::Hash;
-use super::{BaseIndex, Fork, Snapshot, StorageValue};
+use super::{BaseIndex, IndexAccess, IndexAccessMut, StorageValue};
 use super::indexes_metadata::IndexType;
 
 /// An index that may only contain one element.
@@ -33,7 +33,7 @@ pub struct Entry<T, V> {
 
 impl<T, V> Entry<T, V>
 where
-    T: AsRef<Snapshot>,
+    T: IndexAccess,
     V: StorageValue,
 {
     /// Creates a new index representation based on the name and storage view.
@@ -62,6 +62,11 @@ where
         }
     }
 
+    /// Returns the storage view of the index, consuming the index.
+    pub fn into_view(self) -> T {
+        self.base.into_view()
+    }
+
     /// Returns a value of the entry or `None` if does not exist.
     ///
     /// # Examples
@@ -128,8 +133,9 @@ where
     }
 }
 
-impl<'a, V> Entry<&'a mut Fork, V>
+impl<T, V> Entry<T, V>
 where
+    T: IndexAccessMut,
     V: StorageValue,
 {
     /// Changes a value of the entry.
@@ -172,4 +178,74 @@ where
     pub fn remove(&mut self) {
         self.base.remove(&())
     }
+
+    /// Changes a value of the entry to `new` if the current value is equal to `expected`.
+    /// Returns `true` if the value has been changed.
+    ///
+    /// The value is read with [`Fork::get_for_update`], so merging the fork fails if the value
+    /// of the entry is changed concurrently by another fork.
+    ///
+    /// [`Fork::get_for_update`]: struct.Fork.html#method.get_for_update
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, Entry};
+    ///
+    /// let db = MemoryDB::new();
+    /// let name = "name";
+    /// let mut fork = db.fork();
+    /// let mut index = Entry::new(name, &mut fork);
+    ///
+    /// assert!(index.compare_and_set(None, 10));
+    /// assert!(!index.compare_and_set(Some(&5), 20));
+    /// assert!(index.compare_and_set(Some(&10), 20));
+    /// assert_eq!(Some(20), index.get());
+    /// ```
+    pub fn compare_and_set(&mut self, expected: Option<&V>, new: V) -> bool
+    where
+        V: PartialEq,
+    {
+        if self.base.get_for_update::<(), V>(&()).as_ref() != expected {
+            return false;
+        }
+        self.base.put(&(), new);
+        true
+    }
+
+    /// Changes a value of the entry to the result of `f` applied to the current value.
+    /// If `f` returns `None`, the value is removed.
+    ///
+    /// The value is read with [`Fork::get_for_update`], so merging the fork fails if the value
+    /// of the entry is changed concurrently by another fork.
+    ///
+    /// [`Fork::get_for_update`]: struct.Fork.html#method.get_for_update
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, Entry};
+    ///
+    /// let db = MemoryDB::new();
+    /// let name = "name";
+    /// let mut fork = db.fork();
+    /// let mut index = Entry::new(name, &mut fork);
+    ///
+    /// index.update(|old: Option<u64>| Some(old.unwrap_or(0) + 1));
+    /// index.update(|old| Some(old.unwrap_or(0) + 1));
+    /// assert_eq!(Some(2), index.get());
+    ///
+    /// index.update(|_| None);
+    /// assert!(!index.exists());
+    /// ```
+    pub fn update<F>(&mut self, f: F)
+    where
+        F: FnOnce(Option<V>) -> Option<V>,
+    {
+        let old = self.base.get_for_update(&());
+        match f(old) {
+            Some(new) => self.base.put(&(), new),
+            None => self.base.remove(&()),
+        }
+    }
 }
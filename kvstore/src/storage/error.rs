@@ -19,6 +19,7 @@
 #[fail(display = "{}", message)]
 pub struct Error {
     message: String,
+    conflict: bool,
 }
 
 impl Error {
@@ -34,6 +35,22 @@ impl Error {
     pub fn new<T: Into<String>>(message: T) -> Error {
         Error {
             message: message.into(),
+            conflict: false,
         }
     }
+
+    /// Creates a new error signalling that a patch could not be merged, because the values
+    /// read by its fork have been changed in the database since the fork was created.
+    pub(crate) fn conflict<T: Into<String>>(message: T) -> Error {
+        Error {
+            message: message.into(),
+            conflict: true,
+        }
+    }
+
+    /// Returns `true` if the error was caused by a conflicting change of the values read
+    /// by the merged fork. The operations performed on the fork can be retried on a new fork.
+    pub fn is_conflict(&self) -> bool {
+        self.conflict
+    }
 }
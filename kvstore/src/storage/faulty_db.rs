@@ -0,0 +1,481 @@
+// Copyright 2018 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! An implementation of a `Database` wrapper that injects storage faults.
+
+use rand::{Rng, SeedableRng, XorShiftRng};
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use super::{Database, Error, Iter, Patch, Result, Snapshot};
+use super::db::Change;
+
+/// A script of faults injected by a `FaultyDatabase`.
+///
+/// Merges are numbered starting from 1; both `merge` and `merge_sync` calls are counted.
+/// All random decisions (which bits of a value are corrupted, how much of a patch survives
+/// a crash) are derived from `seed`, so a failing scenario can be reproduced by running it
+/// with the same plan.
+#[derive(Debug, Clone, Default)]
+pub struct FaultPlan {
+    /// Seed for the random decisions.
+    pub seed: u64,
+    /// Numbers of merges that fail with an error without applying any changes.
+    pub fail_merges: Vec<usize>,
+    /// Number of the merge that simulates a crash. Only a random prefix of the patch
+    /// of this merge is persisted, and all subsequent merges fail.
+    pub crash_at_merge: Option<usize>,
+    /// Whether changes applied with `merge` are kept in memory until the next durable
+    /// `merge_sync` and lost on a crash.
+    ///
+    /// Defaults to `false`, which means changes are passed to the underlying database
+    /// immediately.
+    pub buffer_unsynced: bool,
+    /// Whether `merge_sync` loses its durability guarantee and behaves like `merge`.
+    pub drop_sync_durability: bool,
+    /// Probability of corrupting a value returned by `Snapshot::get`, from `0.0` to `1.0`.
+    pub corrupt_reads: f64,
+}
+
+/// Database wrapper that injects faults according to a [`FaultPlan`].
+///
+/// `FaultyDatabase` is intended for testing how the code built on top of the storage
+/// handles failed merges, lost writes, corrupted reads and crashes in the middle of a merge.
+/// For crash tests, wrap a database persisted to disk (e.g., [`RocksDB`]), and reopen
+/// it after the crash with [`into_inner`].
+///
+/// [`FaultPlan`]: struct.FaultPlan.html
+/// [`RocksDB`]: ../struct.RocksDB.html
+/// [`into_inner`]: #method.into_inner
+///
+/// # Examples
+///
+/// ```
+/// use exonum::storage::{Database, MemoryDB};
+/// use exonum::storage::faulty_db::{FaultPlan, FaultyDatabase};
+///
+/// let plan = FaultPlan {
+///     fail_merges: vec![2],
+///     ..FaultPlan::default()
+/// };
+/// let db = FaultyDatabase::new(MemoryDB::new(), plan);
+/// assert!(db.merge(db.fork().into_patch()).is_ok());
+/// assert!(db.merge(db.fork().into_patch()).is_err());
+/// assert!(db.merge(db.fork().into_patch()).is_ok());
+/// ```
+pub struct FaultyDatabase<D> {
+    db: D,
+    state: Arc<Mutex<FaultState>>,
+}
+
+/// A snapshot of a `FaultyDatabase`.
+pub struct FaultySnapshot {
+    snapshot: Box<Snapshot>,
+    state: Arc<Mutex<FaultState>>,
+}
+
+struct FaultState {
+    plan: FaultPlan,
+    rng: XorShiftRng,
+    merges: usize,
+    pending: Vec<Patch>,
+    crashed: bool,
+}
+
+impl<D: Database> FaultyDatabase<D> {
+    /// Wraps the database with the given fault plan.
+    pub fn new(db: D, plan: FaultPlan) -> Self {
+        let rng = XorShiftRng::from_seed([
+            plan.seed as u32 | 1,
+            (plan.seed >> 32) as u32,
+            0x9E37_79B9,
+            0x7F4A_7C15,
+        ]);
+        FaultyDatabase {
+            db,
+            state: Arc::new(Mutex::new(FaultState {
+                plan,
+                rng,
+                merges: 0,
+                pending: Vec::new(),
+                crashed: false,
+            })),
+        }
+    }
+
+    /// Returns the fault plan of the database.
+    pub fn plan(&self) -> FaultPlan {
+        self.state.lock().unwrap().plan.clone()
+    }
+
+    /// Returns the number of merges performed so far, including the failed ones.
+    pub fn merges(&self) -> usize {
+        self.state.lock().unwrap().merges
+    }
+
+    /// Returns `true` if the database has crashed.
+    pub fn is_crashed(&self) -> bool {
+        self.state.lock().unwrap().crashed
+    }
+
+    /// Simulates a crash: the changes that are not durable yet are lost, and all subsequent
+    /// merges fail.
+    pub fn crash(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.pending.clear();
+        state.crashed = true;
+    }
+
+    /// Returns the underlying database. The changes that are not durable yet are lost.
+    pub fn into_inner(self) -> D {
+        self.db
+    }
+
+    fn do_merge(&self, mut patch: Patch, durable: bool) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if state.crashed {
+            return Err(Error::new("Database has crashed"));
+        }
+        // Reads are checked against the buffered changes as well, so the patch is merged
+        // unconditionally afterwards.
+        if patch.has_reads() {
+            let mut view = self.db.fork();
+            for pending in &state.pending {
+                view.merge(pending.clone());
+            }
+            patch.check_reads(&view)?;
+            patch.clear_reads();
+        }
+        state.merges += 1;
+        let merge_number = state.merges;
+
+        if state.plan.fail_merges.contains(&merge_number) {
+            return Err(Error::new(format!("Injected failure of merge #{}", merge_number)));
+        }
+        if state.plan.crash_at_merge == Some(merge_number) {
+            self.persist_prefix(&mut state, patch)?;
+            state.pending.clear();
+            state.crashed = true;
+            return Err(Error::new(format!("Injected crash at merge #{}", merge_number)));
+        }
+
+        let durable = durable && !state.plan.drop_sync_durability;
+        if state.plan.buffer_unsynced && !durable {
+            state.pending.push(patch);
+            return Ok(());
+        }
+
+        if state.pending.is_empty() {
+            return if durable {
+                self.db.merge_sync(patch)
+            } else {
+                self.db.merge(patch)
+            };
+        }
+
+        let mut fork = self.db.fork();
+        for pending in state.pending.drain(..) {
+            fork.merge(pending);
+        }
+        fork.merge(patch);
+        self.db.merge_sync(fork.into_raw_patch())
+    }
+
+    /// Persists a random prefix of the patch changes, ordered by column family names and keys.
+    fn persist_prefix(&self, state: &mut FaultState, patch: Patch) -> Result<()> {
+        let mut changes = patch
+            .into_iter()
+            .flat_map(|(name, changes)| {
+                changes
+                    .into_iter()
+                    .map(move |(key, change)| (name.clone(), key, change))
+            })
+            .collect::<Vec<_>>();
+        changes.sort_by(|a, b| (&a.0, &a.1).cmp(&(&b.0, &b.1)));
+
+        let len = state.rng.gen_range(0, changes.len() + 1);
+        let mut fork = self.db.fork();
+        for (name, key, change) in changes.into_iter().take(len) {
+            match change {
+                Change::Put(value) => fork.put(&name, key, value),
+                Change::Delete => fork.remove(&name, key),
+            }
+        }
+        self.db.merge_sync(fork.into_raw_patch())
+    }
+}
+
+impl<D: Database> Database for FaultyDatabase<D> {
+    fn snapshot(&self) -> Box<Snapshot> {
+        let state = self.state.lock().unwrap();
+        let snapshot = if state.pending.is_empty() {
+            self.db.snapshot()
+        } else {
+            let mut fork = self.db.fork();
+            for patch in &state.pending {
+                fork.merge(patch.clone());
+            }
+            Box::new(fork)
+        };
+        Box::new(FaultySnapshot {
+            snapshot,
+            state: Arc::clone(&self.state),
+        })
+    }
+
+    fn merge(&self, patch: Patch) -> Result<()> {
+        self.do_merge(patch, false)
+    }
+
+    fn merge_sync(&self, patch: Patch) -> Result<()> {
+        self.do_merge(patch, true)
+    }
+}
+
+impl Snapshot for FaultySnapshot {
+    fn get(&self, name: &str, key: &[u8]) -> Option<Vec<u8>> {
+        let mut value = self.snapshot.get(name, key);
+        if let Some(ref mut value) = value {
+            let mut state = self.state.lock().unwrap();
+            let probability = state.plan.corrupt_reads;
+            if !value.is_empty() && probability > 0.0 && state.rng.gen::<f64>() < probability {
+                let byte = state.rng.gen_range(0, value.len());
+                let bit = state.rng.gen_range(0, 8);
+                value[byte] ^= 1 << bit;
+            }
+        }
+        value
+    }
+
+    fn contains(&self, name: &str, key: &[u8]) -> bool {
+        self.snapshot.contains(name, key)
+    }
+
+    fn iter<'a>(&'a self, name: &str, from: &[u8]) -> Iter<'a> {
+        self.snapshot.iter(name, from)
+    }
+
+    fn iter_keys<'a>(&'a self, name: &str, from: &[u8]) -> Iter<'a> {
+        self.snapshot.iter_keys(name, from)
+    }
+
+    fn column_families(&self) -> Vec<String> {
+        self.snapshot.column_families()
+    }
+}
+
+impl<D> fmt::Debug for FaultyDatabase<D> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "FaultyDatabase({:?})", self.state.lock().unwrap().plan)
+    }
+}
+
+impl fmt::Debug for FaultySnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "FaultySnapshot(..)")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+
+    use storage::{DbOptions, MemoryDB, RocksDB};
+    use super::*;
+
+    const IDX_NAME: &str = "idx_name";
+
+    fn patch_with_keys<D: Database>(db: &D, keys: &[u8]) -> Patch {
+        let mut fork = db.fork();
+        for &key in keys {
+            fork.put(IDX_NAME, vec![key], vec![key]);
+        }
+        fork.into_patch()
+    }
+
+    #[test]
+    fn fail_nth_merge() {
+        let plan = FaultPlan {
+            fail_merges: vec![2],
+            ..FaultPlan::default()
+        };
+        let db = FaultyDatabase::new(MemoryDB::new(), plan);
+        db.merge(patch_with_keys(&db, &[1])).unwrap();
+        assert!(db.merge_sync(patch_with_keys(&db, &[2])).is_err());
+        db.merge(patch_with_keys(&db, &[3])).unwrap();
+
+        let snapshot = db.snapshot();
+        assert!(snapshot.contains(IDX_NAME, &[1]));
+        assert!(!snapshot.contains(IDX_NAME, &[2]));
+        assert!(snapshot.contains(IDX_NAME, &[3]));
+        assert_eq!(db.merges(), 3);
+    }
+
+    #[test]
+    fn crash_persists_prefix() {
+        let keys = (0..32).collect::<Vec<u8>>();
+        for seed in 0..16 {
+            let plan = FaultPlan {
+                seed,
+                crash_at_merge: Some(1),
+                ..FaultPlan::default()
+            };
+            let db = FaultyDatabase::new(MemoryDB::new(), plan);
+            assert!(db.merge(patch_with_keys(&db, &keys)).is_err());
+            assert!(db.is_crashed());
+            assert!(db.merge(patch_with_keys(&db, &[1])).is_err());
+
+            let inner = db.into_inner();
+            let snapshot = inner.snapshot();
+            let persisted = keys.iter()
+                .take_while(|&&key| snapshot.contains(IDX_NAME, &[key]))
+                .count();
+            assert!(keys[persisted..]
+                .iter()
+                .all(|&key| !snapshot.contains(IDX_NAME, &[key])));
+        }
+    }
+
+    #[test]
+    fn crash_is_reproducible() {
+        let keys = (0..64).collect::<Vec<u8>>();
+        let persisted = |seed| {
+            let plan = FaultPlan {
+                seed,
+                crash_at_merge: Some(1),
+                ..FaultPlan::default()
+            };
+            let db = FaultyDatabase::new(MemoryDB::new(), plan);
+            let _ = db.merge(patch_with_keys(&db, &keys));
+            let snapshot = db.into_inner().snapshot();
+            keys.iter()
+                .filter(|&&key| snapshot.contains(IDX_NAME, &[key]))
+                .count()
+        };
+        assert_eq!(persisted(7), persisted(7));
+        assert_eq!(persisted(1234), persisted(1234));
+    }
+
+    #[test]
+    fn crash_with_rocksdb_backend() {
+        let dir = TempDir::new("exonum_faulty_db").unwrap();
+        let keys = (0..32).collect::<Vec<u8>>();
+        {
+            let plan = FaultPlan {
+                seed: 42,
+                crash_at_merge: Some(2),
+                ..FaultPlan::default()
+            };
+            let rocksdb = RocksDB::open(dir.path(), &DbOptions::default()).unwrap();
+            let db = FaultyDatabase::new(rocksdb, plan);
+            db.merge_sync(patch_with_keys(&db, &[100])).unwrap();
+            assert!(db.merge_sync(patch_with_keys(&db, &keys)).is_err());
+        }
+
+        let db = RocksDB::open(dir.path(), &DbOptions::default()).unwrap();
+        let snapshot = db.snapshot();
+        assert!(snapshot.contains(IDX_NAME, &[100]));
+        let persisted = keys.iter()
+            .take_while(|&&key| snapshot.contains(IDX_NAME, &[key]))
+            .count();
+        assert!(keys[persisted..]
+            .iter()
+            .all(|&key| !snapshot.contains(IDX_NAME, &[key])));
+    }
+
+    #[test]
+    fn unsynced_writes_are_lost_on_crash() {
+        let plan = FaultPlan {
+            buffer_unsynced: true,
+            drop_sync_durability: true,
+            ..FaultPlan::default()
+        };
+        let db = FaultyDatabase::new(MemoryDB::new(), plan);
+        db.merge(patch_with_keys(&db, &[1])).unwrap();
+        db.merge_sync(patch_with_keys(&db, &[2])).unwrap();
+
+        // Buffered changes are visible before the crash.
+        let snapshot = db.snapshot();
+        assert!(snapshot.contains(IDX_NAME, &[1]));
+        assert!(snapshot.contains(IDX_NAME, &[2]));
+
+        db.crash();
+        let snapshot = db.into_inner().snapshot();
+        assert!(!snapshot.contains(IDX_NAME, &[1]));
+        assert!(!snapshot.contains(IDX_NAME, &[2]));
+    }
+
+    #[test]
+    fn durable_merge_flushes_buffered_writes() {
+        let plan = FaultPlan {
+            buffer_unsynced: true,
+            ..FaultPlan::default()
+        };
+        let db = FaultyDatabase::new(MemoryDB::new(), plan);
+        db.merge(patch_with_keys(&db, &[1])).unwrap();
+        db.merge_sync(patch_with_keys(&db, &[2])).unwrap();
+        db.merge(patch_with_keys(&db, &[3])).unwrap();
+
+        db.crash();
+        let snapshot = db.into_inner().snapshot();
+        assert!(snapshot.contains(IDX_NAME, &[1]));
+        assert!(snapshot.contains(IDX_NAME, &[2]));
+        assert!(!snapshot.contains(IDX_NAME, &[3]));
+    }
+
+    #[test]
+    fn reads_are_checked_against_buffered_writes() {
+        let plan = FaultPlan {
+            buffer_unsynced: true,
+            ..FaultPlan::default()
+        };
+        let db = FaultyDatabase::new(MemoryDB::new(), plan);
+        let mut fork = db.fork();
+        let mut other = db.fork();
+        assert_eq!(fork.get_for_update(IDX_NAME, &[1]), None);
+        fork.put(IDX_NAME, vec![1], vec![2]);
+        assert_eq!(other.get_for_update(IDX_NAME, &[2]), None);
+        other.put(IDX_NAME, vec![2], vec![2]);
+
+        db.merge(patch_with_keys(&db, &[1])).unwrap();
+        assert!(db.merge(fork.into_patch()).unwrap_err().is_conflict());
+        db.merge(other.into_patch()).unwrap();
+        db.merge_sync(patch_with_keys(&db, &[3])).unwrap();
+
+        let snapshot = db.into_inner().snapshot();
+        assert_eq!(snapshot.get(IDX_NAME, &[1]), Some(vec![1]));
+        assert_eq!(snapshot.get(IDX_NAME, &[2]), Some(vec![2]));
+    }
+
+    #[test]
+    fn corrupted_reads() {
+        let plan = FaultPlan {
+            corrupt_reads: 1.0,
+            ..FaultPlan::default()
+        };
+        let db = FaultyDatabase::new(MemoryDB::new(), plan);
+        let mut fork = db.fork();
+        fork.put(IDX_NAME, vec![1], vec![0; 8]);
+        db.merge(fork.into_patch()).unwrap();
+
+        let snapshot = db.snapshot();
+        for _ in 0..16 {
+            let value = snapshot.get(IDX_NAME, &[1]).unwrap();
+            assert_eq!(value.len(), 8);
+            assert_eq!(value.iter().map(|b| b.count_ones()).sum::<u32>(), 1);
+        }
+        assert_eq!(snapshot.get(IDX_NAME, &[2]), None);
+    }
+}
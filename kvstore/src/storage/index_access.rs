@@ -0,0 +1,177 @@
+// Copyright 2018 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Storage views which indexes are generic over.
+
+use std::rc::Rc;
+use std::sync::Arc;
+
+use super::{Fork, Snapshot};
+
+/// A storage view an index can read from.
+///
+/// The trait is implemented for `Snapshot` trait objects and for `Fork`, and is forwarded
+/// through references, `Box`, `Rc` and `Arc`. Thus, an index may borrow its view
+/// (`&Snapshot`, `&Fork`), own it (`Box<Snapshot>`, `Fork`) or share it with other indexes
+/// (`Arc<Fork>`).
+///
+/// # Examples
+///
+/// ```
+/// use exonum::storage::{Database, Fork, MapIndex, MemoryDB, Snapshot};
+///
+/// // The index owns the fork, so it can be stored in a struct or returned from a function.
+/// fn new_index(db: &MemoryDB) -> MapIndex<Fork, u8, u8> {
+///     MapIndex::new("map", db.fork())
+/// }
+///
+/// let db = MemoryDB::new();
+/// let mut index = new_index(&db);
+/// index.put(&1, 2);
+/// db.merge(index.into_view().into_patch()).unwrap();
+///
+/// let index: MapIndex<Box<Snapshot>, u8, u8> = MapIndex::new("map", db.snapshot());
+/// assert_eq!(index.get(&1), Some(2));
+/// ```
+pub trait IndexAccess {
+    /// Returns the snapshot the index reads from.
+    fn snapshot(&self) -> &Snapshot;
+}
+
+/// A storage view an index can both read from and write to.
+///
+/// The trait is implemented for `Fork` and is forwarded through mutable references and `Box`.
+pub trait IndexAccessMut: IndexAccess {
+    /// Returns the fork the index writes to.
+    fn fork(&mut self) -> &mut Fork;
+}
+
+impl IndexAccess for Snapshot {
+    fn snapshot(&self) -> &Snapshot {
+        self
+    }
+}
+
+impl IndexAccess for Fork {
+    fn snapshot(&self) -> &Snapshot {
+        self
+    }
+}
+
+impl IndexAccessMut for Fork {
+    fn fork(&mut self) -> &mut Fork {
+        self
+    }
+}
+
+impl<'a, T: IndexAccess + ?Sized> IndexAccess for &'a T {
+    fn snapshot(&self) -> &Snapshot {
+        (**self).snapshot()
+    }
+}
+
+impl<'a, T: IndexAccess + ?Sized> IndexAccess for &'a mut T {
+    fn snapshot(&self) -> &Snapshot {
+        (**self).snapshot()
+    }
+}
+
+impl<'a, T: IndexAccessMut + ?Sized> IndexAccessMut for &'a mut T {
+    fn fork(&mut self) -> &mut Fork {
+        (**self).fork()
+    }
+}
+
+impl<T: IndexAccess + ?Sized> IndexAccess for Box<T> {
+    fn snapshot(&self) -> &Snapshot {
+        (**self).snapshot()
+    }
+}
+
+impl<T: IndexAccessMut + ?Sized> IndexAccessMut for Box<T> {
+    fn fork(&mut self) -> &mut Fork {
+        (**self).fork()
+    }
+}
+
+impl<T: IndexAccess + ?Sized> IndexAccess for Rc<T> {
+    fn snapshot(&self) -> &Snapshot {
+        (**self).snapshot()
+    }
+}
+
+impl<T: IndexAccess + ?Sized> IndexAccess for Arc<T> {
+    fn snapshot(&self) -> &Snapshot {
+        (**self).snapshot()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use storage::{Database, Entry, Fork, KeySetIndex, ListIndex, MapIndex, MemoryDB, Snapshot,
+                  SparseListIndex, ValueSetIndex};
+
+    struct Owner {
+        list: ListIndex<Fork, u64>,
+    }
+
+    #[test]
+    fn owned_views() {
+        let db = MemoryDB::new();
+        let mut owner = Owner {
+            list: ListIndex::new("list", db.fork()),
+        };
+        owner.list.push(1);
+        owner.list.push(2);
+        db.merge(owner.list.into_view().into_patch()).unwrap();
+
+        let mut fork = Box::new(db.fork());
+        {
+            let mut index = MapIndex::new("map", &mut fork);
+            index.put(&1_u8, 10_u64);
+        }
+        let mut set = KeySetIndex::new("set", fork);
+        set.insert(5_u8);
+        db.merge(set.into_view().into_patch()).unwrap();
+
+        let snapshot: Box<Snapshot> = db.snapshot();
+        let list: ListIndex<_, u64> = ListIndex::new("list", snapshot);
+        assert_eq!(list.iter().collect::<Vec<_>>(), vec![1, 2]);
+    }
+
+    #[test]
+    fn shared_views() {
+        let db = MemoryDB::new();
+        let mut fork = db.fork();
+        Entry::new("entry", &mut fork).set(1_u64);
+        SparseListIndex::new("sparse", &mut fork).push(2_u64);
+        ValueSetIndex::new("values", &mut fork).insert(3_u64);
+        db.merge(fork.into_patch()).unwrap();
+
+        let snapshot: Arc<Snapshot> = Arc::from(db.snapshot());
+        let entry: Entry<_, u64> = Entry::new("entry", Arc::clone(&snapshot));
+        let sparse: SparseListIndex<_, u64> = SparseListIndex::new("sparse", Arc::clone(&snapshot));
+        let values: ValueSetIndex<_, u64> = ValueSetIndex::new("values", snapshot);
+        assert_eq!(entry.get(), Some(1));
+        assert_eq!(sparse.get(0), Some(2));
+        assert!(values.contains(&3));
+
+        let fork = Arc::new(db.fork());
+        let first: Entry<_, u64> = Entry::new("entry", Arc::clone(&fork));
+        let second: Entry<_, u64> = Entry::new("entry", &*fork);
+        assert_eq!(first.get(), second.get());
+    }
+}
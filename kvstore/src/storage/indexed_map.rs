@@ -0,0 +1,820 @@
+// Copyright 2018 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! An implementation of key-value map with secondary indexes.
+
+use std::borrow::Borrow;
+use std::fmt;
+use std::marker::PhantomData;
+
+use super::{BaseIndex, BaseIndexIter, BaseIndexKeysIter, Error, IndexAccess, IndexAccessMut,
+            Result, StorageKey, StorageValue};
+use super::indexes_metadata::IndexType;
+use super::keys::{skip_escaped, write_escaped};
+
+/// Subprefix of the entries, ordered by primary keys.
+const ENTRIES_PREFIX: u8 = 0;
+/// Subprefix of the secondary index records.
+const RECORDS_PREFIX: u8 = 1;
+
+/// A secondary index of an `IndexedMap`.
+struct SecondaryIndex<V> {
+    name: String,
+    unique: bool,
+    extract: Box<Fn(&V) -> Vec<u8>>,
+}
+
+/// A map of keys and values, which maintains secondary indexes over the values.
+///
+/// Each secondary index is defined by a name and an extractor function, which computes
+/// the secondary key of a value. On every [`put`] and [`remove`], the records of all
+/// the secondary indexes are updated in the same fork as the entry itself, so they never get
+/// out of sync with the map. The entries can be looked up by a secondary key with [`get_by`]
+/// or by a range of secondary keys with [`range_by`].
+///
+/// A secondary index can be unique, in which case `put` fails if another entry of the map
+/// already has the same secondary key.
+///
+/// Secondary indexes are not persisted: they must be registered with [`with_index`] or
+/// [`with_unique_index`] each time the map is created, and the set of registered indexes must
+/// be the same for all the views of the map. Entries put before an index is registered are not
+/// found by the index. The records left by views of the map with a different set of indexes
+/// are checked against the entries when they are read, and skipped if the entry has been
+/// removed or its secondary key has changed.
+///
+/// `IndexedMap` requires that the keys implement the [`StorageKey`] trait and the values
+/// implement [`StorageValue`] trait.
+///
+/// # Examples
+///
+/// ```
+/// use exonum::storage::{MemoryDB, Database, IndexedMap};
+///
+/// let db = MemoryDB::new();
+/// let mut fork = db.fork();
+/// let mut users = IndexedMap::new("users", &mut fork)
+///     .with_unique_index("by_name", |name: &String| name.to_lowercase())
+///     .with_index("by_length", |name: &String| name.len() as u64);
+///
+/// users.put(&1_u64, "Alice".to_owned()).unwrap();
+/// users.put(&2_u64, "Bob".to_owned()).unwrap();
+/// users.put(&3_u64, "Carol".to_owned()).unwrap();
+/// assert!(users.put(&4_u64, "alice".to_owned()).is_err());
+///
+/// let ids: Vec<u64> = users.get_by("by_name", "bob").map(|(id, _)| id).collect();
+/// assert_eq!(ids, vec![2]);
+/// let ids: Vec<u64> = users.get_by("by_length", &5_u64).map(|(id, _)| id).collect();
+/// assert_eq!(ids, vec![1, 3]);
+/// let ids: Vec<u64> = users.range_by("by_length", &0_u64, &5_u64).map(|(id, _)| id).collect();
+/// assert_eq!(ids, vec![2]);
+/// ```
+///
+/// [`put`]: #method.put
+/// [`remove`]: #method.remove
+/// [`get_by`]: #method.get_by
+/// [`range_by`]: #method.range_by
+/// [`with_index`]: #method.with_index
+/// [`with_unique_index`]: #method.with_unique_index
+/// [`StorageKey`]: ../trait.StorageKey.html
+/// [`StorageValue`]: ../trait.StorageValue.html
+pub struct IndexedMap<T, K, V> {
+    base: BaseIndex<T>,
+    indexes: Vec<SecondaryIndex<V>>,
+    _k: PhantomData<K>,
+}
+
+/// An iterator over the entries of an `IndexedMap` in ascending order of keys.
+///
+/// This struct is created by the [`iter`] method on [`IndexedMap`].
+/// See its documentation for more.
+///
+/// [`iter`]: struct.IndexedMap.html#method.iter
+/// [`IndexedMap`]: struct.IndexedMap.html
+#[derive(Debug)]
+pub struct IndexedMapIter<'a, K, V> {
+    base_iter: BaseIndexIter<'a, Vec<u8>, V>,
+    _k: PhantomData<K>,
+}
+
+/// An iterator over the entries of an `IndexedMap` found by a secondary index, in ascending
+/// order of secondary keys and then primary keys.
+///
+/// This struct is created by the [`get_by`] or [`range_by`] methods on [`IndexedMap`].
+/// See its documentation for more.
+///
+/// [`get_by`]: struct.IndexedMap.html#method.get_by
+/// [`range_by`]: struct.IndexedMap.html#method.range_by
+/// [`IndexedMap`]: struct.IndexedMap.html
+pub struct IndexedMapLookup<'a, T: 'a, K, V: 'a> {
+    base: &'a BaseIndex<T>,
+    extract: &'a Fn(&V) -> Vec<u8>,
+    records: BaseIndexKeysIter<'a, Vec<u8>>,
+    end: Option<Vec<u8>>,
+    _k: PhantomData<K>,
+    _v: PhantomData<V>,
+}
+
+impl<T, K, V> IndexedMap<T, K, V>
+where
+    T: IndexAccess,
+    K: StorageKey,
+    V: StorageValue,
+{
+    /// Creates a new index representation based on the name and storage view.
+    ///
+    /// Storage view can be specified as [`&Snapshot`] or [`&mut Fork`]. In the first case only
+    /// immutable methods are available. In the second case both immutable and mutable methods are
+    /// available.
+    ///
+    /// [`&Snapshot`]: ../trait.Snapshot.html
+    /// [`&mut Fork`]: ../struct.Fork.html
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, IndexedMap};
+    ///
+    /// let db = MemoryDB::new();
+    /// let name = "name";
+    /// let snapshot = db.snapshot();
+    /// let index: IndexedMap<_, u8, u8> = IndexedMap::new(name, &snapshot);
+    /// ```
+    pub fn new<S: AsRef<str>>(index_name: S, view: T) -> Self {
+        IndexedMap {
+            base: BaseIndex::new(index_name, IndexType::IndexedMap, view),
+            indexes: Vec::new(),
+            _k: PhantomData,
+        }
+    }
+
+    /// Creates a new index representation based on the name, index id in family
+    /// and storage view.
+    ///
+    /// Storage view can be specified as [`&Snapshot`] or [`&mut Fork`]. In the first case only
+    /// immutable methods are available. In the second case both immutable and mutable methods are
+    /// available.
+    ///
+    /// [`&Snapshot`]: ../trait.Snapshot.html
+    /// [`&mut Fork`]: ../struct.Fork.html
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, IndexedMap};
+    ///
+    /// let db = MemoryDB::new();
+    /// let name = "name";
+    /// let index_id = vec![01];
+    ///
+    /// let snapshot = db.snapshot();
+    /// let index: IndexedMap<_, u8, u8> = IndexedMap::new_in_family(name, &index_id, &snapshot);
+    /// ```
+    pub fn new_in_family<S: AsRef<str>, I: StorageKey>(
+        family_name: S,
+        index_id: &I,
+        view: T,
+    ) -> Self {
+        IndexedMap {
+            base: BaseIndex::new_in_family(family_name, index_id, IndexType::IndexedMap, view),
+            indexes: Vec::new(),
+            _k: PhantomData,
+        }
+    }
+
+    /// Registers a non-unique secondary index with the specified name and extractor of
+    /// secondary keys.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a secondary index with the same name is already registered.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, IndexedMap};
+    ///
+    /// let db = MemoryDB::new();
+    /// let snapshot = db.snapshot();
+    /// let index: IndexedMap<_, u8, u64> = IndexedMap::new("name", &snapshot)
+    ///     .with_index("by_parity", |value: &u64| value % 2);
+    /// ```
+    pub fn with_index<S, F>(self, name: &str, extract: F) -> Self
+    where
+        S: StorageKey,
+        F: Fn(&V) -> S + 'static,
+    {
+        self.add_index(name, false, extract)
+    }
+
+    /// Registers a unique secondary index with the specified name and extractor of
+    /// secondary keys. No two entries of the map may have the same secondary key.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a secondary index with the same name is already registered.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, IndexedMap};
+    ///
+    /// let db = MemoryDB::new();
+    /// let snapshot = db.snapshot();
+    /// let index: IndexedMap<_, u8, String> = IndexedMap::new("name", &snapshot)
+    ///     .with_unique_index("by_value", |value: &String| value.clone());
+    /// ```
+    pub fn with_unique_index<S, F>(self, name: &str, extract: F) -> Self
+    where
+        S: StorageKey,
+        F: Fn(&V) -> S + 'static,
+    {
+        self.add_index(name, true, extract)
+    }
+
+    /// Returns the storage view of the index, consuming the index.
+    pub fn into_view(self) -> T {
+        self.base.into_view()
+    }
+
+    /// Returns a value corresponding to the key.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, IndexedMap};
+    ///
+    /// let db = MemoryDB::new();
+    /// let name = "name";
+    /// let mut fork = db.fork();
+    /// let mut index = IndexedMap::new(name, &mut fork);
+    /// assert!(index.get(&1).is_none());
+    ///
+    /// index.put(&1, 2_u8).unwrap();
+    /// assert_eq!(Some(2), index.get(&1));
+    /// ```
+    pub fn get<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: StorageKey + ?Sized,
+    {
+        self.base.get(&entry_key(key))
+    }
+
+    /// Returns `true` if the map contains a value for the specified key.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, IndexedMap};
+    ///
+    /// let db = MemoryDB::new();
+    /// let name = "name";
+    /// let mut fork = db.fork();
+    /// let mut index = IndexedMap::new(name, &mut fork);
+    /// assert!(!index.contains(&1));
+    ///
+    /// index.put(&1, 2_u8).unwrap();
+    /// assert!(index.contains(&1));
+    /// ```
+    pub fn contains<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: StorageKey + ?Sized,
+    {
+        self.base.contains(&entry_key(key))
+    }
+
+    /// Returns an iterator over the entries of the map in ascending order of keys. The iterator
+    /// element type is (K, V).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, IndexedMap};
+    ///
+    /// let db = MemoryDB::new();
+    /// let name = "name";
+    /// let snapshot = db.snapshot();
+    /// let index: IndexedMap<_, u8, u8> = IndexedMap::new(name, &snapshot);
+    ///
+    /// for v in index.iter() {
+    ///     println!("{:?}", v);
+    /// }
+    /// ```
+    pub fn iter(&self) -> IndexedMapIter<K, V> {
+        IndexedMapIter {
+            base_iter: self.base.iter(&ENTRIES_PREFIX),
+            _k: PhantomData,
+        }
+    }
+
+    /// Returns an iterator over the entries with the specified key in the secondary index,
+    /// in ascending order of primary keys. The iterator element type is (K, V).
+    ///
+    /// # Panics
+    ///
+    /// Panics if the secondary index with the specified name is not registered.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, IndexedMap};
+    ///
+    /// let db = MemoryDB::new();
+    /// let name = "name";
+    /// let mut fork = db.fork();
+    /// let mut index = IndexedMap::new(name, &mut fork)
+    ///     .with_index("by_parity", |value: &u64| value % 2);
+    /// index.put(&1_u8, 10).unwrap();
+    /// index.put(&2_u8, 11).unwrap();
+    /// index.put(&3_u8, 12).unwrap();
+    ///
+    /// let odd = index.get_by("by_parity", &1_u64).collect::<Vec<_>>();
+    /// assert_eq!(odd, vec![(2, 11)]);
+    /// ```
+    pub fn get_by<S>(&self, index: &str, key: &S) -> IndexedMapLookup<T, K, V>
+    where
+        S: StorageKey + ?Sized,
+    {
+        let mut prefix = self.index_prefix(index);
+        write_escaped(&key_bytes(key), &mut prefix);
+        IndexedMapLookup {
+            base: &self.base,
+            extract: &*self.secondary_index(index).extract,
+            records: self.base.iter_keys(&prefix),
+            end: None,
+            _k: PhantomData,
+            _v: PhantomData,
+        }
+    }
+
+    /// Returns an iterator over the entries with keys in the secondary index that are greater
+    /// than or equal to `from` and less than `to`, in ascending order of the serialized secondary
+    /// keys and then primary keys. The iterator element type is (K, V).
+    ///
+    /// # Panics
+    ///
+    /// Panics if the secondary index with the specified name is not registered.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, IndexedMap};
+    ///
+    /// let db = MemoryDB::new();
+    /// let name = "name";
+    /// let mut fork = db.fork();
+    /// let mut index = IndexedMap::new(name, &mut fork)
+    ///     .with_unique_index("by_value", |value: &u64| *value);
+    /// index.put(&1_u8, 30).unwrap();
+    /// index.put(&2_u8, 10).unwrap();
+    /// index.put(&3_u8, 20).unwrap();
+    ///
+    /// let keys = index.range_by("by_value", &10_u64, &30_u64).map(|(k, _)| k);
+    /// assert_eq!(keys.collect::<Vec<_>>(), vec![2, 3]);
+    /// ```
+    pub fn range_by<S>(&self, index: &str, from: &S, to: &S) -> IndexedMapLookup<T, K, V>
+    where
+        S: StorageKey + ?Sized,
+    {
+        let index_prefix = self.index_prefix(index);
+        let mut start = index_prefix.clone();
+        write_escaped(&key_bytes(from), &mut start);
+        let mut end = index_prefix.clone();
+        write_escaped(&key_bytes(to), &mut end);
+        IndexedMapLookup {
+            base: &self.base,
+            extract: &*self.secondary_index(index).extract,
+            records: self.base.iter_keys_from(&index_prefix, &start),
+            end: Some(end),
+            _k: PhantomData,
+            _v: PhantomData,
+        }
+    }
+
+    fn add_index<S, F>(mut self, name: &str, unique: bool, extract: F) -> Self
+    where
+        S: StorageKey,
+        F: Fn(&V) -> S + 'static,
+    {
+        assert!(
+            self.indexes.iter().all(|index| index.name != name),
+            "Secondary index '{}' is already registered",
+            name
+        );
+        self.indexes.push(SecondaryIndex {
+            name: name.to_owned(),
+            unique,
+            extract: Box::new(move |value: &V| key_bytes(&extract(value))),
+        });
+        self
+    }
+
+    /// Returns the registered secondary index with the specified name.
+    fn secondary_index(&self, index: &str) -> &SecondaryIndex<V> {
+        self.indexes
+            .iter()
+            .find(|i| i.name == index)
+            .unwrap_or_else(|| panic!("Secondary index '{}' is not registered", index))
+    }
+
+    /// Returns the common prefix of the records of the secondary index.
+    fn index_prefix(&self, index: &str) -> Vec<u8> {
+        self.secondary_index(index);
+        let mut prefix = vec![RECORDS_PREFIX];
+        write_escaped(index.as_bytes(), &mut prefix);
+        prefix
+    }
+
+    /// Returns the prefixes of the secondary index records of the value, one per
+    /// secondary index. The record keys are the prefixes followed by the primary key.
+    fn record_prefixes(&self, value: &V) -> Vec<Vec<u8>> {
+        self.indexes
+            .iter()
+            .map(|index| {
+                let mut prefix = vec![RECORDS_PREFIX];
+                write_escaped(index.name.as_bytes(), &mut prefix);
+                write_escaped(&(index.extract)(value), &mut prefix);
+                prefix
+            })
+            .collect()
+    }
+}
+
+impl<T, K, V> IndexedMap<T, K, V>
+where
+    T: IndexAccessMut,
+    K: StorageKey,
+    V: StorageValue,
+{
+    /// Inserts the key-value pair into the map and updates the secondary indexes.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if another entry of the map has the same key in a unique secondary
+    /// index. The map is not changed in this case.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, IndexedMap};
+    ///
+    /// let db = MemoryDB::new();
+    /// let name = "name";
+    /// let mut fork = db.fork();
+    /// let mut index = IndexedMap::new(name, &mut fork)
+    ///     .with_unique_index("by_value", |value: &u64| *value);
+    /// index.put(&1_u8, 10).unwrap();
+    /// index.put(&1_u8, 20).unwrap();
+    /// index.put(&2_u8, 10).unwrap();
+    /// assert!(index.put(&3_u8, 10).is_err());
+    /// ```
+    pub fn put(&mut self, key: &K, value: V) -> Result<()> {
+        let entry_key = entry_key(key);
+        let prefixes = self.record_prefixes(&value);
+
+        for (index, prefix) in self.indexes.iter().zip(&prefixes) {
+            if !index.unique {
+                continue;
+            }
+            let duplicate = self.base
+                .iter_keys::<_, Vec<u8>>(prefix)
+                .filter(|record| record[prefix.len()..] != entry_key[1..])
+                .any(|record| live_entry(&self.base, &*index.extract, &record).is_some());
+            if duplicate {
+                return Err(Error::new(format!(
+                    "Duplicate key in the unique secondary index '{}'",
+                    index.name
+                )));
+            }
+        }
+
+        self.remove_records(&entry_key);
+        for mut record in prefixes {
+            record.extend_from_slice(&entry_key[1..]);
+            self.base.put(&record, ());
+        }
+        self.base.put(&entry_key, value);
+        Ok(())
+    }
+
+    /// Removes the key from the map and updates the secondary indexes.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, IndexedMap};
+    ///
+    /// let db = MemoryDB::new();
+    /// let name = "name";
+    /// let mut fork = db.fork();
+    /// let mut index = IndexedMap::new(name, &mut fork)
+    ///     .with_index("by_value", |value: &u64| *value);
+    ///
+    /// index.put(&1_u8, 2).unwrap();
+    /// assert!(index.contains(&1));
+    ///
+    /// index.remove(&1);
+    /// assert!(!index.contains(&1));
+    /// assert_eq!(index.get_by("by_value", &2_u64).count(), 0);
+    /// ```
+    pub fn remove<Q>(&mut self, key: &Q)
+    where
+        K: Borrow<Q>,
+        Q: StorageKey + ?Sized,
+    {
+        let entry_key = entry_key(key);
+        if self.remove_records(&entry_key) {
+            self.base.remove(&entry_key);
+        }
+    }
+
+    /// Clears the map, removing all entries together with the secondary index records.
+    ///
+    /// # Notes
+    ///
+    /// Currently this method is not optimized to delete large set of data. During the execution of
+    /// this method the amount of allocated memory is linearly dependent on the number of elements
+    /// in the index.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, IndexedMap};
+    ///
+    /// let db = MemoryDB::new();
+    /// let name = "name";
+    /// let mut fork = db.fork();
+    /// let mut index = IndexedMap::new(name, &mut fork);
+    ///
+    /// index.put(&1, 2_u8).unwrap();
+    /// assert!(index.contains(&1));
+    ///
+    /// index.clear();
+    /// assert!(!index.contains(&1));
+    /// ```
+    pub fn clear(&mut self) {
+        self.base.clear()
+    }
+
+    /// Removes the secondary index records of the entry with the specified key, if the entry
+    /// exists, and returns `true` in this case.
+    fn remove_records(&mut self, entry_key: &[u8]) -> bool {
+        let old_value = match self.base.get::<_, V>(entry_key) {
+            Some(value) => value,
+            None => return false,
+        };
+        for mut record in self.record_prefixes(&old_value) {
+            record.extend_from_slice(&entry_key[1..]);
+            self.base.remove(&record);
+        }
+        true
+    }
+}
+
+impl<T, K, V> fmt::Debug for IndexedMap<T, K, V>
+where
+    T: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let indexes = self.indexes
+            .iter()
+            .map(|index| &index.name)
+            .collect::<Vec<_>>();
+        f.debug_struct("IndexedMap")
+            .field("base", &self.base)
+            .field("indexes", &indexes)
+            .finish()
+    }
+}
+
+impl<'a, T, K, V> ::std::iter::IntoIterator for &'a IndexedMap<T, K, V>
+where
+    T: IndexAccess,
+    K: StorageKey,
+    V: StorageValue,
+{
+    type Item = (K::Owned, V);
+    type IntoIter = IndexedMapIter<'a, K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a, K, V> Iterator for IndexedMapIter<'a, K, V>
+where
+    K: StorageKey,
+    V: StorageValue,
+{
+    type Item = (K::Owned, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.base_iter
+            .next()
+            .map(|(key, value)| (K::read(&key[1..]), value))
+    }
+}
+
+impl<'a, T, K, V> Iterator for IndexedMapLookup<'a, T, K, V>
+where
+    T: IndexAccess,
+    K: StorageKey,
+    V: StorageValue,
+{
+    type Item = (K::Owned, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(record) = self.records.next() {
+            if let Some(ref end) = self.end {
+                if record >= *end {
+                    return None;
+                }
+            }
+            if let Some((key_start, value)) = live_entry(self.base, self.extract, &record) {
+                return Some((K::read(&record[key_start..]), value));
+            }
+        }
+        None
+    }
+}
+
+impl<'a, T, K, V> fmt::Debug for IndexedMapLookup<'a, T, K, V>
+where
+    T: fmt::Debug + 'a,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("IndexedMapLookup")
+            .field("base", &self.base)
+            .field("records", &self.records)
+            .field("end", &self.end)
+            .finish()
+    }
+}
+
+/// Returns the offset of the primary key in the secondary index record and the entry
+/// the record refers to, or `None` if the entry doesn't exist or has another secondary key.
+fn live_entry<T, V>(
+    base: &BaseIndex<T>,
+    extract: &Fn(&V) -> Vec<u8>,
+    record: &[u8],
+) -> Option<(usize, V)>
+where
+    T: IndexAccess,
+    V: StorageValue,
+{
+    let secondary_key_start = skip_escaped(record, 1);
+    let key_start = skip_escaped(record, secondary_key_start);
+    let mut entry_key = vec![ENTRIES_PREFIX];
+    entry_key.extend_from_slice(&record[key_start..]);
+    let value = base.get::<_, V>(&entry_key)?;
+
+    let mut secondary_key = Vec::new();
+    write_escaped(&extract(&value), &mut secondary_key);
+    if record[secondary_key_start..key_start] == secondary_key[..] {
+        Some((key_start, value))
+    } else {
+        None
+    }
+}
+
+/// Returns the storage key of the entry with the specified key.
+fn entry_key<K: StorageKey + ?Sized>(key: &K) -> Vec<u8> {
+    let mut buffer = vec![ENTRIES_PREFIX; 1 + key.size()];
+    key.write(&mut buffer[1..]);
+    buffer
+}
+
+fn key_bytes<K: StorageKey + ?Sized>(key: &K) -> Vec<u8> {
+    let mut buffer = vec![0; key.size()];
+    key.write(&mut buffer);
+    buffer
+}
+
+#[cfg(test)]
+mod tests {
+    use storage::{Database, Fork, MemoryDB};
+    use super::IndexedMap;
+
+    const IDX_NAME: &str = "idx_name";
+
+    fn users(fork: &mut Fork) -> IndexedMap<&mut Fork, u64, String> {
+        IndexedMap::new(IDX_NAME, fork)
+            .with_unique_index("by_name", |name: &String| name.clone())
+            .with_index("by_initial", |name: &String| name.as_bytes()[0])
+    }
+
+    #[test]
+    fn secondary_indexes_follow_updates() {
+        let db = MemoryDB::new();
+        let mut fork = db.fork();
+        {
+            let mut map = users(&mut fork);
+            map.put(&1, "alice".to_owned()).unwrap();
+            map.put(&2, "bob".to_owned()).unwrap();
+            map.put(&3, "anna".to_owned()).unwrap();
+            map.put(&2, "andrew".to_owned()).unwrap();
+            map.remove(&3);
+        }
+        db.merge(fork.into_patch()).unwrap();
+
+        let snapshot = db.snapshot();
+        let map: IndexedMap<_, u64, String> = IndexedMap::new(IDX_NAME, &snapshot)
+            .with_unique_index("by_name", |name: &String| name.clone())
+            .with_index("by_initial", |name: &String| name.as_bytes()[0]);
+        assert_eq!(map.get_by("by_name", "bob").count(), 0);
+        assert_eq!(map.get_by("by_name", "anna").count(), 0);
+        assert_eq!(
+            map.get_by("by_name", "andrew").collect::<Vec<_>>(),
+            vec![(2, "andrew".to_owned())]
+        );
+        assert_eq!(
+            map.get_by("by_initial", &b'a').map(|(k, _)| k).collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+        assert_eq!(map.get_by("by_initial", &b'b').count(), 0);
+        assert_eq!(map.iter().map(|(k, _)| k).collect::<Vec<_>>(), vec![1, 2]);
+    }
+
+    #[test]
+    fn unique_index_violation() {
+        let db = MemoryDB::new();
+        let mut fork = db.fork();
+        let mut map = users(&mut fork);
+        map.put(&1, "alice".to_owned()).unwrap();
+        map.put(&1, "alice".to_owned()).unwrap();
+
+        let err = map.put(&2, "alice".to_owned()).unwrap_err();
+        assert!(err.to_string().contains("by_name"));
+        assert!(!map.contains(&2));
+        assert_eq!(map.get_by("by_initial", &b'a').count(), 1);
+    }
+
+    #[test]
+    fn range_over_variable_length_keys() {
+        let db = MemoryDB::new();
+        let mut fork = db.fork();
+        let mut map = users(&mut fork);
+        for (key, name) in vec!["a", "ab", "abc", "b", "ba"].into_iter().enumerate() {
+            map.put(&(key as u64), name.to_owned()).unwrap();
+        }
+
+        let names = |from: &str, to: &str| {
+            map.range_by("by_name", from, to)
+                .map(|(_, name)| name)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names("a", "b"), vec!["a", "ab", "abc"]);
+        assert_eq!(names("ab", "ba"), vec!["ab", "abc", "b"]);
+        assert_eq!(names("", "ab"), vec!["a"]);
+        assert_eq!(names("c", "d"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn stale_records_are_skipped() {
+        let db = MemoryDB::new();
+        let mut fork = db.fork();
+        {
+            let mut map = users(&mut fork);
+            map.put(&1, "alice".to_owned()).unwrap();
+            map.put(&2, "bob".to_owned()).unwrap();
+        }
+        {
+            // A view without the secondary indexes leaves their records behind.
+            let mut map: IndexedMap<_, u64, String> = IndexedMap::new(IDX_NAME, &mut fork);
+            map.put(&1, "carol".to_owned()).unwrap();
+            map.remove(&2);
+        }
+
+        let mut map = users(&mut fork);
+        assert_eq!(map.get_by("by_name", "alice").count(), 0);
+        assert_eq!(map.get_by("by_name", "bob").count(), 0);
+        assert_eq!(map.range_by("by_initial", &b'a', &b'z').count(), 0);
+        map.put(&3, "alice".to_owned()).unwrap();
+        map.put(&4, "bob".to_owned()).unwrap();
+        assert_eq!(
+            map.get_by("by_name", "alice").collect::<Vec<_>>(),
+            vec![(3, "alice".to_owned())]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Secondary index 'by_age' is not registered")]
+    fn unknown_secondary_index() {
+        let db = MemoryDB::new();
+        let mut fork = db.fork();
+        let map = users(&mut fork);
+        map.get_by("by_age", &1_u8);
+    }
+}
@@ -12,24 +12,55 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-#![allow(unsafe_code)]
-
 use std::borrow::Cow;
-use std::error::Error;
 
 use crypto::{Hash, CryptoHash};
 use storage::{BaseIndex, Fork, Snapshot, StorageValue};
 
 pub const INDEXES_METADATA_TABLE_NAME: &str = "__INDEXES_METADATA__";
 
-encoding_struct!(
-    struct IndexMetadata {
-        index_type: IndexType,
-        is_family: bool,
+/// Type of an index and whether it is an index family, as stored in the indexes metadata.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct IndexMetadata {
+    index_type: IndexType,
+    is_family: bool,
+}
+
+impl IndexMetadata {
+    fn new(index_type: IndexType, is_family: bool) -> Self {
+        IndexMetadata {
+            index_type,
+            is_family,
+        }
     }
-);
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+    fn index_type(&self) -> IndexType {
+        self.index_type
+    }
+
+    fn is_family(&self) -> bool {
+        self.is_family
+    }
+}
+
+impl CryptoHash for IndexMetadata {
+    fn hash(&self) -> Hash {
+        ::crypto::hash(&self.into_bytes())
+    }
+}
+
+impl StorageValue for IndexMetadata {
+    fn into_bytes(self) -> Vec<u8> {
+        vec![self.index_type as u8, self.is_family as u8]
+    }
+
+    fn from_bytes(value: Cow<[u8]>) -> Self {
+        let value = value.as_ref();
+        IndexMetadata::new(value[0].into(), value[1] != 0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 #[repr(u8)]
 pub enum IndexType {
     Entry,
@@ -40,6 +71,13 @@ pub enum IndexType {
     ProofList,
     ProofMap,
     ValueSet,
+    TtlMap,
+    IndexedMap,
+    MultiMap,
+    SortedSet,
+    Deque,
+    Queue,
+    Text,
 }
 
 impl From<u8> for IndexType {
@@ -54,6 +92,13 @@ impl From<u8> for IndexType {
             5 => ProofList,
             6 => ProofMap,
             7 => ValueSet,
+            8 => TtlMap,
+            9 => IndexedMap,
+            10 => MultiMap,
+            11 => SortedSet,
+            12 => Deque,
+            13 => Queue,
+            14 => Text,
             invalid => panic!(
                 "Unreachable pattern ({:?}) while constructing table type. \
                  Storage data is probably corrupted",
@@ -79,27 +124,43 @@ impl StorageValue for IndexType {
     }
 }
 
-impl<'a> Field<'a> for IndexType {
-    unsafe fn read(buffer: &'a [u8], from: Offset, to: Offset) -> Self {
-        u8::read(buffer, from, to).into()
-    }
-
-    fn write(&self, buffer: &mut Vec<u8>, from: Offset, to: Offset) {
-        (*self as u8).write(buffer, from, to)
-    }
+/// Information about an index stored in the indexes metadata table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexInfo {
+    /// Name of the index or of the index family.
+    pub name: String,
+    /// Type of the index.
+    pub index_type: IndexType,
+    /// Whether the entry describes an index family.
+    pub is_family: bool,
+}
 
-    fn field_size() -> Offset {
-        u8::field_size()
-    }
+/// Returns information about all the indexes created in the given storage view
+/// in ascending order of their names.
+pub fn list_indexes(view: &Snapshot) -> Vec<IndexInfo> {
+    let metadata = BaseIndex::indexes_metadata(view);
+    let indexes = metadata
+        .iter::<_, String, IndexMetadata>(&())
+        .map(|(name, value)| IndexInfo {
+            name,
+            index_type: value.index_type(),
+            is_family: value.is_family(),
+        })
+        .collect();
+    indexes
+}
 
-    fn check(
-        buffer: &'a [u8],
-        from: CheckedOffset,
-        to: CheckedOffset,
-        latest_segment: CheckedOffset,
-    ) -> ::std::result::Result<CheckedOffset, EncodingError> {
-        u8::check(buffer, from, to, latest_segment)
-    }
+/// Returns information about the index with the given name, or `None` if the index
+/// has not been created in the given storage view.
+pub fn index_info(view: &Snapshot, name: &str) -> Option<IndexInfo> {
+    let metadata = BaseIndex::indexes_metadata(view);
+    metadata
+        .get::<_, IndexMetadata>(name)
+        .map(|value| IndexInfo {
+            name: name.to_owned(),
+            index_type: value.index_type(),
+            is_family: value.is_family(),
+        })
 }
 
 pub fn assert_index_type(name: &str, index_type: IndexType, is_family: bool, view: &Snapshot) {
@@ -144,9 +205,9 @@ pub fn set_index_type(name: &str, index_type: IndexType, is_family: bool, view:
 
 #[cfg(test)]
 mod tests {
-    use super::{IndexMetadata, IndexType, INDEXES_METADATA_TABLE_NAME};
-    use crypto::{Hash, PublicKey};
-    use storage::{Database, MapIndex, MemoryDB, ProofMapIndex};
+    use super::{list_indexes, IndexInfo, IndexMetadata, IndexType, INDEXES_METADATA_TABLE_NAME};
+    use crypto::Hash;
+    use storage::{Database, ListIndex, MapIndex, MemoryDB, ProofMapIndex};
 
     #[test]
     fn index_metadata_roundtrip() {
@@ -163,6 +224,41 @@ mod tests {
         }
     }
 
+    #[test]
+    fn list_created_indexes() {
+        let database = MemoryDB::new();
+        let mut fork = database.fork();
+        {
+            let mut index = MapIndex::new("b_map", &mut fork);
+            index.put(&1_u8, 1_u8);
+        }
+        {
+            let mut index = ListIndex::new_in_family("a_lists", &1_u8, &mut fork);
+            index.push(1_u8);
+        }
+        {
+            // Read-only access doesn't create an index.
+            let index: MapIndex<_, u8, u8> = MapIndex::new("c_map", &mut fork);
+            assert!(index.get(&1).is_none());
+        }
+
+        assert_eq!(
+            list_indexes(&fork),
+            vec![
+                IndexInfo {
+                    name: "a_lists".to_owned(),
+                    index_type: IndexType::List,
+                    is_family: true,
+                },
+                IndexInfo {
+                    name: "b_map".to_owned(),
+                    index_type: IndexType::Map,
+                    is_family: false,
+                },
+            ]
+        );
+    }
+
     #[test]
     fn access_indexes_metadata() {
         let database = MemoryDB::new();
@@ -190,10 +286,10 @@ mod tests {
         let mut fork = database.fork();
         {
             let mut index = ProofMapIndex::new("test_index", &mut fork);
-            index.put(&PublicKey::zero(), 42);
+            index.put(&Hash::zero(), 42);
         }
 
-        let _: MapIndex<_, PublicKey, i32> = MapIndex::new("test_index", &mut fork);
+        let _: MapIndex<_, Hash, i32> = MapIndex::new("test_index", &mut fork);
     }
 
     #[test]
@@ -202,10 +298,10 @@ mod tests {
         let mut fork = database.fork();
         {
             let mut index = ProofMapIndex::new("test_index", &mut fork);
-            index.put(&PublicKey::zero(), 42);
+            index.put(&Hash::zero(), 42);
         }
 
-        let _: ProofMapIndex<_, PublicKey, i32> = ProofMapIndex::new("test_index", &mut fork);
+        let _: ProofMapIndex<_, Hash, i32> = ProofMapIndex::new("test_index", &mut fork);
     }
 
     #[test]
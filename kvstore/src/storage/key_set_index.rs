@@ -17,7 +17,8 @@
 use std::marker::PhantomData;
 use std::borrow::Borrow;
 
-use super::{BaseIndex, BaseIndexIter, Fork, Snapshot, StorageKey};
+use super::{BaseIndex, BaseIndexKeysIter, IndexAccess, IndexAccessMut, StorageKey, StorageKeyRef};
+use super::bloom_filter::{BloomFilter, BloomFilterCache};
 use super::indexes_metadata::IndexType;
 
 /// A set of items that implement `StorageKey` trait.
@@ -25,10 +26,22 @@ use super::indexes_metadata::IndexType;
 /// `KeySetIndex` implements a set, storing the elements as keys with empty values.
 /// `KeySetIndex` requires that the elements implement the [`StorageKey`] trait.
 ///
+/// The set may have a bloom filter, created with [`create_filter`], which lets [`contains`]
+/// answer for most of the absent elements without reading the storage. The filter is stored
+/// in the column family of the set and is updated on insertions; it is loaded into memory on
+/// the first lookup and shared by the index instances of the thread. Removed elements
+/// remain in the filter, so the filter becomes less selective over time; use
+/// [`is_filter_stale`] and [`rebuild_filter`] to keep it accurate.
+///
 /// [`StorageKey`]: ../trait.StorageKey.html
+/// [`create_filter`]: #method.create_filter
+/// [`contains`]: #method.contains
+/// [`is_filter_stale`]: #method.is_filter_stale
+/// [`rebuild_filter`]: #method.rebuild_filter
 #[derive(Debug)]
 pub struct KeySetIndex<T, K> {
     base: BaseIndex<T>,
+    filter: BloomFilterCache,
     _k: PhantomData<K>,
 }
 
@@ -42,12 +55,12 @@ pub struct KeySetIndex<T, K> {
 /// [`KeySetIndex`]: struct.KeySetIndex.html
 #[derive(Debug)]
 pub struct KeySetIndexIter<'a, K> {
-    base_iter: BaseIndexIter<'a, K, ()>,
+    base_iter: BaseIndexKeysIter<'a, K>,
 }
 
 impl<T, K> KeySetIndex<T, K>
 where
-    T: AsRef<Snapshot>,
+    T: IndexAccess,
     K: StorageKey,
 {
     /// Creates a new index representation based on the name and storage view.
@@ -71,7 +84,8 @@ where
     /// ```
     pub fn new<S: AsRef<str>>(index_name: S, view: T) -> Self {
         KeySetIndex {
-            base: BaseIndex::new(index_name, IndexType::KeySet, view),
+            base: BaseIndex::new(index_name, IndexType::KeySet, view).with_auxiliary_data(),
+            filter: BloomFilterCache::default(),
             _k: PhantomData,
         }
     }
@@ -103,11 +117,22 @@ where
         view: T,
     ) -> Self {
         KeySetIndex {
-            base: BaseIndex::new_in_family(family_name, index_id, IndexType::KeySet, view),
+            base: BaseIndex::new_in_family(family_name, index_id, IndexType::KeySet, view)
+                .with_auxiliary_data(),
+            filter: BloomFilterCache::default(),
             _k: PhantomData,
         }
     }
 
+    /// Returns the storage view of the index, consuming the index.
+    pub fn into_view(self) -> T {
+        self.base.into_view()
+    }
+
+    pub(crate) fn base(&self) -> &BaseIndex<T> {
+        &self.base
+    }
+
     /// Returns `true` if the set contains a value.
     ///
     /// # Examples
@@ -129,7 +154,52 @@ where
         K: Borrow<Q>,
         Q: StorageKey + ?Sized,
     {
-        self.base.contains(item)
+        self.filter.may_contain(&self.base, item) && self.base.contains(item)
+    }
+
+    /// Returns `true` if the set has a bloom filter.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, KeySetIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let name = "name";
+    /// let mut fork = db.fork();
+    /// let mut index: KeySetIndex<_, u8> = KeySetIndex::new(name, &mut fork);
+    /// assert!(!index.has_filter());
+    ///
+    /// index.create_filter(1000, 0.01);
+    /// assert!(index.has_filter());
+    /// ```
+    pub fn has_filter(&self) -> bool {
+        self.filter.is_enabled(&self.base)
+    }
+
+    /// Returns `true` if the expected false positive rate of the bloom filter has grown to
+    /// more than twice the rate the filter was created with, because more elements were
+    /// inserted than the filter was sized for, or because of the removed elements remaining
+    /// in the filter. Returns `false` if the set has no filter.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, KeySetIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let name = "name";
+    /// let mut fork = db.fork();
+    /// let mut index = KeySetIndex::new(name, &mut fork);
+    /// index.create_filter(10, 0.01);
+    ///
+    /// for i in 0..100_u8 {
+    ///     index.insert(i);
+    /// }
+    /// assert!(index.is_filter_stale());
+    /// ```
+    pub fn is_filter_stale(&self) -> bool {
+        self.filter.is_stale(&self.base)
     }
 
     /// An iterator visiting all elements in ascending order. The iterator element type is K.
@@ -150,7 +220,7 @@ where
     /// ```
     pub fn iter(&self) -> KeySetIndexIter<K> {
         KeySetIndexIter {
-            base_iter: self.base.iter(&()),
+            base_iter: self.base.iter_keys(&()),
         }
     }
 
@@ -173,13 +243,14 @@ where
     /// ```
     pub fn iter_from(&self, from: &K) -> KeySetIndexIter<K> {
         KeySetIndexIter {
-            base_iter: self.base.iter_from(&(), from),
+            base_iter: self.base.iter_keys_from(&(), from),
         }
     }
 }
 
-impl<'a, K> KeySetIndex<&'a mut Fork, K>
+impl<T, K> KeySetIndex<T, K>
 where
+    T: IndexAccessMut,
     K: StorageKey,
 {
     /// Adds a value to the set.
@@ -199,6 +270,7 @@ where
     /// ```
     #[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
     pub fn insert(&mut self, item: K) {
+        self.filter.insert(&mut self.base, &item);
         self.base.put(&item, ())
     }
 
@@ -252,13 +324,99 @@ where
     /// assert!(!index.contains(&1));
     /// ```
     pub fn clear(&mut self) {
+        self.filter.clear(&mut self.base);
         self.base.clear()
     }
+
+    /// Creates a bloom filter sized for the specified number of elements and false positive
+    /// rate, and fills it with the elements of the set. Replaces the existing filter, if any.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is zero or `false_positive_rate` is not between 0 and 1.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, KeySetIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let name = "name";
+    /// let mut fork = db.fork();
+    /// let mut index = KeySetIndex::new(name, &mut fork);
+    ///
+    /// index.insert(1);
+    /// index.create_filter(1000, 0.01);
+    /// assert!(index.contains(&1));
+    /// assert!(!index.contains(&2));
+    /// ```
+    pub fn create_filter(&mut self, capacity: u64, false_positive_rate: f64) {
+        let filter = {
+            let keys = self.base.iter_keys::<_, Vec<u8>>(&());
+            BloomFilter::build(capacity, false_positive_rate, keys)
+        };
+        self.filter.replace(&mut self.base, filter);
+    }
+
+    /// Rebuilds the bloom filter from the elements of the set, dropping the removed elements
+    /// from it. The filter keeps its capacity and false positive rate; if the set has outgrown
+    /// the capacity, use [`create_filter`] instead. Returns `false` if the set has no filter.
+    ///
+    /// [`create_filter`]: #method.create_filter
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, KeySetIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let name = "name";
+    /// let mut fork = db.fork();
+    /// let mut index = KeySetIndex::new(name, &mut fork);
+    /// assert!(!index.rebuild_filter());
+    ///
+    /// index.create_filter(10, 0.01);
+    /// for i in 0..100_u8 {
+    ///     index.insert(i);
+    ///     index.remove(&i);
+    /// }
+    /// assert!(index.is_filter_stale());
+    /// assert!(index.rebuild_filter());
+    /// assert!(!index.is_filter_stale());
+    /// ```
+    pub fn rebuild_filter(&mut self) -> bool {
+        let (capacity, false_positive_rate) = match self.filter.parameters(&self.base) {
+            Some(parameters) => parameters,
+            None => return false,
+        };
+        self.create_filter(capacity, false_positive_rate);
+        true
+    }
+
+    /// Removes the bloom filter of the set, if any.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, KeySetIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let name = "name";
+    /// let mut fork = db.fork();
+    /// let mut index: KeySetIndex<_, u8> = KeySetIndex::new(name, &mut fork);
+    ///
+    /// index.create_filter(1000, 0.01);
+    /// index.remove_filter();
+    /// assert!(!index.has_filter());
+    /// ```
+    pub fn remove_filter(&mut self) {
+        self.filter.remove(&mut self.base);
+    }
 }
 
 impl<'a, T, K> ::std::iter::IntoIterator for &'a KeySetIndex<T, K>
 where
-    T: AsRef<Snapshot>,
+    T: IndexAccess,
     K: StorageKey,
 {
     type Item = K::Owned;
@@ -276,7 +434,18 @@ where
     type Item = K::Owned;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.base_iter.next().map(|(k, ..)| k)
+        self.base_iter.next()
+    }
+}
+
+impl<'a, K> KeySetIndexIter<'a, K> {
+    /// Advances the iterator and returns the next item borrowed from the storage
+    /// without allocating an owned item.
+    pub fn next_ref<'b, RK>(&'b mut self) -> Option<RK>
+    where
+        RK: StorageKeyRef<'b>,
+    {
+        self.base_iter.next_ref()
     }
 }
 
@@ -320,4 +489,118 @@ mod tests {
         index.remove(KEY);
         assert_eq!(false, index.contains(KEY));
     }
+
+    #[test]
+    fn filter_is_updated_and_persisted() {
+        let db = MemoryDB::new();
+        let mut fork = db.fork();
+        {
+            let mut index = KeySetIndex::new(INDEX_NAME, &mut fork);
+            index.insert(1_u32);
+            index.create_filter(100, 0.01);
+            index.insert(2);
+            assert!(index.contains(&1));
+            assert!(index.contains(&2));
+            assert!(!index.is_filter_stale());
+        }
+        db.merge(fork.into_patch()).unwrap();
+
+        let snapshot = db.snapshot();
+        let index: KeySetIndex<_, u32> = KeySetIndex::new(INDEX_NAME, &snapshot);
+        assert!(index.has_filter());
+        assert!(index.contains(&1));
+        assert!(index.contains(&2));
+        assert!(!index.contains(&3));
+    }
+
+    #[test]
+    fn filter_answers_definite_misses() {
+        let db = MemoryDB::new();
+        let mut fork = db.fork();
+        {
+            let mut index: KeySetIndex<_, Vec<u8>> = KeySetIndex::new(INDEX_NAME, &mut fork);
+            index.create_filter(100, 0.01);
+        }
+        // The item is written bypassing the index, so the filter doesn't know about it.
+        fork.put(INDEX_NAME, vec![0, 1, 2, 3], vec![]);
+        {
+            let index: KeySetIndex<_, Vec<u8>> = KeySetIndex::new(INDEX_NAME, &mut fork);
+            assert!(!index.contains(&vec![1, 2, 3]));
+        }
+        let mut index: KeySetIndex<_, Vec<u8>> = KeySetIndex::new(INDEX_NAME, &mut fork);
+        index.rebuild_filter();
+        assert!(index.contains(&vec![1, 2, 3]));
+    }
+
+    #[test]
+    fn filter_of_family_index() {
+        let db = MemoryDB::new();
+        let mut fork = db.fork();
+        {
+            let mut index = KeySetIndex::new_in_family(INDEX_NAME, &1_u8, &mut fork);
+            index.insert(1_u32);
+            index.create_filter(100, 0.01);
+        }
+        {
+            let mut index = KeySetIndex::new_in_family(INDEX_NAME, &2_u8, &mut fork);
+            assert!(!index.has_filter());
+            index.insert(2_u32);
+        }
+        let mut index = KeySetIndex::new_in_family(INDEX_NAME, &1_u8, &mut fork);
+        assert!(index.contains(&1_u32));
+        assert!(!index.contains(&2));
+
+        index.clear();
+        assert!(index.has_filter());
+        assert!(!index.contains(&1));
+        index.insert(3);
+        assert!(index.contains(&3));
+
+        index.remove_filter();
+        assert!(!index.has_filter());
+        assert!(index.contains(&3));
+    }
+
+    #[test]
+    fn filter_is_copied_with_the_set() {
+        use storage::diff;
+
+        let db = MemoryDB::new();
+        let mut fork = db.fork();
+        {
+            let mut index = KeySetIndex::new(INDEX_NAME, &mut fork);
+            index.create_filter(100, 0.01);
+            index.insert(1_u32);
+        }
+        db.merge(fork.into_patch()).unwrap();
+
+        let copy = MemoryDB::new();
+        copy.merge(diff(&*copy.snapshot(), &*db.snapshot())).unwrap();
+        let snapshot = copy.snapshot();
+        let index: KeySetIndex<_, u32> = KeySetIndex::new(INDEX_NAME, &snapshot);
+        assert!(index.has_filter());
+        assert!(index.contains(&1));
+        assert!(!index.contains(&2));
+    }
+
+    #[test]
+    fn concurrent_filter_updates_conflict() {
+        let db = MemoryDB::new();
+        let mut fork = db.fork();
+        KeySetIndex::<_, u32>::new(INDEX_NAME, &mut fork).create_filter(100, 0.01);
+        db.merge(fork.into_patch()).unwrap();
+
+        let mut first = db.fork();
+        let mut second = db.fork();
+        KeySetIndex::new(INDEX_NAME, &mut first).insert(1_u32);
+        KeySetIndex::new(INDEX_NAME, &mut second).insert(2_u32);
+        db.merge(first.into_patch()).unwrap();
+        let err = db.merge(second.into_patch()).unwrap_err();
+        assert!(err.is_conflict());
+
+        let snapshot = db.snapshot();
+        let index: KeySetIndex<_, u32> = KeySetIndex::new(INDEX_NAME, &snapshot);
+        assert!(index.contains(&1));
+        assert!(!index.contains(&2));
+    }
 }
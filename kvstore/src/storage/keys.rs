@@ -177,19 +177,78 @@ storage_key_for_ints!{u16, i16, 2, read_u16, write_u16}
 storage_key_for_ints!{u32, i32, 4, read_u32, write_u32}
 storage_key_for_ints!{u64, i64, 8, read_u64, write_u64}
 
-//impl StorageKey for Hash {
-//    fn size(&self) -> usize {
-//        HASH_SIZE
-//    }
-//
-//    fn write(&self, buffer: &mut [u8]) {
-//        buffer.copy_from_slice(self.as_ref())
-//    }
-//
-//    fn read(buffer: &[u8]) -> Self::Owned {
-//        Hash::from_slice(buffer).unwrap()
-//    }
-//}
+/// A 32-bit floating point number, which can be used as a storage key.
+///
+/// The number is encoded so that the serialized keys are sorted in the natural order: from
+/// the negative infinity to the positive one, with `-0.0` preceding `0.0`. NaNs are sorted
+/// before the negative infinity or after the positive one, depending on their sign bit.
+///
+/// # Examples
+///
+/// ```
+/// use exonum::storage::{StorageKey, F32};
+///
+/// let (mut x, mut y) = (vec![0; 4], vec![0; 4]);
+/// F32(-1.5).write(&mut x);
+/// F32(0.25).write(&mut y);
+/// assert!(x < y);
+/// assert_eq!(F32::read(&x), F32(-1.5));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Default)]
+pub struct F32(pub f32);
+
+/// A 64-bit floating point number, which can be used as a storage key.
+///
+/// The number is encoded in the same way as [`F32`](struct.F32.html), so that the serialized
+/// keys are sorted in the natural order.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Default)]
+pub struct F64(pub f64);
+
+macro_rules! storage_key_for_floats {
+    (
+        $type:ident, $float:ident, $utype:ident, $size:expr,
+        $read_method:ident, $write_method:ident
+    ) => {
+        /// Uses big-endian encoding of the bits with the sign bit flipped for the positive
+        /// numbers and all bits flipped for the negative ones.
+        impl StorageKey for $type {
+            fn size(&self) -> usize {
+                $size
+            }
+
+            fn write(&self, buffer: &mut [u8]) {
+                const SIGN_BIT: $utype = 1 << ($size * 8 - 1);
+                let bits = self.0.to_bits();
+                let bits = if bits & SIGN_BIT == 0 { bits | SIGN_BIT } else { !bits };
+                BigEndian::$write_method(buffer, bits);
+            }
+
+            fn read(buffer: &[u8]) -> Self {
+                const SIGN_BIT: $utype = 1 << ($size * 8 - 1);
+                let bits = BigEndian::$read_method(buffer);
+                let bits = if bits & SIGN_BIT == 0 { !bits } else { bits & !SIGN_BIT };
+                $type($float::from_bits(bits))
+            }
+        }
+    }
+}
+
+storage_key_for_floats!{F32, f32, u32, 4, read_u32, write_u32}
+storage_key_for_floats!{F64, f64, u64, 8, read_u64, write_u64}
+
+impl StorageKey for Hash {
+    fn size(&self) -> usize {
+        HASH_SIZE
+    }
+
+    fn write(&self, buffer: &mut [u8]) {
+        buffer.copy_from_slice(self.as_ref())
+    }
+
+    fn read(buffer: &[u8]) -> Self::Owned {
+        Hash::from_slice(buffer).unwrap()
+    }
+}
 
 //impl StorageKey for PublicKey {
 //    fn size(&self) -> usize {
@@ -299,6 +358,86 @@ impl StorageKey for Uuid {
     }
 }
 
+/// A type of keys that can be decoded from a slice borrowed from the storage without copying.
+///
+/// The trait is implemented for byte slices and strings, which borrow the key bytes,
+/// and for the primitive types, which are decoded as per their `StorageKey` implementation.
+/// It is used by the `next_ref` methods of the index iterators.
+pub trait StorageKeyRef<'a>: Sized {
+    /// Deserializes a key from the buffer borrowed from the storage.
+    fn read_ref(buffer: &'a [u8]) -> Self;
+}
+
+impl<'a> StorageKeyRef<'a> for &'a [u8] {
+    fn read_ref(buffer: &'a [u8]) -> Self {
+        buffer
+    }
+}
+
+/// Uses UTF-8 string serialization.
+///
+/// # Panics
+///
+/// Panics if the key is not a valid UTF-8 string, which may happen if the keys of the index
+/// are not strings.
+impl<'a> StorageKeyRef<'a> for &'a str {
+    fn read_ref(buffer: &'a [u8]) -> Self {
+        ::std::str::from_utf8(buffer)
+            .unwrap_or_else(|e| panic!("Key is not a valid UTF-8 string: {}", e))
+    }
+}
+
+macro_rules! storage_key_ref_for_primitives {
+    ($($type:ty),*) => {
+        $(
+            impl<'a> StorageKeyRef<'a> for $type {
+                fn read_ref(buffer: &'a [u8]) -> Self {
+                    <$type as StorageKey>::read(buffer)
+                }
+            }
+        )*
+    }
+}
+
+storage_key_ref_for_primitives!{(), u8, i8, u16, i16, u32, i32, u64, i64, F32, F64}
+
+/// Appends the bytes to the buffer so that no escaped sequence is a prefix of another one
+/// and the order of escaped sequences is the same as the order of the original ones.
+///
+/// Zero bytes are escaped as `[0, 0xFF]`, and the sequence is terminated with `[0, 1]`.
+/// Thus, a serialized key of variable length can be followed by other data in a composite key
+/// without breaking the ordering and prefix scans by the first key.
+pub(crate) fn write_escaped(bytes: &[u8], buffer: &mut Vec<u8>) {
+    for &byte in bytes {
+        buffer.push(byte);
+        if byte == 0 {
+            buffer.push(0xFF);
+        }
+    }
+    buffer.extend_from_slice(&[0, 1]);
+}
+
+/// Returns the position right after the escaped sequence starting at `start`.
+pub(crate) fn skip_escaped(bytes: &[u8], start: usize) -> usize {
+    let mut pos = start;
+    while bytes[pos] != 0 || bytes[pos + 1] != 1 {
+        pos += if bytes[pos] == 0 { 2 } else { 1 };
+    }
+    pos + 2
+}
+
+/// Reads the escaped sequence starting at `start` and returns the original bytes together
+/// with the position right after the sequence.
+pub(crate) fn read_escaped(bytes: &[u8], start: usize) -> (Vec<u8>, usize) {
+    let mut unescaped = Vec::new();
+    let mut pos = start;
+    while bytes[pos] != 0 || bytes[pos + 1] != 1 {
+        unescaped.push(bytes[pos]);
+        pos += if bytes[pos] == 0 { 2 } else { 1 };
+    }
+    (unescaped, pos + 2)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -364,6 +503,59 @@ mod tests {
         }
     }
 
+    #[test]
+    fn escaping_preserves_order() {
+        let keys: &[&[u8]] = &[b"", b"\x00", b"\x00\x00", b"\x00\x01", b"a", b"a\x00", b"ab"];
+        let escaped = keys.iter()
+            .map(|key| {
+                let mut buffer = Vec::new();
+                write_escaped(key, &mut buffer);
+                buffer
+            })
+            .collect::<Vec<_>>();
+        for (i, first) in escaped.iter().enumerate() {
+            for second in &escaped[i + 1..] {
+                assert!(first < second);
+                assert!(!second.starts_with(first));
+            }
+            assert_eq!(skip_escaped(first, 0), first.len());
+        }
+        for (key, escaped) in keys.iter().zip(&escaped) {
+            assert_eq!(read_escaped(escaped, 0), (key.to_vec(), escaped.len()));
+        }
+    }
+
+    #[test]
+    fn storage_key_for_floats() {
+        let values = [
+            ::std::f64::NEG_INFINITY,
+            ::std::f64::MIN,
+            -1.5,
+            -::std::f64::MIN_POSITIVE,
+            -0.0,
+            0.0,
+            ::std::f64::MIN_POSITIVE,
+            0.25,
+            ::std::f64::MAX,
+            ::std::f64::INFINITY,
+        ];
+        let (mut x_buffer, mut y_buffer) = ([0u8; 8], [0u8; 8]);
+        for w in values.windows(2) {
+            F64(w[0]).write(&mut x_buffer);
+            F64(w[1]).write(&mut y_buffer);
+            assert!(x_buffer < y_buffer);
+            assert_eq!(F64::read(&x_buffer).0.to_bits(), w[0].to_bits());
+        }
+
+        let (mut x_buffer, mut y_buffer) = ([0u8; 4], [0u8; 4]);
+        for w in values.windows(2) {
+            F32(w[0] as f32).write(&mut x_buffer);
+            F32(w[1] as f32).write(&mut y_buffer);
+            assert!(x_buffer <= y_buffer);
+            assert_eq!(F32::read(&x_buffer).0.to_bits(), (w[0] as f32).to_bits());
+        }
+    }
+
     test_storage_key_for_int_type!{full  u8, 1 => test_storage_key_for_u8}
     test_storage_key_for_int_type!{full  i8, 1 => test_storage_key_for_i8}
     test_storage_key_for_int_type!{full u16, 2 => test_storage_key_for_u16}
@@ -587,4 +779,23 @@ mod tests {
     fn get_buffer<T: StorageKey + ?Sized>(key: &T) -> Vec<u8> {
         vec![0; key.size()]
     }
+
+    #[test]
+    fn borrowed_keys() {
+        let mut buffer = get_buffer("key");
+        "key".write(&mut buffer);
+        assert_eq!(<&str as StorageKeyRef>::read_ref(&buffer), "key");
+        assert_eq!(<&[u8] as StorageKeyRef>::read_ref(&buffer), b"key");
+
+        let mut buffer = get_buffer(&-5_i32);
+        (-5_i32).write(&mut buffer);
+        assert_eq!(<i32 as StorageKeyRef>::read_ref(&buffer), -5);
+    }
+
+    #[test]
+    #[should_panic(expected = "Key is not a valid UTF-8 string")]
+    fn borrowed_invalid_str_key() {
+        let buffer = [0xff, 0xfe];
+        <&str as StorageKeyRef>::read_ref(&buffer);
+    }
 }
@@ -17,7 +17,7 @@
 use std::cell::Cell;
 use std::marker::PhantomData;
 
-use super::{BaseIndex, BaseIndexIter, Fork, Snapshot, StorageKey, StorageValue};
+use super::{BaseIndex, BaseIndexIter, IndexAccess, IndexAccessMut, StorageKey, StorageValue};
 use super::indexes_metadata::IndexType;
 
 /// A list of items that implement `StorageValue` trait.
@@ -48,7 +48,7 @@ pub struct ListIndexIter<'a, V> {
 
 impl<T, V> ListIndex<T, V>
 where
-    T: AsRef<Snapshot>,
+    T: IndexAccess,
     V: StorageValue,
 {
     /// Creates a new index representation based on the name and storage view.
@@ -111,6 +111,11 @@ where
         }
     }
 
+    /// Returns the storage view of the index, consuming the index.
+    pub fn into_view(self) -> T {
+        self.base.into_view()
+    }
+
     /// Returns an element at that position or `None` if out of bounds.
     ///
     /// # Examples
@@ -252,8 +257,9 @@ where
     }
 }
 
-impl<'a, V> ListIndex<&'a mut Fork, V>
+impl<T, V> ListIndex<T, V>
 where
+    T: IndexAccessMut,
     V: StorageValue,
 {
     fn set_len(&mut self, len: u64) {
@@ -432,7 +438,7 @@ where
 
 impl<'a, T, V> ::std::iter::IntoIterator for &'a ListIndex<T, V>
 where
-    T: AsRef<Snapshot>,
+    T: IndexAccess,
     V: StorageValue,
 {
     type Item = V;
@@ -457,7 +463,8 @@ where
 #[cfg(test)]
 mod tests {
     use rand::{thread_rng, Rng};
-    use super::{Fork, ListIndex};
+    use storage::Fork;
+    use super::ListIndex;
 
     fn gen_tempdir_name() -> String {
         thread_rng().gen_ascii_chars().take(10).collect()
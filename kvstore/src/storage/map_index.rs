@@ -16,8 +16,10 @@
 
 use std::marker::PhantomData;
 use std::borrow::Borrow;
+use std::cmp::Ordering;
 
-use super::{BaseIndex, BaseIndexIter, Fork, Snapshot, StorageKey, StorageValue};
+use super::{BaseIndex, BaseIndexIter, BaseIndexKeysIter, IndexAccess, IndexAccessMut, StorageKey,
+            StorageKeyRef, StorageValue, StorageValueRef};
 use super::indexes_metadata::IndexType;
 
 /// A map of keys and values.
@@ -57,7 +59,7 @@ pub struct MapIndexIter<'a, K, V> {
 /// [`MapIndex`]: struct.MapIndex.html
 #[derive(Debug)]
 pub struct MapIndexKeys<'a, K> {
-    base_iter: BaseIndexIter<'a, K, ()>,
+    base_iter: BaseIndexKeysIter<'a, K>,
 }
 
 /// An iterator over the values of a `MapIndex`.
@@ -73,9 +75,58 @@ pub struct MapIndexValues<'a, V> {
     base_iter: BaseIndexIter<'a, (), V>,
 }
 
+/// A view into a single entry of a `MapIndex`, which may be either vacant or occupied.
+///
+/// This struct is created by the [`entry`] method on [`MapIndex`]. The value of the entry is
+/// read with [`Fork::get_for_update`], so merging the fork fails if the value is changed
+/// concurrently by another fork.
+///
+/// [`entry`]: struct.MapIndex.html#method.entry
+/// [`MapIndex`]: struct.MapIndex.html
+/// [`Fork::get_for_update`]: ../struct.Fork.html#method.get_for_update
+#[derive(Debug)]
+pub struct MapIndexEntry<'a, T: 'a, K: 'a, V: 'a> {
+    index: &'a mut MapIndex<T, K, V>,
+    key: &'a K,
+    value: Option<V>,
+}
+
+/// A difference in a single key between two states of a `MapIndex`.
+///
+/// The changes are produced by the [`diff`] method on [`MapIndex`].
+///
+/// [`diff`]: struct.MapIndex.html#method.diff
+/// [`MapIndex`]: struct.MapIndex.html
+#[derive(Debug, Clone, PartialEq)]
+pub enum MapIndexChange<K, V> {
+    /// The key is present only in the new state.
+    Added {
+        /// The added key.
+        key: K,
+        /// The value of the added key.
+        value: V,
+    },
+    /// The key is present only in the old state.
+    Removed {
+        /// The removed key.
+        key: K,
+        /// The value of the key in the old state.
+        value: V,
+    },
+    /// The key is present in both states with different values.
+    Changed {
+        /// The changed key.
+        key: K,
+        /// The value of the key in the old state.
+        old: V,
+        /// The value of the key in the new state.
+        new: V,
+    },
+}
+
 impl<T, K, V> MapIndex<T, K, V>
 where
-    T: AsRef<Snapshot>,
+    T: IndexAccess,
     K: StorageKey,
     V: StorageValue,
 {
@@ -140,6 +191,15 @@ where
         }
     }
 
+    /// Returns the storage view of the index, consuming the index.
+    pub fn into_view(self) -> T {
+        self.base.into_view()
+    }
+
+    pub(crate) fn base(&self) -> &BaseIndex<T> {
+        &self.base
+    }
+
     /// Returns a value corresponding to the key.
     ///
     /// # Examples
@@ -229,7 +289,7 @@ where
     /// ```
     pub fn keys(&self) -> MapIndexKeys<K> {
         MapIndexKeys {
-            base_iter: self.base.iter(&()),
+            base_iter: self.base.iter_keys(&()),
         }
     }
 
@@ -306,7 +366,7 @@ where
         Q: StorageKey + ?Sized,
     {
         MapIndexKeys {
-            base_iter: self.base.iter_from(&(), from),
+            base_iter: self.base.iter_keys_from(&(), from),
         }
     }
 
@@ -335,10 +395,73 @@ where
             base_iter: self.base.iter_from(&(), from),
         }
     }
+
+    /// Returns the changes which transform this map into the `other` one, in ascending
+    /// order of keys. Usually `self` and `other` are the same index in two different snapshots.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, MapIndex};
+    /// use exonum::storage::map_index::MapIndexChange;
+    ///
+    /// let db = MemoryDB::new();
+    /// let name = "name";
+    /// let old = db.snapshot();
+    /// let mut fork = db.fork();
+    /// MapIndex::new(name, &mut fork).put(&1_u8, 2_u8);
+    ///
+    /// let old_index: MapIndex<_, u8, u8> = MapIndex::new(name, &old);
+    /// let new_index: MapIndex<_, u8, u8> = MapIndex::new(name, &fork);
+    /// assert_eq!(
+    ///     old_index.diff(&new_index),
+    ///     vec![MapIndexChange::Added { key: 1, value: 2 }]
+    /// );
+    /// ```
+    pub fn diff<U>(&self, other: &MapIndex<U, K, V>) -> Vec<MapIndexChange<K::Owned, V>>
+    where
+        U: IndexAccess,
+        V: PartialEq,
+    {
+        // Entries are ordered by the serialized keys, so they are compared without decoding.
+        let mut old_iter = self.base.iter::<_, Vec<u8>, V>(&()).peekable();
+        let mut new_iter = other.base.iter::<_, Vec<u8>, V>(&()).peekable();
+        let mut changes = Vec::new();
+        loop {
+            let ordering = match (old_iter.peek(), new_iter.peek()) {
+                (None, None) => break,
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (Some(&(ref old_key, _)), Some(&(ref new_key, _))) => old_key.cmp(new_key),
+            };
+            match ordering {
+                Ordering::Less => {
+                    let (key, value) = old_iter.next().unwrap();
+                    let key = K::read(&key);
+                    changes.push(MapIndexChange::Removed { key, value });
+                }
+                Ordering::Greater => {
+                    let (key, value) = new_iter.next().unwrap();
+                    let key = K::read(&key);
+                    changes.push(MapIndexChange::Added { key, value });
+                }
+                Ordering::Equal => {
+                    let (key, old) = old_iter.next().unwrap();
+                    let (_, new) = new_iter.next().unwrap();
+                    if old != new {
+                        let key = K::read(&key);
+                        changes.push(MapIndexChange::Changed { key, old, new });
+                    }
+                }
+            }
+        }
+        changes
+    }
 }
 
-impl<'a, K, V> MapIndex<&'a mut Fork, K, V>
+impl<T, K, V> MapIndex<T, K, V>
 where
+    T: IndexAccessMut,
     K: StorageKey,
     V: StorageValue,
 {
@@ -410,11 +533,125 @@ where
     pub fn clear(&mut self) {
         self.base.clear()
     }
+
+    /// Returns the entry of the map for the specified key for in-place manipulation.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, MapIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let name = "name";
+    /// let mut fork = db.fork();
+    /// let mut index = MapIndex::new(name, &mut fork);
+    ///
+    /// assert_eq!(1, index.entry(&1).and_modify(|v| *v += 1).or_insert(1));
+    /// assert_eq!(2, index.entry(&1).and_modify(|v| *v += 1).or_insert(1));
+    /// assert_eq!(Some(2), index.get(&1));
+    /// ```
+    pub fn entry<'a>(&'a mut self, key: &'a K) -> MapIndexEntry<'a, T, K, V>
+    where
+        V: Clone,
+    {
+        let value = self.base.get_for_update(key);
+        MapIndexEntry {
+            index: self,
+            key,
+            value,
+        }
+    }
+
+    /// Changes the value for the specified key to the result of `f` applied to the current
+    /// value. If `f` returns `None`, the key is removed from the map.
+    ///
+    /// The value is read with [`Fork::get_for_update`], so merging the fork fails if the value
+    /// is changed concurrently by another fork.
+    ///
+    /// [`Fork::get_for_update`]: ../struct.Fork.html#method.get_for_update
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, MapIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let name = "name";
+    /// let mut fork = db.fork();
+    /// let mut index = MapIndex::new(name, &mut fork);
+    ///
+    /// index.put(&1, 2);
+    /// index.update(&1, |v| v.map(|v| v * 10));
+    /// assert_eq!(Some(20), index.get(&1));
+    ///
+    /// index.update(&1, |_| None);
+    /// assert!(!index.contains(&1));
+    /// ```
+    pub fn update<F>(&mut self, key: &K, f: F)
+    where
+        F: FnOnce(Option<V>) -> Option<V>,
+    {
+        let old = self.base.get_for_update(key);
+        match f(old) {
+            Some(new) => self.base.put(key, new),
+            None => self.base.remove(key),
+        }
+    }
+}
+
+impl<'a, T, K, V> MapIndexEntry<'a, T, K, V>
+where
+    T: IndexAccessMut,
+    K: StorageKey,
+    V: StorageValue + Clone,
+{
+    /// Returns the key of the entry.
+    pub fn key(&self) -> &K {
+        self.key
+    }
+
+    /// Returns the value of the entry, or `None` if the entry is vacant.
+    pub fn get(&self) -> Option<&V> {
+        self.value.as_ref()
+    }
+
+    /// Modifies the value of an occupied entry with `f` and stores it in the map. Does nothing
+    /// if the entry is vacant.
+    pub fn and_modify<F>(mut self, f: F) -> Self
+    where
+        F: FnOnce(&mut V),
+    {
+        if let Some(ref mut value) = self.value {
+            f(value);
+            self.index.put(self.key, value.clone());
+        }
+        self
+    }
+
+    /// Inserts `default` if the entry is vacant. Returns the value of the entry.
+    pub fn or_insert(self, default: V) -> V {
+        self.or_insert_with(|| default)
+    }
+
+    /// Inserts the result of `f` if the entry is vacant. Returns the value of the entry.
+    pub fn or_insert_with<F>(self, f: F) -> V
+    where
+        F: FnOnce() -> V,
+    {
+        match self.value {
+            Some(value) => value,
+            None => {
+                let value = f();
+                self.index.put(self.key, value.clone());
+                value
+            }
+        }
+    }
 }
 
 impl<'a, T, K, V> ::std::iter::IntoIterator for &'a MapIndex<T, K, V>
 where
-    T: AsRef<Snapshot>,
+    T: IndexAccess,
     K: StorageKey,
     V: StorageValue,
 {
@@ -445,7 +682,46 @@ where
     type Item = K::Owned;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.base_iter.next().map(|(k, ..)| k)
+        self.base_iter.next()
+    }
+}
+
+impl<'a, K, V> MapIndexIter<'a, K, V> {
+    /// Advances the iterator and returns the next entry borrowed from the storage
+    /// without allocating owned keys and values.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, MapIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let mut fork = db.fork();
+    /// MapIndex::new("name", &mut fork).put(&"key".to_owned(), "value".to_owned());
+    /// db.merge(fork.into_patch()).unwrap();
+    ///
+    /// let snapshot = db.snapshot();
+    /// let index: MapIndex<_, String, String> = MapIndex::new("name", &snapshot);
+    /// let mut iter = index.iter();
+    /// assert_eq!(iter.next_ref::<&str, &str>(), Some(("key", "value")));
+    /// ```
+    pub fn next_ref<'b, RK, RV>(&'b mut self) -> Option<(RK, RV)>
+    where
+        RK: StorageKeyRef<'b>,
+        RV: StorageValueRef<'b>,
+    {
+        self.base_iter.next_ref()
+    }
+}
+
+impl<'a, K> MapIndexKeys<'a, K> {
+    /// Advances the iterator and returns the next key borrowed from the storage
+    /// without allocating an owned key.
+    pub fn next_ref<'b, RK>(&'b mut self) -> Option<RK>
+    where
+        RK: StorageKeyRef<'b>,
+    {
+        self.base_iter.next_ref()
     }
 }
 
@@ -468,6 +744,49 @@ mod tests {
 
     const IDX_NAME: &'static str = "idx_name";
 
+    #[test]
+    fn diff_between_snapshots() {
+        let db = MemoryDB::new();
+        let mut fork = db.fork();
+        {
+            let mut index = MapIndex::new(IDX_NAME, &mut fork);
+            index.put(&"a".to_owned(), 1_u64);
+            index.put(&"b".to_owned(), 2_u64);
+            index.put(&"c".to_owned(), 3_u64);
+        }
+        db.merge(fork.into_patch()).unwrap();
+        let old = db.snapshot();
+
+        let mut fork = db.fork();
+        {
+            let mut index = MapIndex::new(IDX_NAME, &mut fork);
+            index.remove(&"a".to_owned());
+            index.put(&"b".to_owned(), 20_u64);
+            index.put(&"d".to_owned(), 4_u64);
+        }
+        let old_index: MapIndex<_, String, u64> = MapIndex::new(IDX_NAME, &old);
+        let new_index: MapIndex<_, String, u64> = MapIndex::new(IDX_NAME, &fork);
+        assert_eq!(
+            old_index.diff(&new_index),
+            vec![
+                MapIndexChange::Removed {
+                    key: "a".to_owned(),
+                    value: 1,
+                },
+                MapIndexChange::Changed {
+                    key: "b".to_owned(),
+                    old: 2,
+                    new: 20,
+                },
+                MapIndexChange::Added {
+                    key: "d".to_owned(),
+                    value: 4,
+                },
+            ]
+        );
+        assert!(new_index.diff(&new_index).is_empty());
+    }
+
     #[test]
     fn str_key() {
         let db = MemoryDB::new();
@@ -502,6 +821,70 @@ mod tests {
         assert_eq!(false, index.contains(KEY));
     }
 
+    #[test]
+    fn concurrent_updates() {
+        let db = MemoryDB::new();
+        let mut fork = db.fork();
+        MapIndex::new(IDX_NAME, &mut fork).put(&1_u8, 10_u64);
+        db.merge(fork.into_patch()).unwrap();
+
+        let mut first = db.fork();
+        let mut second = db.fork();
+        {
+            let mut index: MapIndex<_, u8, u64> = MapIndex::new(IDX_NAME, &mut first);
+            index.update(&1, |v| v.map(|v| v + 1));
+            assert_eq!(index.entry(&2).or_insert(5), 5);
+            assert_eq!(index.entry(&2).key(), &2);
+            assert_eq!(index.entry(&2).get(), Some(&5));
+        }
+        {
+            let mut index: MapIndex<_, u8, u64> = MapIndex::new(IDX_NAME, &mut second);
+            index.entry(&1).and_modify(|v| *v *= 2).or_insert(0);
+        }
+        db.merge(first.into_patch()).unwrap();
+        let error = db.merge(second.into_patch()).unwrap_err();
+        assert!(error.is_conflict());
+
+        // Retrying on a new fork succeeds.
+        let mut fork = db.fork();
+        MapIndex::new(IDX_NAME, &mut fork)
+            .entry(&1_u8)
+            .and_modify(|v: &mut u64| *v *= 2)
+            .or_insert(0);
+        db.merge(fork.into_patch()).unwrap();
+
+        let snapshot = db.snapshot();
+        let index: MapIndex<_, u8, u64> = MapIndex::new(IDX_NAME, &snapshot);
+        assert_eq!(index.iter().collect::<Vec<_>>(), vec![(1, 22), (2, 5)]);
+    }
+
+    #[test]
+    fn borrowed_iteration_in_family() {
+        let db = MemoryDB::new();
+        let mut fork = db.fork();
+        {
+            let mut index = MapIndex::new_in_family(IDX_NAME, &1_u8, &mut fork);
+            index.put(&vec![1, 2], "first".to_owned());
+            index.put(&vec![3], "second".to_owned());
+            let mut other = MapIndex::new_in_family(IDX_NAME, &2_u8, &mut fork);
+            other.put(&vec![0], "other".to_owned());
+        }
+        db.merge(fork.into_patch()).unwrap();
+
+        let snapshot = db.snapshot();
+        let index: MapIndex<_, Vec<u8>, String> =
+            MapIndex::new_in_family(IDX_NAME, &1_u8, &snapshot);
+        let mut iter = index.iter();
+        assert_eq!(iter.next_ref::<&[u8], &str>(), Some((&[1, 2][..], "first")));
+        assert_eq!(iter.next_ref::<&[u8], &str>(), Some((&[3][..], "second")));
+        assert_eq!(iter.next_ref::<&[u8], &str>(), None);
+
+        let mut keys = index.keys_from(&vec![2]);
+        assert_eq!(keys.next_ref::<&[u8]>(), Some(&[3][..]));
+        assert_eq!(keys.next_ref::<&[u8]>(), None);
+        assert_eq!(index.keys().collect::<Vec<_>>(), vec![vec![1, 2], vec![3]]);
+    }
+
     fn iter(db: Box<Database>) {
         let mut fork = db.fork();
         let mut map_index = MapIndex::new(IDX_NAME, &mut fork);
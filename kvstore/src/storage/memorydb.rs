@@ -17,11 +17,16 @@
 use std::sync::{Arc, RwLock};
 use std::clone::Clone;
 use std::collections::{BTreeMap, HashMap};
+use std::collections::Bound::{Included, Unbounded};
+use std::collections::btree_map::Range;
+use std::iter::Peekable;
 
 use super::{Database, Iter, Iterator, Patch, Result, Snapshot};
 use super::db::Change;
 
-type DB = HashMap<String, BTreeMap<Vec<u8>, Vec<u8>>>;
+type Table = BTreeMap<Vec<u8>, Vec<u8>>;
+type DB = HashMap<String, Arc<Table>>;
+type TableRange<'a> = Peekable<Range<'a, Vec<u8>, Vec<u8>>>;
 
 /// Database implementation that stores all the data in memory.
 ///
@@ -31,8 +36,21 @@ pub struct MemoryDB {
     map: RwLock<DB>,
 }
 
-/// An iterator over the entries of a `MemoryDB`.
-struct MemoryDBIter {
+/// A snapshot of a `MemoryDB`. Tables are shared with the database until they are modified.
+#[derive(Debug)]
+struct MemoryDBSnapshot {
+    map: DB,
+}
+
+/// An iterator over the entries of a `MemoryDB` snapshot, which borrows the entries
+/// from the snapshot.
+struct MemoryDBIter<'a> {
+    range: Option<TableRange<'a>>,
+    keys_only: bool,
+}
+
+/// An iterator over the entries of a `MemoryDB` copied from the database.
+struct MemoryDBCopyIter {
     data: Vec<(Vec<u8>, Vec<u8>)>,
     index: usize,
 }
@@ -48,22 +66,23 @@ impl MemoryDB {
 
 impl Database for MemoryDB {
     fn snapshot(&self) -> Box<Snapshot> {
-        Box::new(MemoryDB {
-            map: RwLock::new(self.map.read().unwrap().clone()),
+        Box::new(MemoryDBSnapshot {
+            map: self.map.read().unwrap().clone(),
         })
     }
 
     fn merge(&self, patch: Patch) -> Result<()> {
         let mut guard = self.map.write().unwrap();
+        if patch.has_reads() {
+            patch.check_reads(&MemoryDBSnapshot { map: guard.clone() })?;
+        }
         for (cf_name, changes) in patch {
-            if !guard.contains_key(&cf_name) {
-                guard.insert(cf_name.clone(), BTreeMap::new());
-            }
-            let table = guard.get_mut(&cf_name).unwrap();
+            // Tables shared with snapshots are copied before the modification.
+            let table = Arc::make_mut(guard.entry(cf_name).or_insert_with(Default::default));
             for (key, change) in changes {
                 match change {
-                    Change::Put(ref value) => {
-                        table.insert(key, value.to_vec());
+                    Change::Put(value) => {
+                        table.insert(key, value);
                     }
                     Change::Delete => {
                         table.remove(&key);
@@ -100,18 +119,86 @@ impl Snapshot for MemoryDB {
         let map_guard = self.map.read().unwrap();
         let data = match map_guard.get(name) {
             Some(table) => table
-                .iter()
-                .skip_while(|&(k, _)| k.as_slice() < from)
+                .range::<[u8], _>((Included(from), Unbounded))
                 .map(|(k, v)| (k.to_vec(), v.to_vec()))
                 .collect(),
             None => Vec::new(),
         };
 
-        Box::new(MemoryDBIter { data, index: 0 })
+        Box::new(MemoryDBCopyIter { data, index: 0 })
+    }
+
+    fn column_families(&self) -> Vec<String> {
+        let mut names = self.map.read().unwrap().keys().cloned().collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+}
+
+impl Snapshot for MemoryDBSnapshot {
+    fn get(&self, name: &str, key: &[u8]) -> Option<Vec<u8>> {
+        self.map.get(name).and_then(|table| table.get(key).cloned())
+    }
+
+    fn contains(&self, name: &str, key: &[u8]) -> bool {
+        self.map
+            .get(name)
+            .map_or(false, |table| table.contains_key(key))
+    }
+
+    fn iter<'a>(&'a self, name: &str, from: &[u8]) -> Iter<'a> {
+        Box::new(MemoryDBIter {
+            range: self.range(name, from),
+            keys_only: false,
+        })
+    }
+
+    fn iter_keys<'a>(&'a self, name: &str, from: &[u8]) -> Iter<'a> {
+        Box::new(MemoryDBIter {
+            range: self.range(name, from),
+            keys_only: true,
+        })
+    }
+
+    fn column_families(&self) -> Vec<String> {
+        let mut names = self.map.keys().cloned().collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+}
+
+impl MemoryDBSnapshot {
+    fn range<'a>(&'a self, name: &str, from: &[u8]) -> Option<TableRange<'a>> {
+        self.map.get(name).map(|table| {
+            table
+                .range::<[u8], _>((Included(from), Unbounded))
+                .peekable()
+        })
+    }
+}
+
+impl<'a> MemoryDBIter<'a> {
+    fn entry(&self, entry: (&'a Vec<u8>, &'a Vec<u8>)) -> (&'a [u8], &'a [u8]) {
+        let value: &[u8] = if self.keys_only { &[] } else { entry.1 };
+        (entry.0, value)
+    }
+}
+
+impl<'a> Iterator for MemoryDBIter<'a> {
+    fn next(&mut self) -> Option<(&[u8], &[u8])> {
+        let entry = self.range.as_mut().and_then(|range| range.next());
+        entry.map(|entry| self.entry(entry))
+    }
+
+    fn peek(&mut self) -> Option<(&[u8], &[u8])> {
+        let entry = self.range
+            .as_mut()
+            .and_then(|range| range.peek().cloned());
+        entry.map(|entry| self.entry(entry))
     }
 }
 
-impl Iterator for MemoryDBIter {
+impl Iterator for MemoryDBCopyIter {
     fn next(&mut self) -> Option<(&[u8], &[u8])> {
         if self.index < self.data.len() {
             self.index += 1;
@@ -0,0 +1,533 @@
+// Copyright 2018 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Lazy set operations over [`KeySetIndex`]es and joins of [`MapIndex`]es on keys.
+//!
+//! The operations merge the sorted streams of keys of the indexes without collecting them
+//! into memory. [`intersection`], [`difference`] and [`join_on_key`] are leapfrog joins:
+//! when a key is missing from one of the indexes, the other indexes skip directly to the next
+//! candidate key by seeking the storage instead of reading the keys in between, so the cost
+//! depends on the size of the result and of the smallest input rather than of the largest one.
+//!
+//! All the indexes must have the same key type, as the keys are compared by their
+//! serialized representation.
+//!
+//! [`KeySetIndex`]: ../key_set_index/struct.KeySetIndex.html
+//! [`MapIndex`]: ../map_index/struct.MapIndex.html
+//! [`intersection`]: fn.intersection.html
+//! [`difference`]: fn.difference.html
+//! [`join_on_key`]: fn.join_on_key.html
+
+use std::marker::PhantomData;
+
+use super::{BaseIndex, BaseIndexKeysIter, IndexAccess, KeySetIndex, MapIndex, StorageKey,
+            StorageValue};
+
+/// A cursor over the serialized keys of an index, which can skip forward to a key.
+struct KeyCursor<'a, T: 'a> {
+    base: &'a BaseIndex<T>,
+    iter: BaseIndexKeysIter<'a, Vec<u8>>,
+    current: Option<Vec<u8>>,
+}
+
+impl<'a, T> KeyCursor<'a, T>
+where
+    T: IndexAccess,
+{
+    fn new(base: &'a BaseIndex<T>) -> Self {
+        let mut iter = base.iter_keys(&());
+        let current = iter.next();
+        KeyCursor {
+            base,
+            iter,
+            current,
+        }
+    }
+
+    /// Returns the key the cursor points to, or `None` if the cursor is exhausted.
+    fn current(&self) -> Option<&[u8]> {
+        self.current.as_ref().map(|key| key.as_slice())
+    }
+
+    /// Moves the cursor to the next key.
+    fn advance(&mut self) {
+        if self.current.is_some() {
+            self.current = self.iter.next();
+        }
+    }
+
+    /// Moves the cursor to the first key which is greater than or equal to `target`.
+    /// Does nothing if the cursor already points to such a key.
+    fn seek(&mut self, target: &[u8]) {
+        let behind = match self.current {
+            Some(ref key) => key.as_slice() < target,
+            None => false,
+        };
+        if behind {
+            let base = self.base;
+            self.iter = base.iter_keys_from::<_, _, Vec<u8>>(&(), target);
+            self.current = self.iter.next();
+        }
+    }
+}
+
+impl<'a, T> ::std::fmt::Debug for KeyCursor<'a, T> {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        f.debug_struct("KeyCursor")
+            .field("current", &self.current)
+            .finish()
+    }
+}
+
+/// A lazy iterator over the union of `KeySetIndex`es.
+///
+/// This struct is created by the [`union`] function. See its documentation for more.
+///
+/// [`union`]: fn.union.html
+#[derive(Debug)]
+pub struct Union<'a, T: 'a, K> {
+    cursors: Vec<KeyCursor<'a, T>>,
+    _k: PhantomData<K>,
+}
+
+/// A lazy iterator over the intersection of `KeySetIndex`es.
+///
+/// This struct is created by the [`intersection`] function. See its documentation for more.
+///
+/// [`intersection`]: fn.intersection.html
+#[derive(Debug)]
+pub struct Intersection<'a, T: 'a, K> {
+    cursors: Vec<KeyCursor<'a, T>>,
+    /// Cursor which was advanced past the last returned key.
+    position: usize,
+    _k: PhantomData<K>,
+}
+
+/// A lazy iterator over the difference of `KeySetIndex`es.
+///
+/// This struct is created by the [`difference`] function. See its documentation for more.
+///
+/// [`difference`]: fn.difference.html
+#[derive(Debug)]
+pub struct Difference<'a, T: 'a, K> {
+    minuend: KeyCursor<'a, T>,
+    subtrahends: Vec<KeyCursor<'a, T>>,
+    _k: PhantomData<K>,
+}
+
+/// A lazy iterator over the entries of two `MapIndex`es with the same keys.
+///
+/// This struct is created by the [`join_on_key`] function. See its documentation for more.
+///
+/// [`join_on_key`]: fn.join_on_key.html
+#[derive(Debug)]
+pub struct JoinOnKey<'a, T1: 'a, T2: 'a, K, V1, V2> {
+    left: KeyCursor<'a, T1>,
+    right: KeyCursor<'a, T2>,
+    _k: PhantomData<K>,
+    _v: PhantomData<(V1, V2)>,
+}
+
+/// Returns a lazy iterator over the items contained in any of the sets, in ascending order
+/// and without duplicates.
+///
+/// # Examples
+///
+/// ```
+/// use exonum::storage::{self, MemoryDB, Database, KeySetIndex};
+///
+/// let db = MemoryDB::new();
+/// let mut fork = db.fork();
+/// KeySetIndex::new("a", &mut fork).insert(1_u8);
+/// KeySetIndex::new("b", &mut fork).insert(2_u8);
+/// KeySetIndex::new("b", &mut fork).insert(1_u8);
+/// db.merge(fork.into_patch()).unwrap();
+///
+/// let snapshot = db.snapshot();
+/// let a: KeySetIndex<_, u8> = KeySetIndex::new("a", &snapshot);
+/// let b: KeySetIndex<_, u8> = KeySetIndex::new("b", &snapshot);
+/// assert_eq!(vec![1, 2], storage::union(&[&a, &b]).collect::<Vec<_>>());
+/// ```
+pub fn union<'a, T, K>(sets: &[&'a KeySetIndex<T, K>]) -> Union<'a, T, K>
+where
+    T: IndexAccess,
+    K: StorageKey,
+{
+    Union {
+        cursors: sets.iter().map(|set| KeyCursor::new(set.base())).collect(),
+        _k: PhantomData,
+    }
+}
+
+/// Returns a lazy iterator over the items contained in all the sets, in ascending order.
+/// The intersection of no sets is empty.
+///
+/// # Examples
+///
+/// ```
+/// use exonum::storage::{self, MemoryDB, Database, KeySetIndex};
+///
+/// let db = MemoryDB::new();
+/// let mut fork = db.fork();
+/// for i in 0..10_u8 {
+///     KeySetIndex::new("a", &mut fork).insert(i);
+///     KeySetIndex::new("b", &mut fork).insert(i * 2);
+/// }
+/// db.merge(fork.into_patch()).unwrap();
+///
+/// let snapshot = db.snapshot();
+/// let a: KeySetIndex<_, u8> = KeySetIndex::new("a", &snapshot);
+/// let b: KeySetIndex<_, u8> = KeySetIndex::new("b", &snapshot);
+/// assert_eq!(
+///     vec![0, 2, 4, 6, 8],
+///     storage::intersection(&[&a, &b]).collect::<Vec<_>>()
+/// );
+/// ```
+pub fn intersection<'a, T, K>(sets: &[&'a KeySetIndex<T, K>]) -> Intersection<'a, T, K>
+where
+    T: IndexAccess,
+    K: StorageKey,
+{
+    Intersection {
+        cursors: sets.iter().map(|set| KeyCursor::new(set.base())).collect(),
+        position: 0,
+        _k: PhantomData,
+    }
+}
+
+/// Returns a lazy iterator over the items contained in `set`, but not in any of `others`,
+/// in ascending order.
+///
+/// # Examples
+///
+/// ```
+/// use exonum::storage::{self, MemoryDB, Database, KeySetIndex};
+///
+/// let db = MemoryDB::new();
+/// let mut fork = db.fork();
+/// for i in 0..5_u8 {
+///     KeySetIndex::new("a", &mut fork).insert(i);
+/// }
+/// KeySetIndex::new("b", &mut fork).insert(1_u8);
+/// KeySetIndex::new("c", &mut fork).insert(3_u8);
+/// db.merge(fork.into_patch()).unwrap();
+///
+/// let snapshot = db.snapshot();
+/// let a: KeySetIndex<_, u8> = KeySetIndex::new("a", &snapshot);
+/// let b: KeySetIndex<_, u8> = KeySetIndex::new("b", &snapshot);
+/// let c: KeySetIndex<_, u8> = KeySetIndex::new("c", &snapshot);
+/// assert_eq!(vec![0, 2, 4], storage::difference(&a, &[&b, &c]).collect::<Vec<_>>());
+/// ```
+pub fn difference<'a, T, K>(
+    set: &'a KeySetIndex<T, K>,
+    others: &[&'a KeySetIndex<T, K>],
+) -> Difference<'a, T, K>
+where
+    T: IndexAccess,
+    K: StorageKey,
+{
+    Difference {
+        minuend: KeyCursor::new(set.base()),
+        subtrahends: others
+            .iter()
+            .map(|set| KeyCursor::new(set.base()))
+            .collect(),
+        _k: PhantomData,
+    }
+}
+
+/// Returns a lazy iterator over the keys contained in both maps, in ascending order,
+/// together with the corresponding values of the maps. The iterator element type is
+/// (K, V1, V2).
+///
+/// The maps may be based on different storage views.
+///
+/// # Examples
+///
+/// ```
+/// use exonum::storage::{self, MemoryDB, Database, MapIndex};
+///
+/// let db = MemoryDB::new();
+/// let mut fork = db.fork();
+/// {
+///     let mut names = MapIndex::new("names", &mut fork);
+///     names.put(&1_u64, "Alice".to_owned());
+///     names.put(&2_u64, "Bob".to_owned());
+/// }
+/// MapIndex::new("balances", &mut fork).put(&2_u64, 100_u64);
+/// db.merge(fork.into_patch()).unwrap();
+///
+/// let snapshot = db.snapshot();
+/// let names: MapIndex<_, u64, String> = MapIndex::new("names", &snapshot);
+/// let balances: MapIndex<_, u64, u64> = MapIndex::new("balances", &snapshot);
+/// assert_eq!(
+///     vec![(2, "Bob".to_owned(), 100)],
+///     storage::join_on_key(&names, &balances).collect::<Vec<_>>()
+/// );
+/// ```
+pub fn join_on_key<'a, T1, T2, K, V1, V2>(
+    left: &'a MapIndex<T1, K, V1>,
+    right: &'a MapIndex<T2, K, V2>,
+) -> JoinOnKey<'a, T1, T2, K, V1, V2>
+where
+    T1: IndexAccess,
+    T2: IndexAccess,
+    K: StorageKey,
+    V1: StorageValue,
+    V2: StorageValue,
+{
+    JoinOnKey {
+        left: KeyCursor::new(left.base()),
+        right: KeyCursor::new(right.base()),
+        _k: PhantomData,
+        _v: PhantomData,
+    }
+}
+
+impl<'a, T, K> Iterator for Union<'a, T, K>
+where
+    T: IndexAccess,
+    K: StorageKey,
+{
+    type Item = K::Owned;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let min_key = {
+            let mut min_key: Option<&[u8]> = None;
+            for key in self.cursors.iter().filter_map(|cursor| cursor.current()) {
+                if min_key.map_or(true, |min_key| key < min_key) {
+                    min_key = Some(key);
+                }
+            }
+            match min_key {
+                Some(key) => key.to_vec(),
+                None => return None,
+            }
+        };
+        for cursor in &mut self.cursors {
+            if cursor.current() == Some(&min_key[..]) {
+                cursor.advance();
+            }
+        }
+        Some(K::read(&min_key))
+    }
+}
+
+impl<'a, T, K> Iterator for Intersection<'a, T, K>
+where
+    T: IndexAccess,
+    K: StorageKey,
+{
+    type Item = K::Owned;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let count = self.cursors.len();
+        if count == 0 {
+            return None;
+        }
+        let mut target = match self.cursors[self.position].current() {
+            Some(key) => key.to_vec(),
+            None => return None,
+        };
+        // Cycles through the cursors, moving each one to the current target; when a cursor
+        // skips past the target, its key becomes the new target.
+        let mut matched = 1;
+        let mut position = self.position;
+        while matched < count {
+            position = (position + 1) % count;
+            let cursor = &mut self.cursors[position];
+            cursor.seek(&target);
+            match cursor.current() {
+                Some(key) if key == &target[..] => matched += 1,
+                Some(key) => {
+                    target = key.to_vec();
+                    matched = 1;
+                }
+                None => return None,
+            }
+        }
+        self.cursors[position].advance();
+        self.position = position;
+        Some(K::read(&target))
+    }
+}
+
+impl<'a, T, K> Iterator for Difference<'a, T, K>
+where
+    T: IndexAccess,
+    K: StorageKey,
+{
+    type Item = K::Owned;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let key = match self.minuend.current() {
+                Some(key) => key.to_vec(),
+                None => return None,
+            };
+            self.minuend.advance();
+            let excluded = self.subtrahends.iter_mut().any(|cursor| {
+                cursor.seek(&key);
+                cursor.current() == Some(&key[..])
+            });
+            if !excluded {
+                return Some(K::read(&key));
+            }
+        }
+    }
+}
+
+impl<'a, T1, T2, K, V1, V2> Iterator for JoinOnKey<'a, T1, T2, K, V1, V2>
+where
+    T1: IndexAccess,
+    T2: IndexAccess,
+    K: StorageKey,
+    V1: StorageValue,
+    V2: StorageValue,
+{
+    type Item = (K::Owned, V1, V2);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let key = match self.left.current() {
+                Some(key) => key.to_vec(),
+                None => return None,
+            };
+            self.right.seek(&key);
+            let right_key = match self.right.current() {
+                Some(key) => key.to_vec(),
+                None => return None,
+            };
+            if right_key == key {
+                self.left.advance();
+                self.right.advance();
+                let left_value = self.left.base.get(&key[..]).expect("Joined value is missing");
+                let right_value = self.right.base.get(&key[..]).expect("Joined value is missing");
+                return Some((K::read(&key), left_value, right_value));
+            }
+            self.left.seek(&right_key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use storage::{Database, KeySetIndex, MapIndex, MemoryDB};
+    use super::{difference, intersection, join_on_key, union};
+
+    fn fill_sets(db: &MemoryDB, sets: &[(&str, Vec<u32>)]) {
+        let mut fork = db.fork();
+        for &(name, ref items) in sets {
+            let mut index = KeySetIndex::new(name, &mut fork);
+            for item in items {
+                index.insert(*item);
+            }
+        }
+        db.merge(fork.into_patch()).unwrap();
+    }
+
+    #[test]
+    fn set_operations() {
+        let db = MemoryDB::new();
+        fill_sets(
+            &db,
+            &[
+                ("a", vec![1, 3, 5, 7, 9, 11, 300]),
+                ("b", vec![3, 4, 5, 9, 10, 11, 300, 400]),
+                ("c", vec![0, 5, 9, 11, 12, 300]),
+                ("empty", vec![]),
+            ],
+        );
+        let snapshot = db.snapshot();
+        let a: KeySetIndex<_, u32> = KeySetIndex::new("a", &snapshot);
+        let b: KeySetIndex<_, u32> = KeySetIndex::new("b", &snapshot);
+        let c: KeySetIndex<_, u32> = KeySetIndex::new("c", &snapshot);
+        let empty: KeySetIndex<_, u32> = KeySetIndex::new("empty", &snapshot);
+
+        assert_eq!(
+            intersection(&[&a, &b, &c]).collect::<Vec<_>>(),
+            vec![5, 9, 11, 300]
+        );
+        assert_eq!(intersection(&[&a]).collect::<Vec<_>>(), vec![1, 3, 5, 7, 9, 11, 300]);
+        assert_eq!(intersection(&[&a, &empty]).count(), 0);
+        let no_sets: Vec<&KeySetIndex<_, u32>> = Vec::new();
+        assert_eq!(intersection(&no_sets).count(), 0);
+
+        assert_eq!(
+            union(&[&a, &c, &empty]).collect::<Vec<_>>(),
+            vec![0, 1, 3, 5, 7, 9, 11, 12, 300]
+        );
+        assert_eq!(union(&no_sets).count(), 0);
+
+        assert_eq!(difference(&a, &[&b]).collect::<Vec<_>>(), vec![1, 7]);
+        assert_eq!(difference(&b, &[&a, &c]).collect::<Vec<_>>(), vec![4, 10, 400]);
+        assert_eq!(difference(&a, &no_sets).count(), 7);
+        assert_eq!(difference(&empty, &[&a]).count(), 0);
+    }
+
+    #[test]
+    fn set_operations_on_families() {
+        let db = MemoryDB::new();
+        let mut fork = db.fork();
+        for id in 0..3_u8 {
+            let mut index = KeySetIndex::new_in_family("family", &id, &mut fork);
+            for item in 0..20_u32 {
+                if item % (u32::from(id) + 2) == 0 {
+                    index.insert(item);
+                }
+            }
+        }
+        db.merge(fork.into_patch()).unwrap();
+
+        let snapshot = db.snapshot();
+        let sets = (0..3_u8)
+            .map(|id| KeySetIndex::new_in_family("family", &id, &snapshot))
+            .collect::<Vec<KeySetIndex<_, u32>>>();
+        let refs = sets.iter().collect::<Vec<_>>();
+        assert_eq!(intersection(&refs).collect::<Vec<_>>(), vec![0, 12]);
+        assert_eq!(intersection(&refs[..2]).collect::<Vec<_>>(), vec![0, 6, 12, 18]);
+        assert_eq!(union(&refs[1..]).count(), 10);
+    }
+
+    #[test]
+    fn join_maps() {
+        let db = MemoryDB::new();
+        let mut fork = db.fork();
+        {
+            let mut index = MapIndex::new("left", &mut fork);
+            for i in 0..100_u32 {
+                index.put(&i, u64::from(i) * 10);
+            }
+        }
+        {
+            let mut index = MapIndex::new("right", &mut fork);
+            for i in (50..60_u32).chain(1000..1010) {
+                index.put(&i, i.to_string());
+            }
+        }
+        db.merge(fork.into_patch()).unwrap();
+
+        let snapshot = db.snapshot();
+        let left: MapIndex<_, u32, u64> = MapIndex::new("left", &snapshot);
+        let right: MapIndex<_, u32, String> = MapIndex::new("right", &snapshot);
+        let joined = join_on_key(&left, &right).collect::<Vec<_>>();
+        assert_eq!(joined.len(), 10);
+        assert_eq!(joined[0], (50, 500, "50".to_owned()));
+        assert_eq!(joined[9], (59, 590, "59".to_owned()));
+
+        let fork = db.fork();
+        let right: MapIndex<_, u32, String> = MapIndex::new("right", &fork);
+        assert_eq!(join_on_key(&right, &left).count(), 10);
+    }
+}
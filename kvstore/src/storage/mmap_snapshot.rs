@@ -0,0 +1,663 @@
+// Copyright 2018 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![allow(unsafe_code)]
+
+//! An implementation of an immutable snapshot stored in a memory-mapped file.
+
+use byteorder::{BigEndian, ByteOrder};
+use memmap::Mmap;
+
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crypto::{HashStream, HASH_SIZE};
+use super::{Error, IndexAccess, Iter, Iterator, Result, Snapshot};
+use super::indexes_metadata::{self, INDEXES_METADATA_TABLE_NAME};
+
+const MAGIC: &[u8] = b"EXOMMAP\x01";
+const FORMAT_VERSION: u32 = 1;
+
+/// Approximate size of a data block in bytes.
+const BLOCK_SIZE: usize = 4096;
+/// Size of the entry header with the lengths of the key and the value.
+const ENTRY_HEADER_SIZE: usize = 8;
+/// Size of a block index record with the block offset, length and number of entries.
+const INDEX_RECORD_SIZE: usize = 16;
+/// Size of the footer with the offset and the length of the column family table, the hash
+/// of the file contents preceding the footer, the format version and the magic bytes.
+const FOOTER_SIZE: usize = 16 + HASH_SIZE + 4 + 8;
+
+/// An immutable snapshot stored in a single memory-mapped file.
+///
+/// The file is produced by [`create`] from an arbitrary `Snapshot`. It contains the entries
+/// of every column family sorted by keys and split into blocks, and a block index for each
+/// column family. Opening the file only reads the column family table, so it is fast
+/// regardless of the size of the data. Lookups use binary search over the blocks, and both
+/// `get` and iteration return the data directly from the mapped memory without copying
+/// it first.
+///
+/// Only the column family table and the block indexes are checked when the snapshot
+/// is opened; use [`verify`] to compare the file contents with the hash stored in the file.
+/// Reading an entry which has been corrupted panics.
+///
+/// [`create`]: #method.create
+/// [`verify`]: #method.verify
+///
+/// # Examples
+///
+/// ```
+/// # extern crate exonum;
+/// # extern crate tempdir;
+/// use exonum::storage::{Database, MapIndex, MemoryDB, MmapSnapshot};
+/// # use tempdir::TempDir;
+///
+/// # fn main() {
+/// # let dir = TempDir::new("exonum_mmap").unwrap();
+/// # let path = dir.path().join("snapshot");
+/// let db = MemoryDB::new();
+/// let mut fork = db.fork();
+/// MapIndex::new("map", &mut fork).put(&1_u8, 2_u64);
+/// db.merge(fork.into_patch()).unwrap();
+///
+/// MmapSnapshot::create(&*db.snapshot(), &path).unwrap();
+/// let snapshot = MmapSnapshot::open(&path).unwrap();
+/// let index: MapIndex<_, u8, u64> = MapIndex::new("map", &snapshot);
+/// assert_eq!(index.get(&1), Some(2));
+/// # }
+/// ```
+pub struct MmapSnapshot {
+    mmap: Mmap,
+    families: HashMap<String, Family>,
+}
+
+/// Location of the block index of a column family.
+#[derive(Debug, Clone, Copy)]
+struct Family {
+    index_offset: usize,
+    blocks: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Block {
+    offset: usize,
+    len: usize,
+}
+
+struct MmapIter<'a> {
+    data: &'a [u8],
+    family: Family,
+    block: usize,
+    pos: usize,
+    end: usize,
+}
+
+impl MmapSnapshot {
+    /// Writes the column families registered in the indexes metadata of the snapshot,
+    /// together with the metadata itself, into a file at the given path.
+    ///
+    /// As with [`dump`], raw changes made with [`Fork::put`] to other column families
+    /// are not included; use [`create_from_families`] to list the column families explicitly.
+    ///
+    /// [`dump`]: dump/index.html
+    /// [`Fork::put`]: struct.Fork.html#method.put
+    /// [`create_from_families`]: #method.create_from_families
+    pub fn create<P: AsRef<Path>>(snapshot: &Snapshot, path: P) -> Result<()> {
+        let mut names = indexes_metadata::list_indexes(snapshot)
+            .into_iter()
+            .map(|info| info.name)
+            .collect::<BTreeSet<_>>();
+        names.insert(INDEXES_METADATA_TABLE_NAME.to_owned());
+        let names = names.iter().map(String::as_str).collect::<Vec<_>>();
+        Self::create_from_families(snapshot, &names, path)
+    }
+
+    /// Writes the given column families of the snapshot into a file at the given path.
+    ///
+    /// The file is written under a temporary name and then renamed, so an interrupted write
+    /// never leaves a partially written file at `path`.
+    pub fn create_from_families<P: AsRef<Path>>(
+        snapshot: &Snapshot,
+        names: &[&str],
+        path: P,
+    ) -> Result<()> {
+        let path = path.as_ref();
+        let tmp_path = path.with_extension("tmp");
+        {
+            let file = File::create(&tmp_path).map_err(io_error)?;
+            let mut writer = FileWriter {
+                writer: BufWriter::new(file),
+                hasher: HashStream::new(),
+                offset: 0,
+            };
+            writer.write(MAGIC)?;
+
+            let mut names = names.to_vec();
+            names.sort();
+            names.dedup();
+            let mut indexes = Vec::with_capacity(names.len());
+            for name in &names {
+                indexes.push(write_family(&mut writer, snapshot, name)?);
+            }
+
+            let mut table = Vec::new();
+            let mut buf = [0; 8];
+            BigEndian::write_u32(&mut buf[..4], names.len() as u32);
+            table.extend_from_slice(&buf[..4]);
+            for (name, blocks) in names.iter().zip(indexes) {
+                let index_offset = writer.offset;
+                for block in &blocks {
+                    let mut record = [0; INDEX_RECORD_SIZE];
+                    BigEndian::write_u64(&mut record[..8], block.0 as u64);
+                    BigEndian::write_u32(&mut record[8..12], block.1 as u32);
+                    BigEndian::write_u32(&mut record[12..], block.2 as u32);
+                    writer.write(&record)?;
+                }
+                BigEndian::write_u32(&mut buf[..4], name.len() as u32);
+                table.extend_from_slice(&buf[..4]);
+                table.extend_from_slice(name.as_bytes());
+                BigEndian::write_u64(&mut buf, index_offset as u64);
+                table.extend_from_slice(&buf);
+                BigEndian::write_u64(&mut buf, blocks.len() as u64);
+                table.extend_from_slice(&buf);
+            }
+
+            let table_offset = writer.offset;
+            writer.write(&table)?;
+
+            let mut footer = vec![0; 16];
+            BigEndian::write_u64(&mut footer[..8], table_offset as u64);
+            BigEndian::write_u64(&mut footer[8..], table.len() as u64);
+            let hash = ::std::mem::replace(&mut writer.hasher, HashStream::new()).hash();
+            footer.extend_from_slice(hash.as_ref());
+            BigEndian::write_u32(&mut buf[..4], FORMAT_VERSION);
+            footer.extend_from_slice(&buf[..4]);
+            footer.extend_from_slice(MAGIC);
+            writer.write(&footer)?;
+
+            let file = writer
+                .writer
+                .into_inner()
+                .map_err(|e| Error::new(e.to_string()))?;
+            file.sync_all().map_err(io_error)?;
+        }
+        fs::rename(&tmp_path, path).map_err(io_error)
+    }
+
+    /// Opens a snapshot file created with [`create`](#method.create).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be mapped, if it is not a snapshot file
+    /// of a supported format version, or if its block indexes point outside the data.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = File::open(path).map_err(io_error)?;
+        let mmap = unsafe { Mmap::map(&file) }.map_err(io_error)?;
+        let families = read_families(&mmap)?;
+        Ok(MmapSnapshot { mmap, families })
+    }
+
+    /// Returns the sorted names of the column families stored in the snapshot.
+    pub fn column_families(&self) -> Vec<&str> {
+        let mut names = self.families.keys().map(String::as_str).collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    /// Returns the size of the snapshot file in bytes.
+    pub fn len(&self) -> usize {
+        self.mmap.len()
+    }
+
+    /// Returns `true` if no column family of the snapshot contains entries.
+    pub fn is_empty(&self) -> bool {
+        self.families.values().all(|family| family.blocks == 0)
+    }
+
+    /// Checks the contents of the file against the hash stored in it. Unlike other methods,
+    /// this reads the whole file.
+    pub fn verify(&self) -> Result<()> {
+        let data = &self.mmap[..];
+        let footer = &data[data.len() - FOOTER_SIZE..];
+        let expected = &footer[16..16 + HASH_SIZE];
+        let actual = HashStream::new()
+            .update(&data[..data.len() - FOOTER_SIZE])
+            .hash();
+        if actual.as_ref() != expected {
+            return Err(Error::new("Snapshot file is corrupted: hash mismatch"));
+        }
+        Ok(())
+    }
+
+    fn iter_family(&self, name: &str, from: &[u8]) -> MmapIter {
+        let family = self.families.get(name).cloned().unwrap_or(Family {
+            index_offset: 0,
+            blocks: 0,
+        });
+        let mut iter = MmapIter {
+            data: &self.mmap,
+            family,
+            block: 0,
+            pos: 0,
+            end: 0,
+        };
+        iter.seek(from);
+        iter
+    }
+}
+
+impl Snapshot for MmapSnapshot {
+    fn get(&self, name: &str, key: &[u8]) -> Option<Vec<u8>> {
+        let mut iter = self.iter_family(name, key);
+        let value = match iter.peek() {
+            Some((k, v)) if k == key => Some(v.to_vec()),
+            _ => None,
+        };
+        value
+    }
+
+    fn contains(&self, name: &str, key: &[u8]) -> bool {
+        let mut iter = self.iter_family(name, key);
+        let found = match iter.peek() {
+            Some((k, _)) => k == key,
+            None => false,
+        };
+        found
+    }
+
+    fn iter<'a>(&'a self, name: &str, from: &[u8]) -> Iter<'a> {
+        Box::new(self.iter_family(name, from))
+    }
+
+    fn column_families(&self) -> Vec<String> {
+        let mut names = self.families.keys().cloned().collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+}
+
+impl IndexAccess for MmapSnapshot {
+    fn snapshot(&self) -> &Snapshot {
+        self
+    }
+}
+
+impl<'a> MmapIter<'a> {
+    fn block(&self, index: usize) -> Block {
+        let offset = self.family.index_offset + index * INDEX_RECORD_SIZE;
+        let record = &self.data[offset..offset + INDEX_RECORD_SIZE];
+        Block {
+            offset: BigEndian::read_u64(&record[..8]) as usize,
+            len: BigEndian::read_u32(&record[8..12]) as usize,
+        }
+    }
+
+    /// Returns the key and the value of the entry at the given offset of a block ending
+    /// at `end`, and the offset of the next entry.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the entry doesn't fit into the block.
+    fn entry(&self, pos: usize, end: usize) -> (&'a [u8], &'a [u8], usize) {
+        let data = self.data;
+        let entry = if pos + ENTRY_HEADER_SIZE <= end {
+            let key_len = BigEndian::read_u32(&data[pos..pos + 4]) as usize;
+            let value_len = BigEndian::read_u32(&data[pos + 4..pos + 8]) as usize;
+            let key_start = pos + ENTRY_HEADER_SIZE;
+            let value_start = key_start + key_len;
+            let next = value_start + value_len;
+            if next <= end {
+                Some((
+                    &data[key_start..value_start],
+                    &data[value_start..next],
+                    next,
+                ))
+            } else {
+                None
+            }
+        } else {
+            None
+        };
+        entry.unwrap_or_else(|| {
+            panic!(
+                "Snapshot file is corrupted: entry at offset {} exceeds its block",
+                pos
+            )
+        })
+    }
+
+    fn enter_block(&mut self, index: usize) {
+        self.block = index;
+        if index < self.family.blocks {
+            let block = self.block(index);
+            self.pos = block.offset;
+            self.end = block.offset + block.len;
+        } else {
+            self.pos = 0;
+            self.end = 0;
+        }
+    }
+
+    /// Positions the iterator at the first entry with the key not less than `from`.
+    fn seek(&mut self, from: &[u8]) {
+        // Find the last block whose first key is not greater than `from`.
+        let (mut low, mut high) = (0, self.family.blocks);
+        while low < high {
+            let mid = (low + high) / 2;
+            let block = self.block(mid);
+            let (first_key, ..) = self.entry(block.offset, block.offset + block.len);
+            if first_key <= from {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        self.enter_block(low.saturating_sub(1));
+        while let Some((key, ..)) = self.current() {
+            if key >= from {
+                break;
+            }
+            self.advance();
+        }
+    }
+
+    fn current(&mut self) -> Option<(&'a [u8], &'a [u8])> {
+        while self.pos >= self.end {
+            if self.block + 1 >= self.family.blocks {
+                return None;
+            }
+            let next = self.block + 1;
+            self.enter_block(next);
+        }
+        let (key, value, _) = self.entry(self.pos, self.end);
+        Some((key, value))
+    }
+
+    fn advance(&mut self) {
+        if self.pos < self.end {
+            self.pos = self.entry(self.pos, self.end).2;
+        }
+    }
+}
+
+impl<'a> Iterator for MmapIter<'a> {
+    fn next(&mut self) -> Option<(&[u8], &[u8])> {
+        let current = self.current();
+        self.advance();
+        current
+    }
+
+    fn peek(&mut self) -> Option<(&[u8], &[u8])> {
+        self.current()
+    }
+}
+
+impl fmt::Debug for MmapSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MmapSnapshot")
+            .field("len", &self.mmap.len())
+            .field("column_families", &self.column_families())
+            .finish()
+    }
+}
+
+struct FileWriter<W> {
+    writer: W,
+    hasher: HashStream,
+    offset: usize,
+}
+
+impl<W: Write> FileWriter<W> {
+    fn write(&mut self, bytes: &[u8]) -> Result<()> {
+        self.writer.write_all(bytes).map_err(io_error)?;
+        let hasher = ::std::mem::replace(&mut self.hasher, HashStream::new());
+        self.hasher = hasher.update(bytes);
+        self.offset += bytes.len();
+        Ok(())
+    }
+}
+
+/// Writes the entries of the column family split into blocks. Returns the offset, length and
+/// number of entries of each block.
+fn write_family<W: Write>(
+    writer: &mut FileWriter<W>,
+    snapshot: &Snapshot,
+    name: &str,
+) -> Result<Vec<(usize, usize, usize)>> {
+    let mut blocks = Vec::new();
+    let mut block = Vec::with_capacity(BLOCK_SIZE);
+    let mut entries = 0;
+    let mut header = [0; ENTRY_HEADER_SIZE];
+
+    let mut iter = snapshot.iter(name, &[]);
+    while let Some((key, value)) = iter.next() {
+        BigEndian::write_u32(&mut header[..4], key.len() as u32);
+        BigEndian::write_u32(&mut header[4..], value.len() as u32);
+        block.extend_from_slice(&header);
+        block.extend_from_slice(key);
+        block.extend_from_slice(value);
+        entries += 1;
+        if block.len() >= BLOCK_SIZE {
+            blocks.push((writer.offset, block.len(), entries));
+            writer.write(&block)?;
+            block.clear();
+            entries = 0;
+        }
+    }
+    if !block.is_empty() {
+        blocks.push((writer.offset, block.len(), entries));
+        writer.write(&block)?;
+    }
+    Ok(blocks)
+}
+
+/// Reads the column family table, checking that the footer and block indexes are within
+/// the file bounds, and that the blocks are within the data preceding the block indexes.
+fn read_families(data: &[u8]) -> Result<HashMap<String, Family>> {
+    if data.len() < MAGIC.len() + FOOTER_SIZE || &data[..MAGIC.len()] != MAGIC {
+        return Err(invalid_file());
+    }
+    let footer = &data[data.len() - FOOTER_SIZE..];
+    if &footer[FOOTER_SIZE - MAGIC.len()..] != MAGIC {
+        return Err(invalid_file());
+    }
+    let version = BigEndian::read_u32(&footer[16 + HASH_SIZE..20 + HASH_SIZE]);
+    if version != FORMAT_VERSION {
+        return Err(Error::new(format!(
+            "Unsupported snapshot format version {}",
+            version
+        )));
+    }
+
+    let table_offset = BigEndian::read_u64(&footer[..8]) as usize;
+    let table_len = BigEndian::read_u64(&footer[8..16]) as usize;
+    let footer_offset = data.len() - FOOTER_SIZE;
+    if table_offset > footer_offset || table_len != footer_offset - table_offset {
+        return Err(invalid_file());
+    }
+
+    let mut table = &data[table_offset..footer_offset];
+    let count = BigEndian::read_u32(take(&mut table, 4)?);
+    let mut families = HashMap::new();
+    for _ in 0..count {
+        let name_len = BigEndian::read_u32(take(&mut table, 4)?) as usize;
+        let name = String::from_utf8(take(&mut table, name_len)?.to_vec())
+            .map_err(|_| invalid_file())?;
+        let index_offset = BigEndian::read_u64(take(&mut table, 8)?) as usize;
+        let blocks = BigEndian::read_u64(take(&mut table, 8)?) as usize;
+        let index_end = blocks
+            .checked_mul(INDEX_RECORD_SIZE)
+            .and_then(|len| len.checked_add(index_offset));
+        let index_end = match index_end {
+            Some(end) if end <= table_offset => end,
+            _ => return Err(invalid_file()),
+        };
+        for record in data[index_offset..index_end].chunks(INDEX_RECORD_SIZE) {
+            let offset = BigEndian::read_u64(&record[..8]) as usize;
+            let len = BigEndian::read_u32(&record[8..12]) as usize;
+            let in_bounds = offset
+                .checked_add(len)
+                .map_or(false, |end| end <= index_offset);
+            if offset < MAGIC.len() || len < ENTRY_HEADER_SIZE || !in_bounds {
+                return Err(invalid_file());
+            }
+        }
+        families.insert(
+            name,
+            Family {
+                index_offset,
+                blocks,
+            },
+        );
+    }
+    Ok(families)
+}
+
+/// Splits off the first `len` bytes of the column family table.
+fn take<'a>(table: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+    if table.len() < len {
+        return Err(invalid_file());
+    }
+    let (head, tail) = table.split_at(len);
+    *table = tail;
+    Ok(head)
+}
+
+fn invalid_file() -> Error {
+    Error::new("Not a valid snapshot file")
+}
+
+fn io_error(e: ::std::io::Error) -> Error {
+    Error::new(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+
+    use storage::{Database, MapIndex, MemoryDB};
+    use super::*;
+
+    const CF: &str = "cf";
+
+    fn create_db(keys: usize) -> MemoryDB {
+        let db = MemoryDB::new();
+        let mut fork = db.fork();
+        for i in 0..keys {
+            let key = format!("key_{:06}", i * 2).into_bytes();
+            fork.put(CF, key, vec![i as u8; i % 50]);
+        }
+        fork.put("other", vec![1], vec![2]);
+        db.merge(fork.into_patch()).unwrap();
+        db
+    }
+
+    fn collect(view: &Snapshot, name: &str, from: &[u8]) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let mut entries = Vec::new();
+        let mut iter = view.iter(name, from);
+        while let Some((k, v)) = iter.next() {
+            entries.push((k.to_vec(), v.to_vec()));
+        }
+        entries
+    }
+
+    #[test]
+    fn snapshot_matches_source() {
+        let dir = TempDir::new("exonum_mmap_snapshot").unwrap();
+        let path = dir.path().join("snapshot");
+        let db = create_db(2_000);
+        let source = db.snapshot();
+        MmapSnapshot::create_from_families(&*source, &[CF, "other", "empty"], &path).unwrap();
+
+        let snapshot = MmapSnapshot::open(&path).unwrap();
+        snapshot.verify().unwrap();
+        assert_eq!(snapshot.column_families(), vec![CF, "empty", "other"]);
+        assert!(snapshot.families[CF].blocks > 1);
+        assert!(!snapshot.is_empty());
+
+        for i in 0..2_001 {
+            for key in &[format!("key_{:06}", i), format!("key_{:06}", i) + "\x00"] {
+                let key = key.as_bytes();
+                assert_eq!(snapshot.get(CF, key), source.get(CF, key));
+                assert_eq!(snapshot.contains(CF, key), source.contains(CF, key));
+            }
+        }
+        for from in &[&b""[..], b"key_000001", b"key_001999", b"key_003998", b"z"] {
+            assert_eq!(collect(&snapshot, CF, from), collect(&*source, CF, from));
+        }
+        assert_eq!(snapshot.get("other", &[1]), Some(vec![2]));
+        assert!(collect(&snapshot, "empty", &[]).is_empty());
+        assert!(collect(&snapshot, "missing", &[]).is_empty());
+
+        let mut iter = snapshot.iter(CF, b"key_000100");
+        assert_eq!(iter.peek().map(|(k, _)| k.to_vec()), Some(b"key_000100".to_vec()));
+        assert_eq!(iter.next().map(|(k, _)| k.to_vec()), Some(b"key_000100".to_vec()));
+        assert_eq!(iter.next().map(|(k, _)| k.to_vec()), Some(b"key_000102".to_vec()));
+    }
+
+    #[test]
+    fn create_from_indexes() {
+        let dir = TempDir::new("exonum_mmap_snapshot").unwrap();
+        let path = dir.path().join("snapshot");
+        let db = MemoryDB::new();
+        let mut fork = db.fork();
+        {
+            let mut index = MapIndex::new("map", &mut fork);
+            for i in 0..100_u64 {
+                index.put(&i, i * i);
+            }
+        }
+        fork.put("raw", vec![1], vec![1]);
+        db.merge(fork.into_patch()).unwrap();
+
+        MmapSnapshot::create(&*db.snapshot(), &path).unwrap();
+        let snapshot = MmapSnapshot::open(&path).unwrap();
+        let index: MapIndex<_, u64, u64> = MapIndex::new("map", &snapshot);
+        assert_eq!(index.get(&7), Some(49));
+        assert_eq!(index.iter().count(), 100);
+        assert!(!snapshot.contains("raw", &[1]));
+    }
+
+    #[test]
+    fn corrupted_files() {
+        let dir = TempDir::new("exonum_mmap_snapshot").unwrap();
+        let path = dir.path().join("snapshot");
+        let db = create_db(100);
+        MmapSnapshot::create_from_families(&*db.snapshot(), &[CF], &path).unwrap();
+
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[MAGIC.len() + 20] ^= 1;
+        fs::write(&path, &bytes).unwrap();
+        let snapshot = MmapSnapshot::open(&path).unwrap();
+        assert!(snapshot.verify().is_err());
+
+        // A block pointing outside the data is rejected on open.
+        let index_offset = snapshot.families[CF].index_offset;
+        drop(snapshot);
+        let mut corrupted = bytes.clone();
+        let len_range = index_offset + 8..index_offset + 12;
+        BigEndian::write_u32(&mut corrupted[len_range], u32::max_value());
+        fs::write(&path, &corrupted).unwrap();
+        assert!(MmapSnapshot::open(&path).is_err());
+
+        let len = bytes.len();
+        fs::write(&path, &bytes[..len - 1]).unwrap();
+        assert!(MmapSnapshot::open(&path).is_err());
+        fs::write(&path, b"not a snapshot").unwrap();
+        assert!(MmapSnapshot::open(&path).is_err());
+    }
+}
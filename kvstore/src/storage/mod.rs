@@ -24,7 +24,12 @@
 //! that is, the Exonum process has exclusive access to the DB during blockchain operation.
 //! You can interact with the `Database` from multiple threads by cloning its instance.
 //!
-//! Exonum provides two database types: [`RocksDB`] and [`MemoryDB`].
+//! Exonum provides two database types: [`RocksDB`] and [`MemoryDB`]. Any of them can be
+//! wrapped into a [`CachedDatabase`] to keep recently read values in memory, or into
+//! a [`VersionedDatabase`] to read the database state as of previous commits. Several
+//! databases can be combined into a [`ShardedDatabase`], which distributes column families
+//! among them. A snapshot of any database can be frozen into an [`MmapSnapshot`], a read-only
+//! memory-mapped file that opens instantly.
 //!
 //! # Snapshot and Fork
 //!
@@ -71,19 +76,37 @@
 //! - [`ListIndex`] is a list of items stored in the sequential order. Similar to [`Vec`].
 //! - [`SparseListIndex`] is a list of items stored in the sequential order. Similar to `ListIndex`,
 //!   but may contain indices without elements.
+//! - [`DequeIndex`] is a double-ended queue of items. Similar to [`VecDeque`].
+//! - [`QueueIndex`] is a durable FIFO queue of work items, which are read by consumers with
+//!   their own cursors and are kept until acknowledged.
 //! - [`MapIndex`] is a map of keys and values. Similar to [`BTreeMap`].
+//! - [`TtlMapIndex`] is a map of keys and values, each of which expires at a certain time.
+//!   Expired entries are hidden from reads and can be deleted in batches.
+//! - [`IndexedMap`] is a map of keys and values with secondary indexes over the values,
+//!   which are updated together with the map.
+//! - [`MultiMapIndex`] is a map from keys to multiple values, with or without duplicates.
+//! - [`SortedSetIndex`] is a set of members ordered by scores, similar to sorted sets in Redis.
 //! - [`ProofListIndex`] is a Merkelized version of `ListIndex` that supports cryptographic
 //!   proofs of existence and is implemented as a Merkle tree.
 //! - [`ProofMapIndex`] is a Merkelized version of `MapIndex` that supports cryptographic
 //!   proofs of existence and is implemented as a binary Merkle Patricia tree.
 //! - [`KeySetIndex`] and [`ValueSetIndex`] is a set of items, similar to [`BTreeSet`] and
 //!   [`HashSet`].
+//! - [`TextIndex`] is an inverted full-text index over texts of documents, which supports
+//!   term and phrase queries ranked with BM25.
+//!
+//! The [`merge_join`] module provides lazy unions, intersections and differences of
+//! `KeySetIndex`es and joins of `MapIndex`es on keys.
 //!
 //! To implement a new index type, you should create a wrapper around [`BaseIndex`].
 //!
 //! [`Database`]: trait.Database.html
 //! [`RocksDB`]: struct.RocksDB.html
 //! [`MemoryDB`]: struct.MemoryDB.html
+//! [`CachedDatabase`]: struct.CachedDatabase.html
+//! [`VersionedDatabase`]: struct.VersionedDatabase.html
+//! [`ShardedDatabase`]: struct.ShardedDatabase.html
+//! [`MmapSnapshot`]: struct.MmapSnapshot.html
 //! [`Snapshot`]: trait.Snapshot.html
 //! [`Fork`]: struct.Fork.html
 //! [`Patch`]: struct.Patch.html
@@ -95,67 +118,118 @@
 //! [`Entry`]: struct.Entry.html
 //! [`ListIndex`]: list_index/struct.ListIndex.html
 //! [`SparseListIndex`]: sparse_list_index/struct.SparseListIndex.html
+//! [`DequeIndex`]: deque_index/struct.DequeIndex.html
+//! [`QueueIndex`]: queue_index/struct.QueueIndex.html
 //! [`MapIndex`]: map_index/struct.MapIndex.html
+//! [`TtlMapIndex`]: ttl_map_index/struct.TtlMapIndex.html
+//! [`IndexedMap`]: indexed_map/struct.IndexedMap.html
+//! [`MultiMapIndex`]: multimap_index/struct.MultiMapIndex.html
+//! [`SortedSetIndex`]: sorted_set_index/struct.SortedSetIndex.html
+//! [`TextIndex`]: text_index/struct.TextIndex.html
 //! [`ProofListIndex`]: proof_list_index/struct.ProofListIndex.html
 //! [`ProofMapIndex`]: proof_map_index/struct.ProofMapIndex.html
 //! [`KeySetIndex`]: key_set_index/struct.KeySetIndex.html
 //! [`ValueSetIndex`]: value_set_index/struct.ValueSetIndex.html
 //! [`BaseIndex`]: base_index/struct.BaseIndex.html
+//! [`merge_join`]: merge_join/index.html
 //! [doc:storage]: https://exonum.com/doc/architecture/storage
 //! [`Option`]: https://doc.rust-lang.org/std/option/enum.Option.html
 //! [`Box`]: https://doc.rust-lang.org/std/boxed/struct.Box.html
 //! [`Vec`]: https://doc.rust-lang.org/std/vec/struct.Vec.html
+//! [`VecDeque`]: https://doc.rust-lang.org/std/collections/struct.VecDeque.html
 //! [`BTreeMap`]: https://doc.rust-lang.org/std/collections/struct.BTreeMap.html
 //! [`BTreeSet`]: https://doc.rust-lang.org/std/collections/struct.BTreeSet.html
 //! [`HashSet`]: https://doc.rust-lang.org/std/collections/struct.HashSet.html
 
 pub use self::error::Error;
 pub use self::db::{Change, Changes, ChangesIterator, Database, Fork, Iter, Iterator, Patch,
-                   PatchIterator, Snapshot};
+                   PatchIterator, Snapshot, SnapshotBounds};
 
 pub use self::options::DbOptions;
-pub use self::rocksdb::RocksDB;
+pub use self::rocksdb::{ReadOnlyRocksDB, RocksDB, SecondaryRocksDB};
 pub use self::memorydb::MemoryDB;
+pub use self::cached_db::{CacheStats, CachedDatabase, CachedSnapshot};
+pub use self::versioned_db::{VersionedDatabase, VersionedSnapshot};
+pub use self::sharded_db::{RoutingTable, ShardedDatabase, ShardedSnapshot};
+pub use self::mmap_snapshot::MmapSnapshot;
+pub use self::diff::diff;
+pub use self::merge_join::{difference, intersection, join_on_key, union};
 
-pub use self::keys::StorageKey;
-pub use self::values::StorageValue;
+pub use self::keys::{F32, F64, StorageKey, StorageKeyRef};
+pub use self::values::{StorageValue, StorageValueRef};
 
 pub use self::entry::Entry;
+pub use self::index_access::{IndexAccess, IndexAccessMut};
 
-pub use self::base_index::{BaseIndex, BaseIndexIter};
+pub use self::base_index::{BaseIndex, BaseIndexIter, BaseIndexKeysIter};
 pub use self::map_index::MapIndex;
 pub use self::list_index::ListIndex;
 pub use self::sparse_list_index::SparseListIndex;
+pub use self::deque_index::DequeIndex;
+pub use self::queue_index::QueueIndex;
 pub use self::key_set_index::KeySetIndex;
 pub use self::value_set_index::ValueSetIndex;
-//pub use self::proof_list_index::{ListProof, ProofListIndex};
-//#[doc(no_inline)]
-//pub use self::proof_map_index::{HashedKey, MapProof, ProofMapIndex};
+pub use self::ttl_map_index::TtlMapIndex;
+pub use self::indexed_map::IndexedMap;
+pub use self::multimap_index::MultiMapIndex;
+pub use self::sorted_set_index::SortedSetIndex;
+pub use self::text_index::TextIndex;
+pub use self::proof_list_index::{ListProof, ProofListIndex};
+#[doc(no_inline)]
+pub use self::proof_map_index::{HashedKey, MapProof, ProofMapIndex};
 pub use self::hash::UniqueHash;
+pub use self::indexes_metadata::{list_indexes, IndexInfo, IndexType};
 
 /// A specialized `Result` type for I/O operations with storage.
 pub type Result<T> = ::std::result::Result<T, Error>;
 
+#[macro_use]
+pub mod schema;
+
 mod error;
 mod db;
 mod options;
 mod rocksdb;
 mod memorydb;
+mod cached_db;
+mod versioned_db;
+mod sharded_db;
+mod mmap_snapshot;
+mod diff;
 mod keys;
 mod values;
 mod entry;
+mod index_access;
 mod hash;
+mod bloom_filter;
 
 pub mod base_index;
+pub mod dump;
+pub mod replication;
+pub mod state_hash;
+#[cfg(feature = "async")]
+pub mod async_db;
 mod indexes_metadata;
 
 pub mod map_index;
 pub mod list_index;
 pub mod sparse_list_index;
+pub mod deque_index;
+pub mod queue_index;
 pub mod key_set_index;
 pub mod value_set_index;
-//pub mod proof_list_index;
-//pub mod proof_map_index;
+pub mod ttl_map_index;
+pub mod indexed_map;
+pub mod multimap_index;
+pub mod sorted_set_index;
+pub mod text_index;
+pub mod proof_list_index;
+pub mod proof_map_index;
+pub mod merge_join;
 
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+#[cfg(any(test, feature = "testing"))]
+pub mod faulty_db;
 #[cfg(test)]
 mod tests;
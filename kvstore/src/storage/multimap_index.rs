@@ -0,0 +1,488 @@
+// Copyright 2018 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! An implementation of map from keys to multiple values.
+
+use std::borrow::Borrow;
+use std::marker::PhantomData;
+
+use super::{BaseIndex, BaseIndexIter, IndexAccess, IndexAccessMut, StorageKey};
+use super::indexes_metadata::IndexType;
+use super::keys::write_escaped;
+
+/// Subprefix of the `(key, value)` pairs, ordered by keys and then values.
+const PAIRS_PREFIX: u8 = 0;
+/// Subprefix of the numbers of values per key.
+const COUNTS_PREFIX: u8 = 1;
+
+/// A map from keys to multiple values.
+///
+/// The index stores each `(key, value)` pair as a composite storage key, so the values of
+/// a key are read with a prefix scan and are ordered as per their serialization. Thus,
+/// both the keys and the values must implement the [`StorageKey`] trait.
+///
+/// By default, the index is duplicate-free: inserting a value which the key already has
+/// does nothing, like in a set. A map created with [`with_duplicates`] preserves duplicates
+/// instead: each insertion of the same value is counted, and the value is returned as many
+/// times as it was inserted.
+///
+/// [`StorageKey`]: ../trait.StorageKey.html
+/// [`with_duplicates`]: #method.with_duplicates
+#[derive(Debug)]
+pub struct MultiMapIndex<T, K, V> {
+    base: BaseIndex<T>,
+    duplicates: bool,
+    _k: PhantomData<K>,
+    _v: PhantomData<V>,
+}
+
+/// An iterator over the values of a key in a `MultiMapIndex`.
+///
+/// This struct is created by the [`get`] method on [`MultiMapIndex`].
+/// See its documentation for more.
+///
+/// [`get`]: struct.MultiMapIndex.html#method.get
+/// [`MultiMapIndex`]: struct.MultiMapIndex.html
+#[derive(Debug)]
+pub struct MultiMapIndexValues<'a, V> {
+    base_iter: BaseIndexIter<'a, Vec<u8>, u64>,
+    prefix_len: usize,
+    current: Vec<u8>,
+    remaining: u64,
+    _v: PhantomData<V>,
+}
+
+impl<T, K, V> MultiMapIndex<T, K, V>
+where
+    T: IndexAccess,
+    K: StorageKey,
+    V: StorageKey,
+{
+    /// Creates a new index representation based on the name and storage view.
+    ///
+    /// Storage view can be specified as [`&Snapshot`] or [`&mut Fork`]. In the first case only
+    /// immutable methods are available. In the second case both immutable and mutable methods are
+    /// available.
+    ///
+    /// [`&Snapshot`]: ../trait.Snapshot.html
+    /// [`&mut Fork`]: ../struct.Fork.html
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, MultiMapIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let name = "name";
+    /// let snapshot = db.snapshot();
+    /// let index: MultiMapIndex<_, u8, u8> = MultiMapIndex::new(name, &snapshot);
+    /// ```
+    pub fn new<S: AsRef<str>>(index_name: S, view: T) -> Self {
+        MultiMapIndex {
+            base: BaseIndex::new(index_name, IndexType::MultiMap, view),
+            duplicates: false,
+            _k: PhantomData,
+            _v: PhantomData,
+        }
+    }
+
+    /// Creates a new index representation based on the name, index id in family
+    /// and storage view.
+    ///
+    /// Storage view can be specified as [`&Snapshot`] or [`&mut Fork`]. In the first case only
+    /// immutable methods are available. In the second case both immutable and mutable methods are
+    /// available.
+    ///
+    /// [`&Snapshot`]: ../trait.Snapshot.html
+    /// [`&mut Fork`]: ../struct.Fork.html
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, MultiMapIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let name = "name";
+    /// let index_id = vec![01];
+    ///
+    /// let snapshot = db.snapshot();
+    /// let index: MultiMapIndex<_, u8, u8> =
+    ///     MultiMapIndex::new_in_family(name, &index_id, &snapshot);
+    /// ```
+    pub fn new_in_family<S: AsRef<str>, I: StorageKey>(
+        family_name: S,
+        index_id: &I,
+        view: T,
+    ) -> Self {
+        MultiMapIndex {
+            base: BaseIndex::new_in_family(family_name, index_id, IndexType::MultiMap, view),
+            duplicates: false,
+            _k: PhantomData,
+            _v: PhantomData,
+        }
+    }
+
+    /// Switches the index to the duplicate-preserving mode, in which inserting the same value
+    /// several times stores all the occurrences.
+    ///
+    /// The mode affects only [`insert`](#method.insert); the occurrences stored by
+    /// a duplicate-preserving index are returned by the index in either mode.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, MultiMapIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let name = "name";
+    /// let mut fork = db.fork();
+    /// let mut index = MultiMapIndex::new(name, &mut fork).with_duplicates();
+    /// index.insert(&1_u8, 2_u8);
+    /// index.insert(&1_u8, 2_u8);
+    /// assert_eq!(index.get(&1).collect::<Vec<_>>(), vec![2, 2]);
+    /// ```
+    pub fn with_duplicates(mut self) -> Self {
+        self.duplicates = true;
+        self
+    }
+
+    /// Returns the storage view of the index, consuming the index.
+    pub fn into_view(self) -> T {
+        self.base.into_view()
+    }
+
+    /// Returns an iterator over the values of the key in ascending order. The iterator element
+    /// type is V.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, MultiMapIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let name = "name";
+    /// let mut fork = db.fork();
+    /// let mut index = MultiMapIndex::new(name, &mut fork);
+    /// index.insert(&1_u8, 3_u8);
+    /// index.insert(&1_u8, 2_u8);
+    /// index.insert(&2_u8, 4_u8);
+    /// assert_eq!(index.get(&1).collect::<Vec<_>>(), vec![2, 3]);
+    /// ```
+    pub fn get<Q>(&self, key: &Q) -> MultiMapIndexValues<V>
+    where
+        K: Borrow<Q>,
+        Q: StorageKey + ?Sized,
+    {
+        let prefix = pairs_prefix(key);
+        MultiMapIndexValues {
+            base_iter: self.base.iter(&prefix),
+            prefix_len: prefix.len(),
+            current: Vec::new(),
+            remaining: 0,
+            _v: PhantomData,
+        }
+    }
+
+    /// Returns the number of values of the key, including duplicates.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, MultiMapIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let name = "name";
+    /// let mut fork = db.fork();
+    /// let mut index = MultiMapIndex::new(name, &mut fork);
+    /// assert_eq!(index.count(&1), 0);
+    ///
+    /// index.insert(&1_u8, 2_u8);
+    /// index.insert(&1_u8, 3_u8);
+    /// assert_eq!(index.count(&1), 2);
+    /// ```
+    pub fn count<Q>(&self, key: &Q) -> u64
+    where
+        K: Borrow<Q>,
+        Q: StorageKey + ?Sized,
+    {
+        self.base.get(&count_key(key)).unwrap_or(0)
+    }
+
+    /// Returns `true` if the key has the specified value.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, MultiMapIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let name = "name";
+    /// let mut fork = db.fork();
+    /// let mut index = MultiMapIndex::new(name, &mut fork);
+    /// index.insert(&1_u8, 2_u8);
+    /// assert!(index.contains(&1, &2));
+    /// assert!(!index.contains(&1, &3));
+    /// ```
+    pub fn contains(&self, key: &K, value: &V) -> bool {
+        self.base.contains(&pair_key(key, value))
+    }
+}
+
+impl<T, K, V> MultiMapIndex<T, K, V>
+where
+    T: IndexAccessMut,
+    K: StorageKey,
+    V: StorageKey,
+{
+    /// Adds the value to the key and returns `true`, unless the index is duplicate-free
+    /// and the key already has the value. In the latter case the index is not changed
+    /// and `false` is returned.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, MultiMapIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let name = "name";
+    /// let mut fork = db.fork();
+    /// let mut index = MultiMapIndex::new(name, &mut fork);
+    /// assert!(index.insert(&1_u8, 2_u8));
+    /// assert!(!index.insert(&1_u8, 2_u8));
+    /// assert_eq!(index.count(&1), 1);
+    /// ```
+    pub fn insert(&mut self, key: &K, value: V) -> bool {
+        let pair_key = pair_key(key, &value);
+        let occurrences = self.base.get::<_, u64>(&pair_key).unwrap_or(0);
+        if occurrences > 0 && !self.duplicates {
+            return false;
+        }
+        self.base.put(&pair_key, occurrences + 1);
+        self.add_to_count(key, 1);
+        true
+    }
+
+    /// Removes one occurrence of the value from the key and returns `true` if the key had
+    /// the value.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, MultiMapIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let name = "name";
+    /// let mut fork = db.fork();
+    /// let mut index = MultiMapIndex::new(name, &mut fork).with_duplicates();
+    /// index.insert(&1_u8, 2_u8);
+    /// index.insert(&1_u8, 2_u8);
+    ///
+    /// assert!(index.remove(&1, &2));
+    /// assert_eq!(index.count(&1), 1);
+    /// assert!(index.remove(&1, &2));
+    /// assert!(!index.remove(&1, &2));
+    /// ```
+    pub fn remove(&mut self, key: &K, value: &V) -> bool {
+        let pair_key = pair_key(key, value);
+        match self.base.get::<_, u64>(&pair_key) {
+            Some(1) => self.base.remove(&pair_key),
+            Some(occurrences) => self.base.put(&pair_key, occurrences - 1),
+            None => return false,
+        }
+        self.add_to_count(key, -1);
+        true
+    }
+
+    /// Removes all the values of the key and returns the number of removed values, including
+    /// duplicates.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, MultiMapIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let name = "name";
+    /// let mut fork = db.fork();
+    /// let mut index = MultiMapIndex::new(name, &mut fork);
+    /// index.insert(&1_u8, 2_u8);
+    /// index.insert(&1_u8, 3_u8);
+    ///
+    /// assert_eq!(index.remove_all(&1), 2);
+    /// assert_eq!(index.get(&1).count(), 0);
+    /// ```
+    pub fn remove_all<Q>(&mut self, key: &Q) -> u64
+    where
+        K: Borrow<Q>,
+        Q: StorageKey + ?Sized,
+    {
+        let count = self.count(key);
+        if count == 0 {
+            return 0;
+        }
+        let pairs = self.base
+            .iter_keys::<_, Vec<u8>>(&pairs_prefix(key))
+            .collect::<Vec<_>>();
+        for pair_key in &pairs {
+            self.base.remove(pair_key);
+        }
+        self.base.remove(&count_key(key));
+        count
+    }
+
+    /// Clears the index, removing all the keys and values.
+    ///
+    /// # Notes
+    ///
+    /// Currently this method is not optimized to delete large set of data. During the execution of
+    /// this method the amount of allocated memory is linearly dependent on the number of elements
+    /// in the index.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, MultiMapIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let name = "name";
+    /// let mut fork = db.fork();
+    /// let mut index = MultiMapIndex::new(name, &mut fork);
+    ///
+    /// index.insert(&1_u8, 2_u8);
+    /// index.clear();
+    /// assert_eq!(index.count(&1), 0);
+    /// ```
+    pub fn clear(&mut self) {
+        self.base.clear()
+    }
+
+    fn add_to_count(&mut self, key: &K, delta: i64) {
+        let count_key = count_key(key);
+        let count = self.base.get::<_, u64>(&count_key).unwrap_or(0) as i64 + delta;
+        if count > 0 {
+            self.base.put(&count_key, count as u64);
+        } else {
+            self.base.remove(&count_key);
+        }
+    }
+}
+
+impl<'a, V> Iterator for MultiMapIndexValues<'a, V>
+where
+    V: StorageKey,
+{
+    type Item = V::Owned;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.remaining == 0 {
+            match self.base_iter.next() {
+                Some((pair_key, occurrences)) => {
+                    self.current = pair_key;
+                    self.remaining = occurrences;
+                }
+                None => return None,
+            }
+        }
+        self.remaining -= 1;
+        Some(V::read(&self.current[self.prefix_len..]))
+    }
+}
+
+/// Returns the common prefix of the storage keys of the pairs with the specified key.
+fn pairs_prefix<K: StorageKey + ?Sized>(key: &K) -> Vec<u8> {
+    let mut key_bytes = vec![0; key.size()];
+    key.write(&mut key_bytes);
+    let mut prefix = vec![PAIRS_PREFIX];
+    write_escaped(&key_bytes, &mut prefix);
+    prefix
+}
+
+/// Returns the storage key of the pair.
+fn pair_key<K: StorageKey + ?Sized, V: StorageKey + ?Sized>(key: &K, value: &V) -> Vec<u8> {
+    let mut buffer = pairs_prefix(key);
+    let prefix_len = buffer.len();
+    buffer.resize(prefix_len + value.size(), 0);
+    value.write(&mut buffer[prefix_len..]);
+    buffer
+}
+
+/// Returns the storage key of the number of values of the key.
+fn count_key<K: StorageKey + ?Sized>(key: &K) -> Vec<u8> {
+    let mut buffer = vec![COUNTS_PREFIX; 1 + key.size()];
+    key.write(&mut buffer[1..]);
+    buffer
+}
+
+#[cfg(test)]
+mod tests {
+    use storage::{Database, MemoryDB};
+    use super::MultiMapIndex;
+
+    const IDX_NAME: &str = "idx_name";
+
+    #[test]
+    fn duplicate_free_mode() {
+        let db = MemoryDB::new();
+        let mut fork = db.fork();
+        let mut index = MultiMapIndex::new(IDX_NAME, &mut fork);
+        assert!(index.insert(&1_u8, 10_u64));
+        assert!(index.insert(&1_u8, 5_u64));
+        assert!(!index.insert(&1_u8, 10_u64));
+        assert!(index.insert(&2_u8, 10_u64));
+
+        assert_eq!(index.get(&1).collect::<Vec<_>>(), vec![5, 10]);
+        assert_eq!(index.count(&1), 2);
+        assert!(index.remove(&1, &10));
+        assert!(!index.remove(&1, &10));
+        assert_eq!(index.get(&1).collect::<Vec<_>>(), vec![5]);
+        assert_eq!(index.get(&2).collect::<Vec<_>>(), vec![10]);
+    }
+
+    #[test]
+    fn duplicate_preserving_mode() {
+        let db = MemoryDB::new();
+        let mut fork = db.fork();
+        {
+            let mut index = MultiMapIndex::new(IDX_NAME, &mut fork).with_duplicates();
+            for &value in &[3_u64, 1, 3, 2, 3] {
+                assert!(index.insert(&1_u8, value));
+            }
+            assert!(index.remove(&1, &3));
+        }
+        db.merge(fork.into_patch()).unwrap();
+
+        let snapshot = db.snapshot();
+        let index: MultiMapIndex<_, u8, u64> = MultiMapIndex::new(IDX_NAME, &snapshot);
+        assert_eq!(index.get(&1).collect::<Vec<_>>(), vec![1, 2, 3, 3]);
+        assert_eq!(index.count(&1), 4);
+        assert!(index.contains(&1, &3));
+    }
+
+    #[test]
+    fn keys_of_variable_length() {
+        let db = MemoryDB::new();
+        let mut fork = db.fork();
+        let mut index = MultiMapIndex::new(IDX_NAME, &mut fork);
+        index.insert(&"a".to_owned(), "b".to_owned());
+        index.insert(&"ab".to_owned(), "c".to_owned());
+        index.insert(&"a\u{0}".to_owned(), "d".to_owned());
+
+        assert_eq!(index.get("a").collect::<Vec<_>>(), vec!["b"]);
+        assert_eq!(index.get("ab").collect::<Vec<_>>(), vec!["c"]);
+        assert_eq!(index.remove_all("a"), 1);
+        assert_eq!(index.count("ab"), 1);
+        assert_eq!(index.count("a\u{0}"), 1);
+        assert_eq!(index.remove_all("a"), 0);
+    }
+}
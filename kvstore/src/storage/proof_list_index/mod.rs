@@ -20,7 +20,7 @@ use std::cell::Cell;
 use std::marker::PhantomData;
 
 use crypto::{hash, Hash, HashStream};
-use super::{BaseIndex, BaseIndexIter, Fork, Snapshot, StorageKey, StorageValue};
+use super::{BaseIndex, BaseIndexIter, IndexAccess, IndexAccessMut, StorageKey, StorageValue};
 use super::indexes_metadata::IndexType;
 use self::key::ProofListKey;
 
@@ -66,7 +66,7 @@ fn pair_hash(h1: &Hash, h2: &Hash) -> Hash {
 
 impl<T, V> ProofListIndex<T, V>
 where
-    T: AsRef<Snapshot>,
+    T: IndexAccess,
     V: StorageValue,
 {
     /// Creates a new index representation based on the name and storage view.
@@ -139,6 +139,11 @@ where
         }
     }
 
+    /// Returns the storage view of the index, consuming the index.
+    pub fn into_view(self) -> T {
+        self.base.into_view()
+    }
+
     fn has_branch(&self, key: ProofListKey) -> bool {
         debug_assert!(key.height() > 0);
 
@@ -436,8 +441,9 @@ where
     }
 }
 
-impl<'a, V> ProofListIndex<&'a mut Fork, V>
+impl<T, V> ProofListIndex<T, V>
 where
+    T: IndexAccessMut,
     V: StorageValue,
 {
     fn set_len(&mut self, len: u64) {
@@ -590,7 +596,7 @@ where
 
 impl<'a, T, V> ::std::iter::IntoIterator for &'a ProofListIndex<T, V>
 where
-    T: AsRef<Snapshot>,
+    T: IndexAccess,
     V: StorageValue,
 {
     type Item = V;
@@ -16,8 +16,8 @@ use rand::{thread_rng, Rng};
 
 use crypto::{hash, CryptoHash, Hash};
 use storage::Database;
-use encoding::serialize::json::reexport::{from_str, to_string};
-use encoding::serialize::reexport::Serialize;
+use serde::Serialize;
+use serde_json::{from_str, to_string};
 use super::{pair_hash, ListProof, ProofListIndex};
 use self::ListProof::*;
 
@@ -14,7 +14,7 @@
 
 use std::cmp::{min, Ordering};
 
-use crypto::{CryptoHash, Hash, PublicKey, HASH_SIZE};
+use crypto::{CryptoHash, Hash, HASH_SIZE};
 use storage::StorageKey;
 
 pub const BRANCH_KEY_PREFIX: u8 = 0;
@@ -107,17 +107,17 @@ impl<T: HashedKey> ProofMapKey for T {
     }
 }
 
-impl ProofMapKey for PublicKey {
-    type Output = PublicKey;
-
-    fn write_key(&self, buffer: &mut [u8]) {
-        StorageKey::write(self, buffer);
-    }
-
-    fn read_key(raw: &[u8]) -> PublicKey {
-        <PublicKey as StorageKey>::read(raw)
-    }
-}
+//impl ProofMapKey for PublicKey {
+//    type Output = PublicKey;
+//
+//    fn write_key(&self, buffer: &mut [u8]) {
+//        StorageKey::write(self, buffer);
+//    }
+//
+//    fn read_key(raw: &[u8]) -> PublicKey {
+//        <PublicKey as StorageKey>::read(raw)
+//    }
+//}
 
 impl ProofMapKey for Hash {
     type Output = Hash;
@@ -21,7 +21,7 @@ use std::marker::PhantomData;
 use std::fmt;
 
 use crypto::{CryptoHash, Hash, HashStream};
-use super::{BaseIndex, BaseIndexIter, Fork, Snapshot, StorageKey, StorageValue};
+use super::{BaseIndex, BaseIndexIter, IndexAccess, IndexAccessMut, StorageKey, StorageValue};
 use super::indexes_metadata::IndexType;
 use self::key::{BitsRange, ChildKind, LEAF_KEY_PREFIX};
 use self::node::{BranchNode, Node};
@@ -41,12 +41,11 @@ mod proof;
 /// [`StorageValue`] trait.
 ///
 /// **The size of the proof map keys must be exactly 32 bytes and the keys must have a uniform
-/// distribution.** Usually [`Hash`] and [`PublicKey`] are used as types of proof map keys.
+/// distribution.** Usually [`Hash`] is used as the type of proof map keys.
 ///
 /// [`ProofMapKey`]: trait.ProofMapKey.html
 /// [`StorageValue`]: ../trait.StorageValue.html
 /// [`Hash`]: ../../crypto/struct.Hash.html
-/// [`PublicKey`]: ../../crypto/struct.PublicKey.html
 pub struct ProofMapIndex<T, K, V> {
     base: BaseIndex<T>,
     _k: PhantomData<K>,
@@ -103,7 +102,7 @@ enum RemoveResult {
 
 impl<T, K, V> ProofMapIndex<T, K, V>
 where
-    T: AsRef<Snapshot>,
+    T: IndexAccess,
     K: ProofMapKey,
     V: StorageValue,
 {
@@ -184,6 +183,11 @@ where
         }
     }
 
+    /// Returns the storage view of the index, consuming the index.
+    pub fn into_view(self) -> T {
+        self.base.into_view()
+    }
+
     fn get_root_path(&self) -> Option<ProofPath> {
         self.base
             .iter::<_, ProofPath, _>(&())
@@ -478,8 +482,9 @@ where
     }
 }
 
-impl<'a, K, V> ProofMapIndex<&'a mut Fork, K, V>
+impl<T, K, V> ProofMapIndex<T, K, V>
 where
+    T: IndexAccessMut,
     K: ProofMapKey,
     V: StorageValue,
 {
@@ -757,7 +762,7 @@ where
 
 impl<'a, T, K, V> ::std::iter::IntoIterator for &'a ProofMapIndex<T, K, V>
 where
-    T: AsRef<Snapshot>,
+    T: IndexAccess,
     K: ProofMapKey,
     V: StorageValue,
 {
@@ -807,7 +812,7 @@ where
 
 impl<T, K, V> fmt::Debug for ProofMapIndex<T, K, V>
 where
-    T: AsRef<Snapshot>,
+    T: IndexAccess,
     K: ProofMapKey,
     V: StorageValue + fmt::Debug,
 {
@@ -821,7 +826,7 @@ where
 
         impl<'a, T, K, V> Entry<'a, T, K, V>
         where
-            T: AsRef<Snapshot>,
+            T: IndexAccess,
             K: ProofMapKey,
             V: StorageValue,
         {
@@ -845,7 +850,7 @@ where
 
         impl<'a, T, K, V> fmt::Debug for Entry<'a, T, K, V>
         where
-            T: AsRef<Snapshot>,
+            T: IndexAccess,
             K: ProofMapKey,
             V: StorageValue + fmt::Debug,
         {
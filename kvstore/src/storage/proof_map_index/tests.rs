@@ -23,7 +23,7 @@ use std::hash::Hash as StdHash;
 
 use crypto::{hash, CryptoHash, Hash, HashStream};
 use storage::{Database, Fork, StorageValue};
-use encoding::serialize::reexport::Serialize;
+use serde::Serialize;
 use super::{HashedKey, MapProof, MapProofError, ProofMapIndex, ProofMapKey, ProofPath};
 use super::key::{BitsRange, ChildKind, KEY_SIZE, LEAF_KEY_PREFIX};
 use super::node::BranchNode;
@@ -1205,10 +1205,35 @@ fn iter(db: Box<Database>) {
 fn tree_with_hashed_key(db: Box<Database>) {
     use std::iter::FromIterator;
 
-    encoding_struct! {
-        struct Point {
-            x: u16,
-            y: u16,
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Point {
+        x: u16,
+        y: u16,
+    }
+
+    impl Point {
+        fn new(x: u16, y: u16) -> Self {
+            Point { x, y }
+        }
+    }
+
+    impl CryptoHash for Point {
+        fn hash(&self) -> Hash {
+            hash(&self.into_bytes())
+        }
+    }
+
+    impl StorageValue for Point {
+        fn into_bytes(self) -> Vec<u8> {
+            let mut buf = self.x.into_bytes();
+            buf.extend(self.y.into_bytes());
+            buf
+        }
+
+        fn from_bytes(value: ::std::borrow::Cow<[u8]>) -> Self {
+            let x = u16::from_bytes(value[0..2].into());
+            let y = u16::from_bytes(value[2..4].into());
+            Point { x, y }
         }
     }
 
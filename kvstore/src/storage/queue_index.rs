@@ -0,0 +1,723 @@
+// Copyright 2018 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! An implementation of durable FIFO queue with consumer cursors and acknowledgements.
+
+use byteorder::{BigEndian, ByteOrder};
+
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::marker::PhantomData;
+
+use crypto::{hash, CryptoHash, Hash};
+use super::{BaseIndex, BaseIndexIter, BaseIndexKeysIter, IndexAccess, IndexAccessMut,
+            StorageKey, StorageValue};
+use super::indexes_metadata::IndexType;
+use super::keys::write_escaped;
+
+/// Subprefix of the items, ordered by their ids.
+const ITEMS_PREFIX: u8 = 0;
+/// Subprefix of the ids of the first retained item and of the next pushed item.
+const BOUNDS_PREFIX: u8 = 1;
+/// Subprefix of the cursors of the consumers.
+const CURSORS_PREFIX: u8 = 2;
+/// Subprefix of the items received but not yet acknowledged by the consumers.
+const PENDING_PREFIX: u8 = 3;
+
+#[derive(Debug, Default, Clone, Copy)]
+struct QueueBounds {
+    /// Id of the first retained item.
+    head: u64,
+    /// Id of the next pushed item.
+    tail: u64,
+}
+
+impl QueueBounds {
+    fn to_array(&self) -> [u8; 16] {
+        let mut buf = [0; 16];
+        BigEndian::write_u64(&mut buf[0..8], self.head);
+        BigEndian::write_u64(&mut buf[8..16], self.tail);
+        buf
+    }
+}
+
+impl CryptoHash for QueueBounds {
+    fn hash(&self) -> Hash {
+        hash(&self.to_array())
+    }
+}
+
+impl StorageValue for QueueBounds {
+    fn into_bytes(self) -> Vec<u8> {
+        self.to_array().to_vec()
+    }
+
+    fn from_bytes(value: Cow<[u8]>) -> Self {
+        let buf = value.as_ref();
+        let head = BigEndian::read_u64(&buf[0..8]);
+        let tail = BigEndian::read_u64(&buf[8..16]);
+        QueueBounds { head, tail }
+    }
+}
+
+/// A durable FIFO queue of work items.
+///
+/// Each pushed item is assigned a sequential `u64` id. Items are read by named consumers,
+/// each of which has its own cursor, so every consumer receives every item in the order
+/// the items were pushed. An item received by a consumer stays pending until the consumer
+/// acknowledges it; as the cursors and the pending items are kept in the storage, a consumer
+/// can find the items it has not finished processing with [`pending`] after a restart.
+///
+/// Items are deleted once every subscribed consumer has received and acknowledged them.
+/// A consumer is subscribed on the first call to [`receive`] and starts from the oldest
+/// retained item; use [`subscribe`] to make the queue retain the items for a consumer before
+/// it starts receiving them. While there are no consumers, all the items are retained.
+/// `QueueIndex` requires that the items implement the [`StorageValue`] trait.
+///
+/// [`pending`]: #method.pending
+/// [`receive`]: #method.receive
+/// [`subscribe`]: #method.subscribe
+/// [`StorageValue`]: ../trait.StorageValue.html
+#[derive(Debug)]
+pub struct QueueIndex<T, V> {
+    base: BaseIndex<T>,
+    bounds: Cell<Option<QueueBounds>>,
+    _v: PhantomData<V>,
+}
+
+/// An iterator over the items of a `QueueIndex`.
+///
+/// This struct is created by the [`iter`] method on [`QueueIndex`].
+/// See its documentation for more.
+///
+/// [`iter`]: struct.QueueIndex.html#method.iter
+/// [`QueueIndex`]: struct.QueueIndex.html
+#[derive(Debug)]
+pub struct QueueIndexIter<'a, V> {
+    base_iter: BaseIndexIter<'a, Vec<u8>, V>,
+}
+
+/// An iterator over the items received but not yet acknowledged by a consumer
+/// of a `QueueIndex`.
+///
+/// This struct is created by the [`pending`] method on [`QueueIndex`].
+/// See its documentation for more.
+///
+/// [`pending`]: struct.QueueIndex.html#method.pending
+/// [`QueueIndex`]: struct.QueueIndex.html
+#[derive(Debug)]
+pub struct QueueIndexPending<'a, T: 'a, V> {
+    base: &'a BaseIndex<T>,
+    base_iter: BaseIndexKeysIter<'a, Vec<u8>>,
+    _v: PhantomData<V>,
+}
+
+impl<T, V> QueueIndex<T, V>
+where
+    T: IndexAccess,
+    V: StorageValue,
+{
+    /// Creates a new index representation based on the name and storage view.
+    ///
+    /// Storage view can be specified as [`&Snapshot`] or [`&mut Fork`]. In the first case only
+    /// immutable methods are available. In the second case both immutable and mutable methods are
+    /// available.
+    ///
+    /// [`&Snapshot`]: ../trait.Snapshot.html
+    /// [`&mut Fork`]: ../struct.Fork.html
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, QueueIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let snapshot = db.snapshot();
+    /// let name = "name";
+    /// let index: QueueIndex<_, u8> = QueueIndex::new(name, &snapshot);
+    /// ```
+    pub fn new<S: AsRef<str>>(index_name: S, view: T) -> Self {
+        QueueIndex {
+            base: BaseIndex::new(index_name, IndexType::Queue, view),
+            bounds: Cell::new(None),
+            _v: PhantomData,
+        }
+    }
+
+    /// Creates a new index representation based on the name, index id in family
+    /// and storage view.
+    ///
+    /// Storage view can be specified as [`&Snapshot`] or [`&mut Fork`]. In the first case only
+    /// immutable methods are available. In the second case both immutable and mutable methods are
+    /// available.
+    ///
+    /// [`&Snapshot`]: ../trait.Snapshot.html
+    /// [`&mut Fork`]: ../struct.Fork.html
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, QueueIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let snapshot = db.snapshot();
+    /// let name = "name";
+    /// let index_id = vec![123];
+    /// let index: QueueIndex<_, u8> = QueueIndex::new_in_family(
+    ///     name,
+    ///     &index_id,
+    ///     &snapshot,
+    ///  );
+    /// ```
+    pub fn new_in_family<S: AsRef<str>, I: StorageKey>(
+        family_name: S,
+        index_id: &I,
+        view: T,
+    ) -> Self {
+        QueueIndex {
+            base: BaseIndex::new_in_family(family_name, index_id, IndexType::Queue, view),
+            bounds: Cell::new(None),
+            _v: PhantomData,
+        }
+    }
+
+    /// Returns the storage view of the index, consuming the index.
+    pub fn into_view(self) -> T {
+        self.base.into_view()
+    }
+
+    fn bounds(&self) -> QueueBounds {
+        if let Some(bounds) = self.bounds.get() {
+            return bounds;
+        }
+        let bounds = self.base.get(&BOUNDS_PREFIX).unwrap_or_default();
+        self.bounds.set(Some(bounds));
+        bounds
+    }
+
+    /// Returns the item with the specified id, or `None` if there is no such item or it has
+    /// already been deleted.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, QueueIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let mut fork = db.fork();
+    /// let mut index = QueueIndex::new("name", &mut fork);
+    /// assert_eq!(None, index.get(0));
+    ///
+    /// let id = index.push(42);
+    /// assert_eq!(Some(42), index.get(id));
+    /// ```
+    pub fn get(&self, id: u64) -> Option<V> {
+        self.base.get(&item_key(id))
+    }
+
+    /// Returns `true` if the queue retains no items.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, QueueIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let mut fork = db.fork();
+    /// let mut index = QueueIndex::new("name", &mut fork);
+    /// assert!(index.is_empty());
+    ///
+    /// index.push(1);
+    /// assert!(!index.is_empty());
+    /// ```
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the number of items retained by the queue, including the items which are
+    /// received by some consumers, but not yet acknowledged by all of them.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, QueueIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let mut fork = db.fork();
+    /// let mut index = QueueIndex::new("name", &mut fork);
+    /// assert_eq!(0, index.len());
+    ///
+    /// index.push(1);
+    /// index.push(2);
+    /// assert_eq!(2, index.len());
+    /// ```
+    pub fn len(&self) -> u64 {
+        let bounds = self.bounds();
+        bounds.tail - bounds.head
+    }
+
+    /// Returns an iterator over the retained items in the order they were pushed.
+    /// The iterator element type is (u64, V), where the first element is the id of the item.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, QueueIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let mut fork = db.fork();
+    /// let mut index = QueueIndex::new("name", &mut fork);
+    ///
+    /// index.push(1);
+    /// index.push(2);
+    /// assert_eq!(vec![(0, 1), (1, 2)], index.iter().collect::<Vec<(u64, u8)>>());
+    /// ```
+    pub fn iter(&self) -> QueueIndexIter<V> {
+        QueueIndexIter {
+            base_iter: self.base.iter(&ITEMS_PREFIX),
+        }
+    }
+
+    /// Returns the id of the next item the consumer will receive, or `None` if the consumer
+    /// is not subscribed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, QueueIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let mut fork = db.fork();
+    /// let mut index = QueueIndex::new("name", &mut fork);
+    /// assert_eq!(None, index.cursor("worker"));
+    ///
+    /// index.push(1);
+    /// index.receive("worker");
+    /// assert_eq!(Some(1), index.cursor("worker"));
+    /// ```
+    pub fn cursor(&self, consumer: &str) -> Option<u64> {
+        self.base.get(&cursor_key(consumer))
+    }
+
+    /// Returns an iterator over the items received by the consumer, but not yet acknowledged,
+    /// in the order they were pushed. The iterator element type is (u64, V), where the first
+    /// element is the id of the item.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, QueueIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let mut fork = db.fork();
+    /// let mut index = QueueIndex::new("name", &mut fork);
+    ///
+    /// index.push(1);
+    /// index.push(2);
+    /// index.receive("worker");
+    /// index.receive("worker");
+    /// index.acknowledge("worker", 0);
+    /// assert_eq!(vec![(1, 2)], index.pending("worker").collect::<Vec<(u64, u8)>>());
+    /// ```
+    pub fn pending(&self, consumer: &str) -> QueueIndexPending<T, V> {
+        QueueIndexPending {
+            base: &self.base,
+            base_iter: self.base.iter_keys(&pending_prefix(consumer)),
+            _v: PhantomData,
+        }
+    }
+
+    /// Returns the id of the oldest item some consumer still needs, or `None` if there are
+    /// no consumers.
+    fn low_watermark(&self) -> Option<u64> {
+        let mut low_watermark = None;
+        for (key, cursor) in self.base.iter::<_, Vec<u8>, u64>(&CURSORS_PREFIX) {
+            let consumer = String::from_utf8_lossy(&key[1..]);
+            let first_pending = self.base
+                .iter_keys::<_, Vec<u8>>(&pending_prefix(&consumer))
+                .next()
+                .map(|key| pending_id(&key));
+            let needed = first_pending.unwrap_or(cursor);
+            low_watermark = Some(match low_watermark {
+                Some(low) if low < needed => low,
+                _ => needed,
+            });
+        }
+        low_watermark
+    }
+}
+
+impl<T, V> QueueIndex<T, V>
+where
+    T: IndexAccessMut,
+    V: StorageValue,
+{
+    fn set_bounds(&mut self, bounds: QueueBounds) {
+        self.base.put(&BOUNDS_PREFIX, bounds);
+        self.bounds.set(Some(bounds));
+    }
+
+    /// Appends an item to the back of the queue and returns its id.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, QueueIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let mut fork = db.fork();
+    /// let mut index = QueueIndex::new("name", &mut fork);
+    ///
+    /// assert_eq!(0, index.push(1));
+    /// assert_eq!(1, index.push(2));
+    /// ```
+    pub fn push(&mut self, value: V) -> u64 {
+        let mut bounds = self.bounds();
+        let id = bounds.tail;
+        self.base.put(&item_key(id), value);
+        bounds.tail += 1;
+        self.set_bounds(bounds);
+        id
+    }
+
+    /// Subscribes the consumer to the queue, so that the items are retained until
+    /// the consumer acknowledges them. A new consumer starts from the oldest retained item.
+    /// Does nothing if the consumer is already subscribed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, QueueIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let mut fork = db.fork();
+    /// let mut index = QueueIndex::new("name", &mut fork);
+    ///
+    /// index.subscribe("worker");
+    /// assert_eq!(Some(0), index.cursor("worker"));
+    /// ```
+    pub fn subscribe(&mut self, consumer: &str) {
+        let cursor_key = cursor_key(consumer);
+        if !self.base.contains(&cursor_key) {
+            let head = self.bounds().head;
+            self.base.put(&cursor_key, head);
+        }
+    }
+
+    /// Unsubscribes the consumer from the queue, forgetting its cursor and pending items.
+    /// Items which are no longer needed by the other consumers are deleted.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, QueueIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let mut fork = db.fork();
+    /// let mut index = QueueIndex::new("name", &mut fork);
+    ///
+    /// index.subscribe("worker");
+    /// index.unsubscribe("worker");
+    /// assert_eq!(None, index.cursor("worker"));
+    /// ```
+    pub fn unsubscribe(&mut self, consumer: &str) {
+        let pending_keys = self.base
+            .iter_keys::<_, Vec<u8>>(&pending_prefix(consumer))
+            .collect::<Vec<_>>();
+        for key in pending_keys {
+            self.base.remove(&key);
+        }
+        self.base.remove(&cursor_key(consumer));
+        self.delete_acknowledged();
+    }
+
+    /// Delivers the next item to the consumer and returns it together with its id, or `None`
+    /// if the consumer has received all the items. The item stays pending for the consumer
+    /// until it is acknowledged. Subscribes the consumer if it is not subscribed yet.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, QueueIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let mut fork = db.fork();
+    /// let mut index = QueueIndex::new("name", &mut fork);
+    /// assert_eq!(None, index.receive("worker"));
+    ///
+    /// index.push(1);
+    /// assert_eq!(Some((0, 1)), index.receive("worker"));
+    /// assert_eq!(None, index.receive("worker"));
+    /// ```
+    pub fn receive(&mut self, consumer: &str) -> Option<(u64, V)> {
+        self.subscribe(consumer);
+        let cursor_key = cursor_key(consumer);
+        let id: u64 = self.base.get(&cursor_key).unwrap();
+        if id == self.bounds().tail {
+            return None;
+        }
+        let value = self.get(id).expect("Item needed by the consumer is missing");
+        self.base.put(&pending_key(consumer, id), ());
+        self.base.put(&cursor_key, id + 1);
+        Some((id, value))
+    }
+
+    /// Acknowledges that the consumer has processed the item with the specified id. Returns
+    /// `true` if the item was pending for the consumer. Items which are acknowledged by all
+    /// the consumers are deleted.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, QueueIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let mut fork = db.fork();
+    /// let mut index = QueueIndex::new("name", &mut fork);
+    ///
+    /// index.push(1);
+    /// let (id, _) = index.receive("worker").unwrap();
+    /// assert!(index.acknowledge("worker", id));
+    /// assert!(!index.acknowledge("worker", id));
+    /// assert!(index.is_empty());
+    /// ```
+    pub fn acknowledge(&mut self, consumer: &str, id: u64) -> bool {
+        let pending_key = pending_key(consumer, id);
+        if !self.base.contains(&pending_key) {
+            return false;
+        }
+        self.base.remove(&pending_key);
+        self.delete_acknowledged();
+        true
+    }
+
+    /// Clears the queue, removing all items, cursors and pending items.
+    ///
+    /// # Notes
+    ///
+    /// Currently this method is not optimized to delete large set of data. During the execution of
+    /// this method the amount of allocated memory is linearly dependent on the number of elements
+    /// in the index.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, QueueIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let mut fork = db.fork();
+    /// let mut index = QueueIndex::new("name", &mut fork);
+    ///
+    /// index.push(1);
+    /// assert!(!index.is_empty());
+    ///
+    /// index.clear();
+    /// assert!(index.is_empty());
+    /// ```
+    pub fn clear(&mut self) {
+        self.bounds.set(Some(QueueBounds::default()));
+        self.base.clear()
+    }
+
+    /// Deletes the items which are no longer needed by any consumer.
+    fn delete_acknowledged(&mut self) {
+        let low_watermark = match self.low_watermark() {
+            Some(low_watermark) => low_watermark,
+            None => return,
+        };
+        let mut bounds = self.bounds();
+        if low_watermark <= bounds.head {
+            return;
+        }
+        for id in bounds.head..low_watermark {
+            self.base.remove(&item_key(id));
+        }
+        bounds.head = low_watermark;
+        self.set_bounds(bounds);
+    }
+}
+
+impl<'a, T, V> ::std::iter::IntoIterator for &'a QueueIndex<T, V>
+where
+    T: IndexAccess,
+    V: StorageValue,
+{
+    type Item = (u64, V);
+    type IntoIter = QueueIndexIter<'a, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a, V> Iterator for QueueIndexIter<'a, V>
+where
+    V: StorageValue,
+{
+    type Item = (u64, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.base_iter
+            .next()
+            .map(|(key, value)| (BigEndian::read_u64(&key[1..]), value))
+    }
+}
+
+impl<'a, T, V> Iterator for QueueIndexPending<'a, T, V>
+where
+    T: IndexAccess,
+    V: StorageValue,
+{
+    type Item = (u64, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let key = match self.base_iter.next() {
+            Some(key) => key,
+            None => return None,
+        };
+        let id = pending_id(&key);
+        let value = self.base
+            .get(&item_key(id))
+            .expect("Pending item is missing");
+        Some((id, value))
+    }
+}
+
+/// Returns the storage key of the item.
+fn item_key(id: u64) -> Vec<u8> {
+    let mut buffer = vec![ITEMS_PREFIX; 9];
+    BigEndian::write_u64(&mut buffer[1..], id);
+    buffer
+}
+
+/// Returns the storage key of the cursor of the consumer.
+fn cursor_key(consumer: &str) -> Vec<u8> {
+    let mut buffer = vec![CURSORS_PREFIX];
+    buffer.extend_from_slice(consumer.as_bytes());
+    buffer
+}
+
+/// Returns the common prefix of the storage keys of the items pending for the consumer.
+fn pending_prefix(consumer: &str) -> Vec<u8> {
+    let mut prefix = vec![PENDING_PREFIX];
+    write_escaped(consumer.as_bytes(), &mut prefix);
+    prefix
+}
+
+/// Returns the storage key of the item pending for the consumer.
+fn pending_key(consumer: &str, id: u64) -> Vec<u8> {
+    let mut buffer = pending_prefix(consumer);
+    let prefix_len = buffer.len();
+    buffer.resize(prefix_len + 8, 0);
+    BigEndian::write_u64(&mut buffer[prefix_len..], id);
+    buffer
+}
+
+/// Extracts the item id from the storage key of a pending item.
+fn pending_id(key: &[u8]) -> u64 {
+    BigEndian::read_u64(&key[key.len() - 8..])
+}
+
+#[cfg(test)]
+mod tests {
+    use storage::{Database, MemoryDB};
+    use super::QueueIndex;
+
+    #[test]
+    fn consumers_receive_all_items() {
+        let db = MemoryDB::new();
+        let mut fork = db.fork();
+        let mut index = QueueIndex::new("queue", &mut fork);
+
+        index.subscribe("a");
+        index.subscribe("b");
+        for i in 0..3_u32 {
+            index.push(i * 10);
+        }
+        assert_eq!(index.receive("a"), Some((0, 0)));
+        assert_eq!(index.receive("a"), Some((1, 10)));
+        assert_eq!(index.receive("b"), Some((0, 0)));
+        assert_eq!(index.cursor("a"), Some(2));
+        assert_eq!(index.cursor("b"), Some(1));
+
+        // Items are kept until both consumers acknowledge them.
+        assert!(index.acknowledge("a", 0));
+        assert!(index.acknowledge("a", 1));
+        assert_eq!(index.len(), 3);
+        assert!(index.acknowledge("b", 0));
+        assert_eq!(index.len(), 2);
+        assert_eq!(index.get(0), None);
+        assert_eq!(index.iter().collect::<Vec<_>>(), vec![(1, 10), (2, 20)]);
+
+        // The unsubscribed consumer no longer holds back the items.
+        index.unsubscribe("b");
+        assert_eq!(index.iter().collect::<Vec<_>>(), vec![(2, 20)]);
+        assert!(!index.acknowledge("b", 1));
+    }
+
+    #[test]
+    fn pending_items_survive_restart() {
+        let db = MemoryDB::new();
+        let mut fork = db.fork();
+        {
+            let mut index = QueueIndex::new("queue", &mut fork);
+            index.push(1_u32);
+            index.push(2);
+            index.push(3);
+            assert_eq!(index.receive("worker"), Some((0, 1)));
+            assert_eq!(index.receive("worker"), Some((1, 2)));
+            // An acknowledgement out of order doesn't delete the earlier item.
+            assert!(index.acknowledge("worker", 1));
+            assert_eq!(index.len(), 3);
+        }
+        db.merge(fork.into_patch()).unwrap();
+
+        let mut fork = db.fork();
+        let mut index: QueueIndex<_, u32> = QueueIndex::new("queue", &mut fork);
+        assert_eq!(index.pending("worker").collect::<Vec<_>>(), vec![(0, 1)]);
+        assert!(index.acknowledge("worker", 0));
+        assert_eq!(index.len(), 1);
+        assert_eq!(index.receive("worker"), Some((2, 3)));
+        assert_eq!(index.receive("worker"), None);
+    }
+
+    #[test]
+    fn late_consumer_starts_from_oldest_item() {
+        let db = MemoryDB::new();
+        let mut fork = db.fork();
+        let mut index = QueueIndex::new("queue", &mut fork);
+
+        index.push(1_u32);
+        index.push(2);
+        assert_eq!(index.receive("a"), Some((0, 1)));
+        assert!(index.acknowledge("a", 0));
+        assert_eq!(index.receive("b"), Some((1, 2)));
+        assert_eq!(index.receive("b"), None);
+    }
+
+    #[test]
+    fn consumers_with_common_name_prefix() {
+        let db = MemoryDB::new();
+        let mut fork = db.fork();
+        let mut index = QueueIndex::new("queue", &mut fork);
+
+        index.push(1_u32);
+        index.receive("a");
+        index.receive("ab");
+        assert!(index.acknowledge("ab", 0));
+        assert_eq!(index.pending("a").collect::<Vec<_>>(), vec![(0, 1)]);
+        assert_eq!(index.pending("ab").count(), 0);
+        assert_eq!(index.len(), 1);
+    }
+}
@@ -0,0 +1,922 @@
+// Copyright 2018 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Leader/follower replication of a database over TCP.
+//!
+//! A [`Leader`] wraps a database and assigns consecutive sequence numbers to the merged
+//! patches. The last patches are kept in an in-memory log and streamed to every connected
+//! [`Follower`], which applies them to its own database. The sequence number of the last
+//! applied patch is stored in the database together with the patch itself, so a follower
+//! that reconnects resumes from where it stopped. If the patches a follower needs are no
+//! longer in the log, the leader sends all the column families of its current state
+//! instead, and the follower replaces its data with them.
+//!
+//! # Protocol
+//!
+//! Both sides exchange frames consisting of a one-byte frame type, a big-endian `u32`
+//! payload length and the payload, which is at most 256 MiB long. A follower starts with
+//! a handshake frame containing the sequence number of the last applied patch, and
+//! acknowledges every applied patch or state with its sequence number. A leader sends
+//! either a patch, or its state split into several frames, each prefixed with a sequence
+//! number and a flag marking the last frame of the state.
+//!
+//! [`Leader`]: struct.Leader.html
+//! [`Follower`]: struct.Follower.html
+//!
+//! # Examples
+//!
+//! ```
+//! use exonum::storage::{Database, Entry, MemoryDB};
+//! use exonum::storage::replication::{Follower, Leader};
+//! use std::time::Duration;
+//!
+//! let leader = Leader::new(MemoryDB::new(), 1024);
+//! let addr = leader.listen("127.0.0.1:0").unwrap();
+//! let follower = Follower::connect(MemoryDB::new(), addr).unwrap();
+//!
+//! let mut fork = leader.fork();
+//! Entry::new("entry", &mut fork).set(42_u64);
+//! leader.merge(fork.into_patch()).unwrap();
+//!
+//! assert!(follower.wait_for(leader.sequence(), Duration::from_secs(5)));
+//! let snapshot = follower.database().snapshot();
+//! assert_eq!(Entry::<_, u64>::new("entry", &snapshot).get(), Some(42));
+//! ```
+
+use byteorder::{BigEndian, ByteOrder};
+
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use super::{diff, Database, Error, MemoryDB, Patch, Result, Snapshot};
+use super::db::Change;
+
+/// Column family with the sequence number of the last merged patch.
+const META_CF: &str = "__REPLICATION_META__";
+const SEQUENCE_KEY: &[u8] = b"sequence";
+
+const HELLO: u8 = 0;
+const ACK: u8 = 1;
+const PATCH: u8 = 2;
+const SNAPSHOT: u8 = 3;
+
+const TAG_DELETE: u8 = 0;
+const TAG_PUT: u8 = 1;
+
+/// Maximum length of a frame payload.
+const MAX_FRAME_SIZE: usize = 256 * 1024 * 1024;
+/// Approximate length of the leader state sent in a single frame.
+const STATE_CHUNK_SIZE: usize = 4 * 1024 * 1024;
+
+/// Interval of checking whether the leader is closed or a follower is disconnected.
+const POLL_INTERVAL_MS: u64 = 10;
+
+/// Database wrapper that streams the merged patches to the connected followers.
+///
+/// The leader keeps the last `log_capacity` patches in memory. A follower that is further
+/// behind receives a full export of the leader state. Replication stops when the leader
+/// is dropped.
+pub struct Leader<D> {
+    shared: Arc<LeaderShared<D>>,
+}
+
+/// Replication state of a follower connected to a leader.
+#[derive(Debug, Clone)]
+pub struct FollowerStatus {
+    /// Address of the follower.
+    pub addr: SocketAddr,
+    /// Sequence number of the last patch acknowledged by the follower.
+    pub acked_sequence: u64,
+    /// Number of patches merged into the leader but not acknowledged by the follower yet.
+    pub lag: u64,
+    /// Time elapsed since the follower connected or sent the last acknowledgement.
+    pub since_last_ack: Duration,
+}
+
+struct LeaderShared<D> {
+    db: D,
+    log_capacity: usize,
+    log: Mutex<Log>,
+    appended: Condvar,
+    followers: Mutex<Followers>,
+    closed: AtomicBool,
+}
+
+struct Log {
+    sequence: u64,
+    entries: VecDeque<(u64, Arc<Vec<u8>>)>,
+}
+
+struct Followers {
+    next_id: u64,
+    entries: BTreeMap<u64, FollowerEntry>,
+}
+
+struct FollowerEntry {
+    addr: SocketAddr,
+    acked: u64,
+    last_ack: Instant,
+}
+
+enum Pending {
+    Export(u64, Box<Snapshot>),
+    Patches(Vec<(u64, Arc<Vec<u8>>)>),
+}
+
+impl<D: Database> Leader<D> {
+    /// Wraps the database, keeping at most `log_capacity` last patches for the followers.
+    /// Sequence numbers continue from the last patch merged through a `Leader` before.
+    pub fn new(db: D, log_capacity: usize) -> Self {
+        let sequence = read_sequence(&*db.snapshot());
+        Leader {
+            shared: Arc::new(LeaderShared {
+                db,
+                log_capacity,
+                log: Mutex::new(Log {
+                    sequence,
+                    entries: VecDeque::new(),
+                }),
+                appended: Condvar::new(),
+                followers: Mutex::new(Followers {
+                    next_id: 0,
+                    entries: BTreeMap::new(),
+                }),
+                closed: AtomicBool::new(false),
+            }),
+        }
+    }
+
+    /// Returns a reference to the underlying database.
+    pub fn database(&self) -> &D {
+        &self.shared.db
+    }
+
+    /// Returns the sequence number of the last merged patch, or `0` if there were no merges.
+    pub fn sequence(&self) -> u64 {
+        self.shared.log.lock().unwrap().sequence
+    }
+
+    /// Starts accepting followers on the given address in a background thread.
+    ///
+    /// Returns the address the leader is listening on, which is useful when binding
+    /// to port `0`.
+    pub fn listen<A: ToSocketAddrs>(&self, addr: A) -> Result<SocketAddr> {
+        let listener = TcpListener::bind(addr).map_err(io_error)?;
+        let local_addr = listener.local_addr().map_err(io_error)?;
+        listener.set_nonblocking(true).map_err(io_error)?;
+        let shared = Arc::clone(&self.shared);
+        thread::spawn(move || accept_followers(&shared, &listener));
+        Ok(local_addr)
+    }
+
+    /// Returns the replication state of the connected followers.
+    pub fn followers(&self) -> Vec<FollowerStatus> {
+        let sequence = self.sequence();
+        let followers = self.shared.followers.lock().unwrap();
+        let statuses = followers
+            .entries
+            .values()
+            .map(|entry| FollowerStatus {
+                addr: entry.addr,
+                acked_sequence: entry.acked,
+                lag: sequence.saturating_sub(entry.acked),
+                since_last_ack: entry.last_ack.elapsed(),
+            })
+            .collect();
+        statuses
+    }
+
+    fn do_merge<F>(&self, mut patch: Patch, merge: F) -> Result<()>
+    where
+        F: FnOnce(&D, Patch) -> Result<()>,
+    {
+        let mut log = self.shared.log.lock().unwrap();
+        let sequence = log.sequence + 1;
+        patch.insert_change(
+            META_CF,
+            SEQUENCE_KEY.to_vec(),
+            Change::Put(encode_u64(sequence)),
+        );
+        let encoded = encode_patch(&patch);
+        // The sequence number is sent in the same frame as the patch.
+        if encoded.len() + 8 > MAX_FRAME_SIZE {
+            return Err(Error::new(format!(
+                "Encoded patch of {} bytes exceeds the maximum replication frame size",
+                encoded.len()
+            )));
+        }
+        merge(&self.shared.db, patch)?;
+
+        log.sequence = sequence;
+        log.entries.push_back((sequence, Arc::new(encoded)));
+        while log.entries.len() > self.shared.log_capacity {
+            log.entries.pop_front();
+        }
+        self.shared.appended.notify_all();
+        Ok(())
+    }
+}
+
+impl<D: Database> Database for Leader<D> {
+    fn snapshot(&self) -> Box<Snapshot> {
+        self.shared.db.snapshot()
+    }
+
+    fn merge(&self, patch: Patch) -> Result<()> {
+        self.do_merge(patch, |db, patch| db.merge(patch))
+    }
+
+    fn merge_sync(&self, patch: Patch) -> Result<()> {
+        self.do_merge(patch, |db, patch| db.merge_sync(patch))
+    }
+}
+
+impl<D> Drop for Leader<D> {
+    fn drop(&mut self) {
+        self.shared.closed.store(true, Ordering::SeqCst);
+        self.shared.appended.notify_all();
+    }
+}
+
+impl<D> fmt::Debug for Leader<D> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Leader")
+            .field("sequence", &self.shared.log.lock().unwrap().sequence)
+            .field("log_capacity", &self.shared.log_capacity)
+            .finish()
+    }
+}
+
+fn accept_followers<D: Database>(shared: &Arc<LeaderShared<D>>, listener: &TcpListener) {
+    while !shared.closed.load(Ordering::SeqCst) {
+        match listener.accept() {
+            Ok((stream, addr)) => {
+                let shared = Arc::clone(shared);
+                thread::spawn(move || serve_follower(&shared, stream, addr));
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                thread::sleep(Duration::from_millis(POLL_INTERVAL_MS));
+            }
+            Err(_) => break,
+        }
+    }
+}
+
+fn serve_follower<D: Database>(
+    shared: &Arc<LeaderShared<D>>,
+    mut stream: TcpStream,
+    addr: SocketAddr,
+) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    let (kind, payload) = read_frame(&mut stream)?;
+    if kind != HELLO || payload.len() != 8 {
+        return Err(invalid_data("Expected a handshake"));
+    }
+    let acked = BigEndian::read_u64(&payload);
+
+    let id = {
+        let mut followers = shared.followers.lock().unwrap();
+        let id = followers.next_id;
+        followers.next_id += 1;
+        followers.entries.insert(
+            id,
+            FollowerEntry {
+                addr,
+                acked,
+                last_ack: Instant::now(),
+            },
+        );
+        id
+    };
+
+    let connected = Arc::new(AtomicBool::new(true));
+    let acks = {
+        let shared = Arc::clone(shared);
+        let connected = Arc::clone(&connected);
+        let mut reader = stream.try_clone()?;
+        thread::spawn(move || {
+            let result = read_acks(&shared, id, &mut reader);
+            connected.store(false, Ordering::SeqCst);
+            result
+        })
+    };
+
+    let result = send_updates(shared, &mut stream, acked + 1, &connected);
+    let _ = stream.shutdown(Shutdown::Both);
+    let _ = acks.join();
+    shared.followers.lock().unwrap().entries.remove(&id);
+    result
+}
+
+fn read_acks<D>(shared: &LeaderShared<D>, id: u64, reader: &mut TcpStream) -> io::Result<()> {
+    loop {
+        let (kind, payload) = read_frame(reader)?;
+        if kind != ACK || payload.len() != 8 {
+            return Err(invalid_data("Expected an acknowledgement"));
+        }
+        let mut followers = shared.followers.lock().unwrap();
+        if let Some(entry) = followers.entries.get_mut(&id) {
+            entry.acked = BigEndian::read_u64(&payload);
+            entry.last_ack = Instant::now();
+        }
+    }
+}
+
+/// Sends the patches starting from the `next` sequence number until the leader is closed
+/// or the follower disconnects.
+fn send_updates<D: Database>(
+    shared: &LeaderShared<D>,
+    stream: &mut TcpStream,
+    mut next: u64,
+    connected: &AtomicBool,
+) -> io::Result<()> {
+    let poll_interval = Duration::from_millis(POLL_INTERVAL_MS);
+    loop {
+        let pending = {
+            let mut log = shared.log.lock().unwrap();
+            while next == log.sequence + 1 {
+                if shared.closed.load(Ordering::SeqCst) || !connected.load(Ordering::SeqCst) {
+                    return Ok(());
+                }
+                log = shared.appended.wait_timeout(log, poll_interval).unwrap().0;
+            }
+            let oldest = log.entries.front().map_or(log.sequence + 1, |entry| entry.0);
+            // A follower which is ahead of the leader has diverged and is reset as well.
+            if next < oldest || next > log.sequence + 1 {
+                Pending::Export(log.sequence, shared.db.snapshot())
+            } else {
+                Pending::Patches(
+                    log.entries
+                        .iter()
+                        .filter(|entry| entry.0 >= next)
+                        .cloned()
+                        .collect(),
+                )
+            }
+        };
+
+        match pending {
+            Pending::Export(sequence, snapshot) => {
+                send_state(stream, sequence, &*snapshot)?;
+                next = sequence + 1;
+            }
+            Pending::Patches(entries) => for (sequence, patch) in entries {
+                write_frame(stream, PATCH, &[&encode_u64(sequence)[..], &patch[..]])?;
+                next = sequence + 1;
+            },
+        }
+    }
+}
+
+/// Sends all the column families of the snapshot as patches of about `STATE_CHUNK_SIZE`
+/// bytes, each in a separate frame.
+fn send_state<W: Write>(writer: &mut W, sequence: u64, snapshot: &Snapshot) -> io::Result<()> {
+    let mut chunk = Patch::new();
+    let mut chunk_size = 0;
+    for name in snapshot.column_families() {
+        let mut iter = snapshot.iter(&name, &[]);
+        while let Some((key, value)) = iter.next() {
+            chunk.insert_change(&name, key.to_vec(), Change::Put(value.to_vec()));
+            chunk_size += name.len() + key.len() + value.len();
+            if chunk_size >= STATE_CHUNK_SIZE {
+                write_state_chunk(writer, sequence, &chunk, false)?;
+                chunk = Patch::new();
+                chunk_size = 0;
+            }
+        }
+    }
+    write_state_chunk(writer, sequence, &chunk, true)
+}
+
+fn write_state_chunk<W: Write>(
+    writer: &mut W,
+    sequence: u64,
+    chunk: &Patch,
+    last: bool,
+) -> io::Result<()> {
+    let encoded = encode_patch(chunk);
+    write_frame(
+        writer,
+        SNAPSHOT,
+        &[&encode_u64(sequence)[..], &[last as u8], &encoded[..]],
+    )
+}
+
+/// Replica of a leader database.
+///
+/// A follower applies the patches received from the leader to its database in a background
+/// thread until it is disconnected. The database should not be modified by other means.
+pub struct Follower<D> {
+    shared: Option<Arc<FollowerShared<D>>>,
+    stream: TcpStream,
+    thread: Option<JoinHandle<()>>,
+}
+
+struct FollowerShared<D> {
+    db: D,
+    state: Mutex<FollowerState>,
+    applied: Condvar,
+}
+
+struct FollowerState {
+    sequence: u64,
+    connected: bool,
+    error: Option<Error>,
+}
+
+impl<D: Database> Follower<D> {
+    /// Connects to the leader and starts replication into the database. Replication resumes
+    /// from the last patch applied to the database by a `Follower`.
+    pub fn connect<A: ToSocketAddrs>(db: D, leader: A) -> Result<Self> {
+        let sequence = read_sequence(&*db.snapshot());
+        let mut stream = TcpStream::connect(leader).map_err(io_error)?;
+        write_frame(&mut stream, HELLO, &[&encode_u64(sequence)[..]]).map_err(io_error)?;
+        let reader = stream.try_clone().map_err(io_error)?;
+
+        let shared = Arc::new(FollowerShared {
+            db,
+            state: Mutex::new(FollowerState {
+                sequence,
+                connected: true,
+                error: None,
+            }),
+            applied: Condvar::new(),
+        });
+        let thread = {
+            let shared = Arc::clone(&shared);
+            thread::spawn(move || follow(&shared, reader))
+        };
+        Ok(Follower {
+            shared: Some(shared),
+            stream,
+            thread: Some(thread),
+        })
+    }
+
+    /// Returns a reference to the replicated database.
+    pub fn database(&self) -> &D {
+        &self.shared().db
+    }
+
+    /// Returns the sequence number of the last applied patch.
+    pub fn sequence(&self) -> u64 {
+        self.shared().state.lock().unwrap().sequence
+    }
+
+    /// Returns `true` if the follower is still connected to the leader.
+    pub fn is_connected(&self) -> bool {
+        self.shared().state.lock().unwrap().connected
+    }
+
+    /// Returns the error which has stopped replication, if any.
+    pub fn error(&self) -> Option<Error> {
+        self.shared().state.lock().unwrap().error.clone()
+    }
+
+    /// Blocks until the patch with the given sequence number is applied. Returns `false`
+    /// if it has not been applied before the timeout expired or the follower disconnected.
+    pub fn wait_for(&self, sequence: u64, timeout: Duration) -> bool {
+        let shared = self.shared();
+        let deadline = Instant::now() + timeout;
+        let mut state = shared.state.lock().unwrap();
+        while state.sequence < sequence && state.connected {
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+            state = shared.applied.wait_timeout(state, deadline - now).unwrap().0;
+        }
+        state.sequence >= sequence
+    }
+
+    /// Stops replication and returns the database.
+    pub fn disconnect(mut self) -> D {
+        self.stop();
+        let shared = self.shared.take().unwrap();
+        match Arc::try_unwrap(shared) {
+            Ok(shared) => shared.db,
+            Err(_) => panic!("Replication thread is still running"),
+        }
+    }
+
+    fn shared(&self) -> &FollowerShared<D> {
+        self.shared.as_ref().unwrap()
+    }
+}
+
+impl<D> Follower<D> {
+    fn stop(&mut self) {
+        let _ = self.stream.shutdown(Shutdown::Both);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl<D> Drop for Follower<D> {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+impl<D> fmt::Debug for Follower<D> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let state = self.shared.as_ref().map(|shared| {
+            let state = shared.state.lock().unwrap();
+            (state.sequence, state.connected)
+        });
+        f.debug_struct("Follower").field("state", &state).finish()
+    }
+}
+
+fn follow<D: Database>(shared: &FollowerShared<D>, mut stream: TcpStream) {
+    let result = apply_updates(shared, &mut stream);
+    let mut state = shared.state.lock().unwrap();
+    state.connected = false;
+    if let Err(e) = result {
+        state.error = Some(e);
+    }
+    shared.applied.notify_all();
+}
+
+fn apply_updates<D: Database>(shared: &FollowerShared<D>, stream: &mut TcpStream) -> Result<()> {
+    // The leader state received so far.
+    let mut state: Option<MemoryDB> = None;
+    loop {
+        let (kind, payload) = read_frame(stream).map_err(io_error)?;
+        if payload.len() < 8 {
+            return Err(Error::new("Truncated replication frame"));
+        }
+        let sequence = BigEndian::read_u64(&payload[..8]);
+        match kind {
+            PATCH => {
+                let expected = shared.state.lock().unwrap().sequence + 1;
+                if sequence != expected {
+                    return Err(Error::new(format!(
+                        "Expected patch #{}, received #{}",
+                        expected, sequence
+                    )));
+                }
+                shared.db.merge(decode_patch(&payload[8..])?)?;
+            }
+            SNAPSHOT => {
+                let (last, chunk) = match payload[8..].split_first() {
+                    Some((&last, chunk)) => (last != 0, chunk),
+                    None => return Err(Error::new("Truncated replication frame")),
+                };
+                state
+                    .get_or_insert_with(MemoryDB::new)
+                    .merge(decode_patch(chunk)?)?;
+                if !last {
+                    continue;
+                }
+                let received = state.take().unwrap();
+                let mut patch = diff(&*shared.db.snapshot(), &*received.snapshot());
+                patch.insert_change(
+                    META_CF,
+                    SEQUENCE_KEY.to_vec(),
+                    Change::Put(encode_u64(sequence)),
+                );
+                shared.db.merge_sync(patch)?;
+            }
+            _ => return Err(Error::new(format!("Unexpected frame type {}", kind))),
+        }
+
+        shared.state.lock().unwrap().sequence = sequence;
+        shared.applied.notify_all();
+        write_frame(stream, ACK, &[&encode_u64(sequence)[..]]).map_err(io_error)?;
+    }
+}
+
+fn read_sequence(snapshot: &Snapshot) -> u64 {
+    snapshot
+        .get(META_CF, SEQUENCE_KEY)
+        .map_or(0, |value| BigEndian::read_u64(&value))
+}
+
+fn write_frame<W: Write>(writer: &mut W, kind: u8, parts: &[&[u8]]) -> io::Result<()> {
+    let len = parts.iter().map(|part| part.len()).sum::<usize>();
+    if len > MAX_FRAME_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Frame of {} bytes exceeds the maximum size", len),
+        ));
+    }
+    let mut header = [kind, 0, 0, 0, 0];
+    BigEndian::write_u32(&mut header[1..], len as u32);
+    writer.write_all(&header)?;
+    for part in parts {
+        writer.write_all(part)?;
+    }
+    writer.flush()
+}
+
+fn read_frame<R: Read>(reader: &mut R) -> io::Result<(u8, Vec<u8>)> {
+    let mut header = [0; 5];
+    reader.read_exact(&mut header)?;
+    let len = BigEndian::read_u32(&header[1..]) as usize;
+    if len > MAX_FRAME_SIZE {
+        return Err(invalid_data("Frame exceeds the maximum size"));
+    }
+    let mut payload = vec![0; len];
+    reader.read_exact(&mut payload)?;
+    Ok((header[0], payload))
+}
+
+fn encode_u64(value: u64) -> Vec<u8> {
+    let mut buf = vec![0; 8];
+    BigEndian::write_u64(&mut buf, value);
+    buf
+}
+
+fn push_bytes(buf: &mut Vec<u8>, bytes: &[u8]) {
+    let mut len = [0; 4];
+    BigEndian::write_u32(&mut len, bytes.len() as u32);
+    buf.extend_from_slice(&len);
+    buf.extend_from_slice(bytes);
+}
+
+/// Encodes the patch as the number of changes followed by the changes, each consisting of
+/// the length-prefixed column family name and key, a tag and the length-prefixed value.
+///
+/// The changes are ordered by column family names and then by keys, so that the same patch
+/// is always encoded to the same bytes.
+fn encode_patch(patch: &Patch) -> Vec<u8> {
+    let mut buf = encode_u64(patch.len() as u64);
+    let mut column_families = patch.iter().collect::<Vec<_>>();
+    column_families.sort_by(|a, b| a.0.cmp(b.0));
+    for (name, changes) in column_families {
+        for (key, change) in changes.iter() {
+            push_bytes(&mut buf, name.as_bytes());
+            push_bytes(&mut buf, key);
+            match *change {
+                Change::Put(ref value) => {
+                    buf.push(TAG_PUT);
+                    push_bytes(&mut buf, value);
+                }
+                Change::Delete => buf.push(TAG_DELETE),
+            }
+        }
+    }
+    buf
+}
+
+fn decode_patch(mut raw: &[u8]) -> Result<Patch> {
+    fn take<'a>(raw: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+        if raw.len() < len {
+            return Err(Error::new("Truncated patch"));
+        }
+        let (head, tail) = raw.split_at(len);
+        *raw = tail;
+        Ok(head)
+    }
+    fn take_bytes<'a>(raw: &mut &'a [u8]) -> Result<&'a [u8]> {
+        let len = BigEndian::read_u32(take(raw, 4)?) as usize;
+        take(raw, len)
+    }
+
+    let mut patch = Patch::new();
+    let count = BigEndian::read_u64(take(&mut raw, 8)?);
+    for _ in 0..count {
+        let name = String::from_utf8(take_bytes(&mut raw)?.to_vec())
+            .map_err(|_| Error::new("Invalid column family name"))?;
+        let key = take_bytes(&mut raw)?.to_vec();
+        let tag = take(&mut raw, 1)?[0];
+        let change = match tag {
+            TAG_PUT => Change::Put(take_bytes(&mut raw)?.to_vec()),
+            TAG_DELETE => Change::Delete,
+            tag => return Err(Error::new(format!("Invalid change tag {}", tag))),
+        };
+        patch.insert_change(&name, key, change);
+    }
+    if !raw.is_empty() {
+        return Err(Error::new("Unexpected data after the patch"));
+    }
+    Ok(patch)
+}
+
+fn io_error(e: io::Error) -> Error {
+    Error::new(e.to_string())
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use storage::{MapIndex, MemoryDB};
+    use super::*;
+
+    const MAP: &str = "map";
+    const TIMEOUT_SECS: u64 = 10;
+
+    fn put_values<D: Database>(db: &D, values: &[(u8, u64)]) {
+        let mut fork = db.fork();
+        {
+            let mut map = MapIndex::new(MAP, &mut fork);
+            for &(key, value) in values {
+                map.put(&key, value);
+            }
+        }
+        db.merge(fork.into_patch()).unwrap();
+    }
+
+    fn map_values<D: Database>(db: &D) -> Vec<(u8, u64)> {
+        let snapshot = db.snapshot();
+        let map: MapIndex<_, u8, u64> = MapIndex::new(MAP, &snapshot);
+        map.iter().collect()
+    }
+
+    fn wait_for_ack<D: Database>(leader: &Leader<D>, sequence: u64) -> Vec<FollowerStatus> {
+        let deadline = Instant::now() + Duration::from_secs(TIMEOUT_SECS);
+        loop {
+            let followers = leader.followers();
+            if followers.iter().all(|status| status.acked_sequence >= sequence) {
+                return followers;
+            }
+            assert!(Instant::now() < deadline, "Followers have not acknowledged the patch");
+            thread::sleep(Duration::from_millis(POLL_INTERVAL_MS));
+        }
+    }
+
+    #[test]
+    fn patch_encoding_roundtrip() {
+        let db = MemoryDB::new();
+        let mut fork = db.fork();
+        fork.put("a", vec![1, 2], vec![3]);
+        fork.put("a", vec![], vec![]);
+        fork.put("b", vec![0], vec![4, 5, 6]);
+        fork.remove("b", vec![7]);
+        let patch = fork.into_patch();
+
+        let encoded = encode_patch(&patch);
+        let decoded = decode_patch(&encoded).unwrap();
+        assert_eq!(encode_patch(&decoded), encoded);
+        assert_eq!(decoded.len(), 4);
+
+        assert!(decode_patch(&encoded[..encoded.len() - 1]).is_err());
+        let mut extended = encoded.clone();
+        extended.push(0);
+        assert!(decode_patch(&extended).is_err());
+    }
+
+    #[test]
+    fn follower_applies_patches() {
+        let leader = Leader::new(MemoryDB::new(), 16);
+        let addr = leader.listen("127.0.0.1:0").unwrap();
+        let follower = Follower::connect(MemoryDB::new(), addr).unwrap();
+
+        for i in 0..10 {
+            put_values(&leader, &[(i, u64::from(i) * 10)]);
+        }
+        assert_eq!(leader.sequence(), 10);
+        assert!(follower.wait_for(10, Duration::from_secs(TIMEOUT_SECS)));
+        assert_eq!(follower.sequence(), 10);
+        assert_eq!(map_values(follower.database()), map_values(&leader));
+
+        let followers = wait_for_ack(&leader, 10);
+        assert_eq!(followers.len(), 1);
+        assert_eq!(followers[0].lag, 0);
+        assert!(follower.is_connected());
+    }
+
+    #[test]
+    fn follower_resumes_from_sequence() {
+        let leader = Leader::new(MemoryDB::new(), 16);
+        let addr = leader.listen("127.0.0.1:0").unwrap();
+        let follower = Follower::connect(MemoryDB::new(), addr).unwrap();
+        put_values(&leader, &[(1, 1), (2, 2)]);
+        assert!(follower.wait_for(1, Duration::from_secs(TIMEOUT_SECS)));
+        let db = follower.disconnect();
+
+        put_values(&leader, &[(3, 3)]);
+        put_values(&leader, &[(1, 10)]);
+
+        let follower = Follower::connect(db, addr).unwrap();
+        assert_eq!(follower.sequence(), 1);
+        assert!(follower.wait_for(3, Duration::from_secs(TIMEOUT_SECS)));
+        assert_eq!(map_values(follower.database()), vec![(1, 10), (2, 2), (3, 3)]);
+        assert!(follower.error().is_none());
+    }
+
+    #[test]
+    fn follower_catches_up_from_export() {
+        let leader = Leader::new(MemoryDB::new(), 2);
+        for i in 0..5 {
+            put_values(&leader, &[(i, u64::from(i))]);
+        }
+
+        // Stale data of the follower is replaced by the leader state.
+        let db = MemoryDB::new();
+        put_values(&db, &[(100, 100)]);
+
+        let addr = leader.listen("127.0.0.1:0").unwrap();
+        let follower = Follower::connect(db, addr).unwrap();
+        assert!(follower.wait_for(5, Duration::from_secs(TIMEOUT_SECS)));
+        assert_eq!(map_values(follower.database()), map_values(&leader));
+
+        put_values(&leader, &[(7, 7)]);
+        assert!(follower.wait_for(6, Duration::from_secs(TIMEOUT_SECS)));
+        assert_eq!(map_values(follower.database()), map_values(&leader));
+    }
+
+    #[test]
+    fn catch_up_covers_unregistered_column_families() {
+        let leader = Leader::new(MemoryDB::new(), 1);
+        put_values(&leader, &[(1, 1)]);
+        let mut fork = leader.fork();
+        fork.put("raw", vec![1], vec![2]);
+        leader.merge(fork.into_patch()).unwrap();
+        put_values(&leader, &[(2, 2)]);
+
+        let addr = leader.listen("127.0.0.1:0").unwrap();
+        let follower = Follower::connect(MemoryDB::new(), addr).unwrap();
+        assert!(follower.wait_for(3, Duration::from_secs(TIMEOUT_SECS)));
+        let snapshot = follower.database().snapshot();
+        assert_eq!(snapshot.get("raw", &[1]), Some(vec![2]));
+        assert_eq!(map_values(follower.database()), map_values(&leader));
+    }
+
+    #[test]
+    fn state_is_split_into_frames() {
+        let db = MemoryDB::new();
+        let mut fork = db.fork();
+        for i in 0..5 {
+            fork.put("big", vec![i], vec![i; STATE_CHUNK_SIZE / 2]);
+        }
+        db.merge(fork.into_patch()).unwrap();
+
+        let mut sent = Vec::new();
+        send_state(&mut sent, 7, &*db.snapshot()).unwrap();
+        let mut reader = &sent[..];
+        let mut frames = Vec::new();
+        while !reader.is_empty() {
+            let (kind, payload) = read_frame(&mut reader).unwrap();
+            assert_eq!(kind, SNAPSHOT);
+            assert_eq!(BigEndian::read_u64(&payload[..8]), 7);
+            frames.push((payload[8] != 0, decode_patch(&payload[9..]).unwrap().len()));
+        }
+        assert_eq!(frames, vec![(false, 2), (false, 2), (true, 1)]);
+    }
+
+    #[test]
+    fn oversized_frame_is_rejected() {
+        let header = [PATCH, 0xff, 0xff, 0xff, 0xff];
+        let err = read_frame(&mut &header[..]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn disconnected_follower_is_unregistered() {
+        let leader = Leader::new(MemoryDB::new(), 16);
+        let addr = leader.listen("127.0.0.1:0").unwrap();
+        let follower = Follower::connect(MemoryDB::new(), addr).unwrap();
+        put_values(&leader, &[(1, 1)]);
+        assert!(follower.wait_for(1, Duration::from_secs(TIMEOUT_SECS)));
+        wait_for_ack(&leader, 1);
+
+        drop(follower);
+        let deadline = Instant::now() + Duration::from_secs(TIMEOUT_SECS);
+        while !leader.followers().is_empty() {
+            assert!(Instant::now() < deadline, "Follower is not unregistered");
+            thread::sleep(Duration::from_millis(POLL_INTERVAL_MS));
+        }
+    }
+
+    #[test]
+    fn leader_sequence_survives_restart() {
+        let leader = Leader::new(MemoryDB::new(), 16);
+        put_values(&leader, &[(1, 1)]);
+        put_values(&leader, &[(2, 2)]);
+        assert_eq!(read_sequence(&*leader.database().snapshot()), 2);
+
+        let db = MemoryDB::new();
+        let mut fork = db.fork();
+        fork.put(META_CF, SEQUENCE_KEY.to_vec(), encode_u64(2));
+        db.merge(fork.into_patch()).unwrap();
+        let restarted = Leader::new(db, 16);
+        assert_eq!(restarted.sequence(), 2);
+        put_values(&restarted, &[(3, 3)]);
+        assert_eq!(restarted.sequence(), 3);
+    }
+}
@@ -19,27 +19,27 @@
 pub use rocksdb::WriteOptions as RocksDBWriteOptions;
 pub use rocksdb::BlockBasedOptions as RocksBlockOptions;
 
-use rocksdb::{self, DBIterator, Options as RocksDbOptions, WriteBatch};
-use rocksdb::utils::get_cf_names;
+use rocksdb::{self, DBRawIterator, Options as RocksDbOptions, WriteBatch};
 
 use std::{fmt, mem};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::path::Path;
-use std::error::Error;
-use std::iter::Peekable;
 
 use storage::{self, Database, DbOptions, Iter, Iterator, Patch, Snapshot};
 use storage::db::Change;
 
 impl From<rocksdb::Error> for storage::Error {
     fn from(err: rocksdb::Error) -> storage::Error {
-        storage::Error::new(err.description())
+        storage::Error::new(err.into_string())
     }
 }
 
 /// Database implementation on the top of `RocksDB` backend.
 pub struct RocksDB {
     db: Arc<rocksdb::DB>,
+    // Serializes merges, so that the reads recorded in a patch are checked against the state
+    // the patch is applied to.
+    merge_lock: Mutex<()>,
 }
 
 impl DbOptions {
@@ -51,46 +51,120 @@ impl DbOptions {
     }
 }
 
+/// A read-only instance of a `RocksDB` database.
+///
+/// The instance is created by [`RocksDB::open_read_only`] and provides access to the state
+/// of the database at the moment it was opened. The database may be concurrently used
+/// by another process; the changes made after opening are not visible.
+///
+/// `ReadOnlyRocksDB` doesn't implement the `Database` trait, so it is not possible to
+/// write to the database through it.
+///
+/// [`RocksDB::open_read_only`]: struct.RocksDB.html#method.open_read_only
+pub struct ReadOnlyRocksDB {
+    db: Arc<rocksdb::DB>,
+}
+
+/// A secondary instance of a `RocksDB` database.
+///
+/// The instance is created by [`RocksDB::open_secondary`] for a database used by another
+/// process (the primary instance) and can be brought up to date with the primary instance
+/// with the [`catch_up`] method. Column families created by the primary instance after
+/// opening are not visible.
+///
+/// `SecondaryRocksDB` doesn't implement the `Database` trait, so it is not possible to
+/// write to the database through it.
+///
+/// [`RocksDB::open_secondary`]: struct.RocksDB.html#method.open_secondary
+/// [`catch_up`]: #method.catch_up
+pub struct SecondaryRocksDB {
+    db: Arc<rocksdb::DB>,
+}
+
 /// A snapshot of a `RocksDB`.
 pub struct RocksDBSnapshot {
     snapshot: rocksdb::Snapshot<'static>,
     _db: Arc<rocksdb::DB>,
 }
 
-/// An iterator over the entries of a `RocksDB`.
-struct RocksDBIterator {
-    iter: Peekable<DBIterator>,
-    key: Option<Box<[u8]>>,
-    value: Option<Box<[u8]>>,
+/// An iterator over the entries of a `RocksDB`, which borrows the entries from the underlying
+/// raw iterator instead of copying them.
+struct RocksDBIterator<'a> {
+    iter: DBRawIterator<'a>,
+    // Whether the raw iterator points to an entry already returned by `next`.
+    advance: bool,
+    keys_only: bool,
 }
 
 impl RocksDB {
     /// Open a database stored in the specified path with the specified options.
     pub fn open<P: AsRef<Path>>(path: P, options: &DbOptions) -> storage::Result<RocksDB> {
         let db = {
-            if let Ok(names) = get_cf_names(&path) {
-                let cf_names = names.iter().map(|name| name.as_str()).collect::<Vec<_>>();
-                rocksdb::DB::open_cf(&options.to_rocksdb(), path, cf_names.as_ref())?
+            if let Ok(names) = Self::column_families(&path) {
+                rocksdb::DB::open_cf(&options.to_rocksdb(), path, names)?
             } else {
                 rocksdb::DB::open(&options.to_rocksdb(), path)?
             }
         };
-        Ok(RocksDB { db: Arc::new(db) })
+        Ok(RocksDB {
+            db: Arc::new(db),
+            merge_lock: Mutex::new(()),
+        })
+    }
+
+    /// Open an existing database stored in the specified path in the read-only mode.
+    ///
+    /// Unlike `open`, the database may be opened in this mode while it is used by another
+    /// process.
+    pub fn open_read_only<P: AsRef<Path>>(path: P) -> storage::Result<ReadOnlyRocksDB> {
+        let names = Self::column_families(&path)?;
+        let db = rocksdb::DB::open_cf_for_read_only(&read_only_options(), path, names, false)?;
+        Ok(ReadOnlyRocksDB { db: Arc::new(db) })
+    }
+
+    /// Open an existing database stored in `primary_path` as a secondary instance.
+    ///
+    /// The secondary instance keeps its own logs in `secondary_path`, which must be different
+    /// for each secondary instance of the database.
+    pub fn open_secondary<P, S>(
+        primary_path: P,
+        secondary_path: S,
+    ) -> storage::Result<SecondaryRocksDB>
+    where
+        P: AsRef<Path>,
+        S: AsRef<Path>,
+    {
+        let names = Self::column_families(&primary_path)?;
+        let db = rocksdb::DB::open_cf_as_secondary(
+            &read_only_options(),
+            primary_path.as_ref(),
+            secondary_path.as_ref(),
+            names,
+        )?;
+        Ok(SecondaryRocksDB { db: Arc::new(db) })
+    }
+
+    /// Returns names of the column families of a database stored in the specified path.
+    pub fn column_families<P: AsRef<Path>>(path: P) -> storage::Result<Vec<String>> {
+        rocksdb::DB::list_cf(&RocksDbOptions::default(), path).map_err(Into::into)
     }
 
     fn do_merge(&self, patch: Patch, w_opts: &RocksDBWriteOptions) -> storage::Result<()> {
+        let _guard = self.merge_lock.lock().unwrap();
+        if patch.has_reads() {
+            patch.check_reads(&*create_snapshot(&self.db))?;
+        }
         let mut batch = WriteBatch::default();
         for (cf_name, changes) in patch {
-            let cf = match self.db.cf_handle(&cf_name) {
-                Some(cf) => cf,
-                None => self.db
-                    .create_cf(&cf_name, &DbOptions::default().to_rocksdb())
-                    .unwrap(),
-            };
+            if self.db.cf_handle(&cf_name).is_none() {
+                self.db
+                    .create_cf(&cf_name, &DbOptions::default().to_rocksdb())?;
+            }
+            let cf = self.db.cf_handle(&cf_name).unwrap();
             for (key, change) in changes {
                 match change {
-                    Change::Put(ref value) => batch.put_cf(cf, key.as_ref(), value)?,
-                    Change::Delete => batch.delete_cf(cf, &key)?,
+                    Change::Put(ref value) => batch.put_cf(cf, &key, value),
+                    Change::Delete => batch.delete_cf(cf, &key),
                 }
             }
         }
@@ -98,12 +172,43 @@ impl RocksDB {
     }
 }
 
+fn read_only_options() -> RocksDbOptions {
+    DbOptions {
+        create_if_missing: false,
+        ..DbOptions::default()
+    }.to_rocksdb()
+}
+
+fn create_snapshot(db: &Arc<rocksdb::DB>) -> Box<Snapshot> {
+    Box::new(RocksDBSnapshot {
+        snapshot: unsafe { mem::transmute(db.snapshot()) },
+        _db: Arc::clone(db),
+    })
+}
+
+impl ReadOnlyRocksDB {
+    /// Creates a new snapshot of the database.
+    pub fn snapshot(&self) -> Box<Snapshot> {
+        create_snapshot(&self.db)
+    }
+}
+
+impl SecondaryRocksDB {
+    /// Creates a new snapshot of the database from the state it has been caught up to.
+    pub fn snapshot(&self) -> Box<Snapshot> {
+        create_snapshot(&self.db)
+    }
+
+    /// Catches up with the changes made by the primary instance. The snapshots created
+    /// before the call are not affected.
+    pub fn catch_up(&self) -> storage::Result<()> {
+        self.db.try_catch_up_with_primary().map_err(Into::into)
+    }
+}
+
 impl Database for RocksDB {
     fn snapshot(&self) -> Box<Snapshot> {
-        Box::new(RocksDBSnapshot {
-            snapshot: unsafe { mem::transmute(self.db.snapshot()) },
-            _db: Arc::clone(&self.db),
-        })
+        create_snapshot(&self.db)
     }
 
     fn merge(&self, patch: Patch) -> storage::Result<()> {
@@ -122,8 +227,8 @@ impl Snapshot for RocksDBSnapshot {
     fn get(&self, name: &str, key: &[u8]) -> Option<Vec<u8>> {
         if let Some(cf) = self._db.cf_handle(name) {
             match self.snapshot.get_cf(cf, key) {
-                Ok(value) => value.map(|v| v.to_vec()),
-                Err(e) => panic!(e),
+                Ok(value) => value,
+                Err(e) => panic!("Cannot read from column family `{}`: {}", name, e),
             }
         } else {
             None
@@ -131,38 +236,81 @@ impl Snapshot for RocksDBSnapshot {
     }
 
     fn iter<'a>(&'a self, name: &str, from: &[u8]) -> Iter<'a> {
-        use rocksdb::{Direction, IteratorMode};
+        Box::new(self.raw_iter(name, from, false))
+    }
+
+    fn iter_keys<'a>(&'a self, name: &str, from: &[u8]) -> Iter<'a> {
+        Box::new(self.raw_iter(name, from, true))
+    }
+
+    fn column_families(&self) -> Vec<String> {
+        let mut names = RocksDB::column_families(self._db.path())
+            .unwrap_or_else(|e| panic!("Cannot list column families: {}", e));
+        names.sort();
+        names
+    }
+}
+
+impl RocksDBSnapshot {
+    fn raw_iter<'a>(&'a self, name: &str, from: &[u8], keys_only: bool) -> RocksDBIterator<'a> {
         let iter = match self._db.cf_handle(name) {
-            Some(cf) => self.snapshot
-                .iterator_cf(cf, IteratorMode::From(from, Direction::Forward))
-                .unwrap(),
-            None => self.snapshot.iterator(IteratorMode::Start),
+            Some(cf) => {
+                let mut iter = self.snapshot.raw_iterator_cf(cf);
+                iter.seek(from);
+                iter
+            }
+            None => {
+                let mut iter = self.snapshot.raw_iterator();
+                iter.seek_to_first();
+                iter
+            }
         };
-        Box::new(RocksDBIterator {
-            iter: iter.peekable(),
-            key: None,
-            value: None,
-        })
+        RocksDBIterator {
+            iter,
+            advance: false,
+            keys_only,
+        }
     }
 }
 
-impl Iterator for RocksDBIterator {
-    fn next(&mut self) -> Option<(&[u8], &[u8])> {
-        if let Some((key, value)) = self.iter.next() {
-            self.key = Some(key);
-            self.value = Some(value);
-            Some((self.key.as_ref().unwrap(), self.value.as_ref().unwrap()))
+impl<'a> RocksDBIterator<'a> {
+    fn current(&self) -> Option<(&[u8], &[u8])> {
+        if !self.iter.valid() {
+            // An invalid iterator means either the end of the column family
+            // or an I/O error, which must not be mistaken for the former.
+            if let Err(e) = self.iter.status() {
+                panic!("Cannot iterate over the snapshot: {}", e);
+            }
+            return None;
+        }
+        let key = match self.iter.key() {
+            Some(key) => key,
+            None => return None,
+        };
+        let value = if self.keys_only {
+            &[][..]
         } else {
-            None
+            self.iter.value().unwrap_or(&[])
+        };
+        Some((key, value))
+    }
+}
+
+impl<'a> Iterator for RocksDBIterator<'a> {
+    fn next(&mut self) -> Option<(&[u8], &[u8])> {
+        if self.advance {
+            self.iter.next();
         }
+        self.advance = true;
+        self.current()
     }
 
     fn peek(&mut self) -> Option<(&[u8], &[u8])> {
-        if let Some(&(ref key, ref value)) = self.iter.peek() {
-            Some((key, value))
-        } else {
-            None
+        if self.advance {
+            self.iter.next();
+            self.advance = false;
         }
+        self.current()
     }
 }
 
@@ -178,8 +326,72 @@ impl fmt::Debug for RocksDB {
     }
 }
 
+impl fmt::Debug for ReadOnlyRocksDB {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ReadOnlyRocksDB(..)")
+    }
+}
+
+impl fmt::Debug for SecondaryRocksDB {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SecondaryRocksDB(..)")
+    }
+}
+
 impl fmt::Debug for RocksDBSnapshot {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "RocksDBSnapshot(..)")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+
+    use storage::{Database, DbOptions, MapIndex};
+    use super::*;
+
+    fn put_value(db: &RocksDB, key: u8, value: u8) {
+        let mut fork = db.fork();
+        MapIndex::new("map", &mut fork).put(&key, value);
+        db.merge(fork.into_patch()).unwrap();
+    }
+
+    #[test]
+    fn read_only_instance() {
+        let dir = TempDir::new("exonum_rocksdb_read_only").unwrap();
+        assert!(RocksDB::open_read_only(dir.path().join("missing")).is_err());
+
+        let db = RocksDB::open(dir.path(), &DbOptions::default()).unwrap();
+        put_value(&db, 1, 2);
+
+        // The database can be opened while it is used by another instance.
+        let read_only = RocksDB::open_read_only(dir.path()).unwrap();
+        put_value(&db, 3, 4);
+        let snapshot = read_only.snapshot();
+        let index: MapIndex<_, u8, u8> = MapIndex::new("map", &snapshot);
+        assert_eq!(index.get(&1), Some(2));
+        assert_eq!(index.get(&3), None);
+    }
+
+    #[test]
+    fn secondary_instance() {
+        let dir = TempDir::new("exonum_rocksdb_secondary").unwrap();
+        let primary_path = dir.path().join("primary");
+        let db = RocksDB::open(&primary_path, &DbOptions::default()).unwrap();
+        put_value(&db, 1, 2);
+
+        let secondary = RocksDB::open_secondary(&primary_path, dir.path().join("secondary"))
+            .unwrap();
+        let old_snapshot = secondary.snapshot();
+        put_value(&db, 3, 4);
+        secondary.catch_up().unwrap();
+
+        let snapshot = secondary.snapshot();
+        let index: MapIndex<_, u8, u8> = MapIndex::new("map", &snapshot);
+        assert_eq!(index.get(&1), Some(2));
+        assert_eq!(index.get(&3), Some(4));
+        let index: MapIndex<_, u8, u8> = MapIndex::new("map", &old_snapshot);
+        assert_eq!(index.get(&3), None);
+    }
+}
@@ -0,0 +1,399 @@
+// Copyright 2018 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Typed declarations of the indexes used by a service.
+//!
+//! The [`schema!`] macro declares a struct wrapping a storage view, with an accessor for each
+//! index of the schema. Index names are composed of the schema prefix and the accessor name,
+//! so they are never spelled out at the call site, and two indexes of a schema cannot share
+//! a name: declaring them produces duplicate accessors, which is a compile-time error.
+//!
+//! Each index is declared as `name / name_mut: Type<..>`. The schema over any view
+//! implementing `IndexAccess` has a read-only accessor `name`, and the schema over a view
+//! implementing `IndexAccessMut` additionally has a mutable accessor `name_mut`. An index
+//! family is declared by adding the type of the index identifier in square brackets after
+//! the accessor names; its accessors take the identifier as an argument.
+//!
+//! Indexes are registered in the indexes metadata when they are first modified, like indexes
+//! created directly. The `register` method of the mutable schema registers all
+//! of them at once, so that they are listed by [`list_indexes`] even while they are empty.
+//!
+//! [`schema!`]: ../../macro.schema.html
+//! [`list_indexes`]: ../fn.list_indexes.html
+//!
+//! # Examples
+//!
+//! ```
+//! #[macro_use]
+//! extern crate exonum;
+//!
+//! use exonum::crypto::Hash;
+//! use exonum::storage::{self, Database, MemoryDB};
+//!
+//! schema! {
+//!     /// Schema of the wallets service.
+//!     pub struct WalletSchema("wallets") {
+//!         /// Balances of the wallets.
+//!         balances / balances_mut: MapIndex<Hash, u64>,
+//!         /// Transactions of each wallet.
+//!         history / history_mut [Hash]: ListIndex<Hash>,
+//!         /// Total amount of funds.
+//!         total / total_mut: Entry<u64>,
+//!     }
+//! }
+//!
+//! # fn main() {
+//! let db = MemoryDB::new();
+//! let key = Hash::zero();
+//! let mut fork = db.fork();
+//! {
+//!     let mut schema = WalletSchema::new(&mut fork);
+//!     schema.register();
+//!     schema.balances_mut().put(&key, 100);
+//!     schema.history_mut(&key).push(Hash::zero());
+//! }
+//! db.merge(fork.into_patch()).unwrap();
+//!
+//! let snapshot = db.snapshot();
+//! let schema = WalletSchema::new(&snapshot);
+//! assert_eq!(schema.balances().get(&key), Some(100));
+//! assert_eq!(schema.history(&key).len(), 1);
+//! assert_eq!(schema.total().get(), None);
+//!
+//! let names = storage::list_indexes(&*snapshot)
+//!     .into_iter()
+//!     .map(|info| info.name)
+//!     .collect::<Vec<_>>();
+//! assert_eq!(names, vec!["wallets.balances", "wallets.history", "wallets.total"]);
+//! # }
+//! ```
+//!
+//! Indexes with the same name are rejected at compile time:
+//!
+//! ```compile_fail
+//! #[macro_use]
+//! extern crate exonum;
+//!
+//! schema! {
+//!     pub struct Schema("service") {
+//!         values / values_mut: MapIndex<u64, u64>,
+//!         values / other_values_mut: ListIndex<u64>,
+//!     }
+//! }
+//! # fn main() {}
+//! ```
+
+use super::{DequeIndex, Entry, Fork, IndexType, KeySetIndex, ListIndex, MapIndex, MultiMapIndex,
+            ProofListIndex, ProofMapIndex, QueueIndex, SortedSetIndex, SparseListIndex,
+            TextIndex, TtlMapIndex, ValueSetIndex};
+use super::indexes_metadata;
+
+/// Declares a struct with typed accessors for the indexes of a service.
+///
+/// See the [`schema`](storage/schema/index.html) module for details.
+#[macro_export]
+macro_rules! schema {
+    (
+        $(#[$attr:meta])*
+        pub struct $name:ident($prefix:tt) {
+            $(
+                $(#[$index_attr:meta])*
+                $index:ident / $index_mut:ident $([$family:ty])* : $ty:ident<$($arg:ty),*>
+            ),* $(,)*
+        }
+    ) => {
+        $(#[$attr])*
+        pub struct $name<T> {
+            view: T,
+        }
+
+        impl<T> $name<T>
+        where
+            T: $crate::storage::IndexAccess,
+        {
+            /// Creates a new schema over the storage view.
+            pub fn new(view: T) -> Self {
+                $name { view }
+            }
+
+            /// Returns the storage view of the schema.
+            pub fn into_view(self) -> T {
+                self.view
+            }
+
+            /// Returns the names, types and kinds of the indexes declared in the schema.
+            pub fn indexes(&self) -> Vec<$crate::storage::IndexInfo> {
+                vec![$(
+                    $crate::storage::IndexInfo {
+                        name: concat!($prefix, ".", stringify!($index)).to_owned(),
+                        index_type: <$crate::storage::$ty<(), $($arg),*>
+                            as $crate::storage::schema::SchemaIndex>::INDEX_TYPE,
+                        is_family: __schema_is_family!($($family)*),
+                    }
+                ),*]
+            }
+
+            $(
+                __schema_accessor!(
+                    ref $(#[$index_attr])*
+                    $index, concat!($prefix, ".", stringify!($index)),
+                    $ty, ($($arg),*), ($($family)*)
+                );
+            )*
+        }
+
+        impl<T> $name<T>
+        where
+            T: $crate::storage::IndexAccessMut,
+        {
+            /// Registers all the indexes of the schema in the indexes metadata.
+            pub fn register(&mut self) {
+                $(
+                    $crate::storage::schema::register_index::<
+                        $crate::storage::$ty<(), $($arg),*>
+                    >(
+                        self.view.fork(),
+                        concat!($prefix, ".", stringify!($index)),
+                        __schema_is_family!($($family)*),
+                    );
+                )*
+            }
+
+            $(
+                __schema_accessor!(
+                    mut $(#[$index_attr])*
+                    $index_mut, concat!($prefix, ".", stringify!($index)),
+                    $ty, ($($arg),*), ($($family)*)
+                );
+            )*
+        }
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __schema_is_family {
+    () => {
+        false
+    };
+    ($family:ty) => {
+        true
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __schema_accessor {
+    (
+        ref $(#[$attr:meta])*
+        $method:ident, $name:expr, $ty:ident, ($($arg:ty),*), ()
+    ) => {
+        $(#[$attr])*
+        pub fn $method(&self) -> $crate::storage::$ty<&$crate::storage::Snapshot, $($arg),*> {
+            $crate::storage::$ty::new($name, self.view.snapshot())
+        }
+    };
+    (
+        ref $(#[$attr:meta])*
+        $method:ident, $name:expr, $ty:ident, ($($arg:ty),*), ($family:ty)
+    ) => {
+        $(#[$attr])*
+        pub fn $method(
+            &self,
+            index_id: &$family,
+        ) -> $crate::storage::$ty<&$crate::storage::Snapshot, $($arg),*> {
+            $crate::storage::$ty::new_in_family($name, index_id, self.view.snapshot())
+        }
+    };
+    (
+        mut $(#[$attr:meta])*
+        $method:ident, $name:expr, $ty:ident, ($($arg:ty),*), ()
+    ) => {
+        $(#[$attr])*
+        pub fn $method(
+            &mut self,
+        ) -> $crate::storage::$ty<&mut $crate::storage::Fork, $($arg),*> {
+            $crate::storage::$ty::new($name, self.view.fork())
+        }
+    };
+    (
+        mut $(#[$attr:meta])*
+        $method:ident, $name:expr, $ty:ident, ($($arg:ty),*), ($family:ty)
+    ) => {
+        $(#[$attr])*
+        pub fn $method(
+            &mut self,
+            index_id: &$family,
+        ) -> $crate::storage::$ty<&mut $crate::storage::Fork, $($arg),*> {
+            $crate::storage::$ty::new_in_family($name, index_id, self.view.fork())
+        }
+    };
+}
+
+/// An index type which can be declared in a [`schema!`](../../macro.schema.html).
+pub trait SchemaIndex {
+    /// Type of the index recorded in the indexes metadata.
+    const INDEX_TYPE: IndexType;
+}
+
+impl<T, V> SchemaIndex for Entry<T, V> {
+    const INDEX_TYPE: IndexType = IndexType::Entry;
+}
+
+impl<T, K> SchemaIndex for KeySetIndex<T, K> {
+    const INDEX_TYPE: IndexType = IndexType::KeySet;
+}
+
+impl<T, V> SchemaIndex for ListIndex<T, V> {
+    const INDEX_TYPE: IndexType = IndexType::List;
+}
+
+impl<T, V> SchemaIndex for SparseListIndex<T, V> {
+    const INDEX_TYPE: IndexType = IndexType::SparseList;
+}
+
+impl<T, V> SchemaIndex for DequeIndex<T, V> {
+    const INDEX_TYPE: IndexType = IndexType::Deque;
+}
+
+impl<T, V> SchemaIndex for QueueIndex<T, V> {
+    const INDEX_TYPE: IndexType = IndexType::Queue;
+}
+
+impl<T, K, V> SchemaIndex for MapIndex<T, K, V> {
+    const INDEX_TYPE: IndexType = IndexType::Map;
+}
+
+impl<T, V> SchemaIndex for ProofListIndex<T, V> {
+    const INDEX_TYPE: IndexType = IndexType::ProofList;
+}
+
+impl<T, K, V> SchemaIndex for ProofMapIndex<T, K, V> {
+    const INDEX_TYPE: IndexType = IndexType::ProofMap;
+}
+
+impl<T, V> SchemaIndex for ValueSetIndex<T, V> {
+    const INDEX_TYPE: IndexType = IndexType::ValueSet;
+}
+
+impl<T, K, V> SchemaIndex for TtlMapIndex<T, K, V> {
+    const INDEX_TYPE: IndexType = IndexType::TtlMap;
+}
+
+impl<T, K, V> SchemaIndex for MultiMapIndex<T, K, V> {
+    const INDEX_TYPE: IndexType = IndexType::MultiMap;
+}
+
+impl<T, M: ?Sized, S> SchemaIndex for SortedSetIndex<T, M, S> {
+    const INDEX_TYPE: IndexType = IndexType::SortedSet;
+}
+
+impl<T, K> SchemaIndex for TextIndex<T, K> {
+    const INDEX_TYPE: IndexType = IndexType::Text;
+}
+
+/// Registers the index with the given name in the indexes metadata, if it is not registered
+/// yet. Used by the `register` method generated by [`schema!`](../../macro.schema.html).
+///
+/// # Panics
+///
+/// Panics if the index is already registered with a different type.
+pub fn register_index<I: SchemaIndex>(fork: &mut Fork, name: &str, is_family: bool) {
+    indexes_metadata::assert_index_type(name, I::INDEX_TYPE, is_family, &*fork);
+    indexes_metadata::set_index_type(name, I::INDEX_TYPE, is_family, fork);
+}
+
+#[cfg(test)]
+mod tests {
+    use storage::{list_indexes, Database, IndexInfo, IndexType, MapIndex, MemoryDB};
+
+    schema! {
+        /// Schema used in tests.
+        pub struct TestSchema("test") {
+            /// Map of numbers.
+            numbers / numbers_mut: MapIndex<u64, String>,
+            /// Lists of numbers.
+            lists / lists_mut [u8]: ListIndex<u64>,
+            counter / counter_mut: Entry<u64>,
+        }
+    }
+
+    #[test]
+    fn schema_accessors() {
+        let db = MemoryDB::new();
+        let mut fork = db.fork();
+        {
+            let mut schema = TestSchema::new(&mut fork);
+            schema.numbers_mut().put(&1, "one".to_owned());
+            schema.lists_mut(&2).push(3);
+            schema.counter_mut().set(4);
+            assert_eq!(schema.numbers().get(&1), Some("one".to_owned()));
+        }
+        db.merge(fork.into_patch()).unwrap();
+
+        let snapshot = db.snapshot();
+        let schema = TestSchema::new(&snapshot);
+        assert_eq!(schema.numbers().get(&1), Some("one".to_owned()));
+        assert_eq!(schema.lists(&2).iter().collect::<Vec<_>>(), vec![3]);
+        assert!(schema.lists(&1).is_empty());
+        assert_eq!(schema.counter().get(), Some(4));
+
+        let index: MapIndex<_, u64, String> = MapIndex::new("test.numbers", &snapshot);
+        assert_eq!(index.get(&1), Some("one".to_owned()));
+    }
+
+    #[test]
+    fn schema_registration() {
+        let db = MemoryDB::new();
+        let mut fork = db.fork();
+        let expected = vec![
+            IndexInfo {
+                name: "test.numbers".to_owned(),
+                index_type: IndexType::Map,
+                is_family: false,
+            },
+            IndexInfo {
+                name: "test.lists".to_owned(),
+                index_type: IndexType::List,
+                is_family: true,
+            },
+            IndexInfo {
+                name: "test.counter".to_owned(),
+                index_type: IndexType::Entry,
+                is_family: false,
+            },
+        ];
+        {
+            let mut schema = TestSchema::new(&mut fork);
+            assert_eq!(schema.indexes(), expected);
+            schema.register();
+        }
+        db.merge(fork.into_patch()).unwrap();
+
+        let mut registered = list_indexes(&*db.snapshot());
+        let mut expected = expected;
+        registered.sort_by(|a, b| a.name.cmp(&b.name));
+        expected.sort_by(|a, b| a.name.cmp(&b.name));
+        assert_eq!(registered, expected);
+    }
+
+    #[test]
+    #[should_panic(expected = "Attempt to access index 'test.numbers' of type Map")]
+    fn registration_with_different_type() {
+        let db = MemoryDB::new();
+        let mut fork = db.fork();
+        ::storage::ListIndex::new("test.numbers", &mut fork).push(1_u64);
+        TestSchema::new(&mut fork).register();
+    }
+}
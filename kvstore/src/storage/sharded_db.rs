@@ -0,0 +1,838 @@
+// Copyright 2018 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! An implementation of a database that distributes the data among several databases.
+
+use byteorder::{BigEndian, ByteOrder};
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use super::{Database, Error, Iter, Iterator, Patch, Result, Snapshot};
+use super::db::Change;
+
+/// Column family of the coordinator shard with the log of a cross-shard merge.
+const LOG_CF: &str = "__SHARDED_DB_LOG__";
+/// Column family of the coordinator shard with the routing table the shards were created with.
+const ROUTING_CF: &str = "__SHARDED_DB_ROUTING__";
+/// Key of the encoded routing table.
+const ROUTING_KEY: &[u8] = &[0];
+/// Index of the shard which keeps the commit log.
+const COORDINATOR: usize = 0;
+
+/// Prefix of the keys of the log records.
+const RECORD_PREFIX: u8 = 0;
+/// Key of the commit marker. It is sorted after all the log records, so the marker is
+/// only persisted together with all of them.
+const COMMIT_KEY: &[u8] = &[1];
+
+const TAG_DELETE: u8 = 0;
+const TAG_PUT: u8 = 1;
+
+/// Rules that assign the keys of column families to the shards of a `ShardedDatabase`.
+///
+/// A key is routed by the first matching rule in the following order:
+///
+/// 1. The longest prefix added with [`route_prefix`] for the column family of the key.
+/// 2. The shard assigned to the whole column family with [`route_column_family`].
+/// 3. The default shard.
+///
+/// [`route_prefix`]: #method.route_prefix
+/// [`route_column_family`]: #method.route_column_family
+#[derive(Debug, Clone)]
+pub struct RoutingTable {
+    default_shard: usize,
+    families: HashMap<String, usize>,
+    prefixes: HashMap<String, Vec<(Vec<u8>, usize)>>,
+}
+
+impl RoutingTable {
+    /// Creates a routing table which routes all keys to `default_shard`.
+    pub fn new(default_shard: usize) -> Self {
+        RoutingTable {
+            default_shard,
+            families: HashMap::new(),
+            prefixes: HashMap::new(),
+        }
+    }
+
+    /// Routes the column family with the given name to `shard`.
+    pub fn route_column_family<S: AsRef<str>>(mut self, name: S, shard: usize) -> Self {
+        self.families.insert(name.as_ref().to_owned(), shard);
+        self
+    }
+
+    /// Routes the keys of the column family that start with `prefix` to `shard`.
+    pub fn route_prefix<S: AsRef<str>>(mut self, name: S, prefix: Vec<u8>, shard: usize) -> Self {
+        {
+            let rules = self.prefixes
+                .entry(name.as_ref().to_owned())
+                .or_insert_with(Vec::new);
+            rules.retain(|&(ref p, _)| *p != prefix);
+            rules.push((prefix, shard));
+        }
+        self
+    }
+
+    /// Returns the index of the shard the key is routed to.
+    pub fn shard(&self, name: &str, key: &[u8]) -> usize {
+        let by_prefix = self.prefixes.get(name).and_then(|rules| {
+            rules
+                .iter()
+                .filter(|&&(ref prefix, _)| key.starts_with(prefix))
+                .max_by_key(|&&(ref prefix, _)| prefix.len())
+                .map(|&(_, shard)| shard)
+        });
+        by_prefix
+            .or_else(|| self.families.get(name).cloned())
+            .unwrap_or(self.default_shard)
+    }
+
+    /// Returns the sorted indices of all the shards that may contain keys of the column family.
+    fn shards(&self, name: &str) -> Vec<usize> {
+        let mut shards = vec![*self.families.get(name).unwrap_or(&self.default_shard)];
+        if let Some(rules) = self.prefixes.get(name) {
+            shards.extend(rules.iter().map(|&(_, shard)| shard));
+        }
+        shards.sort();
+        shards.dedup();
+        shards
+    }
+
+    /// Encodes the rules of the table in a canonical form, so that equal tables have
+    /// equal encodings regardless of the order in which the rules were added.
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        write_u32(&mut buf, self.default_shard);
+        let families = self.families.iter().collect::<BTreeMap<_, _>>();
+        write_u32(&mut buf, families.len());
+        for (name, &shard) in families {
+            write_bytes(&mut buf, name.as_bytes());
+            write_u32(&mut buf, shard);
+        }
+        let prefixes = self.prefixes.iter().collect::<BTreeMap<_, _>>();
+        write_u32(&mut buf, prefixes.len());
+        for (name, rules) in prefixes {
+            let mut rules = rules.clone();
+            rules.sort();
+            write_bytes(&mut buf, name.as_bytes());
+            write_u32(&mut buf, rules.len());
+            for (prefix, shard) in rules {
+                write_bytes(&mut buf, &prefix);
+                write_u32(&mut buf, shard);
+            }
+        }
+        buf
+    }
+
+    fn max_shard(&self) -> usize {
+        self.families
+            .values()
+            .chain(self.prefixes.values().flat_map(|rules| rules.iter().map(|r| &r.1)))
+            .cloned()
+            .max()
+            .map_or(self.default_shard, |max| max.max(self.default_shard))
+    }
+}
+
+/// Database that distributes column families, or ranges of keys within them, among several
+/// underlying databases (shards) according to a [`RoutingTable`].
+///
+/// A `ShardedDatabase` presents a single consistent [`snapshot`] of all the shards.
+/// A patch that touches a single shard is merged into it directly. A patch that touches
+/// several shards is merged with a two-phase commit: first, the changes of all the shards
+/// are durably written as a log to the first shard (the coordinator), and only then they
+/// are applied to the shards one by one. If the process is interrupted in the middle of
+/// a cross-shard merge, the patch is completed when the database is opened again, provided
+/// that the log has been written completely, or rolled back otherwise.
+///
+/// If an error occurs after the log has been written, `merge` returns it, but the patch
+/// is still completed by the next merge or when the database is reopened. Until then,
+/// snapshots read the shards together with the logged changes, so the patch is visible
+/// either completely or not at all. The log is always written with `merge_sync`, while
+/// the logged changes are applied with the method the patch was merged with.
+///
+/// The routing table is stored in the coordinator when the shards are used for the first
+/// time, and the database cannot be opened later with a different table.
+///
+/// [`RoutingTable`]: struct.RoutingTable.html
+/// [`snapshot`]: ../trait.Database.html#tymethod.snapshot
+///
+/// # Examples
+///
+/// ```
+/// use exonum::storage::{Database, MapIndex, MemoryDB, RoutingTable, ShardedDatabase};
+///
+/// let routing = RoutingTable::new(0).route_prefix("map", vec![0x80], 1);
+/// let shards: Vec<Box<Database>> = vec![Box::new(MemoryDB::new()), Box::new(MemoryDB::new())];
+/// let db = ShardedDatabase::new(shards, routing).unwrap();
+///
+/// let mut fork = db.fork();
+/// {
+///     let mut map = MapIndex::new("map", &mut fork);
+///     map.put(&0x10_u8, 1_u64);
+///     map.put(&0x90_u8, 2_u64);
+/// }
+/// db.merge(fork.into_patch()).unwrap();
+///
+/// let snapshot = db.snapshot();
+/// let map: MapIndex<_, u8, u64> = MapIndex::new("map", &snapshot);
+/// assert_eq!(map.values().collect::<Vec<_>>(), vec![1, 2]);
+/// ```
+pub struct ShardedDatabase {
+    shards: Vec<Box<Database>>,
+    routing: Arc<RoutingTable>,
+    lock: RwLock<()>,
+    // Whether a logged cross-shard merge has not been applied completely yet.
+    pending: AtomicBool,
+}
+
+/// A snapshot of a `ShardedDatabase`.
+pub struct ShardedSnapshot {
+    snapshots: Vec<Box<Snapshot>>,
+    routing: Arc<RoutingTable>,
+}
+
+struct ShardedIter<'a> {
+    name: String,
+    routing: &'a RoutingTable,
+    iters: Vec<(usize, Iter<'a>)>,
+    heads: Vec<Option<(Vec<u8>, Vec<u8>)>>,
+    item: Option<(Vec<u8>, Vec<u8>)>,
+    peeked: bool,
+}
+
+impl ShardedDatabase {
+    /// Creates a database over the given shards. If a cross-shard merge was interrupted
+    /// the last time the shards were used, it is completed or rolled back.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the routing table refers to a shard which is not provided,
+    /// if it differs from the table the shards were created with, or if the interrupted
+    /// merge cannot be completed.
+    pub fn new(shards: Vec<Box<Database>>, routing: RoutingTable) -> Result<Self> {
+        if shards.is_empty() {
+            return Err(Error::new("At least one shard is required"));
+        }
+        if routing.max_shard() >= shards.len() {
+            return Err(Error::new(format!(
+                "Routing table refers to shard {}, but there are only {} shards",
+                routing.max_shard(),
+                shards.len()
+            )));
+        }
+        let db = ShardedDatabase {
+            shards,
+            routing: Arc::new(routing),
+            lock: RwLock::new(()),
+            pending: AtomicBool::new(false),
+        };
+        db.check_routing()?;
+        db.complete_pending(&|db, patch| db.merge_sync(patch))?;
+        Ok(db)
+    }
+
+    /// Returns the routing table of the database.
+    pub fn routing(&self) -> &RoutingTable {
+        &self.routing
+    }
+
+    /// Returns the shards of the database.
+    pub fn shards(&self) -> &[Box<Database>] {
+        &self.shards
+    }
+
+    /// Splits the patch into the patches of the individual shards.
+    fn split(&self, patch: Patch) -> Vec<Patch> {
+        let mut patches = self.shards.iter().map(|_| Patch::new()).collect::<Vec<_>>();
+        for (name, changes) in patch {
+            for (key, change) in changes {
+                let shard = self.routing.shard(&name, &key);
+                patches[shard].insert_change(&name, key, change);
+            }
+        }
+        patches
+    }
+
+    /// Checks that the routing table matches the one the shards were created with,
+    /// and stores it if the shards are used for the first time.
+    fn check_routing(&self) -> Result<()> {
+        let coordinator = &self.shards[COORDINATOR];
+        let encoded = self.routing.encode();
+        match coordinator.snapshot().get(ROUTING_CF, ROUTING_KEY) {
+            Some(ref stored) if *stored == encoded => Ok(()),
+            Some(_) => Err(Error::new(
+                "Routing table differs from the one the shards were created with",
+            )),
+            None => {
+                let mut fork = coordinator.fork();
+                fork.put(ROUTING_CF, ROUTING_KEY.to_vec(), encoded);
+                coordinator.merge_sync(fork.into_raw_patch())
+            }
+        }
+    }
+
+    /// Applies the changes recorded in the commit log with `merge`, if the log is complete,
+    /// and clears the log.
+    fn complete_pending(&self, merge: &Fn(&Database, Patch) -> Result<()>) -> Result<()> {
+        let coordinator = &self.shards[COORDINATOR];
+        let (log_keys, patches) = self.read_log(&*coordinator.snapshot());
+        if log_keys.is_empty() {
+            self.pending.store(false, Ordering::SeqCst);
+            return Ok(());
+        }
+
+        if let Some(patches) = patches {
+            for (shard, patch) in patches.into_iter().enumerate() {
+                if !patch.is_empty() {
+                    merge(&*self.shards[shard], patch)?;
+                }
+            }
+        }
+
+        let mut fork = coordinator.fork();
+        for key in log_keys {
+            fork.remove(LOG_CF, key);
+        }
+        merge(&**coordinator, fork.into_raw_patch())?;
+        self.pending.store(false, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Reads the keys of the commit log and, if the log is complete, the patches
+    /// of the individual shards recorded in it.
+    fn read_log(&self, snapshot: &Snapshot) -> (Vec<Vec<u8>>, Option<Vec<Patch>>) {
+        let mut log_keys = Vec::new();
+        let mut patches = self.shards.iter().map(|_| Patch::new()).collect::<Vec<_>>();
+        let mut records = 0_u64;
+        let mut committed = None;
+
+        let mut iter = snapshot.iter(LOG_CF, &[]);
+        while let Some((key, value)) = iter.next() {
+            log_keys.push(key.to_vec());
+            if key == COMMIT_KEY {
+                committed = Some(BigEndian::read_u64(value));
+            } else if let Some((shard, name, key, change)) = decode_record(value) {
+                if shard < patches.len() {
+                    patches[shard].insert_change(&name, key, change);
+                    records += 1;
+                }
+            }
+        }
+        // The log is applied only if the commit marker confirms that all records are present.
+        if committed == Some(records) {
+            (log_keys, Some(patches))
+        } else {
+            (log_keys, None)
+        }
+    }
+
+    fn do_merge<F>(&self, patch: Patch, merge: F) -> Result<()>
+    where
+        F: Fn(&Database, Patch) -> Result<()>,
+    {
+        let _guard = self.lock.write().unwrap();
+        self.complete_pending(&merge)?;
+        if patch.has_reads() {
+            patch.check_reads(&ShardedSnapshot {
+                snapshots: self.shards.iter().map(|shard| shard.snapshot()).collect(),
+                routing: Arc::clone(&self.routing),
+            })?;
+        }
+
+        let patches = self.split(patch)
+            .into_iter()
+            .enumerate()
+            .filter(|&(_, ref patch)| !patch.is_empty())
+            .collect::<Vec<_>>();
+        if patches.len() <= 1 {
+            for (shard, patch) in patches {
+                merge(&*self.shards[shard], patch)?;
+            }
+            return Ok(());
+        }
+
+        let mut log = Patch::new();
+        let mut records = 0_u64;
+        for &(shard, ref patch) in &patches {
+            for (name, changes) in patch.iter() {
+                for (key, change) in changes.iter() {
+                    log.insert_change(
+                        LOG_CF,
+                        record_key(records),
+                        Change::Put(encode_record(shard, name, key, change)),
+                    );
+                    records += 1;
+                }
+            }
+        }
+        let mut count = vec![0; 8];
+        BigEndian::write_u64(&mut count, records);
+        log.insert_change(LOG_CF, COMMIT_KEY.to_vec(), Change::Put(count));
+        self.shards[COORDINATOR].merge_sync(log)?;
+        self.pending.store(true, Ordering::SeqCst);
+
+        self.complete_pending(&merge)
+    }
+}
+
+impl Database for ShardedDatabase {
+    fn snapshot(&self) -> Box<Snapshot> {
+        let _guard = self.lock.read().unwrap();
+        let mut snapshots = self.shards
+            .iter()
+            .map(|shard| shard.snapshot())
+            .collect::<Vec<_>>();
+        if self.pending.load(Ordering::SeqCst) {
+            // The logged merge has been applied partially, so the shards are read through
+            // the log until it is completed by the next merge.
+            if let (_, Some(patches)) = self.read_log(&*snapshots[COORDINATOR]) {
+                snapshots = self.shards
+                    .iter()
+                    .zip(patches)
+                    .map(|(shard, patch)| {
+                        let mut fork = shard.fork();
+                        fork.merge(patch);
+                        Box::new(fork) as Box<Snapshot>
+                    })
+                    .collect();
+            }
+        }
+        Box::new(ShardedSnapshot {
+            snapshots,
+            routing: Arc::clone(&self.routing),
+        })
+    }
+
+    fn merge(&self, patch: Patch) -> Result<()> {
+        self.do_merge(patch, |db, patch| db.merge(patch))
+    }
+
+    fn merge_sync(&self, patch: Patch) -> Result<()> {
+        self.do_merge(patch, |db, patch| db.merge_sync(patch))
+    }
+}
+
+impl Snapshot for ShardedSnapshot {
+    fn get(&self, name: &str, key: &[u8]) -> Option<Vec<u8>> {
+        self.snapshots[self.routing.shard(name, key)].get(name, key)
+    }
+
+    fn contains(&self, name: &str, key: &[u8]) -> bool {
+        self.snapshots[self.routing.shard(name, key)].contains(name, key)
+    }
+
+    fn iter<'a>(&'a self, name: &str, from: &[u8]) -> Iter<'a> {
+        self.merged_iter(name, from, false)
+    }
+
+    fn iter_keys<'a>(&'a self, name: &str, from: &[u8]) -> Iter<'a> {
+        self.merged_iter(name, from, true)
+    }
+
+    fn column_families(&self) -> Vec<String> {
+        let names = self.snapshots
+            .iter()
+            .flat_map(|snapshot| snapshot.column_families())
+            .filter(|name| name != LOG_CF && name != ROUTING_CF)
+            .collect::<BTreeSet<_>>();
+        names.into_iter().collect()
+    }
+}
+
+impl ShardedSnapshot {
+    fn merged_iter<'a>(&'a self, name: &str, from: &[u8], keys_only: bool) -> Iter<'a> {
+        let shards = self.routing.shards(name);
+        let iters = shards
+            .into_iter()
+            .map(|shard| {
+                let snapshot = &self.snapshots[shard];
+                let iter = if keys_only {
+                    snapshot.iter_keys(name, from)
+                } else {
+                    snapshot.iter(name, from)
+                };
+                (shard, iter)
+            })
+            .collect::<Vec<_>>();
+        let mut iter = ShardedIter {
+            name: name.to_owned(),
+            routing: &self.routing,
+            heads: iters.iter().map(|_| None).collect(),
+            iters,
+            item: None,
+            peeked: false,
+        };
+        for i in 0..iter.iters.len() {
+            iter.fill(i);
+        }
+        Box::new(iter)
+    }
+}
+
+impl<'a> ShardedIter<'a> {
+    /// Reads the next entry of the `i`-th shard which is routed to this shard.
+    fn fill(&mut self, i: usize) {
+        let (shard, ref mut iter) = self.iters[i];
+        let mut head = None;
+        while let Some((key, value)) = iter.next() {
+            if self.routing.shard(&self.name, key) == shard {
+                head = Some((key.to_vec(), value.to_vec()));
+                break;
+            }
+        }
+        self.heads[i] = head;
+    }
+
+    fn advance(&mut self) -> Option<(Vec<u8>, Vec<u8>)> {
+        let next = self.heads
+            .iter()
+            .enumerate()
+            .filter_map(|(i, head)| head.as_ref().map(|&(ref key, _)| (i, key)))
+            .min_by(|a, b| a.1.cmp(b.1))
+            .map(|(i, _)| i);
+        next.and_then(|i| {
+            let item = self.heads[i].take();
+            self.fill(i);
+            item
+        })
+    }
+}
+
+impl<'a> Iterator for ShardedIter<'a> {
+    fn next(&mut self) -> Option<(&[u8], &[u8])> {
+        if !self.peeked {
+            self.item = self.advance();
+        }
+        self.peeked = false;
+        self.item
+            .as_ref()
+            .map(|&(ref k, ref v)| (k.as_slice(), v.as_slice()))
+    }
+
+    fn peek(&mut self) -> Option<(&[u8], &[u8])> {
+        if !self.peeked {
+            self.item = self.advance();
+            self.peeked = true;
+        }
+        self.item
+            .as_ref()
+            .map(|&(ref k, ref v)| (k.as_slice(), v.as_slice()))
+    }
+}
+
+impl fmt::Debug for ShardedDatabase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ShardedDatabase")
+            .field("shards", &self.shards.len())
+            .field("routing", &self.routing)
+            .finish()
+    }
+}
+
+impl fmt::Debug for ShardedSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ShardedSnapshot")
+            .field("shards", &self.snapshots.len())
+            .finish()
+    }
+}
+
+fn write_u32(buf: &mut Vec<u8>, number: usize) {
+    let mut word = [0; 4];
+    BigEndian::write_u32(&mut word, number as u32);
+    buf.extend_from_slice(&word);
+}
+
+fn write_bytes(buf: &mut Vec<u8>, bytes: &[u8]) {
+    write_u32(buf, bytes.len());
+    buf.extend_from_slice(bytes);
+}
+
+fn record_key(index: u64) -> Vec<u8> {
+    let mut buf = vec![RECORD_PREFIX; 9];
+    BigEndian::write_u64(&mut buf[1..], index);
+    buf
+}
+
+/// Encodes a change of the log as the shard index, the length-prefixed column family name
+/// and key, and the tagged value.
+fn encode_record(shard: usize, name: &str, key: &[u8], change: &Change) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(name.len() + key.len() + 13);
+    write_u32(&mut buf, shard);
+    write_bytes(&mut buf, name.as_bytes());
+    write_bytes(&mut buf, key);
+    match *change {
+        Change::Put(ref value) => {
+            buf.push(TAG_PUT);
+            buf.extend_from_slice(value);
+        }
+        Change::Delete => buf.push(TAG_DELETE),
+    }
+    buf
+}
+
+fn decode_record(raw: &[u8]) -> Option<(usize, String, Vec<u8>, Change)> {
+    if raw.len() < 13 {
+        return None;
+    }
+    let shard = BigEndian::read_u32(&raw[0..4]) as usize;
+    let name_len = BigEndian::read_u32(&raw[4..8]) as usize;
+    let rest = &raw[8..];
+    if rest.len() < name_len + 5 {
+        return None;
+    }
+    let (name, rest) = rest.split_at(name_len);
+    let name = match String::from_utf8(name.to_vec()) {
+        Ok(name) => name,
+        Err(_) => return None,
+    };
+    let key_len = BigEndian::read_u32(&rest[0..4]) as usize;
+    let rest = &rest[4..];
+    if rest.len() < key_len + 1 {
+        return None;
+    }
+    let (key, rest) = rest.split_at(key_len);
+    let change = match rest[0] {
+        TAG_PUT => Change::Put(rest[1..].to_vec()),
+        TAG_DELETE => Change::Delete,
+        _ => return None,
+    };
+    Some((shard, name, key.to_vec(), change))
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+
+    use std::path::Path;
+
+    use storage::{DbOptions, MapIndex, MemoryDB, RocksDB};
+    use storage::faulty_db::{FaultPlan, FaultyDatabase};
+    use super::*;
+
+    const MAP: &str = "map";
+    // Keys routed to each of the three shards, so that a merge of them touches all the shards.
+    const CRASH_KEYS: [u16; 6] = [0x0001, 0x0002, 0x4001, 0x4002, 0x8001, 0x8002];
+
+    fn routing() -> RoutingTable {
+        RoutingTable::new(0)
+            .route_column_family("other", 2)
+            .route_prefix(MAP, vec![0x40], 1)
+            .route_prefix(MAP, vec![0x80], 2)
+    }
+
+    fn memory_shards(count: usize) -> Vec<Box<Database>> {
+        (0..count)
+            .map(|_| Box::new(MemoryDB::new()) as Box<Database>)
+            .collect()
+    }
+
+    fn put_keys<D: Database>(db: &D, keys: &[u16]) -> Result<()> {
+        let mut fork = db.fork();
+        {
+            let mut map = MapIndex::new(MAP, &mut fork);
+            for &key in keys {
+                map.put(&key, u64::from(key));
+            }
+        }
+        db.merge_sync(fork.into_patch())
+    }
+
+    fn map_keys(snapshot: &Snapshot) -> Vec<u16> {
+        let map: MapIndex<_, u16, u64> = MapIndex::new(MAP, snapshot);
+        map.keys().collect()
+    }
+
+    #[test]
+    fn routing_rules() {
+        let routing = routing().route_prefix(MAP, vec![0x40, 0x01], 0);
+        assert_eq!(routing.shard(MAP, &[0x10]), 0);
+        assert_eq!(routing.shard(MAP, &[0x40]), 1);
+        assert_eq!(routing.shard(MAP, &[0x40, 0x01, 0x05]), 0);
+        assert_eq!(routing.shard(MAP, &[0x80, 0x01]), 2);
+        assert_eq!(routing.shard("other", &[0x40]), 2);
+        assert_eq!(routing.shard("unknown", &[0x40]), 0);
+        assert_eq!(routing.shards(MAP), vec![0, 1, 2]);
+        assert_eq!(routing.shards("other"), vec![2]);
+        assert_eq!(routing.shards("unknown"), vec![0]);
+    }
+
+    #[test]
+    fn invalid_routing_table() {
+        assert!(ShardedDatabase::new(memory_shards(2), routing()).is_err());
+        assert!(ShardedDatabase::new(Vec::new(), RoutingTable::new(0)).is_err());
+    }
+
+    #[test]
+    fn cross_shard_merge_and_iteration() {
+        let db = ShardedDatabase::new(memory_shards(3), routing()).unwrap();
+        let keys = vec![0x0001, 0x4001, 0x8001, 0x0002, 0x4002, 0x8002, 0x7f00, 0xff00];
+        put_keys(&db, &keys).unwrap();
+
+        let mut expected = keys.clone();
+        expected.sort();
+        assert_eq!(map_keys(&*db.snapshot()), expected);
+        let expected_shards = [
+            vec![0x0001, 0x0002, 0x7f00, 0xff00],
+            vec![0x4001, 0x4002],
+            vec![0x8001, 0x8002],
+        ];
+        for (shard, expected) in expected_shards.iter().enumerate() {
+            let snapshot = db.shards()[shard].snapshot();
+            assert_eq!(map_keys(&*snapshot), *expected);
+            assert!(snapshot.iter(LOG_CF, &[]).next().is_none());
+        }
+
+        let snapshot = db.snapshot();
+        let mut iter = snapshot.iter(MAP, &[0x40, 0x02]);
+        assert_eq!(iter.peek().map(|(k, _)| k.to_vec()), Some(vec![0x40, 0x02]));
+        assert_eq!(iter.next().map(|(k, _)| k.to_vec()), Some(vec![0x40, 0x02]));
+        assert_eq!(iter.next().map(|(k, _)| k.to_vec()), Some(vec![0x7f, 0x00]));
+        assert_eq!(iter.next().map(|(k, _)| k.to_vec()), Some(vec![0x80, 0x01]));
+    }
+
+    #[test]
+    fn log_record_roundtrip() {
+        let changes = vec![Change::Put(vec![1, 2, 3]), Change::Put(vec![]), Change::Delete];
+        for change in changes {
+            let raw = encode_record(3, "name", &[0, 1], &change);
+            let (shard, name, key, decoded) = decode_record(&raw).unwrap();
+            assert_eq!((shard, name.as_str(), key), (3, "name", vec![0, 1]));
+            match (change, decoded) {
+                (Change::Put(a), Change::Put(b)) => assert_eq!(a, b),
+                (Change::Delete, Change::Delete) => {}
+                _ => panic!("Change is decoded incorrectly"),
+            }
+        }
+        assert!(decode_record(&[0; 5]).is_none());
+    }
+
+    fn open_shards(dirs: &[TempDir], plans: &[FaultPlan]) -> Vec<Box<Database>> {
+        dirs.iter()
+            .zip(plans)
+            .map(|(dir, plan)| {
+                let db = RocksDB::open(dir.path(), &DbOptions::default()).unwrap();
+                Box::new(FaultyDatabase::new(db, plan.clone())) as Box<Database>
+            })
+            .collect()
+    }
+
+    fn reopen(dirs: &[TempDir]) -> ShardedDatabase {
+        let shards = dirs.iter()
+            .map(|dir| open_rocksdb(dir.path()))
+            .collect();
+        ShardedDatabase::new(shards, routing()).unwrap()
+    }
+
+    fn open_rocksdb(path: &Path) -> Box<Database> {
+        Box::new(RocksDB::open(path, &DbOptions::default()).unwrap())
+    }
+
+    fn crash_scenario(seed: u64, crashed_shard: usize, crash_at_merge: usize) -> Vec<u16> {
+        let dirs = (0..3)
+            .map(|_| TempDir::new("exonum_sharded_db").unwrap())
+            .collect::<Vec<_>>();
+        let mut plans = vec![FaultPlan::default(); 3];
+        plans[crashed_shard] = FaultPlan {
+            seed,
+            crash_at_merge: Some(crash_at_merge),
+            ..FaultPlan::default()
+        };
+        {
+            let db = ShardedDatabase::new(open_shards(&dirs, &plans), routing()).unwrap();
+            assert!(put_keys(&db, &CRASH_KEYS).is_err());
+        }
+        let db = reopen(&dirs);
+        let snapshot = db.shards()[COORDINATOR].snapshot();
+        assert!(snapshot.iter(LOG_CF, &[]).next().is_none());
+        map_keys(&*db.snapshot())
+    }
+
+    #[test]
+    fn interrupted_log_write_is_rolled_back() {
+        for seed in 0..8 {
+            // The first merge of the coordinator stores the routing table.
+            let keys = crash_scenario(seed, COORDINATOR, 2);
+            assert!(keys.is_empty() || keys == CRASH_KEYS);
+        }
+    }
+
+    #[test]
+    fn interrupted_apply_is_completed() {
+        for seed in 0..4 {
+            // The first merge of a non-coordinator shard applies its part of the logged patch.
+            let keys = crash_scenario(seed, 2, 1);
+            assert_eq!(keys, CRASH_KEYS);
+        }
+    }
+
+    #[test]
+    fn failed_apply_is_read_through_log() {
+        let mut shards = memory_shards(2);
+        let plan = FaultPlan {
+            fail_merges: vec![1],
+            ..FaultPlan::default()
+        };
+        shards.push(Box::new(FaultyDatabase::new(MemoryDB::new(), plan)));
+        let db = ShardedDatabase::new(shards, routing()).unwrap();
+
+        assert!(put_keys(&db, &CRASH_KEYS).is_err());
+        assert!(map_keys(&*db.shards()[2].snapshot()).is_empty());
+        assert_eq!(map_keys(&*db.snapshot()), CRASH_KEYS);
+        assert!(!db.snapshot().column_families().contains(&LOG_CF.to_owned()));
+
+        // The next merge completes the logged one.
+        put_keys(&db, &[0x0003]).unwrap();
+        assert_eq!(map_keys(&*db.shards()[2].snapshot()), vec![0x8001, 0x8002]);
+        assert_eq!(map_keys(&*db.snapshot()).len(), CRASH_KEYS.len() + 1);
+    }
+
+    #[test]
+    fn routing_table_is_validated_on_reopen() {
+        let dirs = (0..3)
+            .map(|_| TempDir::new("exonum_sharded_db").unwrap())
+            .collect::<Vec<_>>();
+        let open = |routing: RoutingTable| {
+            let shards = dirs.iter().map(|dir| open_rocksdb(dir.path())).collect();
+            ShardedDatabase::new(shards, routing).map(|_| ())
+        };
+        open(routing()).unwrap();
+        // The order of the rules doesn't matter.
+        let reordered = RoutingTable::new(0)
+            .route_prefix(MAP, vec![0x80], 2)
+            .route_prefix(MAP, vec![0x40], 1)
+            .route_column_family("other", 2);
+        open(reordered).unwrap();
+        assert!(open(routing().route_prefix(MAP, vec![0x50], 1)).is_err());
+        assert!(open(RoutingTable::new(1)).is_err());
+    }
+
+    #[test]
+    fn single_shard_merge_bypasses_log() {
+        let db = ShardedDatabase::new(memory_shards(3), routing()).unwrap();
+        put_keys(&db, &[0x4001, 0x4002]).unwrap();
+        let mut fork = db.fork();
+        fork.put("other", vec![1], vec![2]);
+        db.merge(fork.into_patch()).unwrap();
+
+        assert_eq!(map_keys(&*db.shards()[1].snapshot()), vec![0x4001, 0x4002]);
+        assert!(map_keys(&*db.shards()[0].snapshot()).is_empty());
+        assert_eq!(db.shards()[2].snapshot().get("other", &[1]), Some(vec![2]));
+        assert_eq!(db.snapshot().get("other", &[1]), Some(vec![2]));
+    }
+}
@@ -0,0 +1,674 @@
+// Copyright 2018 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! An implementation of set of members ordered by scores.
+
+use std::borrow::Borrow;
+use std::marker::PhantomData;
+
+use super::{BaseIndex, BaseIndexKeysIter, IndexAccess, IndexAccessMut, StorageKey};
+use super::indexes_metadata::IndexType;
+use super::keys::{read_escaped, skip_escaped, write_escaped};
+
+/// Subprefix of the scores, ordered by members.
+const SCORES_PREFIX: u8 = 0;
+/// Subprefix of the `(score, member)` records in ascending order.
+const ASCENDING_PREFIX: u8 = 1;
+/// Subprefix of the `(score, member)` records in descending order.
+const DESCENDING_PREFIX: u8 = 2;
+
+/// A set of members, each of which has a score, ordered by the scores.
+///
+/// Like a sorted set in Redis, the index maps members to scores and keeps the members ordered
+/// by `(score, member)` pairs, so that the members with the lowest or highest scores and
+/// the members within a range of scores are read without scanning the whole set.
+///
+/// Both members and scores must implement the [`StorageKey`] trait. The scores are ordered
+/// as per their serialization, which is the natural order for the unsigned and signed integer
+/// types. Floating point scores are supported with the [`F32`] and [`F64`] wrappers.
+///
+/// # Examples
+///
+/// ```
+/// use exonum::storage::{MemoryDB, Database, SortedSetIndex, F64};
+///
+/// let db = MemoryDB::new();
+/// let mut fork = db.fork();
+/// let mut leaderboard = SortedSetIndex::new("leaderboard", &mut fork);
+/// leaderboard.add("alice", F64(12.5));
+/// leaderboard.add("bob", F64(-3.0));
+/// leaderboard.add("carol", F64(40.0));
+///
+/// assert_eq!(leaderboard.rank("alice"), Some(1));
+/// assert_eq!(leaderboard.pop_max(), Some(("carol".to_owned(), F64(40.0))));
+/// let members = leaderboard.iter().map(|(member, _)| member).collect::<Vec<_>>();
+/// assert_eq!(members, vec!["bob", "alice"]);
+/// ```
+///
+/// [`StorageKey`]: ../trait.StorageKey.html
+/// [`F32`]: ../struct.F32.html
+/// [`F64`]: ../struct.F64.html
+#[derive(Debug)]
+pub struct SortedSetIndex<T, M: ?Sized, S> {
+    base: BaseIndex<T>,
+    _m: PhantomData<M>,
+    _s: PhantomData<S>,
+}
+
+/// An iterator over the members of a `SortedSetIndex` and their scores in ascending order
+/// of scores.
+///
+/// This struct is created by the [`iter`], [`range_by_score`] or [`range_by_rank`] methods
+/// on [`SortedSetIndex`]. See its documentation for more.
+///
+/// [`iter`]: struct.SortedSetIndex.html#method.iter
+/// [`range_by_score`]: struct.SortedSetIndex.html#method.range_by_score
+/// [`range_by_rank`]: struct.SortedSetIndex.html#method.range_by_rank
+/// [`SortedSetIndex`]: struct.SortedSetIndex.html
+#[derive(Debug)]
+pub struct SortedSetIndexIter<'a, M: ?Sized, S> {
+    base_iter: BaseIndexKeysIter<'a, Vec<u8>>,
+    max_score: Option<Vec<u8>>,
+    skip: u64,
+    remaining: Option<u64>,
+    _m: PhantomData<M>,
+    _s: PhantomData<S>,
+}
+
+impl<T, M, S> SortedSetIndex<T, M, S>
+where
+    T: IndexAccess,
+    M: StorageKey + ?Sized,
+    S: StorageKey,
+{
+    /// Creates a new index representation based on the name and storage view.
+    ///
+    /// Storage view can be specified as [`&Snapshot`] or [`&mut Fork`]. In the first case only
+    /// immutable methods are available. In the second case both immutable and mutable methods are
+    /// available.
+    ///
+    /// [`&Snapshot`]: ../trait.Snapshot.html
+    /// [`&mut Fork`]: ../struct.Fork.html
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, SortedSetIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let name = "name";
+    /// let snapshot = db.snapshot();
+    /// let index: SortedSetIndex<_, str, u64> = SortedSetIndex::new(name, &snapshot);
+    /// ```
+    pub fn new<N: AsRef<str>>(index_name: N, view: T) -> Self {
+        SortedSetIndex {
+            base: BaseIndex::new(index_name, IndexType::SortedSet, view),
+            _m: PhantomData,
+            _s: PhantomData,
+        }
+    }
+
+    /// Creates a new index representation based on the name, index id in family
+    /// and storage view.
+    ///
+    /// Storage view can be specified as [`&Snapshot`] or [`&mut Fork`]. In the first case only
+    /// immutable methods are available. In the second case both immutable and mutable methods are
+    /// available.
+    ///
+    /// [`&Snapshot`]: ../trait.Snapshot.html
+    /// [`&mut Fork`]: ../struct.Fork.html
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, SortedSetIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let name = "name";
+    /// let index_id = vec![01];
+    ///
+    /// let snapshot = db.snapshot();
+    /// let index: SortedSetIndex<_, u8, u64> =
+    ///     SortedSetIndex::new_in_family(name, &index_id, &snapshot);
+    /// ```
+    pub fn new_in_family<N: AsRef<str>, I: StorageKey>(
+        family_name: N,
+        index_id: &I,
+        view: T,
+    ) -> Self {
+        SortedSetIndex {
+            base: BaseIndex::new_in_family(family_name, index_id, IndexType::SortedSet, view),
+            _m: PhantomData,
+            _s: PhantomData,
+        }
+    }
+
+    /// Returns the storage view of the index, consuming the index.
+    pub fn into_view(self) -> T {
+        self.base.into_view()
+    }
+
+    /// Returns the score of the member.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, SortedSetIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let name = "name";
+    /// let mut fork = db.fork();
+    /// let mut index = SortedSetIndex::new(name, &mut fork);
+    /// assert_eq!(index.score(&1_u8), None);
+    ///
+    /// index.add(&1_u8, -5_i64);
+    /// assert_eq!(index.score(&1_u8), Some(-5));
+    /// ```
+    pub fn score<Q>(&self, member: &Q) -> Option<S::Owned>
+    where
+        M: Borrow<Q>,
+        Q: StorageKey + ?Sized,
+    {
+        self.score_bytes(&key_bytes(member)).map(|score| S::read(&score))
+    }
+
+    /// Returns `true` if the set contains the member.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, SortedSetIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let name = "name";
+    /// let mut fork = db.fork();
+    /// let mut index = SortedSetIndex::new(name, &mut fork);
+    /// assert!(!index.contains(&1_u8));
+    ///
+    /// index.add(&1_u8, 10_u64);
+    /// assert!(index.contains(&1_u8));
+    /// ```
+    pub fn contains<Q>(&self, member: &Q) -> bool
+    where
+        M: Borrow<Q>,
+        Q: StorageKey + ?Sized,
+    {
+        self.base.contains(&score_key(&key_bytes(member)))
+    }
+
+    /// Returns the zero-based position of the member in the ascending order of scores.
+    ///
+    /// The members with equal scores are ordered as per their serialization. The method reads
+    /// all the members preceding the specified one, so its complexity is linear in the rank.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, SortedSetIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let name = "name";
+    /// let mut fork = db.fork();
+    /// let mut index = SortedSetIndex::new(name, &mut fork);
+    /// index.add(&1_u8, 30_u64);
+    /// index.add(&2_u8, 10_u64);
+    /// index.add(&3_u8, 20_u64);
+    /// assert_eq!(index.rank(&1_u8), Some(2));
+    /// assert_eq!(index.rank(&4_u8), None);
+    /// ```
+    pub fn rank<Q>(&self, member: &Q) -> Option<u64>
+    where
+        M: Borrow<Q>,
+        Q: StorageKey + ?Sized,
+    {
+        let member = key_bytes(member);
+        let score = match self.score_bytes(&member) {
+            Some(score) => score,
+            None => return None,
+        };
+        let record = ascending_key(&score, &member);
+        let rank = self.base
+            .iter_keys::<_, Vec<u8>>(&ASCENDING_PREFIX)
+            .take_while(|key| *key != record)
+            .count();
+        Some(rank as u64)
+    }
+
+    /// Returns an iterator over the members and their scores in ascending order of scores.
+    /// The iterator element type is (M, S).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, SortedSetIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let name = "name";
+    /// let snapshot = db.snapshot();
+    /// let index: SortedSetIndex<_, u8, u64> = SortedSetIndex::new(name, &snapshot);
+    ///
+    /// for (member, score) in index.iter() {
+    ///     println!("{} {}", member, score);
+    /// }
+    /// ```
+    pub fn iter(&self) -> SortedSetIndexIter<M, S> {
+        SortedSetIndexIter {
+            base_iter: self.base.iter_keys(&ASCENDING_PREFIX),
+            max_score: None,
+            skip: 0,
+            remaining: None,
+            _m: PhantomData,
+            _s: PhantomData,
+        }
+    }
+
+    /// Returns an iterator over the members with scores greater than or equal to `min` and less
+    /// than or equal to `max`, in ascending order of scores. The iterator element type is (M, S).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, SortedSetIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let name = "name";
+    /// let mut fork = db.fork();
+    /// let mut index = SortedSetIndex::new(name, &mut fork);
+    /// for i in 0..10_u8 {
+    ///     index.add(&i, i64::from(i) - 5);
+    /// }
+    ///
+    /// let members = index.range_by_score(&-1, &1).map(|(member, _)| member);
+    /// assert_eq!(members.collect::<Vec<_>>(), vec![4, 5, 6]);
+    /// ```
+    pub fn range_by_score(&self, min: &S, max: &S) -> SortedSetIndexIter<M, S> {
+        let mut from = vec![ASCENDING_PREFIX];
+        write_escaped(&key_bytes(min), &mut from);
+        let mut max_score = Vec::new();
+        write_escaped(&key_bytes(max), &mut max_score);
+        SortedSetIndexIter {
+            base_iter: self.base.iter_keys_from(&ASCENDING_PREFIX, &from),
+            max_score: Some(max_score),
+            skip: 0,
+            remaining: None,
+            _m: PhantomData,
+            _s: PhantomData,
+        }
+    }
+
+    /// Returns an iterator over the members with ranks from `start` inclusive to `end`
+    /// exclusive, in ascending order of scores. The iterator element type is (M, S).
+    ///
+    /// The complexity of reading the first member is linear in `start`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, SortedSetIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let name = "name";
+    /// let mut fork = db.fork();
+    /// let mut index = SortedSetIndex::new(name, &mut fork);
+    /// for i in 0..10_u8 {
+    ///     index.add(&i, 10 - u64::from(i));
+    /// }
+    ///
+    /// let members = index.range_by_rank(1, 3).map(|(member, _)| member);
+    /// assert_eq!(members.collect::<Vec<_>>(), vec![8, 7]);
+    /// ```
+    pub fn range_by_rank(&self, start: u64, end: u64) -> SortedSetIndexIter<M, S> {
+        SortedSetIndexIter {
+            base_iter: self.base.iter_keys(&ASCENDING_PREFIX),
+            max_score: None,
+            skip: start,
+            remaining: Some(end.saturating_sub(start)),
+            _m: PhantomData,
+            _s: PhantomData,
+        }
+    }
+
+    fn score_bytes(&self, member: &[u8]) -> Option<Vec<u8>> {
+        self.base.get(&score_key(member))
+    }
+}
+
+impl<T, M, S> SortedSetIndex<T, M, S>
+where
+    T: IndexAccessMut,
+    M: StorageKey + ?Sized,
+    S: StorageKey,
+{
+    /// Adds the member with the specified score to the set, or updates the score if the set
+    /// already contains the member. Returns `true` if the member is added.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, SortedSetIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let name = "name";
+    /// let mut fork = db.fork();
+    /// let mut index = SortedSetIndex::new(name, &mut fork);
+    /// assert!(index.add(&1_u8, 10_u64));
+    /// assert!(!index.add(&1_u8, 20_u64));
+    /// assert_eq!(index.score(&1_u8), Some(20));
+    /// ```
+    pub fn add(&mut self, member: &M, score: S) -> bool {
+        let member = key_bytes(member);
+        let score = key_bytes(&score);
+        let added = match self.score_bytes(&member) {
+            Some(ref old_score) if *old_score == score => return false,
+            Some(old_score) => {
+                self.remove_records(&member, &old_score);
+                false
+            }
+            None => true,
+        };
+        self.base.put(&ascending_key(&score, &member), ());
+        self.base.put(&descending_key(&score, &member), ());
+        self.base.put(&score_key(&member), score);
+        added
+    }
+
+    /// Removes the member from the set. Returns `true` if the set contained the member.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, SortedSetIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let name = "name";
+    /// let mut fork = db.fork();
+    /// let mut index = SortedSetIndex::new(name, &mut fork);
+    /// index.add(&1_u8, 10_u64);
+    /// assert!(index.remove(&1_u8));
+    /// assert!(!index.remove(&1_u8));
+    /// ```
+    pub fn remove<Q>(&mut self, member: &Q) -> bool
+    where
+        M: Borrow<Q>,
+        Q: StorageKey + ?Sized,
+    {
+        let member = key_bytes(member);
+        match self.score_bytes(&member) {
+            Some(score) => {
+                self.remove_records(&member, &score);
+                self.base.remove(&score_key(&member));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Removes the member with the lowest score from the set and returns it together with
+    /// the score.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, SortedSetIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let name = "name";
+    /// let mut fork = db.fork();
+    /// let mut index = SortedSetIndex::new(name, &mut fork);
+    /// index.add(&1_u8, 20_u64);
+    /// index.add(&2_u8, 10_u64);
+    /// assert_eq!(index.pop_min(), Some((2, 10)));
+    /// assert_eq!(index.pop_min(), Some((1, 20)));
+    /// assert_eq!(index.pop_min(), None);
+    /// ```
+    pub fn pop_min(&mut self) -> Option<(M::Owned, S::Owned)> {
+        let record = match self.base
+            .iter_keys::<_, Vec<u8>>(&ASCENDING_PREFIX)
+            .next()
+        {
+            Some(record) => record,
+            None => return None,
+        };
+        let (score, score_end) = read_escaped(&record, 1);
+        Some(self.pop(record[score_end..].to_vec(), score))
+    }
+
+    /// Removes the member with the highest score from the set and returns it together with
+    /// the score.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, SortedSetIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let name = "name";
+    /// let mut fork = db.fork();
+    /// let mut index = SortedSetIndex::new(name, &mut fork);
+    /// index.add(&1_u8, 20_u64);
+    /// index.add(&2_u8, 10_u64);
+    /// assert_eq!(index.pop_max(), Some((1, 20)));
+    /// assert_eq!(index.pop_max(), Some((2, 10)));
+    /// assert_eq!(index.pop_max(), None);
+    /// ```
+    pub fn pop_max(&mut self) -> Option<(M::Owned, S::Owned)> {
+        let record = match self.base
+            .iter_keys::<_, Vec<u8>>(&DESCENDING_PREFIX)
+            .next()
+        {
+            Some(record) => invert(&record[1..]),
+            None => return None,
+        };
+        let (score, score_end) = read_escaped(&record, 0);
+        let (member, _) = read_escaped(&record, score_end);
+        Some(self.pop(member, score))
+    }
+
+    /// Clears the set, removing all the members.
+    ///
+    /// # Notes
+    ///
+    /// Currently this method is not optimized to delete large set of data. During the execution of
+    /// this method the amount of allocated memory is linearly dependent on the number of elements
+    /// in the index.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, SortedSetIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let name = "name";
+    /// let mut fork = db.fork();
+    /// let mut index = SortedSetIndex::new(name, &mut fork);
+    /// index.add(&1_u8, 10_u64);
+    /// index.clear();
+    /// assert!(!index.contains(&1_u8));
+    /// ```
+    pub fn clear(&mut self) {
+        self.base.clear()
+    }
+
+    fn pop(&mut self, member: Vec<u8>, score: Vec<u8>) -> (M::Owned, S::Owned) {
+        self.remove_records(&member, &score);
+        self.base.remove(&score_key(&member));
+        (M::read(&member), S::read(&score))
+    }
+
+    fn remove_records(&mut self, member: &[u8], score: &[u8]) {
+        self.base.remove(&ascending_key(score, member));
+        self.base.remove(&descending_key(score, member));
+    }
+}
+
+impl<'a, M, S> Iterator for SortedSetIndexIter<'a, M, S>
+where
+    M: StorageKey + ?Sized,
+    S: StorageKey,
+{
+    type Item = (M::Owned, S::Owned);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.remaining == Some(0) {
+                return None;
+            }
+            let record = match self.base_iter.next() {
+                Some(record) => record,
+                None => return None,
+            };
+            let score_end = skip_escaped(&record, 1);
+            if let Some(ref max_score) = self.max_score {
+                if record[1..score_end] > max_score[..] {
+                    self.remaining = Some(0);
+                    return None;
+                }
+            }
+            if self.skip > 0 {
+                self.skip -= 1;
+                continue;
+            }
+            if let Some(ref mut remaining) = self.remaining {
+                *remaining -= 1;
+            }
+            let (score, _) = read_escaped(&record, 1);
+            return Some((M::read(&record[score_end..]), S::read(&score)));
+        }
+    }
+}
+
+fn key_bytes<K: StorageKey + ?Sized>(key: &K) -> Vec<u8> {
+    let mut buffer = vec![0; key.size()];
+    key.write(&mut buffer);
+    buffer
+}
+
+/// Returns the storage key of the score of the serialized member.
+fn score_key(member: &[u8]) -> Vec<u8> {
+    let mut key = vec![SCORES_PREFIX];
+    key.extend_from_slice(member);
+    key
+}
+
+/// Returns the storage key of the record ordering the members in ascending order.
+fn ascending_key(score: &[u8], member: &[u8]) -> Vec<u8> {
+    let mut key = vec![ASCENDING_PREFIX];
+    write_escaped(score, &mut key);
+    key.extend_from_slice(member);
+    key
+}
+
+/// Returns the storage key of the record ordering the members in descending order.
+///
+/// Since both the score and the member are escaped, the pair is not a prefix of another one,
+/// so inverting its bits inverts the order of the records.
+fn descending_key(score: &[u8], member: &[u8]) -> Vec<u8> {
+    let mut pair = Vec::new();
+    write_escaped(score, &mut pair);
+    write_escaped(member, &mut pair);
+    let mut key = vec![DESCENDING_PREFIX];
+    key.extend_from_slice(&invert(&pair));
+    key
+}
+
+fn invert(bytes: &[u8]) -> Vec<u8> {
+    bytes.iter().map(|byte| !byte).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use storage::{Database, MemoryDB, F64};
+    use super::SortedSetIndex;
+
+    const IDX_NAME: &str = "idx_name";
+
+    #[test]
+    fn members_are_ordered_by_scores() {
+        let db = MemoryDB::new();
+        let mut fork = db.fork();
+        {
+            let mut index = SortedSetIndex::new(IDX_NAME, &mut fork);
+            assert!(index.add("a", F64(1.0)));
+            assert!(index.add("ab", F64(-0.5)));
+            assert!(index.add("b", F64(1.0)));
+            assert!(index.add("c", F64(::std::f64::INFINITY)));
+            assert!(!index.add("c", F64(-2.0)));
+        }
+        db.merge(fork.into_patch()).unwrap();
+
+        let snapshot = db.snapshot();
+        let index: SortedSetIndex<_, str, F64> = SortedSetIndex::new(IDX_NAME, &snapshot);
+        assert_eq!(
+            index.iter().collect::<Vec<_>>(),
+            vec![
+                ("c".to_owned(), F64(-2.0)),
+                ("ab".to_owned(), F64(-0.5)),
+                ("a".to_owned(), F64(1.0)),
+                ("b".to_owned(), F64(1.0)),
+            ]
+        );
+        assert_eq!(index.rank("c"), Some(0));
+        assert_eq!(index.rank("b"), Some(3));
+        assert_eq!(
+            index
+                .range_by_score(&F64(-0.5), &F64(1.0))
+                .map(|(member, _)| member)
+                .collect::<Vec<_>>(),
+            vec!["ab", "a", "b"]
+        );
+        assert_eq!(index.range_by_score(&F64(2.0), &F64(1.0)).count(), 0);
+        assert_eq!(
+            index
+                .range_by_rank(1, 10)
+                .map(|(member, _)| member)
+                .collect::<Vec<_>>(),
+            vec!["ab", "a", "b"]
+        );
+    }
+
+    #[test]
+    fn pop_from_both_ends() {
+        let db = MemoryDB::new();
+        let mut fork = db.fork();
+        let mut index = SortedSetIndex::new(IDX_NAME, &mut fork);
+        for (member, score) in vec!["a", "ab", "b", "ba"].into_iter().zip(vec![1_i32, 1, -1, -1]) {
+            index.add(member, score);
+        }
+
+        // Members with equal scores are popped in the order of members from either end.
+        assert_eq!(index.pop_max(), Some(("ab".to_owned(), 1)));
+        assert_eq!(index.pop_min(), Some(("b".to_owned(), -1)));
+        assert_eq!(index.pop_max(), Some(("a".to_owned(), 1)));
+        assert_eq!(index.pop_max(), Some(("ba".to_owned(), -1)));
+        assert_eq!(index.pop_max(), None);
+        assert_eq!(index.pop_min(), None);
+        assert_eq!(index.iter().count(), 0);
+    }
+
+    #[test]
+    fn updates_keep_records_consistent() {
+        let db = MemoryDB::new();
+        let mut fork = db.fork();
+        let mut index = SortedSetIndex::new(IDX_NAME, &mut fork);
+        index.add(&1_u64, 10_u64);
+        index.add(&2_u64, 20_u64);
+        index.add(&1_u64, 30_u64);
+        assert!(index.remove(&2_u64));
+
+        assert_eq!(index.iter().collect::<Vec<_>>(), vec![(1, 30)]);
+        assert_eq!(index.pop_max(), Some((1, 30)));
+        assert!(!index.contains(&1_u64));
+        assert_eq!(index.pop_min(), None);
+    }
+}
@@ -24,7 +24,7 @@ use std::cell::Cell;
 use std::marker::PhantomData;
 
 use crypto::{hash, CryptoHash, Hash};
-use super::{BaseIndex, BaseIndexIter, Fork, Snapshot, StorageKey, StorageValue};
+use super::{BaseIndex, BaseIndexIter, IndexAccess, IndexAccessMut, StorageKey, StorageValue};
 use super::indexes_metadata::IndexType;
 
 #[derive(Debug, Default, Clone, Copy)]
@@ -118,7 +118,7 @@ pub struct SparseListIndexValues<'a, V> {
 
 impl<T, V> SparseListIndex<T, V>
 where
-    T: AsRef<Snapshot>,
+    T: IndexAccess,
     V: StorageValue,
 {
     /// Creates a new index representation based on the name and storage view.
@@ -185,6 +185,11 @@ where
         }
     }
 
+    /// Returns the storage view of the index, consuming the index.
+    pub fn into_view(self) -> T {
+        self.base.into_view()
+    }
+
     fn size(&self) -> SparseListSize {
         if let Some(size) = self.size.get() {
             return size;
@@ -382,8 +387,9 @@ where
     }
 }
 
-impl<'a, V> SparseListIndex<&'a mut Fork, V>
+impl<T, V> SparseListIndex<T, V>
 where
+    T: IndexAccessMut,
     V: StorageValue,
 {
     fn set_size(&mut self, size: SparseListSize) {
@@ -576,7 +582,7 @@ where
 
 impl<'a, T, V> ::std::iter::IntoIterator for &'a SparseListIndex<T, V>
 where
-    T: AsRef<Snapshot>,
+    T: IndexAccess,
     V: StorageValue,
 {
     type Item = (u64, V);
@@ -0,0 +1,563 @@
+// Copyright 2018 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Aggregation of the Merkle roots of indexes into a single hash of the database state.
+//!
+//! Merkelized indexes ([`ProofMapIndex`] and [`ProofListIndex`]) can be registered as
+//! contributing to the state with [`Fork::register_state_index`]. The roots of all registered
+//! indexes are kept in the meta `ProofMapIndex` named [`STATE_AGGREGATOR`], keyed by
+//! [`state_index_key`]. The aggregator is brought up to date whenever a fork is converted
+//! into a patch with [`Fork::into_patch`]; [`Fork::state_hash`] updates it in advance and
+//! returns the hash of the state the fork will have after the merge.
+//!
+//! A proof for an entry of a registered index is chained to the state hash with
+//! a [`StateProof`], which combines a proof of the index root in the aggregator with
+//! a proof of the entry in the index.
+//!
+//! [`ProofMapIndex`]: ../proof_map_index/struct.ProofMapIndex.html
+//! [`ProofListIndex`]: ../proof_list_index/struct.ProofListIndex.html
+//! [`Fork::register_state_index`]: ../struct.Fork.html#method.register_state_index
+//! [`Fork::into_patch`]: ../struct.Fork.html#method.into_patch
+//! [`Fork::state_hash`]: ../struct.Fork.html#method.state_hash
+//! [`STATE_AGGREGATOR`]: constant.STATE_AGGREGATOR.html
+//! [`state_index_key`]: fn.state_index_key.html
+//! [`StateProof`]: struct.StateProof.html
+//!
+//! # Examples
+//!
+//! ```
+//! use exonum::storage::{Database, IndexType, MemoryDB, ProofMapIndex};
+//! use exonum::storage::state_hash::{self, StateProof};
+//! use exonum::crypto::Hash;
+//!
+//! let db = MemoryDB::new();
+//! let mut fork = db.fork();
+//! fork.register_state_index("balances", IndexType::ProofMap).unwrap();
+//! ProofMapIndex::new("balances", &mut fork).put(&Hash::zero(), 100_u64);
+//! let state_hash = fork.state_hash();
+//! db.merge(fork.into_patch()).unwrap();
+//!
+//! let snapshot = db.snapshot();
+//! assert_eq!(state_hash::state_hash(&*snapshot), state_hash);
+//!
+//! let index_key = state_hash::state_index_key("balances");
+//! let index: ProofMapIndex<_, Hash, u64> = ProofMapIndex::new("balances", &snapshot);
+//! let proof = StateProof::new(
+//!     state_hash::aggregator_proof(&*snapshot, index_key),
+//!     index.get_proof(Hash::zero()),
+//! );
+//! let checked = proof.check(&state_hash, &index_key).unwrap();
+//! assert_eq!(checked.entries(), vec![(&Hash::zero(), &100)]);
+//! ```
+
+use byteorder::{BigEndian, ByteOrder};
+
+use std::borrow::Cow;
+
+use crypto::{self, CryptoHash, Hash};
+use storage::{self, Error, Fork, MapIndex, Snapshot, StorageKey, StorageValue};
+use super::indexes_metadata::{self, IndexType};
+use super::proof_list_index::{ListProof, ListProofError, ProofListIndex};
+use super::proof_map_index::{CheckedMapProof, MapProof, MapProofError, ProofMapIndex,
+                             ProofMapKey};
+
+/// Name of the meta `ProofMapIndex` with the Merkle roots of the registered indexes.
+pub const STATE_AGGREGATOR: &str = "__STATE_AGGREGATOR__";
+
+/// Name of the `MapIndex` with the registered indexes.
+const STATE_INDEXES: &str = "__STATE_INDEXES__";
+
+/// Description of an index contributing to the state hash.
+#[derive(Debug, Clone, PartialEq)]
+struct StateIndex {
+    name: String,
+    index_id: Option<Vec<u8>>,
+}
+
+/// A proof of entries of a registered index chained to the state hash.
+///
+/// See the [module documentation](index.html) for an example of usage.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StateProof<P> {
+    /// Proof of the Merkle root of the index in the state aggregator.
+    pub aggregator: MapProof<Hash, Hash>,
+    /// Proof of the entries in the index, either a `MapProof` or a `ListProof`.
+    pub index: P,
+}
+
+/// An error returned when a state proof is invalid.
+#[derive(Debug, Fail)]
+pub enum StateProofError {
+    /// The proof of the index root in the state aggregator is malformed.
+    #[fail(display = "invalid aggregator proof: {}", _0)]
+    Aggregator(#[cause] MapProofError),
+
+    /// The aggregator proof doesn't match the trusted state hash.
+    #[fail(display = "state hash mismatch")]
+    StateHashMismatch,
+
+    /// The aggregator proof doesn't contain the root of the requested index.
+    #[fail(display = "index is not found in the aggregator proof")]
+    MissingIndex,
+
+    /// The proof of the entries in a map index is malformed.
+    #[fail(display = "invalid map proof: {}", _0)]
+    Map(#[cause] MapProofError),
+
+    /// The proof of the entries in a list index is invalid.
+    #[fail(display = "invalid list proof: {:?}", _0)]
+    List(ListProofError),
+
+    /// The index proof doesn't match the index root from the aggregator.
+    #[fail(display = "index root mismatch")]
+    IndexRootMismatch,
+}
+
+impl StateIndex {
+    fn key(&self) -> Hash {
+        CryptoHash::hash(self)
+    }
+
+    fn merkle_root(&self, view: &Snapshot) -> Hash {
+        let index_type = indexes_metadata::index_info(view, &self.name).map(|info| info.index_type);
+        // Values are treated as raw bytes, which doesn't affect the root hash.
+        match (index_type, self.index_id.as_ref()) {
+            // The index hasn't been created yet, so it is empty.
+            (None, _) => Hash::zero(),
+            (Some(IndexType::ProofMap), None) => {
+                ProofMapIndex::<_, Hash, Vec<u8>>::new(&self.name, view).merkle_root()
+            }
+            (Some(IndexType::ProofMap), Some(id)) => {
+                ProofMapIndex::<_, Hash, Vec<u8>>::new_in_family(&self.name, id, view)
+                    .merkle_root()
+            }
+            (Some(IndexType::ProofList), None) => {
+                ProofListIndex::<_, Vec<u8>>::new(&self.name, view).merkle_root()
+            }
+            (Some(IndexType::ProofList), Some(id)) => {
+                ProofListIndex::<_, Vec<u8>>::new_in_family(&self.name, id, view).merkle_root()
+            }
+            (Some(index_type), _) => unreachable!(
+                "Index '{}' of type {:?} passed the state index registration",
+                self.name, index_type
+            ),
+        }
+    }
+}
+
+impl CryptoHash for StateIndex {
+    fn hash(&self) -> Hash {
+        crypto::hash(&self.clone().into_bytes())
+    }
+}
+
+impl StorageValue for StateIndex {
+    fn into_bytes(self) -> Vec<u8> {
+        let name = self.name.into_bytes();
+        let mut buffer = vec![0; 5];
+        buffer[0] = self.index_id.is_some() as u8;
+        BigEndian::write_u32(&mut buffer[1..5], name.len() as u32);
+        buffer.extend_from_slice(&name);
+        if let Some(index_id) = self.index_id {
+            buffer.extend_from_slice(&index_id);
+        }
+        buffer
+    }
+
+    fn from_bytes(value: Cow<[u8]>) -> Self {
+        let name_len = BigEndian::read_u32(&value[1..5]) as usize;
+        let name = String::from_utf8(value[5..5 + name_len].to_vec()).unwrap();
+        let index_id = if value[0] == 0 {
+            None
+        } else {
+            Some(value[5 + name_len..].to_vec())
+        };
+        StateIndex { name, index_id }
+    }
+}
+
+impl Fork {
+    /// Registers the Merkelized index with the given name and type as contributing to
+    /// the state hash.
+    ///
+    /// The index may be created later; until then its root is considered to be `Hash::zero()`.
+    /// The type is recorded in the indexes metadata, so the index can't be created with
+    /// another type afterwards.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the type is neither `ProofMap` nor `ProofList`, or the index
+    /// already exists with another type.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the name is reserved for the state aggregator itself.
+    pub fn register_state_index<S: AsRef<str>>(
+        &mut self,
+        name: S,
+        index_type: IndexType,
+    ) -> storage::Result<()> {
+        let index = StateIndex {
+            name: name.as_ref().to_owned(),
+            index_id: None,
+        };
+        register(self, index, index_type)
+    }
+
+    /// Registers the Merkelized index with the given name, type and id from an index family
+    /// as contributing to the state hash.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the type is neither `ProofMap` nor `ProofList`, or the index
+    /// family already exists with another type.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the name is reserved for the state aggregator itself.
+    pub fn register_state_index_in_family<S, I>(
+        &mut self,
+        name: S,
+        index_id: &I,
+        index_type: IndexType,
+    ) -> storage::Result<()>
+    where
+        S: AsRef<str>,
+        I: StorageKey + ?Sized,
+    {
+        let index = StateIndex {
+            name: name.as_ref().to_owned(),
+            index_id: Some(key_bytes(index_id)),
+        };
+        register(self, index, index_type)
+    }
+
+    /// Updates the Merkle roots of the registered indexes in the state aggregator and returns
+    /// the resulting hash of the database state.
+    pub fn state_hash(&mut self) -> Hash {
+        update_aggregator(self);
+        state_hash(&*self)
+    }
+}
+
+fn register(fork: &mut Fork, index: StateIndex, index_type: IndexType) -> storage::Result<()> {
+    if index.name == STATE_AGGREGATOR || index.name == STATE_INDEXES {
+        panic!("Attempt to register an internal state hash index");
+    }
+    if index_type != IndexType::ProofMap && index_type != IndexType::ProofList {
+        return Err(Error::new(format!(
+            "Index '{}' of type {:?} can't contribute to the state hash, \
+             only Merkelized indexes can",
+            index.name, index_type
+        )));
+    }
+    let is_family = index.index_id.is_some();
+    if let Some(info) = indexes_metadata::index_info(&*fork, &index.name) {
+        if info.index_type != index_type || info.is_family != is_family {
+            return Err(Error::new(format!(
+                "Index '{}' already exists with type {:?}",
+                index.name, info.index_type
+            )));
+        }
+    }
+    indexes_metadata::set_index_type(&index.name, index_type, is_family, fork);
+    MapIndex::new(STATE_INDEXES, fork).put(&index.key(), index);
+    Ok(())
+}
+
+/// Updates the Merkle roots of the registered indexes in the state aggregator.
+pub(crate) fn update_aggregator(fork: &mut Fork) {
+    let roots = {
+        let view: &Snapshot = &*fork;
+        // The registered indexes are read directly from the column family, so that forks
+        // without them don't pay for the indexes metadata lookup.
+        let mut roots = Vec::new();
+        let mut iter = view.iter(STATE_INDEXES, &[]);
+        while let Some((key, value)) = iter.next() {
+            let index = StateIndex::from_bytes(Cow::Borrowed(value));
+            roots.push((Hash::read(key), index.merkle_root(view)));
+        }
+        roots
+    };
+    if roots.is_empty() {
+        return;
+    }
+
+    let mut aggregator = ProofMapIndex::new(STATE_AGGREGATOR, fork);
+    for (key, root) in roots {
+        if aggregator.get(&key) != Some(root) {
+            aggregator.put(&key, root);
+        }
+    }
+}
+
+fn key_bytes<K: StorageKey + ?Sized>(key: &K) -> Vec<u8> {
+    let mut buffer = vec![0; key.size()];
+    key.write(&mut buffer);
+    buffer
+}
+
+/// Returns the hash of the database state as of the last call to [`Fork::state_hash`]
+/// before the snapshot was taken.
+///
+/// [`Fork::state_hash`]: ../struct.Fork.html#method.state_hash
+pub fn state_hash(view: &Snapshot) -> Hash {
+    let aggregator: ProofMapIndex<_, Hash, Hash> = ProofMapIndex::new(STATE_AGGREGATOR, view);
+    aggregator.merkle_root()
+}
+
+/// Returns the key of the index with the given name in the state aggregator.
+pub fn state_index_key<S: AsRef<str>>(name: S) -> Hash {
+    StateIndex {
+        name: name.as_ref().to_owned(),
+        index_id: None,
+    }.key()
+}
+
+/// Returns the key of the index with the given name and id from an index family
+/// in the state aggregator.
+pub fn state_index_key_in_family<S, I>(name: S, index_id: &I) -> Hash
+where
+    S: AsRef<str>,
+    I: StorageKey + ?Sized,
+{
+    StateIndex {
+        name: name.as_ref().to_owned(),
+        index_id: Some(key_bytes(index_id)),
+    }.key()
+}
+
+/// Returns a proof of the Merkle root of the index with the given key in the state aggregator.
+pub fn aggregator_proof(view: &Snapshot, index_key: Hash) -> MapProof<Hash, Hash> {
+    let aggregator: ProofMapIndex<_, Hash, Hash> = ProofMapIndex::new(STATE_AGGREGATOR, view);
+    aggregator.get_proof(index_key)
+}
+
+impl<P> StateProof<P> {
+    /// Creates a state proof from the proof of the index root in the aggregator and
+    /// the proof of the entries in the index.
+    pub fn new(aggregator: MapProof<Hash, Hash>, index: P) -> Self {
+        StateProof { aggregator, index }
+    }
+
+    fn check_index_root(
+        &self,
+        state_hash: &Hash,
+        index_key: &Hash,
+    ) -> Result<Hash, StateProofError> {
+        let checked = self.aggregator
+            .clone()
+            .check()
+            .map_err(StateProofError::Aggregator)?;
+        if checked.merkle_root() != *state_hash {
+            return Err(StateProofError::StateHashMismatch);
+        }
+        let root = checked
+            .entries()
+            .into_iter()
+            .find(|&(key, _)| key == index_key)
+            .map(|(_, root)| *root)
+            .ok_or(StateProofError::MissingIndex);
+        root
+    }
+}
+
+impl<K, V> StateProof<MapProof<K, V>>
+where
+    K: ProofMapKey,
+    V: StorageValue,
+{
+    /// Checks the proof against the trusted state hash for the index with the given key
+    /// in the state aggregator.
+    ///
+    /// If the proof is valid, the checked proof of the index entries is returned.
+    pub fn check(
+        self,
+        state_hash: &Hash,
+        index_key: &Hash,
+    ) -> Result<CheckedMapProof<K, V>, StateProofError> {
+        let root = self.check_index_root(state_hash, index_key)?;
+        let checked = self.index.check().map_err(StateProofError::Map)?;
+        if checked.merkle_root() != root {
+            return Err(StateProofError::IndexRootMismatch);
+        }
+        Ok(checked)
+    }
+}
+
+impl<V: StorageValue> StateProof<ListProof<V>> {
+    /// Validates the proof against the trusted state hash for the index with the given key
+    /// in the state aggregator and the number of elements in the list.
+    ///
+    /// If the proof is valid, a vector with indices and references to elements is returned.
+    pub fn validate(
+        &self,
+        state_hash: &Hash,
+        index_key: &Hash,
+        len: u64,
+    ) -> Result<Vec<(u64, &V)>, StateProofError> {
+        let root = self.check_index_root(state_hash, index_key)?;
+        self.index.validate(root, len).map_err(|e| match e {
+            ListProofError::UnmatchedRootHash => StateProofError::IndexRootMismatch,
+            e => StateProofError::List(e),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use storage::{Database, MemoryDB};
+
+    fn create_state(db: &Database) -> Hash {
+        let mut fork = db.fork();
+        fork.register_state_index("map", IndexType::ProofMap).unwrap();
+        fork.register_state_index_in_family("lists", &1_u8, IndexType::ProofList)
+            .unwrap();
+        ProofMapIndex::new("map", &mut fork).put(&crypto::hash(&[1]), 1_u64);
+        ProofListIndex::new_in_family("lists", &1_u8, &mut fork).extend(vec![1_u64, 2, 3]);
+        let state_hash = fork.state_hash();
+        db.merge(fork.into_patch()).unwrap();
+        state_hash
+    }
+
+    #[test]
+    fn state_hash_tracks_registered_indexes() {
+        let db = MemoryDB::new();
+        let state_hash = create_state(&db);
+        assert_ne!(state_hash, Hash::zero());
+        assert_eq!(super::state_hash(&*db.snapshot()), state_hash);
+
+        // Changes in an unregistered index don't affect the state hash.
+        let mut fork = db.fork();
+        ProofListIndex::new("other", &mut fork).push(1_u8);
+        assert_eq!(fork.state_hash(), state_hash);
+
+        // Changes in a registered one do.
+        ProofListIndex::new_in_family("lists", &1_u8, &mut fork).push(4_u64);
+        let new_state_hash = fork.state_hash();
+        assert_ne!(new_state_hash, state_hash);
+
+        let aggregator: ProofMapIndex<_, Hash, Hash> =
+            ProofMapIndex::new(STATE_AGGREGATOR, &fork);
+        let list: ProofListIndex<_, u64> = ProofListIndex::new_in_family("lists", &1_u8, &fork);
+        assert_eq!(
+            aggregator.get(&state_index_key_in_family("lists", &1_u8)),
+            Some(list.merkle_root())
+        );
+        assert_eq!(aggregator.merkle_root(), new_state_hash);
+    }
+
+    #[test]
+    fn registered_index_may_be_created_later() {
+        let db = MemoryDB::new();
+        let mut fork = db.fork();
+        fork.register_state_index("map", IndexType::ProofMap).unwrap();
+        let empty_state_hash = fork.state_hash();
+        ProofMapIndex::new("map", &mut fork).put(&crypto::hash(&[1]), 1_u64);
+        assert_ne!(fork.state_hash(), empty_state_hash);
+    }
+
+    #[test]
+    fn state_hash_is_maintained_on_merge() {
+        let db = MemoryDB::new();
+        let state_hash = create_state(&db);
+
+        let mut fork = db.fork();
+        ProofMapIndex::new("map", &mut fork).put(&crypto::hash(&[2]), 2_u64);
+        db.merge(fork.into_patch()).unwrap();
+
+        let snapshot = db.snapshot();
+        let map: ProofMapIndex<_, Hash, u64> = ProofMapIndex::new("map", &snapshot);
+        let aggregator: ProofMapIndex<_, Hash, Hash> =
+            ProofMapIndex::new(STATE_AGGREGATOR, &snapshot);
+        assert_ne!(super::state_hash(&*snapshot), state_hash);
+        assert_eq!(
+            aggregator.get(&state_index_key("map")),
+            Some(map.merkle_root())
+        );
+    }
+
+    #[test]
+    fn non_merkelized_index() {
+        let db = MemoryDB::new();
+        let mut fork = db.fork();
+        assert!(fork.register_state_index("map", IndexType::Map).is_err());
+
+        MapIndex::new("other", &mut fork).put(&1_u8, 1_u8);
+        assert!(fork.register_state_index("other", IndexType::ProofMap).is_err());
+        assert!(
+            fork.register_state_index_in_family("other", &1_u8, IndexType::ProofMap)
+                .is_err()
+        );
+        assert_eq!(fork.state_hash(), Hash::zero());
+    }
+
+    #[test]
+    #[should_panic(expected = "initially created with type ProofList")]
+    fn registered_index_type_is_enforced() {
+        let db = MemoryDB::new();
+        let mut fork = db.fork();
+        fork.register_state_index("map", IndexType::ProofList).unwrap();
+        ProofMapIndex::new("map", &mut fork).put(&crypto::hash(&[1]), 1_u64);
+    }
+
+    #[test]
+    fn map_state_proof() {
+        let db = MemoryDB::new();
+        let state_hash = create_state(&db);
+        let snapshot = db.snapshot();
+        let index_key = state_index_key("map");
+        let index: ProofMapIndex<_, Hash, u64> = ProofMapIndex::new("map", &snapshot);
+
+        let key = crypto::hash(&[1]);
+        let proof = StateProof::new(
+            aggregator_proof(&*snapshot, index_key),
+            index.get_proof(key),
+        );
+        let checked = proof.clone().check(&state_hash, &index_key).unwrap();
+        assert_eq!(checked.entries(), vec![(&key, &1)]);
+
+        match proof.clone().check(&Hash::zero(), &index_key) {
+            Err(StateProofError::StateHashMismatch) => {}
+            other => panic!("Unexpected result: {:?}", other),
+        }
+        let other_key = state_index_key_in_family("lists", &1_u8);
+        match proof.check(&state_hash, &other_key) {
+            Err(StateProofError::MissingIndex) => {}
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn list_state_proof() {
+        let db = MemoryDB::new();
+        let state_hash = create_state(&db);
+        let snapshot = db.snapshot();
+        let index_key = state_index_key_in_family("lists", &1_u8);
+        let index: ProofListIndex<_, u64> =
+            ProofListIndex::new_in_family("lists", &1_u8, &snapshot);
+
+        let proof = StateProof::new(aggregator_proof(&*snapshot, index_key), index.get_proof(1));
+        assert_eq!(
+            proof.validate(&state_hash, &index_key, index.len()).unwrap(),
+            vec![(1, &2)]
+        );
+
+        // A proof of an entry of another index doesn't match the index root.
+        let other_key = state_index_key("map");
+        let proof = StateProof::new(aggregator_proof(&*snapshot, other_key), index.get_proof(1));
+        assert!(proof.validate(&state_hash, &other_key, index.len()).is_err());
+    }
+}
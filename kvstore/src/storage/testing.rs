@@ -0,0 +1,741 @@
+// Copyright 2018 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Conformance tests for `Database` implementations.
+//!
+//! The module is available with the `testing` feature. It contains fixed scenarios that
+//! exercise the semantics of forks, snapshots and merges, and a differential test that runs
+//! random sequences of fork operations against several databases and a trivial reference model,
+//! comparing the results of `get` and `iter` calls after every step.
+//!
+//! # Examples
+//!
+//! ```
+//! use exonum::storage::{Database, MemoryDB};
+//! use exonum::storage::testing;
+//!
+//! testing::run_conformance_suite(|| Box::new(MemoryDB::new()) as Box<Database>);
+//!
+//! let first = MemoryDB::new();
+//! let second = MemoryDB::new();
+//! testing::check_random_operations(&[&first as &Database, &second], 42, 500);
+//! ```
+
+use rand::{Rng, SeedableRng, XorShiftRng};
+
+use std::collections::BTreeMap;
+use std::collections::Bound::{Included, Unbounded};
+
+use super::{Database, Fork, Snapshot};
+
+const IDX_NAME: &str = "idx_name";
+
+/// Column families used by the random operations.
+const COLUMN_FAMILIES: [&str; 2] = ["cf_a", "cf_b"];
+/// Number of distinct bytes used in keys produced by the random operations. Keys are one
+/// or two bytes long, so the whole key space can be checked after every step.
+const KEY_ALPHABET: u8 = 6;
+
+/// Runs all the fixed conformance scenarios, each on a fresh database created with `create_db`,
+/// and a short differential test against the reference model.
+pub fn run_conformance_suite<F>(create_db: F)
+where
+    F: Fn() -> Box<Database>,
+{
+    fork_iter(&*create_db());
+    changelog(&*create_db());
+    snapshot_isolation(&*create_db());
+    remove_by_prefix(&*create_db());
+    conflicting_reads(&*create_db());
+    check_random_operations(&[&*create_db()], 0, 1_000);
+}
+
+/// Checks iteration over a fork with stored, inserted, replaced and deleted entries.
+pub fn fork_iter(db: &Database) {
+    let mut fork = db.fork();
+
+    fork.put(IDX_NAME, vec![10], vec![10]);
+    fork.put(IDX_NAME, vec![20], vec![20]);
+    fork.put(IDX_NAME, vec![30], vec![30]);
+
+    assert!(fork.contains(IDX_NAME, &[10]));
+
+    db.merge(fork.into_patch()).unwrap();
+
+    let mut fork = db.fork();
+
+    assert!(fork.contains(IDX_NAME, &[10]));
+
+    fn assert_iter(fork: &Fork, from: u8, assumed: &[(u8, u8)]) {
+        let mut values = Vec::new();
+
+        let mut iter = fork.iter(IDX_NAME, &[from]);
+        while let Some((k, v)) = iter.next() {
+            values.push((k[0], v[0]));
+        }
+        assert_eq!(values, assumed);
+    }
+
+    // Stored
+    assert_iter(&fork, 0, &[(10, 10), (20, 20), (30, 30)]);
+    assert_iter(&fork, 5, &[(10, 10), (20, 20), (30, 30)]);
+    assert_iter(&fork, 10, &[(10, 10), (20, 20), (30, 30)]);
+    assert_iter(&fork, 11, &[(20, 20), (30, 30)]);
+    assert_iter(&fork, 31, &[]);
+
+    // Inserted
+    fork.put(IDX_NAME, vec![5], vec![5]);
+    assert_iter(&fork, 0, &[(5, 5), (10, 10), (20, 20), (30, 30)]);
+    fork.put(IDX_NAME, vec![25], vec![25]);
+    assert_iter(&fork, 0, &[(5, 5), (10, 10), (20, 20), (25, 25), (30, 30)]);
+    fork.put(IDX_NAME, vec![35], vec![35]);
+    assert_iter(
+        &fork,
+        0,
+        &[(5, 5), (10, 10), (20, 20), (25, 25), (30, 30), (35, 35)],
+    );
+
+    // Double inserted
+    fork.put(IDX_NAME, vec![25], vec![23]);
+    assert_iter(
+        &fork,
+        0,
+        &[(5, 5), (10, 10), (20, 20), (25, 23), (30, 30), (35, 35)],
+    );
+    fork.put(IDX_NAME, vec![26], vec![26]);
+    assert_iter(
+        &fork,
+        0,
+        &[
+            (5, 5),
+            (10, 10),
+            (20, 20),
+            (25, 23),
+            (26, 26),
+            (30, 30),
+            (35, 35),
+        ],
+    );
+
+    // Replaced
+    let mut fork = db.fork();
+
+    fork.put(IDX_NAME, vec![10], vec![11]);
+    assert_iter(&fork, 0, &[(10, 11), (20, 20), (30, 30)]);
+    fork.put(IDX_NAME, vec![30], vec![31]);
+    assert_iter(&fork, 0, &[(10, 11), (20, 20), (30, 31)]);
+
+    // Deleted
+    let mut fork = db.fork();
+
+    fork.remove(IDX_NAME, vec![20]);
+    assert_iter(&fork, 0, &[(10, 10), (30, 30)]);
+    fork.remove(IDX_NAME, vec![10]);
+    assert_iter(&fork, 0, &[(30, 30)]);
+    fork.put(IDX_NAME, vec![10], vec![11]);
+    assert_iter(&fork, 0, &[(10, 11), (30, 30)]);
+    fork.remove(IDX_NAME, vec![10]);
+    assert_iter(&fork, 0, &[(30, 30)]);
+
+    // MissDeleted
+    let mut fork = db.fork();
+
+    fork.remove(IDX_NAME, vec![5]);
+    assert_iter(&fork, 0, &[(10, 10), (20, 20), (30, 30)]);
+    fork.remove(IDX_NAME, vec![15]);
+    assert_iter(&fork, 0, &[(10, 10), (20, 20), (30, 30)]);
+    fork.remove(IDX_NAME, vec![35]);
+    assert_iter(&fork, 0, &[(10, 10), (20, 20), (30, 30)]);
+}
+
+/// Checks checkpoints, commits and rollbacks of fork changes.
+pub fn changelog(db: &Database) {
+    let mut fork = db.fork();
+
+    fork.put(IDX_NAME, vec![1], vec![1]);
+    fork.put(IDX_NAME, vec![2], vec![2]);
+    fork.put(IDX_NAME, vec![3], vec![3]);
+
+    assert_eq!(fork.get(IDX_NAME, &[1]), Some(vec![1]));
+    assert_eq!(fork.get(IDX_NAME, &[2]), Some(vec![2]));
+    assert_eq!(fork.get(IDX_NAME, &[3]), Some(vec![3]));
+
+    fork.checkpoint();
+
+    assert_eq!(fork.get(IDX_NAME, &[1]), Some(vec![1]));
+    assert_eq!(fork.get(IDX_NAME, &[2]), Some(vec![2]));
+    assert_eq!(fork.get(IDX_NAME, &[3]), Some(vec![3]));
+
+    fork.put(IDX_NAME, vec![1], vec![10]);
+    fork.put(IDX_NAME, vec![4], vec![40]);
+    fork.remove(IDX_NAME, vec![2]);
+
+    assert_eq!(fork.get(IDX_NAME, &[1]), Some(vec![10]));
+    assert_eq!(fork.get(IDX_NAME, &[2]), None);
+    assert_eq!(fork.get(IDX_NAME, &[3]), Some(vec![3]));
+    assert_eq!(fork.get(IDX_NAME, &[4]), Some(vec![40]));
+
+    fork.rollback();
+
+    assert_eq!(fork.get(IDX_NAME, &[1]), Some(vec![1]));
+    assert_eq!(fork.get(IDX_NAME, &[2]), Some(vec![2]));
+    assert_eq!(fork.get(IDX_NAME, &[3]), Some(vec![3]));
+    assert_eq!(fork.get(IDX_NAME, &[4]), None);
+
+    fork.checkpoint();
+
+    fork.put(IDX_NAME, vec![4], vec![40]);
+    fork.put(IDX_NAME, vec![4], vec![41]);
+    fork.remove(IDX_NAME, vec![2]);
+    fork.put(IDX_NAME, vec![2], vec![20]);
+
+    assert_eq!(fork.get(IDX_NAME, &[1]), Some(vec![1]));
+    assert_eq!(fork.get(IDX_NAME, &[2]), Some(vec![20]));
+    assert_eq!(fork.get(IDX_NAME, &[3]), Some(vec![3]));
+    assert_eq!(fork.get(IDX_NAME, &[4]), Some(vec![41]));
+
+    fork.rollback();
+
+    assert_eq!(fork.get(IDX_NAME, &[1]), Some(vec![1]));
+    assert_eq!(fork.get(IDX_NAME, &[2]), Some(vec![2]));
+    assert_eq!(fork.get(IDX_NAME, &[3]), Some(vec![3]));
+    assert_eq!(fork.get(IDX_NAME, &[4]), None);
+
+    fork.put(IDX_NAME, vec![2], vec![20]);
+
+    fork.checkpoint();
+
+    fork.put(IDX_NAME, vec![3], vec![30]);
+
+    fork.rollback();
+
+    assert_eq!(fork.get(IDX_NAME, &[1]), Some(vec![1]));
+    assert_eq!(fork.get(IDX_NAME, &[2]), Some(vec![20]));
+    assert_eq!(fork.get(IDX_NAME, &[3]), Some(vec![3]));
+    assert_eq!(fork.get(IDX_NAME, &[4]), None);
+}
+
+/// Checks that snapshots are not affected by the subsequent merges.
+pub fn snapshot_isolation(db: &Database) {
+    let mut fork = db.fork();
+    fork.put(IDX_NAME, vec![1], vec![1]);
+    db.merge(fork.into_patch()).unwrap();
+
+    let snapshot = db.snapshot();
+    let fork = db.fork();
+
+    let mut other_fork = db.fork();
+    other_fork.put(IDX_NAME, vec![1], vec![10]);
+    other_fork.put(IDX_NAME, vec![2], vec![2]);
+    db.merge_sync(other_fork.into_patch()).unwrap();
+
+    for view in &[&*snapshot, &fork as &Snapshot] {
+        assert_eq!(view.get(IDX_NAME, &[1]), Some(vec![1]));
+        assert!(!view.contains(IDX_NAME, &[2]));
+        assert_eq!(collect_iter(*view, IDX_NAME, &[]), vec![(vec![1], vec![1])]);
+    }
+
+    let snapshot = db.snapshot();
+    assert_eq!(snapshot.get(IDX_NAME, &[1]), Some(vec![10]));
+    assert_eq!(snapshot.get(IDX_NAME, &[2]), Some(vec![2]));
+}
+
+/// Checks that patches are not merged if the values read with `get_for_update` have been
+/// changed since their forks were created.
+pub fn conflicting_reads(db: &Database) {
+    let mut fork = db.fork();
+    fork.put(IDX_NAME, vec![1], vec![1]);
+    db.merge(fork.into_patch()).unwrap();
+
+    let mut first = db.fork();
+    let mut second = db.fork();
+    let mut third = db.fork();
+    assert_eq!(first.get_for_update(IDX_NAME, &[1]), Some(vec![1]));
+    first.put(IDX_NAME, vec![1], vec![2]);
+    assert_eq!(second.get_for_update(IDX_NAME, &[1]), Some(vec![1]));
+    second.put(IDX_NAME, vec![1], vec![3]);
+    assert_eq!(third.get_for_update(IDX_NAME, &[2]), None);
+    third.put(IDX_NAME, vec![2], vec![2]);
+
+    db.merge(first.into_patch()).unwrap();
+    assert!(db.merge(second.into_patch()).unwrap_err().is_conflict());
+    db.merge_sync(third.into_patch()).unwrap();
+
+    // A key read as missing is created by another fork.
+    let mut fork = db.fork();
+    let mut other = db.fork();
+    assert_eq!(fork.get_for_update(IDX_NAME, &[3]), None);
+    fork.put(IDX_NAME, vec![3], vec![3]);
+    other.put(IDX_NAME, vec![3], vec![4]);
+    db.merge(other.into_patch()).unwrap();
+    assert!(db.merge_sync(fork.into_patch()).unwrap_err().is_conflict());
+
+    // Reads of the values changed by the fork itself are not recorded.
+    let mut fork = db.fork();
+    let mut other = db.fork();
+    fork.put(IDX_NAME, vec![1], vec![10]);
+    assert_eq!(fork.get_for_update(IDX_NAME, &[1]), Some(vec![10]));
+    other.put(IDX_NAME, vec![1], vec![20]);
+    db.merge(other.into_patch()).unwrap();
+    db.merge(fork.into_patch()).unwrap();
+
+    let snapshot = db.snapshot();
+    assert_eq!(
+        collect_iter(&*snapshot, IDX_NAME, &[]),
+        vec![
+            (vec![1], vec![10]),
+            (vec![2], vec![2]),
+            (vec![3], vec![4]),
+        ]
+    );
+}
+
+/// Checks removal of keys by prefix both from the fork changes and from the database.
+pub fn remove_by_prefix(db: &Database) {
+    let mut fork = db.fork();
+    fork.put(IDX_NAME, vec![1, 1], vec![1]);
+    fork.put(IDX_NAME, vec![1, 2], vec![2]);
+    fork.put(IDX_NAME, vec![2, 1], vec![3]);
+    db.merge(fork.into_patch()).unwrap();
+
+    let mut fork = db.fork();
+    fork.put(IDX_NAME, vec![1, 3], vec![4]);
+    fork.put(IDX_NAME, vec![3], vec![5]);
+    fork.remove_by_prefix(IDX_NAME, Some(&vec![1]));
+    assert_eq!(
+        collect_iter(&fork, IDX_NAME, &[]),
+        vec![(vec![2, 1], vec![3]), (vec![3], vec![5])]
+    );
+
+    fork.remove_by_prefix(IDX_NAME, None);
+    assert!(collect_iter(&fork, IDX_NAME, &[]).is_empty());
+
+    db.merge(fork.into_patch()).unwrap();
+    assert!(collect_iter(&*db.snapshot(), IDX_NAME, &[]).is_empty());
+}
+
+/// An operation over a fork used by the differential tests.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operation {
+    /// Puts a value into the fork.
+    Put {
+        /// Column family name.
+        name: String,
+        /// Key within the column family.
+        key: Vec<u8>,
+        /// Value to put.
+        value: Vec<u8>,
+    },
+    /// Removes a key from the fork.
+    Remove {
+        /// Column family name.
+        name: String,
+        /// Key within the column family.
+        key: Vec<u8>,
+    },
+    /// Removes all keys with the given prefix, or all keys of the column family.
+    RemoveByPrefix {
+        /// Column family name.
+        name: String,
+        /// Prefix of the keys to remove.
+        prefix: Option<Vec<u8>>,
+    },
+    /// Creates a checkpoint in the fork.
+    Checkpoint,
+    /// Commits the changes made after the latest checkpoint.
+    Commit,
+    /// Rolls back the changes made after the latest checkpoint.
+    Rollback,
+    /// Merges the fork into the database and creates a new fork.
+    Merge,
+}
+
+type Tables = BTreeMap<String, BTreeMap<Vec<u8>, Vec<u8>>>;
+
+/// A trivial model of a database with a single fork on top of it.
+#[derive(Debug, Default, Clone)]
+pub struct ReferenceModel {
+    committed: Tables,
+    working: Tables,
+    checkpoint: Option<Tables>,
+}
+
+impl ReferenceModel {
+    /// Creates an empty model.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns `true` if the model has an active checkpoint.
+    pub fn has_checkpoint(&self) -> bool {
+        self.checkpoint.is_some()
+    }
+
+    /// Applies the operation to the model.
+    pub fn apply(&mut self, operation: &Operation) {
+        match *operation {
+            Operation::Put {
+                ref name,
+                ref key,
+                ref value,
+            } => {
+                self.working
+                    .entry(name.clone())
+                    .or_insert_with(BTreeMap::new)
+                    .insert(key.clone(), value.clone());
+            }
+            Operation::Remove { ref name, ref key } => {
+                if let Some(table) = self.working.get_mut(name) {
+                    table.remove(key);
+                }
+            }
+            Operation::RemoveByPrefix {
+                ref name,
+                ref prefix,
+            } => {
+                if let Some(table) = self.working.get_mut(name) {
+                    match *prefix {
+                        Some(ref prefix) => table.retain(|key, _| !key.starts_with(prefix)),
+                        None => table.clear(),
+                    }
+                }
+            }
+            Operation::Checkpoint => self.checkpoint = Some(self.working.clone()),
+            Operation::Commit => self.checkpoint = None,
+            Operation::Rollback => {
+                self.working = self.checkpoint.take().expect("No active checkpoint");
+            }
+            Operation::Merge => self.committed = self.working.clone(),
+        }
+    }
+
+    /// Returns the value for the key as seen by the fork.
+    pub fn get(&self, name: &str, key: &[u8]) -> Option<Vec<u8>> {
+        Self::table_get(&self.working, name, key)
+    }
+
+    /// Returns the entries as seen by the fork starting from the specified key.
+    pub fn iter(&self, name: &str, from: &[u8]) -> Vec<(Vec<u8>, Vec<u8>)> {
+        Self::table_iter(&self.working, name, from)
+    }
+
+    /// Returns the value for the key as seen by the snapshot of the database.
+    pub fn committed_get(&self, name: &str, key: &[u8]) -> Option<Vec<u8>> {
+        Self::table_get(&self.committed, name, key)
+    }
+
+    /// Returns the entries as seen by the snapshot of the database starting from the
+    /// specified key.
+    pub fn committed_iter(&self, name: &str, from: &[u8]) -> Vec<(Vec<u8>, Vec<u8>)> {
+        Self::table_iter(&self.committed, name, from)
+    }
+
+    fn table_get(tables: &Tables, name: &str, key: &[u8]) -> Option<Vec<u8>> {
+        tables.get(name).and_then(|table| table.get(key).cloned())
+    }
+
+    fn table_iter(tables: &Tables, name: &str, from: &[u8]) -> Vec<(Vec<u8>, Vec<u8>)> {
+        tables.get(name).map_or_else(Vec::new, |table| {
+            table
+                .range::<[u8], _>((Included(from), Unbounded))
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect()
+        })
+    }
+}
+
+/// Generates a random sequence of operations. The sequence respects the rules for
+/// checkpoints: there is at most one active checkpoint, and merges happen only if there is no
+/// active checkpoint.
+pub fn generate_operations<R: Rng>(rng: &mut R, count: usize) -> Vec<Operation> {
+    let mut has_checkpoint = false;
+    let mut operations = Vec::with_capacity(count);
+    while operations.len() < count {
+        let name = rng.choose(&COLUMN_FAMILIES).unwrap().to_string();
+        let operation = match rng.gen_range(0, 100) {
+            0...44 => Operation::Put {
+                name,
+                key: random_key(rng),
+                value: vec![rng.gen()],
+            },
+            45...69 => Operation::Remove {
+                name,
+                key: random_key(rng),
+            },
+            70...74 => Operation::RemoveByPrefix {
+                name,
+                prefix: if rng.gen_weighted_bool(4) {
+                    None
+                } else {
+                    Some(vec![rng.gen_range(0, KEY_ALPHABET)])
+                },
+            },
+            75...84 if !has_checkpoint => {
+                has_checkpoint = true;
+                Operation::Checkpoint
+            }
+            75...84 => {
+                has_checkpoint = false;
+                if rng.gen() {
+                    Operation::Commit
+                } else {
+                    Operation::Rollback
+                }
+            }
+            _ if has_checkpoint => continue,
+            _ => Operation::Merge,
+        };
+        operations.push(operation);
+    }
+    operations
+}
+
+fn random_key<R: Rng>(rng: &mut R) -> Vec<u8> {
+    let len = rng.gen_range(1, 3);
+    (0..len).map(|_| rng.gen_range(0, KEY_ALPHABET)).collect()
+}
+
+/// Generates `count` random operations from the `seed` and checks them with
+/// [`check_operations`](fn.check_operations.html).
+pub fn check_random_operations(dbs: &[&Database], seed: u64, count: usize) {
+    let mut rng = XorShiftRng::from_seed([
+        seed as u32 | 1,
+        (seed >> 32) as u32,
+        0x9E37_79B9,
+        0x7F4A_7C15,
+    ]);
+    let operations = generate_operations(&mut rng, count);
+    check_operations(dbs, &operations);
+}
+
+/// Applies the operations to a fork of each database and to the reference model,
+/// and compares the results of `get` and `iter` calls for the whole key space
+/// after every step.
+///
+/// # Panics
+///
+/// Panics with the number of the step and the operation if the databases diverge.
+pub fn check_operations(dbs: &[&Database], operations: &[Operation]) {
+    let mut model = ReferenceModel::new();
+    let mut forks = dbs.iter().map(|db| Some(db.fork())).collect::<Vec<_>>();
+
+    for (step, operation) in operations.iter().enumerate() {
+        model.apply(operation);
+        for (db_index, (db, fork)) in dbs.iter().zip(forks.iter_mut()).enumerate() {
+            apply_operation(*db, fork, operation);
+            let context = format!(
+                "database #{}, step #{}, operation {:?}",
+                db_index, step, operation
+            );
+            check_view(fork.as_ref().unwrap(), &model, false, &context);
+            if *operation == Operation::Merge {
+                check_view(&*db.snapshot(), &model, true, &context);
+            }
+        }
+    }
+}
+
+fn apply_operation(db: &Database, fork: &mut Option<Fork>, operation: &Operation) {
+    match *operation {
+        Operation::Put {
+            ref name,
+            ref key,
+            ref value,
+        } => fork.as_mut().unwrap().put(name, key.clone(), value.clone()),
+        Operation::Remove { ref name, ref key } => {
+            fork.as_mut().unwrap().remove(name, key.clone())
+        }
+        Operation::RemoveByPrefix {
+            ref name,
+            ref prefix,
+        } => fork.as_mut()
+            .unwrap()
+            .remove_by_prefix(name, prefix.as_ref()),
+        Operation::Checkpoint => fork.as_mut().unwrap().checkpoint(),
+        Operation::Commit => fork.as_mut().unwrap().commit(),
+        Operation::Rollback => fork.as_mut().unwrap().rollback(),
+        Operation::Merge => {
+            let patch = fork.take().unwrap().into_patch();
+            db.merge(patch).unwrap();
+            *fork = Some(db.fork());
+        }
+    }
+}
+
+fn check_view(view: &Snapshot, model: &ReferenceModel, committed: bool, context: &str) {
+    let mut keys = (0..KEY_ALPHABET).map(|a| vec![a]).collect::<Vec<_>>();
+    for a in 0..KEY_ALPHABET {
+        keys.extend((0..KEY_ALPHABET).map(|b| vec![a, b]));
+    }
+
+    for name in &COLUMN_FAMILIES {
+        for key in keys.iter().chain(Some(&Vec::new())) {
+            let expected = if committed {
+                model.committed_get(name, key)
+            } else {
+                model.get(name, key)
+            };
+            assert_eq!(
+                view.get(name, key),
+                expected,
+                "`get` mismatch for key {:?} in {}: {}",
+                key,
+                name,
+                context
+            );
+            assert_eq!(
+                view.contains(name, key),
+                expected.is_some(),
+                "`contains` mismatch for key {:?} in {}: {}",
+                key,
+                name,
+                context
+            );
+
+            let expected = if committed {
+                model.committed_iter(name, key)
+            } else {
+                model.iter(name, key)
+            };
+            let expected_keys = expected.iter().map(|e| e.0.clone()).collect::<Vec<_>>();
+            assert_eq!(
+                collect_iter(view, name, key),
+                expected,
+                "`iter` mismatch from key {:?} in {}: {}",
+                key,
+                name,
+                context
+            );
+            assert_eq!(
+                collect_keys(view, name, key),
+                expected_keys,
+                "`iter_keys` mismatch from key {:?} in {}: {}",
+                key,
+                name,
+                context
+            );
+        }
+    }
+}
+
+fn collect_iter(view: &Snapshot, name: &str, from: &[u8]) -> Vec<(Vec<u8>, Vec<u8>)> {
+    let mut entries = Vec::new();
+    let mut iter = view.iter(name, from);
+    while let Some((k, v)) = iter.next() {
+        entries.push((k.to_vec(), v.to_vec()));
+    }
+    entries
+}
+
+/// Collects the keys yielded by `iter_keys`, checking that `peek` agrees with `next`.
+fn collect_keys(view: &Snapshot, name: &str, from: &[u8]) -> Vec<Vec<u8>> {
+    let mut keys = Vec::new();
+    let mut iter = view.iter_keys(name, from);
+    loop {
+        let peeked = iter.peek().map(|(k, _)| k.to_vec());
+        let next = iter.next().map(|(k, _)| k.to_vec());
+        assert_eq!(peeked, next, "`peek` and `next` disagree");
+        match next {
+            Some(key) => keys.push(key),
+            None => break,
+        }
+    }
+    keys
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+
+    use storage::{CachedDatabase, Database, DbOptions, MemoryDB, RocksDB, RoutingTable,
+                  ShardedDatabase, VersionedDatabase};
+    use super::*;
+
+    fn sharded_memorydb() -> ShardedDatabase {
+        let routing = RoutingTable::new(0)
+            .route_prefix(COLUMN_FAMILIES[0], vec![3], 1)
+            .route_column_family(COLUMN_FAMILIES[1], 2);
+        let shards = (0..3)
+            .map(|_| Box::new(MemoryDB::new()) as Box<Database>)
+            .collect();
+        ShardedDatabase::new(shards, routing).unwrap()
+    }
+
+    #[test]
+    fn memorydb_conformance() {
+        run_conformance_suite(|| Box::new(MemoryDB::new()) as Box<Database>);
+    }
+
+    #[test]
+    fn cached_memorydb_conformance() {
+        run_conformance_suite(|| {
+            Box::new(CachedDatabase::new(MemoryDB::new(), 256)) as Box<Database>
+        });
+    }
+
+    #[test]
+    fn versioned_memorydb_conformance() {
+        run_conformance_suite(|| {
+            Box::new(VersionedDatabase::new(MemoryDB::new())) as Box<Database>
+        });
+    }
+
+    #[test]
+    fn sharded_memorydb_conformance() {
+        run_conformance_suite(|| Box::new(sharded_memorydb()) as Box<Database>);
+    }
+
+    #[test]
+    fn rocksdb_conformance() {
+        let dir = TempDir::new("exonum_conformance").unwrap();
+        let counter = ::std::cell::Cell::new(0);
+        run_conformance_suite(|| {
+            counter.set(counter.get() + 1);
+            let path = dir.path().join(counter.get().to_string());
+            Box::new(RocksDB::open(path, &DbOptions::default()).unwrap()) as Box<Database>
+        });
+    }
+
+    #[test]
+    fn differential_memorydb_rocksdb() {
+        let dir = TempDir::new("exonum_differential").unwrap();
+        for seed in 0..8 {
+            let memorydb = MemoryDB::new();
+            let rocksdb = RocksDB::open(dir.path().join(seed.to_string()), &DbOptions::default())
+                .unwrap();
+            let cached = CachedDatabase::new(MemoryDB::new(), 1024);
+            let versioned = VersionedDatabase::new(MemoryDB::new());
+            let sharded = sharded_memorydb();
+            check_random_operations(
+                &[&memorydb as &Database, &rocksdb, &cached, &versioned, &sharded],
+                seed,
+                300,
+            );
+        }
+    }
+
+    #[test]
+    fn generated_operations_are_valid() {
+        let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+        let mut model = ReferenceModel::new();
+        for operation in generate_operations(&mut rng, 10_000) {
+            match operation {
+                Operation::Commit | Operation::Rollback => assert!(model.has_checkpoint()),
+                Operation::Checkpoint | Operation::Merge => assert!(!model.has_checkpoint()),
+                _ => {}
+            }
+            model.apply(&operation);
+        }
+    }
+}
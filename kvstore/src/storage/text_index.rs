@@ -0,0 +1,933 @@
+// Copyright 2018 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! An implementation of inverted full-text index with BM25 ranking.
+
+use byteorder::{BigEndian, ByteOrder};
+
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::marker::PhantomData;
+
+use crypto::{hash, CryptoHash, Hash};
+use super::{BaseIndex, IndexAccess, IndexAccessMut, StorageKey, StorageValue};
+use super::indexes_metadata::IndexType;
+use super::keys::write_escaped;
+
+/// Subprefix of the postings, ordered by terms and then document keys.
+const POSTINGS_PREFIX: u8 = 0;
+/// Subprefix of the numbers of documents containing a term.
+const FREQUENCIES_PREFIX: u8 = 1;
+/// Subprefix of the lengths and terms of the documents.
+const DOCUMENTS_PREFIX: u8 = 2;
+/// Subprefix of the statistics of the whole index.
+const STATS_PREFIX: u8 = 3;
+
+/// BM25 parameter controlling the saturation of term frequencies.
+const BM25_K1: f64 = 1.2;
+/// BM25 parameter controlling the normalization by document lengths.
+const BM25_B: f64 = 0.75;
+
+/// Relevance scores of the documents matching a query, by serialized document keys.
+type Scores = BTreeMap<Vec<u8>, f64>;
+
+/// Splits texts into terms for a [`TextIndex`].
+///
+/// The tokenizer is applied both to the indexed texts and to the texts of the queries,
+/// so it should normalize the terms, e.g., convert them to lowercase. The trait is implemented
+/// for closures taking `&str` and returning `Vec<String>`.
+///
+/// [`TextIndex`]: struct.TextIndex.html
+pub trait Tokenizer {
+    /// Returns the terms of the text in the order they occur in it.
+    fn tokenize(&self, text: &str) -> Vec<String>;
+}
+
+impl<F> Tokenizer for F
+where
+    F: Fn(&str) -> Vec<String>,
+{
+    fn tokenize(&self, text: &str) -> Vec<String> {
+        self(text)
+    }
+}
+
+/// The default tokenizer of a [`TextIndex`], which splits texts on characters other than
+/// letters and digits and converts the terms to lowercase.
+///
+/// [`TextIndex`]: struct.TextIndex.html
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SimpleTokenizer;
+
+impl Tokenizer for SimpleTokenizer {
+    fn tokenize(&self, text: &str) -> Vec<String> {
+        text.split(|c: char| !c.is_alphanumeric())
+            .filter(|term| !term.is_empty())
+            .map(|term| term.to_lowercase())
+            .collect()
+    }
+}
+
+/// A query to a [`TextIndex`].
+///
+/// The texts of the queries are split into terms by the tokenizer of the index.
+///
+/// [`TextIndex`]: struct.TextIndex.html
+#[derive(Debug, Clone, PartialEq)]
+pub enum Query {
+    /// Matches the documents containing all the terms of the text.
+    Term(String),
+    /// Matches the documents containing the terms of the text in a row and in the same order.
+    Phrase(String),
+    /// Matches the documents matched by all the queries.
+    And(Vec<Query>),
+    /// Matches the documents matched by any of the queries.
+    Or(Vec<Query>),
+}
+
+impl Query {
+    /// Creates a query matching the documents which contain all the terms of the text.
+    pub fn term<S: Into<String>>(text: S) -> Self {
+        Query::Term(text.into())
+    }
+
+    /// Creates a query matching the documents which contain the terms of the text in a row.
+    pub fn phrase<S: Into<String>>(text: S) -> Self {
+        Query::Phrase(text.into())
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct TextStats {
+    /// Number of indexed documents.
+    documents: u64,
+    /// Total number of terms in the indexed documents.
+    total_length: u64,
+}
+
+impl TextStats {
+    fn to_array(&self) -> [u8; 16] {
+        let mut buf = [0; 16];
+        BigEndian::write_u64(&mut buf[0..8], self.documents);
+        BigEndian::write_u64(&mut buf[8..16], self.total_length);
+        buf
+    }
+
+    fn average_length(&self) -> f64 {
+        if self.documents == 0 {
+            0.0
+        } else {
+            self.total_length as f64 / self.documents as f64
+        }
+    }
+}
+
+impl CryptoHash for TextStats {
+    fn hash(&self) -> Hash {
+        hash(&self.to_array())
+    }
+}
+
+impl StorageValue for TextStats {
+    fn into_bytes(self) -> Vec<u8> {
+        self.to_array().to_vec()
+    }
+
+    fn from_bytes(value: Cow<[u8]>) -> Self {
+        let buf = value.as_ref();
+        let documents = BigEndian::read_u64(&buf[0..8]);
+        let total_length = BigEndian::read_u64(&buf[8..16]);
+        TextStats {
+            documents,
+            total_length,
+        }
+    }
+}
+
+/// The length and the distinct terms of an indexed document, used to remove its postings.
+#[derive(Debug, Clone)]
+struct DocumentInfo {
+    length: u32,
+    terms: Vec<String>,
+}
+
+impl CryptoHash for DocumentInfo {
+    fn hash(&self) -> Hash {
+        hash(&self.clone().into_bytes())
+    }
+}
+
+impl StorageValue for DocumentInfo {
+    fn into_bytes(self) -> Vec<u8> {
+        let mut buf = vec![0; 4];
+        BigEndian::write_u32(&mut buf, self.length);
+        for term in self.terms {
+            let offset = buf.len();
+            buf.resize(offset + 4, 0);
+            BigEndian::write_u32(&mut buf[offset..], term.len() as u32);
+            buf.extend_from_slice(term.as_bytes());
+        }
+        buf
+    }
+
+    fn from_bytes(value: Cow<[u8]>) -> Self {
+        let buf = value.as_ref();
+        let length = BigEndian::read_u32(&buf[0..4]);
+        let mut terms = Vec::new();
+        let mut offset = 4;
+        while offset < buf.len() {
+            let term_len = BigEndian::read_u32(&buf[offset..offset + 4]) as usize;
+            offset += 4;
+            terms.push(String::from_utf8_lossy(&buf[offset..offset + term_len]).into_owned());
+            offset += term_len;
+        }
+        DocumentInfo { length, terms }
+    }
+}
+
+/// An occurrence of a term in a document.
+struct Posting {
+    /// Number of terms in the document.
+    length: u32,
+    /// Positions of the term in the document in ascending order.
+    positions: Vec<u32>,
+}
+
+impl Posting {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = vec![0; 4 * (1 + self.positions.len())];
+        BigEndian::write_u32(&mut buf[0..4], self.length);
+        for (chunk, position) in buf[4..].chunks_mut(4).zip(&self.positions) {
+            BigEndian::write_u32(chunk, *position);
+        }
+        buf
+    }
+
+    fn from_bytes(buf: &[u8]) -> Self {
+        Posting {
+            length: BigEndian::read_u32(&buf[0..4]),
+            positions: buf[4..].chunks(4).map(BigEndian::read_u32).collect(),
+        }
+    }
+}
+
+/// An inverted full-text index over the texts of documents identified by keys.
+///
+/// For each term, the index keeps a postings list, i.e., the keys of the documents containing
+/// the term together with the positions of the term in them, stored as sorted storage keys.
+/// The index answers [`Query`]s for terms and phrases combined with AND and OR, and ranks
+/// the matching documents with BM25 using the stored document frequencies of the terms.
+///
+/// The texts are split into terms by a [`Tokenizer`], which is [`SimpleTokenizer`] by default
+/// and can be replaced with [`with_tokenizer`]. The tokenizer is not persisted, so the same
+/// tokenizer must be used for all the views of the index.
+///
+/// The index is usually maintained alongside a `MapIndex` with the documents, using the same
+/// keys. As both of them are updated in the same [`Fork`], the changes to the documents and to
+/// the text index are merged into the database together.
+///
+/// `TextIndex` requires that the keys implement the [`StorageKey`] trait.
+///
+/// # Examples
+///
+/// ```
+/// use exonum::storage::{MemoryDB, Database, MapIndex, TextIndex};
+/// use exonum::storage::text_index::Query;
+///
+/// let db = MemoryDB::new();
+/// let mut fork = db.fork();
+/// let articles = [
+///     (1_u64, "The quick brown fox"),
+///     (2_u64, "The lazy brown dog"),
+///     (3_u64, "Quick thinking"),
+/// ];
+/// for &(id, text) in &articles {
+///     MapIndex::new("articles", &mut fork).put(&id, text.to_owned());
+///     TextIndex::new("articles_text", &mut fork).insert(&id, text);
+/// }
+///
+/// let index: TextIndex<_, u64> = TextIndex::new("articles_text", &fork);
+/// let ids: Vec<u64> = index.search(&Query::term("brown")).into_iter().map(|(id, _)| id).collect();
+/// assert_eq!(ids.len(), 2);
+///
+/// let query = Query::And(vec![Query::term("quick"), Query::phrase("brown fox")]);
+/// assert_eq!(index.search(&query)[0].0, 1);
+/// ```
+///
+/// [`Query`]: enum.Query.html
+/// [`Tokenizer`]: trait.Tokenizer.html
+/// [`SimpleTokenizer`]: struct.SimpleTokenizer.html
+/// [`with_tokenizer`]: #method.with_tokenizer
+/// [`Fork`]: ../struct.Fork.html
+/// [`StorageKey`]: ../trait.StorageKey.html
+pub struct TextIndex<T, K> {
+    base: BaseIndex<T>,
+    tokenizer: Box<Tokenizer>,
+    _k: PhantomData<K>,
+}
+
+impl<T, K> TextIndex<T, K>
+where
+    T: IndexAccess,
+    K: StorageKey,
+{
+    /// Creates a new index representation based on the name and storage view.
+    ///
+    /// Storage view can be specified as [`&Snapshot`] or [`&mut Fork`]. In the first case only
+    /// immutable methods are available. In the second case both immutable and mutable methods are
+    /// available.
+    ///
+    /// [`&Snapshot`]: ../trait.Snapshot.html
+    /// [`&mut Fork`]: ../struct.Fork.html
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, TextIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let snapshot = db.snapshot();
+    /// let name = "name";
+    /// let index: TextIndex<_, u64> = TextIndex::new(name, &snapshot);
+    /// ```
+    pub fn new<S: AsRef<str>>(index_name: S, view: T) -> Self {
+        TextIndex {
+            base: BaseIndex::new(index_name, IndexType::Text, view),
+            tokenizer: Box::new(SimpleTokenizer),
+            _k: PhantomData,
+        }
+    }
+
+    /// Creates a new index representation based on the name, index id in family
+    /// and storage view.
+    ///
+    /// Storage view can be specified as [`&Snapshot`] or [`&mut Fork`]. In the first case only
+    /// immutable methods are available. In the second case both immutable and mutable methods are
+    /// available.
+    ///
+    /// [`&Snapshot`]: ../trait.Snapshot.html
+    /// [`&mut Fork`]: ../struct.Fork.html
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, TextIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let snapshot = db.snapshot();
+    /// let name = "name";
+    /// let index_id = vec![123];
+    /// let index: TextIndex<_, u64> = TextIndex::new_in_family(
+    ///     name,
+    ///     &index_id,
+    ///     &snapshot,
+    ///  );
+    /// ```
+    pub fn new_in_family<S: AsRef<str>, I: StorageKey>(
+        family_name: S,
+        index_id: &I,
+        view: T,
+    ) -> Self {
+        TextIndex {
+            base: BaseIndex::new_in_family(family_name, index_id, IndexType::Text, view),
+            tokenizer: Box::new(SimpleTokenizer),
+            _k: PhantomData,
+        }
+    }
+
+    /// Replaces the tokenizer of the index.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, TextIndex};
+    /// use exonum::storage::text_index::Query;
+    ///
+    /// let db = MemoryDB::new();
+    /// let mut fork = db.fork();
+    /// let mut index = TextIndex::new("name", &mut fork).with_tokenizer(|text: &str| {
+    ///     text.split(',').map(|tag| tag.trim().to_owned()).collect::<Vec<_>>()
+    /// });
+    ///
+    /// index.insert(&1_u64, "rust, key-value storage");
+    /// assert_eq!(index.search(&Query::term("key-value storage")).len(), 1);
+    /// assert!(index.search(&Query::term("storage")).is_empty());
+    /// ```
+    pub fn with_tokenizer<Z>(mut self, tokenizer: Z) -> Self
+    where
+        Z: Tokenizer + 'static,
+    {
+        self.tokenizer = Box::new(tokenizer);
+        self
+    }
+
+    /// Returns the storage view of the index, consuming the index.
+    pub fn into_view(self) -> T {
+        self.base.into_view()
+    }
+
+    fn stats(&self) -> TextStats {
+        self.base.get(&STATS_PREFIX).unwrap_or_default()
+    }
+
+    /// Returns `true` if the index contains a document with the specified key.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, TextIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let mut fork = db.fork();
+    /// let mut index = TextIndex::new("name", &mut fork);
+    /// assert!(!index.contains(&1_u64));
+    ///
+    /// index.insert(&1_u64, "text");
+    /// assert!(index.contains(&1_u64));
+    /// ```
+    pub fn contains(&self, key: &K) -> bool {
+        self.base.contains(&document_key(&key_bytes(key)))
+    }
+
+    /// Returns the number of documents in the index.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, TextIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let mut fork = db.fork();
+    /// let mut index = TextIndex::new("name", &mut fork);
+    /// assert_eq!(0, index.len());
+    ///
+    /// index.insert(&1_u64, "text");
+    /// index.insert(&2_u64, "more text");
+    /// assert_eq!(2, index.len());
+    /// ```
+    pub fn len(&self) -> u64 {
+        self.stats().documents
+    }
+
+    /// Returns `true` if the index contains no documents.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, TextIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let mut fork = db.fork();
+    /// let mut index = TextIndex::new("name", &mut fork);
+    /// assert!(index.is_empty());
+    ///
+    /// index.insert(&1_u64, "text");
+    /// assert!(!index.is_empty());
+    /// ```
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the number of documents containing the term. The term is not tokenized.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, TextIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let mut fork = db.fork();
+    /// let mut index = TextIndex::new("name", &mut fork);
+    ///
+    /// index.insert(&1_u64, "red apple");
+    /// index.insert(&2_u64, "red red rose");
+    /// assert_eq!(2, index.document_frequency("red"));
+    /// assert_eq!(0, index.document_frequency("Red"));
+    /// ```
+    pub fn document_frequency(&self, term: &str) -> u64 {
+        self.base.get(&frequency_key(term)).unwrap_or(0)
+    }
+
+    /// Returns the keys of the documents matching the query together with their BM25 scores,
+    /// from the most relevant document to the least relevant one. The documents with equal
+    /// scores are ordered by keys.
+    ///
+    /// The score of a document is the sum of the scores of the terms of the query matched
+    /// by the document.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, TextIndex};
+    /// use exonum::storage::text_index::Query;
+    ///
+    /// let db = MemoryDB::new();
+    /// let mut fork = db.fork();
+    /// let mut index = TextIndex::new("name", &mut fork);
+    ///
+    /// index.insert(&1_u64, "a rose is a rose is a rose");
+    /// index.insert(&2_u64, "a red rose");
+    /// index.insert(&3_u64, "a red apple");
+    ///
+    /// let query = Query::Or(vec![Query::term("rose"), Query::term("apple")]);
+    /// let ids: Vec<u64> = index.search(&query).into_iter().map(|(id, _)| id).collect();
+    /// assert_eq!(ids.len(), 3);
+    /// assert_eq!(ids[2], 2);
+    /// ```
+    pub fn search(&self, query: &Query) -> Vec<(K::Owned, f64)> {
+        let stats = self.stats();
+        let mut results = self.evaluate(query, &stats).into_iter().collect::<Vec<_>>();
+        results.sort_by(|a, b| {
+            b.1
+                .partial_cmp(&a.1)
+                .unwrap_or(Ordering::Equal)
+                .then_with(|| a.0.cmp(&b.0))
+        });
+        results
+            .into_iter()
+            .map(|(key, score)| (K::read(&key), score))
+            .collect()
+    }
+
+    fn evaluate(&self, query: &Query, stats: &TextStats) -> Scores {
+        match *query {
+            Query::Term(ref text) => {
+                let terms = self.tokenizer.tokenize(text);
+                intersect(
+                    terms
+                        .iter()
+                        .map(|term| self.term_scores(term, stats))
+                        .collect(),
+                )
+            }
+            Query::Phrase(ref text) => self.phrase_scores(&self.tokenizer.tokenize(text), stats),
+            Query::And(ref queries) => intersect(
+                queries
+                    .iter()
+                    .map(|query| self.evaluate(query, stats))
+                    .collect(),
+            ),
+            Query::Or(ref queries) => unite(
+                queries
+                    .iter()
+                    .map(|query| self.evaluate(query, stats))
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Returns the postings of the term by the serialized document keys.
+    fn postings(&self, term: &str) -> BTreeMap<Vec<u8>, Posting> {
+        let prefix = postings_prefix(term);
+        self.base
+            .iter::<_, Vec<u8>, Vec<u8>>(&prefix)
+            .map(|(key, value)| (key[prefix.len()..].to_vec(), Posting::from_bytes(&value)))
+            .collect()
+    }
+
+    fn term_scores(&self, term: &str, stats: &TextStats) -> Scores {
+        let frequency = self.document_frequency(term);
+        self.postings(term)
+            .into_iter()
+            .map(|(document, posting)| {
+                let score = bm25(&posting, frequency, stats);
+                (document, score)
+            })
+            .collect()
+    }
+
+    fn phrase_scores(&self, terms: &[String], stats: &TextStats) -> Scores {
+        let postings = terms
+            .iter()
+            .map(|term| self.postings(term))
+            .collect::<Vec<_>>();
+        let frequencies = terms
+            .iter()
+            .map(|term| self.document_frequency(term))
+            .collect::<Vec<_>>();
+        let mut scores = Scores::new();
+        let first = match postings.first() {
+            Some(first) => first,
+            None => return scores,
+        };
+        for document in first.keys() {
+            let document_postings = match postings
+                .iter()
+                .map(|term_postings| term_postings.get(document))
+                .collect::<Option<Vec<_>>>()
+            {
+                Some(document_postings) => document_postings,
+                None => continue,
+            };
+            let occurs = document_postings[0].positions.iter().any(|&start| {
+                document_postings
+                    .iter()
+                    .enumerate()
+                    .all(|(offset, posting)| {
+                        posting
+                            .positions
+                            .binary_search(&(start + offset as u32))
+                            .is_ok()
+                    })
+            });
+            if occurs {
+                let score = document_postings
+                    .iter()
+                    .zip(&frequencies)
+                    .map(|(posting, &frequency)| bm25(posting, frequency, stats))
+                    .sum();
+                scores.insert(document.clone(), score);
+            }
+        }
+        scores
+    }
+}
+
+impl<T, K> TextIndex<T, K>
+where
+    T: IndexAccessMut,
+    K: StorageKey,
+{
+    /// Indexes the text of the document with the specified key, replacing the previously
+    /// indexed text of the document, if any.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, TextIndex};
+    /// use exonum::storage::text_index::Query;
+    ///
+    /// let db = MemoryDB::new();
+    /// let mut fork = db.fork();
+    /// let mut index = TextIndex::new("name", &mut fork);
+    ///
+    /// index.insert(&1_u64, "old text");
+    /// index.insert(&1_u64, "new text");
+    /// assert!(index.search(&Query::term("old")).is_empty());
+    /// assert_eq!(index.search(&Query::term("new"))[0].0, 1);
+    /// ```
+    pub fn insert(&mut self, key: &K, text: &str) {
+        let document = key_bytes(key);
+        self.remove_document(&document);
+
+        let terms = self.tokenizer.tokenize(text);
+        let length = terms.len() as u32;
+        let mut positions = BTreeMap::<String, Vec<u32>>::new();
+        for (position, term) in terms.into_iter().enumerate() {
+            positions
+                .entry(term)
+                .or_insert_with(Vec::new)
+                .push(position as u32);
+        }
+        for (term, term_positions) in &positions {
+            let posting = Posting {
+                length,
+                positions: term_positions.clone(),
+            };
+            self.base
+                .put(&posting_key(term, &document), posting.to_bytes());
+            let frequency = self.document_frequency(term);
+            self.base.put(&frequency_key(term), frequency + 1);
+        }
+        let info = DocumentInfo {
+            length,
+            terms: positions.into_iter().map(|(term, _)| term).collect(),
+        };
+        self.base.put(&document_key(&document), info);
+
+        let mut stats = self.stats();
+        stats.documents += 1;
+        stats.total_length += u64::from(length);
+        self.base.put(&STATS_PREFIX, stats);
+    }
+
+    /// Removes the document with the specified key from the index. Returns `true` if
+    /// the document was indexed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, TextIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let mut fork = db.fork();
+    /// let mut index = TextIndex::new("name", &mut fork);
+    ///
+    /// index.insert(&1_u64, "text");
+    /// assert!(index.remove(&1_u64));
+    /// assert!(!index.remove(&1_u64));
+    /// assert_eq!(0, index.document_frequency("text"));
+    /// ```
+    pub fn remove(&mut self, key: &K) -> bool {
+        self.remove_document(&key_bytes(key))
+    }
+
+    /// Clears the index, removing all documents.
+    ///
+    /// # Notes
+    ///
+    /// Currently this method is not optimized to delete large set of data. During the execution of
+    /// this method the amount of allocated memory is linearly dependent on the number of elements
+    /// in the index.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, TextIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let mut fork = db.fork();
+    /// let mut index = TextIndex::new("name", &mut fork);
+    ///
+    /// index.insert(&1_u64, "text");
+    /// index.clear();
+    /// assert!(index.is_empty());
+    /// ```
+    pub fn clear(&mut self) {
+        self.base.clear()
+    }
+
+    fn remove_document(&mut self, document: &[u8]) -> bool {
+        let document_key = document_key(document);
+        let info = match self.base.get::<_, DocumentInfo>(&document_key) {
+            Some(info) => info,
+            None => return false,
+        };
+        for term in &info.terms {
+            self.base.remove(&posting_key(term, document));
+            let frequency_key = frequency_key(term);
+            match self.base.get::<_, u64>(&frequency_key) {
+                Some(frequency) if frequency > 1 => self.base.put(&frequency_key, frequency - 1),
+                _ => self.base.remove(&frequency_key),
+            }
+        }
+        self.base.remove(&document_key);
+
+        let mut stats = self.stats();
+        stats.documents -= 1;
+        stats.total_length -= u64::from(info.length);
+        if stats.documents == 0 {
+            self.base.remove(&STATS_PREFIX);
+        } else {
+            self.base.put(&STATS_PREFIX, stats);
+        }
+        true
+    }
+}
+
+impl<T, K> fmt::Debug for TextIndex<T, K>
+where
+    T: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TextIndex")
+            .field("base", &self.base)
+            .finish()
+    }
+}
+
+/// Returns the BM25 score of a term in a document.
+fn bm25(posting: &Posting, document_frequency: u64, stats: &TextStats) -> f64 {
+    let documents = stats.documents as f64;
+    let frequency = document_frequency as f64;
+    let idf = ((documents - frequency + 0.5) / (frequency + 0.5) + 1.0).ln();
+    let average_length = stats.average_length();
+    let normalization = if average_length > 0.0 {
+        1.0 - BM25_B + BM25_B * f64::from(posting.length) / average_length
+    } else {
+        1.0
+    };
+    let tf = posting.positions.len() as f64;
+    idf * tf * (BM25_K1 + 1.0) / (tf + BM25_K1 * normalization)
+}
+
+/// Returns the documents contained in all the score maps, summing their scores.
+/// The intersection of no maps is empty.
+fn intersect(mut scores: Vec<Scores>) -> Scores {
+    scores.sort_by_key(|scores| scores.len());
+    let mut scores = scores.into_iter();
+    let mut result = match scores.next() {
+        Some(first) => first,
+        None => return Scores::new(),
+    };
+    for other in scores {
+        result = result
+            .into_iter()
+            .filter_map(|(document, score)| {
+                other
+                    .get(&document)
+                    .map(|other_score| (document, score + other_score))
+            })
+            .collect();
+    }
+    result
+}
+
+/// Returns the documents contained in any of the score maps, summing their scores.
+fn unite(scores: Vec<Scores>) -> Scores {
+    let mut result = Scores::new();
+    for (document, score) in scores.into_iter().flat_map(|scores| scores) {
+        *result.entry(document).or_insert(0.0) += score;
+    }
+    result
+}
+
+fn key_bytes<K: StorageKey + ?Sized>(key: &K) -> Vec<u8> {
+    let mut buffer = vec![0; key.size()];
+    key.write(&mut buffer);
+    buffer
+}
+
+/// Returns the common prefix of the storage keys of the postings of the term.
+fn postings_prefix(term: &str) -> Vec<u8> {
+    let mut prefix = vec![POSTINGS_PREFIX];
+    write_escaped(term.as_bytes(), &mut prefix);
+    prefix
+}
+
+/// Returns the storage key of the posting of the term in the document.
+fn posting_key(term: &str, document: &[u8]) -> Vec<u8> {
+    let mut buffer = postings_prefix(term);
+    buffer.extend_from_slice(document);
+    buffer
+}
+
+/// Returns the storage key of the number of documents containing the term.
+fn frequency_key(term: &str) -> Vec<u8> {
+    let mut buffer = vec![FREQUENCIES_PREFIX];
+    buffer.extend_from_slice(term.as_bytes());
+    buffer
+}
+
+/// Returns the storage key of the information about the document.
+fn document_key(document: &[u8]) -> Vec<u8> {
+    let mut buffer = vec![DOCUMENTS_PREFIX];
+    buffer.extend_from_slice(document);
+    buffer
+}
+
+#[cfg(test)]
+mod tests {
+    use storage::{Database, MapIndex, MemoryDB};
+    use super::{Query, SimpleTokenizer, TextIndex, Tokenizer};
+
+    fn ids<T>(index: &TextIndex<T, u64>, query: &Query) -> Vec<u64>
+    where
+        T: ::storage::IndexAccess,
+    {
+        index.search(query).into_iter().map(|(id, _)| id).collect()
+    }
+
+    #[test]
+    fn simple_tokenizer() {
+        assert_eq!(
+            SimpleTokenizer.tokenize("Hello, World! It's 2018..."),
+            vec!["hello", "world", "it", "s", "2018"]
+        );
+        assert!(SimpleTokenizer.tokenize(" ,. ").is_empty());
+    }
+
+    #[test]
+    fn boolean_and_phrase_queries() {
+        let db = MemoryDB::new();
+        let mut fork = db.fork();
+        let mut index = TextIndex::new("text", &mut fork);
+        index.insert(&1, "New York is a big city");
+        index.insert(&2, "York is older than New York");
+        index.insert(&3, "A new city in the north");
+        index.insert(&4, "Big apple");
+
+        assert_eq!(ids(&index, &Query::phrase("new york")).len(), 2);
+        assert_eq!(ids(&index, &Query::phrase("than new york")), vec![2]);
+        assert!(ids(&index, &Query::phrase("york new")).is_empty());
+        assert_eq!(ids(&index, &Query::phrase("big city")), vec![1]);
+        assert!(ids(&index, &Query::phrase("city big")).is_empty());
+
+        let mut found = ids(&index, &Query::term("new city"));
+        found.sort();
+        assert_eq!(found, vec![1, 3]);
+
+        let query = Query::And(vec![Query::term("city"), Query::phrase("new york")]);
+        assert_eq!(ids(&index, &query), vec![1]);
+
+        let query = Query::Or(vec![Query::term("apple"), Query::term("north")]);
+        let mut found = ids(&index, &query);
+        found.sort();
+        assert_eq!(found, vec![3, 4]);
+
+        assert!(ids(&index, &Query::term("")).is_empty());
+        assert!(ids(&index, &Query::phrase("")).is_empty());
+        assert!(ids(&index, &Query::And(vec![])).is_empty());
+        assert!(ids(&index, &Query::Or(vec![])).is_empty());
+    }
+
+    #[test]
+    fn bm25_ranking() {
+        let db = MemoryDB::new();
+        let mut fork = db.fork();
+        let mut index = TextIndex::new("text", &mut fork);
+        index.insert(&1, "storage storage storage engine");
+        index.insert(&2, "storage engine with a very long description of many other things");
+        index.insert(&3, "storage");
+        index.insert(&4, "unrelated");
+
+        // Rare terms weigh more than common ones.
+        let query = Query::Or(vec![Query::term("storage"), Query::term("unrelated")]);
+        assert_eq!(ids(&index, &query)[0], 4);
+        // Frequent terms and short documents rank higher.
+        assert_eq!(ids(&index, &Query::term("storage")), vec![1, 3, 2]);
+        let scores = index.search(&Query::term("storage"));
+        assert!(scores.iter().all(|&(_, score)| score > 0.0));
+    }
+
+    #[test]
+    fn updates_in_fork_with_map() {
+        let db = MemoryDB::new();
+        let mut fork = db.fork();
+        for &(id, text) in &[(1_u64, "first text"), (2, "second text")] {
+            MapIndex::new("docs", &mut fork).put(&id, text.to_owned());
+            TextIndex::new("docs_text", &mut fork).insert(&id, text);
+        }
+        {
+            MapIndex::<_, u64, String>::new("docs", &mut fork).remove(&1);
+            let mut index = TextIndex::new("docs_text", &mut fork);
+            assert!(index.remove(&1_u64));
+            index.insert(&2, "second version");
+        }
+        db.merge(fork.into_patch()).unwrap();
+
+        let snapshot = db.snapshot();
+        let index: TextIndex<_, u64> = TextIndex::new("docs_text", &snapshot);
+        assert_eq!(index.len(), 1);
+        assert!(!index.contains(&1));
+        assert_eq!(index.document_frequency("text"), 0);
+        assert_eq!(index.document_frequency("second"), 1);
+        assert_eq!(ids(&index, &Query::term("version")), vec![2]);
+        assert!(ids(&index, &Query::term("text")).is_empty());
+    }
+
+    #[test]
+    fn terms_with_common_prefix() {
+        let db = MemoryDB::new();
+        let mut fork = db.fork();
+        let mut index = TextIndex::new("text", &mut fork);
+        index.insert(&1, "car");
+        index.insert(&2, "cart");
+
+        assert_eq!(ids(&index, &Query::term("car")), vec![1]);
+        assert_eq!(ids(&index, &Query::term("cart")), vec![2]);
+    }
+}
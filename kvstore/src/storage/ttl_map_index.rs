@@ -0,0 +1,703 @@
+// Copyright 2018 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! An implementation of key-value map with expiring entries.
+
+use byteorder::{BigEndian, ByteOrder};
+
+use std::borrow::{Borrow, Cow};
+use std::marker::PhantomData;
+
+use super::{BaseIndex, BaseIndexIter, IndexAccess, IndexAccessMut, StorageKey, StorageValue};
+use super::indexes_metadata::IndexType;
+
+/// Subprefix of the entries, ordered by keys.
+const ENTRIES_PREFIX: u8 = 0;
+/// Subprefix of the expiry records, ordered by expiry timestamps.
+const EXPIRY_PREFIX: u8 = 1;
+/// Size of a serialized expiry timestamp.
+const TIMESTAMP_SIZE: usize = 8;
+
+/// A map of keys and values, each of which expires at a certain time.
+///
+/// `TtlMapIndex` stores the expiry timestamp together with each value. Entries which are
+/// expired at the specified current time are hidden from all the reading methods, but remain
+/// in the storage until they are deleted with [`sweep_expired`]. The index keeps its entries
+/// ordered by the expiry timestamps as well, so `sweep_expired` reads only the expired entries.
+///
+/// Timestamps are plain `u64` numbers; the index does not read the clock and does not impose
+/// any unit, so the same unit (e.g., seconds since the Unix epoch or the block height) must be
+/// used for writing and reading. An entry is expired if its expiry timestamp is less than
+/// or equal to the current time.
+///
+/// `TtlMapIndex` requires that the keys implement the [`StorageKey`] trait and the values
+/// implement [`StorageValue`] trait.
+///
+/// [`sweep_expired`]: #method.sweep_expired
+/// [`StorageKey`]: ../trait.StorageKey.html
+/// [`StorageValue`]: ../trait.StorageValue.html
+#[derive(Debug)]
+pub struct TtlMapIndex<T, K, V> {
+    base: BaseIndex<T>,
+    _k: PhantomData<K>,
+    _v: PhantomData<V>,
+}
+
+/// An iterator over the unexpired entries of a `TtlMapIndex`.
+///
+/// This struct is created by the [`iter`] or
+/// [`iter_from`] methods on [`TtlMapIndex`]. See its documentation for more.
+///
+/// [`iter`]: struct.TtlMapIndex.html#method.iter
+/// [`iter_from`]: struct.TtlMapIndex.html#method.iter_from
+/// [`TtlMapIndex`]: struct.TtlMapIndex.html
+#[derive(Debug)]
+pub struct TtlMapIndexIter<'a, K, V> {
+    base_iter: BaseIndexIter<'a, Vec<u8>, Vec<u8>>,
+    now: u64,
+    _k: PhantomData<K>,
+    _v: PhantomData<V>,
+}
+
+/// An iterator over the keys of the unexpired entries of a `TtlMapIndex`.
+///
+/// This struct is created by the [`keys`] method on [`TtlMapIndex`].
+/// See its documentation for more.
+///
+/// [`keys`]: struct.TtlMapIndex.html#method.keys
+/// [`TtlMapIndex`]: struct.TtlMapIndex.html
+#[derive(Debug)]
+pub struct TtlMapIndexKeys<'a, K> {
+    iter: TtlMapIndexIter<'a, K, ()>,
+}
+
+/// An iterator over the values of the unexpired entries of a `TtlMapIndex`.
+///
+/// This struct is created by the [`values`] method on [`TtlMapIndex`].
+/// See its documentation for more.
+///
+/// [`values`]: struct.TtlMapIndex.html#method.values
+/// [`TtlMapIndex`]: struct.TtlMapIndex.html
+#[derive(Debug)]
+pub struct TtlMapIndexValues<'a, V> {
+    iter: TtlMapIndexIter<'a, (), V>,
+}
+
+impl<T, K, V> TtlMapIndex<T, K, V>
+where
+    T: IndexAccess,
+    K: StorageKey,
+    V: StorageValue,
+{
+    /// Creates a new index representation based on the name and storage view.
+    ///
+    /// Storage view can be specified as [`&Snapshot`] or [`&mut Fork`]. In the first case only
+    /// immutable methods are available. In the second case both immutable and mutable methods are
+    /// available.
+    ///
+    /// [`&Snapshot`]: ../trait.Snapshot.html
+    /// [`&mut Fork`]: ../struct.Fork.html
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, TtlMapIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let name = "name";
+    /// let snapshot = db.snapshot();
+    /// let index: TtlMapIndex<_, u8, u8> = TtlMapIndex::new(name, &snapshot);
+    /// ```
+    pub fn new<S: AsRef<str>>(index_name: S, view: T) -> Self {
+        TtlMapIndex {
+            base: BaseIndex::new(index_name, IndexType::TtlMap, view),
+            _k: PhantomData,
+            _v: PhantomData,
+        }
+    }
+
+    /// Creates a new index representation based on the name, index id in family
+    /// and storage view.
+    ///
+    /// Storage view can be specified as [`&Snapshot`] or [`&mut Fork`]. In the first case only
+    /// immutable methods are available. In the second case both immutable and mutable methods are
+    /// available.
+    ///
+    /// [`&Snapshot`]: ../trait.Snapshot.html
+    /// [`&mut Fork`]: ../struct.Fork.html
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, TtlMapIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let name = "name";
+    /// let index_id = vec![01];
+    ///
+    /// let snapshot = db.snapshot();
+    /// let index: TtlMapIndex<_, u8, u8> = TtlMapIndex::new_in_family(name, &index_id, &snapshot);
+    /// ```
+    pub fn new_in_family<S: AsRef<str>, I: StorageKey>(
+        family_name: S,
+        index_id: &I,
+        view: T,
+    ) -> Self {
+        TtlMapIndex {
+            base: BaseIndex::new_in_family(family_name, index_id, IndexType::TtlMap, view),
+            _k: PhantomData,
+            _v: PhantomData,
+        }
+    }
+
+    /// Returns the storage view of the index, consuming the index.
+    pub fn into_view(self) -> T {
+        self.base.into_view()
+    }
+
+    /// Returns a value corresponding to the key, if the entry is not expired at `now`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, TtlMapIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let name = "name";
+    /// let mut fork = db.fork();
+    /// let mut index = TtlMapIndex::new(name, &mut fork);
+    /// index.put(&1, 2, 100);
+    /// assert_eq!(Some(2), index.get(&1, 99));
+    /// assert_eq!(None, index.get(&1, 100));
+    /// ```
+    pub fn get<Q>(&self, key: &Q, now: u64) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: StorageKey + ?Sized,
+    {
+        self.get_with_expiry(key, now).map(|(value, _)| value)
+    }
+
+    /// Returns the expiry timestamp of the entry corresponding to the key, if the entry is not
+    /// expired at `now`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, TtlMapIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let name = "name";
+    /// let mut fork = db.fork();
+    /// let mut index = TtlMapIndex::new(name, &mut fork);
+    /// index.put(&1, 2_u8, 100);
+    /// assert_eq!(Some(100), index.expires_at(&1, 50));
+    /// ```
+    pub fn expires_at<Q>(&self, key: &Q, now: u64) -> Option<u64>
+    where
+        K: Borrow<Q>,
+        Q: StorageKey + ?Sized,
+    {
+        self.get_with_expiry(key, now).map(|(_, expires_at)| expires_at)
+    }
+
+    /// Returns `true` if the map contains an entry for the specified key, which is not expired
+    /// at `now`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, TtlMapIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let name = "name";
+    /// let mut fork = db.fork();
+    /// let mut index = TtlMapIndex::new(name, &mut fork);
+    /// assert!(!index.contains(&1, 0));
+    ///
+    /// index.put(&1, 2_u8, 100);
+    /// assert!(index.contains(&1, 0));
+    /// assert!(!index.contains(&1, 100));
+    /// ```
+    pub fn contains<Q>(&self, key: &Q, now: u64) -> bool
+    where
+        K: Borrow<Q>,
+        Q: StorageKey + ?Sized,
+    {
+        self.expires_at(key, now).is_some()
+    }
+
+    /// Returns an iterator over the entries of the map, which are not expired at `now`,
+    /// in ascending order of keys. The iterator element type is (K, V).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, TtlMapIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let name = "name";
+    /// let snapshot = db.snapshot();
+    /// let index: TtlMapIndex<_, u8, u8> = TtlMapIndex::new(name, &snapshot);
+    ///
+    /// for v in index.iter(10) {
+    ///     println!("{:?}", v);
+    /// }
+    /// ```
+    pub fn iter(&self, now: u64) -> TtlMapIndexIter<K, V> {
+        TtlMapIndexIter {
+            base_iter: self.base.iter(&ENTRIES_PREFIX),
+            now,
+            _k: PhantomData,
+            _v: PhantomData,
+        }
+    }
+
+    /// Returns an iterator over the keys of the entries, which are not expired at `now`,
+    /// in ascending order. The iterator element type is K.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, TtlMapIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let name = "name";
+    /// let snapshot = db.snapshot();
+    /// let index: TtlMapIndex<_, u8, u8> = TtlMapIndex::new(name, &snapshot);
+    ///
+    /// for key in index.keys(10) {
+    ///     println!("{}", key);
+    /// }
+    /// ```
+    pub fn keys(&self, now: u64) -> TtlMapIndexKeys<K> {
+        TtlMapIndexKeys {
+            iter: TtlMapIndexIter {
+                base_iter: self.base.iter(&ENTRIES_PREFIX),
+                now,
+                _k: PhantomData,
+                _v: PhantomData,
+            },
+        }
+    }
+
+    /// Returns an iterator over the values of the entries, which are not expired at `now`,
+    /// in ascending order of keys. The iterator element type is V.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, TtlMapIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let name = "name";
+    /// let snapshot = db.snapshot();
+    /// let index: TtlMapIndex<_, u8, u8> = TtlMapIndex::new(name, &snapshot);
+    ///
+    /// for val in index.values(10) {
+    ///     println!("{}", val);
+    /// }
+    /// ```
+    pub fn values(&self, now: u64) -> TtlMapIndexValues<V> {
+        TtlMapIndexValues {
+            iter: TtlMapIndexIter {
+                base_iter: self.base.iter(&ENTRIES_PREFIX),
+                now,
+                _k: PhantomData,
+                _v: PhantomData,
+            },
+        }
+    }
+
+    /// Returns an iterator over the entries of the map, which are not expired at `now`,
+    /// in ascending order starting from the specified key. The iterator element type is (K, V).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, TtlMapIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let name = "name";
+    /// let snapshot = db.snapshot();
+    /// let index: TtlMapIndex<_, u8, u8> = TtlMapIndex::new(name, &snapshot);
+    ///
+    /// for v in index.iter_from(&2, 10) {
+    ///     println!("{:?}", v);
+    /// }
+    /// ```
+    pub fn iter_from<Q>(&self, from: &Q, now: u64) -> TtlMapIndexIter<K, V>
+    where
+        K: Borrow<Q>,
+        Q: StorageKey + ?Sized,
+    {
+        TtlMapIndexIter {
+            base_iter: self.base.iter_from(&ENTRIES_PREFIX, &entry_key(from)),
+            now,
+            _k: PhantomData,
+            _v: PhantomData,
+        }
+    }
+
+    fn get_with_expiry<Q>(&self, key: &Q, now: u64) -> Option<(V, u64)>
+    where
+        Q: StorageKey + ?Sized,
+    {
+        self.base
+            .get::<_, Vec<u8>>(&entry_key(key))
+            .map(|entry| decode_entry::<V>(&entry))
+            .and_then(|(value, expires_at)| {
+                if expires_at > now {
+                    Some((value, expires_at))
+                } else {
+                    None
+                }
+            })
+    }
+}
+
+impl<T, K, V> TtlMapIndex<T, K, V>
+where
+    T: IndexAccessMut,
+    K: StorageKey,
+    V: StorageValue,
+{
+    /// Inserts the key-value pair into the map, which expires at the specified timestamp.
+    /// If the map already contains an entry for the key, both its value and expiry timestamp
+    /// are replaced.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, TtlMapIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let name = "name";
+    /// let mut fork = db.fork();
+    /// let mut index = TtlMapIndex::new(name, &mut fork);
+    /// index.put(&1, 2, 100);
+    /// assert_eq!(Some(2), index.get(&1, 0));
+    ///
+    /// index.put(&1, 3, 200);
+    /// assert_eq!(Some(3), index.get(&1, 150));
+    /// ```
+    pub fn put(&mut self, key: &K, value: V, expires_at: u64) {
+        let entry_key = entry_key(key);
+        self.remove_expiry_record(&entry_key);
+
+        let mut entry = vec![0; TIMESTAMP_SIZE];
+        BigEndian::write_u64(&mut entry, expires_at);
+        entry.extend_from_slice(&value.into_bytes());
+        self.base.put(&expiry_key(expires_at, &entry_key[1..]), ());
+        self.base.put(&entry_key, entry);
+    }
+
+    /// Removes the key from the map, whether its entry is expired or not.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, TtlMapIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let name = "name";
+    /// let mut fork = db.fork();
+    /// let mut index = TtlMapIndex::new(name, &mut fork);
+    ///
+    /// index.put(&1, 2_u8, 100);
+    /// assert!(index.contains(&1, 0));
+    ///
+    /// index.remove(&1);
+    /// assert!(!index.contains(&1, 0));
+    /// ```
+    pub fn remove<Q>(&mut self, key: &Q)
+    where
+        K: Borrow<Q>,
+        Q: StorageKey + ?Sized,
+    {
+        let entry_key = entry_key(key);
+        if self.remove_expiry_record(&entry_key) {
+            self.base.remove(&entry_key);
+        }
+    }
+
+    /// Deletes at most `limit` entries which are expired at `now`, starting from the ones
+    /// which expired first, and returns the number of deleted entries.
+    ///
+    /// If the returned number is equal to `limit`, more expired entries may remain in the map.
+    /// Thus, expired entries can be deleted in batches of a bounded size, each in its own fork.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, TtlMapIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let name = "name";
+    /// let mut fork = db.fork();
+    /// {
+    ///     let mut index = TtlMapIndex::new(name, &mut fork);
+    ///     for i in 0..10_u64 {
+    ///         index.put(&i, i, i);
+    ///     }
+    /// }
+    /// db.merge(fork.into_patch()).unwrap();
+    ///
+    /// loop {
+    ///     let mut fork = db.fork();
+    ///     let deleted = TtlMapIndex::<_, u64, u64>::new(name, &mut fork).sweep_expired(5, 2);
+    ///     db.merge(fork.into_patch()).unwrap();
+    ///     if deleted < 2 {
+    ///         break;
+    ///     }
+    /// }
+    ///
+    /// let snapshot = db.snapshot();
+    /// let index: TtlMapIndex<_, u64, u64> = TtlMapIndex::new(name, &snapshot);
+    /// assert_eq!(index.keys(0).collect::<Vec<_>>(), vec![6, 7, 8, 9]);
+    /// ```
+    pub fn sweep_expired(&mut self, now: u64, limit: usize) -> usize {
+        let expired = self.base
+            .iter::<_, Vec<u8>, ()>(&EXPIRY_PREFIX)
+            .map(|(key, _)| key)
+            .take_while(|key| BigEndian::read_u64(&key[1..1 + TIMESTAMP_SIZE]) <= now)
+            .take(limit)
+            .collect::<Vec<_>>();
+
+        for key in &expired {
+            let mut entry_key = vec![ENTRIES_PREFIX];
+            entry_key.extend_from_slice(&key[1 + TIMESTAMP_SIZE..]);
+            self.base.remove(&entry_key);
+            self.base.remove(key);
+        }
+        expired.len()
+    }
+
+    /// Clears the map, removing all entries, expired or not.
+    ///
+    /// # Notes
+    ///
+    /// Currently this method is not optimized to delete large set of data. During the execution of
+    /// this method the amount of allocated memory is linearly dependent on the number of elements
+    /// in the index.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, TtlMapIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let name = "name";
+    /// let mut fork = db.fork();
+    /// let mut index = TtlMapIndex::new(name, &mut fork);
+    ///
+    /// index.put(&1, 2_u8, 100);
+    /// assert!(index.contains(&1, 0));
+    ///
+    /// index.clear();
+    /// assert!(!index.contains(&1, 0));
+    /// ```
+    pub fn clear(&mut self) {
+        self.base.clear()
+    }
+
+    /// Removes the expiry record of the entry with the specified key, if the entry exists,
+    /// and returns `true` in this case.
+    fn remove_expiry_record(&mut self, entry_key: &[u8]) -> bool {
+        let expires_at = self.base
+            .get::<_, Vec<u8>>(entry_key)
+            .map(|entry| BigEndian::read_u64(&entry[..TIMESTAMP_SIZE]));
+        match expires_at {
+            Some(expires_at) => {
+                self.base.remove(&expiry_key(expires_at, &entry_key[1..]));
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl<'a, K, V> Iterator for TtlMapIndexIter<'a, K, V>
+where
+    K: StorageKey,
+    V: StorageValue,
+{
+    type Item = (K::Owned, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (key, entry) = match self.base_iter.next() {
+                Some(item) => item,
+                None => return None,
+            };
+            if BigEndian::read_u64(&entry[..TIMESTAMP_SIZE]) > self.now {
+                let (value, _) = decode_entry::<V>(&entry);
+                return Some((K::read(&key[1..]), value));
+            }
+        }
+    }
+}
+
+impl<'a, K> Iterator for TtlMapIndexKeys<'a, K>
+where
+    K: StorageKey,
+{
+    type Item = K::Owned;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter.next().map(|(k, ..)| k)
+    }
+}
+
+impl<'a, V> Iterator for TtlMapIndexValues<'a, V>
+where
+    V: StorageValue,
+{
+    type Item = V;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter.next().map(|(.., v)| v)
+    }
+}
+
+/// Returns the storage key of the entry with the specified key.
+fn entry_key<K: StorageKey + ?Sized>(key: &K) -> Vec<u8> {
+    let mut buffer = vec![ENTRIES_PREFIX; 1 + key.size()];
+    key.write(&mut buffer[1..]);
+    buffer
+}
+
+/// Returns the storage key of the expiry record of the entry with the specified serialized key.
+fn expiry_key(expires_at: u64, key: &[u8]) -> Vec<u8> {
+    let mut buffer = vec![EXPIRY_PREFIX; 1 + TIMESTAMP_SIZE + key.len()];
+    BigEndian::write_u64(&mut buffer[1..1 + TIMESTAMP_SIZE], expires_at);
+    buffer[1 + TIMESTAMP_SIZE..].copy_from_slice(key);
+    buffer
+}
+
+/// Splits the stored entry into the value and its expiry timestamp.
+fn decode_entry<V: StorageValue>(entry: &[u8]) -> (V, u64) {
+    let expires_at = BigEndian::read_u64(&entry[..TIMESTAMP_SIZE]);
+    let value = V::from_bytes(Cow::Borrowed(&entry[TIMESTAMP_SIZE..]));
+    (value, expires_at)
+}
+
+#[cfg(test)]
+mod tests {
+    use storage::{Database, MapIndex, MemoryDB};
+    use super::TtlMapIndex;
+
+    const IDX_NAME: &str = "idx_name";
+
+    #[test]
+    fn expired_entries_are_hidden() {
+        let db = MemoryDB::new();
+        let mut fork = db.fork();
+        let mut index = TtlMapIndex::new(IDX_NAME, &mut fork);
+        index.put(&1_u8, 10_u64, 100);
+        index.put(&2_u8, 20_u64, 50);
+        index.put(&3_u8, 30_u64, 150);
+
+        assert_eq!(index.get(&2, 49), Some(20));
+        assert_eq!(index.get(&2, 50), None);
+        assert_eq!(index.expires_at(&3, 50), Some(150));
+        assert_eq!(index.expires_at(&3, 150), None);
+        assert_eq!(
+            index.iter(0).collect::<Vec<_>>(),
+            vec![(1, 10), (2, 20), (3, 30)]
+        );
+        assert_eq!(index.iter(50).collect::<Vec<_>>(), vec![(1, 10), (3, 30)]);
+        assert_eq!(index.keys(100).collect::<Vec<_>>(), vec![3]);
+        assert_eq!(index.values(150).count(), 0);
+        assert_eq!(index.iter_from(&2, 60).collect::<Vec<_>>(), vec![(3, 30)]);
+    }
+
+    #[test]
+    fn put_replaces_expiry() {
+        let db = MemoryDB::new();
+        let mut fork = db.fork();
+        let mut index = TtlMapIndex::new(IDX_NAME, &mut fork);
+        index.put(&1_u8, 10_u64, 100);
+        index.put(&1_u8, 11_u64, 200);
+        assert_eq!(index.get(&1, 150), Some(11));
+
+        // The previous expiry timestamp must not delete the refreshed entry.
+        assert_eq!(index.sweep_expired(150, 10), 0);
+        assert_eq!(index.get(&1, 150), Some(11));
+        assert_eq!(index.sweep_expired(200, 10), 1);
+        assert_eq!(index.get(&1, 0), None);
+
+        index.put(&2_u8, 20_u64, 100);
+        index.remove(&2_u8);
+        assert_eq!(index.sweep_expired(u64::max_value(), 10), 0);
+    }
+
+    #[test]
+    fn sweep_expired_in_batches() {
+        let db = MemoryDB::new();
+        let mut fork = db.fork();
+        {
+            let mut index = TtlMapIndex::new(IDX_NAME, &mut fork);
+            // Keys are ordered inversely to the expiry timestamps.
+            for i in 0..10_u64 {
+                index.put(&i, i, 100 - i);
+            }
+        }
+        db.merge(fork.into_patch()).unwrap();
+
+        let mut swept = Vec::new();
+        loop {
+            let mut fork = db.fork();
+            let deleted = {
+                let mut index: TtlMapIndex<_, u64, u64> = TtlMapIndex::new(IDX_NAME, &mut fork);
+                index.sweep_expired(95, 3)
+            };
+            db.merge(fork.into_patch()).unwrap();
+            swept.push(deleted);
+            if deleted < 3 {
+                break;
+            }
+        }
+        assert_eq!(swept, vec![3, 2]);
+
+        let snapshot = db.snapshot();
+        let index: TtlMapIndex<_, u64, u64> = TtlMapIndex::new(IDX_NAME, &snapshot);
+        assert_eq!(index.keys(0).collect::<Vec<_>>(), vec![0, 1, 2, 3, 4]);
+        assert_eq!(index.values(94).collect::<Vec<_>>(), vec![0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn indexes_in_family_are_isolated() {
+        let db = MemoryDB::new();
+        let mut fork = db.fork();
+        TtlMapIndex::new_in_family(IDX_NAME, &1_u8, &mut fork).put(&1_u8, 1_u8, 10);
+        TtlMapIndex::new_in_family(IDX_NAME, &2_u8, &mut fork).put(&1_u8, 2_u8, 20);
+
+        {
+            let mut first = TtlMapIndex::<_, u8, u8>::new_in_family(IDX_NAME, &1_u8, &mut fork);
+            assert_eq!(first.sweep_expired(20, 10), 1);
+        }
+        let second = TtlMapIndex::<_, u8, u8>::new_in_family(IDX_NAME, &2_u8, &mut fork);
+        assert_eq!(second.get(&1, 15), Some(2));
+    }
+
+    #[test]
+    #[should_panic(expected = "Attempt to access index")]
+    fn access_as_map_index() {
+        let db = MemoryDB::new();
+        let mut fork = db.fork();
+        TtlMapIndex::new(IDX_NAME, &mut fork).put(&1_u8, 1_u8, 10);
+        let _: MapIndex<_, u8, u8> = MapIndex::new(IDX_NAME, &fork);
+    }
+}
@@ -17,7 +17,9 @@
 use std::marker::PhantomData;
 
 use crypto::Hash;
-use super::{BaseIndex, BaseIndexIter, Fork, Snapshot, StorageKey, StorageValue};
+use super::{BaseIndex, BaseIndexIter, BaseIndexKeysIter, IndexAccess, IndexAccessMut, StorageKey,
+            StorageValue};
+use super::bloom_filter::{BloomFilter, BloomFilterCache};
 use super::indexes_metadata::IndexType;
 
 /// A set of items that implement `StorageValue` trait.
@@ -25,10 +27,19 @@ use super::indexes_metadata::IndexType;
 /// `ValueSetIndex` implements a set, storing the element as values using its hash as a key.
 /// `ValueSetIndex` requires that the elements implement the [`StorageValue`] trait.
 ///
+/// Like [`KeySetIndex`], the set may have a bloom filter over the hashes of the elements,
+/// created with [`create_filter`], which lets [`contains`] and [`contains_by_hash`] answer for
+/// most of the absent elements without reading the storage.
+///
 /// [`StorageValue`]: ../trait.StorageValue.html
+/// [`KeySetIndex`]: ../key_set_index/struct.KeySetIndex.html
+/// [`create_filter`]: #method.create_filter
+/// [`contains`]: #method.contains
+/// [`contains_by_hash`]: #method.contains_by_hash
 #[derive(Debug)]
 pub struct ValueSetIndex<T, V> {
     base: BaseIndex<T>,
+    filter: BloomFilterCache,
     _v: PhantomData<V>,
 }
 
@@ -55,12 +66,12 @@ pub struct ValueSetIndexIter<'a, V> {
 /// [`ValueSetIndex`]: struct.ValueSetIndex.html
 #[derive(Debug)]
 pub struct ValueSetIndexHashes<'a> {
-    base_iter: BaseIndexIter<'a, Hash, ()>,
+    base_iter: BaseIndexKeysIter<'a, Hash>,
 }
 
 impl<T, V> ValueSetIndex<T, V>
 where
-    T: AsRef<Snapshot>,
+    T: IndexAccess,
     V: StorageValue,
 {
     /// Creates a new index representation based on the name and storage view.
@@ -84,7 +95,8 @@ where
     /// ```
     pub fn new<S: AsRef<str>>(index_name: S, view: T) -> Self {
         ValueSetIndex {
-            base: BaseIndex::new(index_name, IndexType::ValueSet, view),
+            base: BaseIndex::new(index_name, IndexType::ValueSet, view).with_auxiliary_data(),
+            filter: BloomFilterCache::default(),
             _v: PhantomData,
         }
     }
@@ -116,11 +128,18 @@ where
         view: T,
     ) -> Self {
         ValueSetIndex {
-            base: BaseIndex::new_in_family(family_name, index_id, IndexType::ValueSet, view),
+            base: BaseIndex::new_in_family(family_name, index_id, IndexType::ValueSet, view)
+                .with_auxiliary_data(),
+            filter: BloomFilterCache::default(),
             _v: PhantomData,
         }
     }
 
+    /// Returns the storage view of the index, consuming the index.
+    pub fn into_view(self) -> T {
+        self.base.into_view()
+    }
+
     /// Returns `true` if the set contains a value.
     ///
     /// # Examples
@@ -161,7 +180,52 @@ where
     /// index.insert(data);
     /// assert!(index.contains_by_hash(&data_hash));
     pub fn contains_by_hash(&self, hash: &Hash) -> bool {
-        self.base.contains(hash)
+        self.filter.may_contain(&self.base, hash) && self.base.contains(hash)
+    }
+
+    /// Returns `true` if the set has a bloom filter.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, ValueSetIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let name  = "name";
+    /// let mut fork = db.fork();
+    /// let mut index: ValueSetIndex<_, u8> = ValueSetIndex::new(name, &mut fork);
+    /// assert!(!index.has_filter());
+    ///
+    /// index.create_filter(1000, 0.01);
+    /// assert!(index.has_filter());
+    /// ```
+    pub fn has_filter(&self) -> bool {
+        self.filter.is_enabled(&self.base)
+    }
+
+    /// Returns `true` if the expected false positive rate of the bloom filter has grown to
+    /// more than twice the rate the filter was created with, because more elements were
+    /// inserted than the filter was sized for, or because of the removed elements remaining
+    /// in the filter. Returns `false` if the set has no filter.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, ValueSetIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let name  = "name";
+    /// let mut fork = db.fork();
+    /// let mut index = ValueSetIndex::new(name, &mut fork);
+    /// index.create_filter(10, 0.01);
+    ///
+    /// for i in 0..100_u8 {
+    ///     index.insert(i);
+    /// }
+    /// assert!(index.is_filter_stale());
+    /// ```
+    pub fn is_filter_stale(&self) -> bool {
+        self.filter.is_stale(&self.base)
     }
 
     /// An iterator visiting all elements in arbitrary order. The iterator element type is V.
@@ -231,7 +295,7 @@ where
     /// ```
     pub fn hashes(&self) -> ValueSetIndexHashes {
         ValueSetIndexHashes {
-            base_iter: self.base.iter(&()),
+            base_iter: self.base.iter_keys(&()),
         }
     }
 
@@ -257,13 +321,14 @@ where
     /// ```
     pub fn hashes_from(&self, from: &Hash) -> ValueSetIndexHashes {
         ValueSetIndexHashes {
-            base_iter: self.base.iter_from(&(), from),
+            base_iter: self.base.iter_keys_from(&(), from),
         }
     }
 }
 
-impl<'a, V> ValueSetIndex<&'a mut Fork, V>
+impl<T, V> ValueSetIndex<T, V>
 where
+    T: IndexAccessMut,
     V: StorageValue,
 {
     /// Adds a value to the set.
@@ -282,7 +347,9 @@ where
     /// assert!(index.contains(&1));
     /// ```
     pub fn insert(&mut self, item: V) {
-        self.base.put(&item.hash(), item)
+        let hash = item.hash();
+        self.filter.insert(&mut self.base, &hash);
+        self.base.put(&hash, item)
     }
 
     /// Removes a value from the set.
@@ -355,13 +422,97 @@ where
     /// assert!(!index.contains(&1));
     /// ```
     pub fn clear(&mut self) {
+        self.filter.clear(&mut self.base);
         self.base.clear()
     }
+
+    /// Creates a bloom filter sized for the specified number of elements and false positive
+    /// rate, and fills it with the hashes of the elements of the set. Replaces the existing
+    /// filter, if any.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is zero or `false_positive_rate` is not between 0 and 1.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, ValueSetIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let name  = "name";
+    /// let mut fork = db.fork();
+    /// let mut index = ValueSetIndex::new(name, &mut fork);
+    ///
+    /// index.insert(1);
+    /// index.create_filter(1000, 0.01);
+    /// assert!(index.contains(&1));
+    /// assert!(!index.contains(&2));
+    /// ```
+    pub fn create_filter(&mut self, capacity: u64, false_positive_rate: f64) {
+        let filter = {
+            let hashes = self.base.iter_keys::<_, Vec<u8>>(&());
+            BloomFilter::build(capacity, false_positive_rate, hashes)
+        };
+        self.filter.replace(&mut self.base, filter);
+    }
+
+    /// Rebuilds the bloom filter from the elements of the set, dropping the removed elements
+    /// from it. The filter keeps its capacity and false positive rate. Returns `false` if
+    /// the set has no filter.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, ValueSetIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let name  = "name";
+    /// let mut fork = db.fork();
+    /// let mut index = ValueSetIndex::new(name, &mut fork);
+    /// assert!(!index.rebuild_filter());
+    ///
+    /// index.create_filter(10, 0.01);
+    /// for i in 0..100_u8 {
+    ///     index.insert(i);
+    ///     index.remove(&i);
+    /// }
+    /// assert!(index.rebuild_filter());
+    /// assert!(!index.is_filter_stale());
+    /// ```
+    pub fn rebuild_filter(&mut self) -> bool {
+        let (capacity, false_positive_rate) = match self.filter.parameters(&self.base) {
+            Some(parameters) => parameters,
+            None => return false,
+        };
+        self.create_filter(capacity, false_positive_rate);
+        true
+    }
+
+    /// Removes the bloom filter of the set, if any.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use exonum::storage::{MemoryDB, Database, ValueSetIndex};
+    ///
+    /// let db = MemoryDB::new();
+    /// let name  = "name";
+    /// let mut fork = db.fork();
+    /// let mut index: ValueSetIndex<_, u8> = ValueSetIndex::new(name, &mut fork);
+    ///
+    /// index.create_filter(1000, 0.01);
+    /// index.remove_filter();
+    /// assert!(!index.has_filter());
+    /// ```
+    pub fn remove_filter(&mut self) {
+        self.filter.remove(&mut self.base);
+    }
 }
 
 impl<'a, T, V> ::std::iter::IntoIterator for &'a ValueSetIndex<T, V>
 where
-    T: AsRef<Snapshot>,
+    T: IndexAccess,
     V: StorageValue,
 {
     type Item = (Hash, V);
@@ -387,6 +538,6 @@ impl<'a> Iterator for ValueSetIndexHashes<'a> {
     type Item = Hash;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.base_iter.next().map(|(k, ..)| k)
+        self.base_iter.next()
     }
 }
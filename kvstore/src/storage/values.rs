@@ -21,7 +21,8 @@ use uuid::Uuid;
 use std::mem;
 use std::borrow::Cow;
 
-//use crypto::{Hash, PublicKey};
+use crypto::Hash;
+//use crypto::PublicKey;
 //use encoding::{Field, Offset};
 //use messages::{MessageBuffer, RawMessage};
 //use helpers::Round;
@@ -213,15 +214,15 @@ impl StorageValue for i64 {
     }
 }
 
-//impl StorageValue for Hash {
-//    fn into_bytes(self) -> Vec<u8> {
-//        self.as_ref().to_vec()
-//    }
-//
-//    fn from_bytes(value: Cow<[u8]>) -> Self {
-//        Self::from_slice(value.as_ref()).unwrap()
-//    }
-//}
+impl StorageValue for Hash {
+    fn into_bytes(self) -> Vec<u8> {
+        self.as_ref().to_vec()
+    }
+
+    fn from_bytes(value: Cow<[u8]>) -> Self {
+        Self::from_slice(value.as_ref()).unwrap()
+    }
+}
 
 //impl StorageValue for PublicKey {
 //    fn into_bytes(self) -> Vec<u8> {
@@ -284,23 +285,24 @@ impl StorageValue for DateTime<Utc> {
 }
 
 /// Uses little-endian encoding.
-//impl StorageValue for Duration {
-//    fn into_bytes(self) -> Vec<u8> {
-//        let mut buffer = vec![0; Duration::field_size() as usize];
-//        let from: Offset = 0;
-//        let to: Offset = Duration::field_size();
-//        self.write(&mut buffer, from, to);
-//        buffer
-//    }
-//
-//    fn from_bytes(value: Cow<[u8]>) -> Self {
-//        #![allow(unsafe_code)]
-//        let from: Offset = 0;
-//        let to: Offset = Duration::field_size();
-//        unsafe { Duration::read(&value, from, to) }
-//    }
-//}
-//
+impl StorageValue for Duration {
+    fn into_bytes(self) -> Vec<u8> {
+        let secs = self.num_seconds();
+        let nanos = (self - Duration::seconds(secs)).num_nanoseconds().unwrap() as i32;
+
+        let mut buffer = vec![0; 12];
+        LittleEndian::write_i64(&mut buffer[0..8], secs);
+        LittleEndian::write_i32(&mut buffer[8..12], nanos);
+        buffer
+    }
+
+    fn from_bytes(value: Cow<[u8]>) -> Self {
+        let secs = LittleEndian::read_i64(&value[0..8]);
+        let nanos = LittleEndian::read_i32(&value[8..12]);
+        Duration::seconds(secs) + Duration::nanoseconds(i64::from(nanos))
+    }
+}
+
 //impl StorageValue for Round {
 //    fn into_bytes(self) -> Vec<u8> {
 //        self.0.into_bytes()
@@ -321,6 +323,43 @@ impl StorageValue for Uuid {
     }
 }
 
+/// A type of values that can be decoded from a slice borrowed from the storage without copying.
+///
+/// The trait is implemented for byte slices and strings, which borrow the value bytes,
+/// and for the primitive types, which are decoded as per their `StorageValue` implementation.
+/// It is used by the `next_ref` methods of the index iterators.
+pub trait StorageValueRef<'a>: Sized {
+    /// Deserializes a value from the buffer borrowed from the storage.
+    fn from_slice(value: &'a [u8]) -> Self;
+}
+
+impl<'a> StorageValueRef<'a> for &'a [u8] {
+    fn from_slice(value: &'a [u8]) -> Self {
+        value
+    }
+}
+
+/// Uses UTF-8 string serialization.
+impl<'a> StorageValueRef<'a> for &'a str {
+    fn from_slice(value: &'a [u8]) -> Self {
+        ::std::str::from_utf8(value).unwrap()
+    }
+}
+
+macro_rules! storage_value_ref_for_primitives {
+    ($($type:ty),*) => {
+        $(
+            impl<'a> StorageValueRef<'a> for $type {
+                fn from_slice(value: &'a [u8]) -> Self {
+                    <$type as StorageValue>::from_bytes(Cow::Borrowed(value))
+                }
+            }
+        )*
+    }
+}
+
+storage_value_ref_for_primitives!{(), bool, u8, i8, u16, i16, u32, i32, u64, i64}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -464,20 +503,6 @@ mod tests {
         }
     }
 
-    #[test]
-    fn round_round_trip() {
-        let values = [
-            Round::zero(),
-            Round::first(),
-            Round(100),
-            Round(u32::max_value()),
-        ];
-        for value in values.iter() {
-            let bytes = value.clone().into_bytes();
-            assert_eq!(*value, Round::from_bytes(Cow::Borrowed(&bytes)));
-        }
-    }
-
     #[test]
     fn uuid_round_trip() {
         let values = [
@@ -494,4 +519,15 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn borrowed_values() {
+        let bytes = String::from("value").into_bytes();
+        assert_eq!(<&str as StorageValueRef>::from_slice(&bytes), "value");
+        assert_eq!(<&[u8] as StorageValueRef>::from_slice(&bytes), b"value");
+
+        let bytes = 7_u64.into_bytes();
+        assert_eq!(<u64 as StorageValueRef>::from_slice(&bytes), 7);
+        assert_eq!(<bool as StorageValueRef>::from_slice(&[1]), true);
+    }
 }
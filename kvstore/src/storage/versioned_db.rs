@@ -0,0 +1,545 @@
+// Copyright 2018 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! An implementation of a multi-version database on top of an arbitrary `Database`.
+
+use byteorder::{BigEndian, ByteOrder};
+
+use std::fmt;
+use std::sync::Mutex;
+
+use super::{Database, Error, Iter, Iterator, Patch, Result, Snapshot};
+use super::db::Change;
+
+/// Column family with the current and the oldest available versions of the database.
+const META_CF: &str = "__VERSIONED_DB_META__";
+/// Column family with the names of all column families written through the database.
+const NAMES_CF: &str = "__VERSIONED_DB_NAMES__";
+
+const CURRENT_VERSION_KEY: &[u8] = b"current";
+const OLDEST_VERSION_KEY: &[u8] = b"oldest";
+
+const TOMBSTONE: u8 = 0;
+const VALUE: u8 = 1;
+
+/// Database wrapper that keeps the history of every key and allows to read the database
+/// state as of any previous commit.
+///
+/// Each [`merge`] is assigned a version, which is one greater than the version of the
+/// previous merge; the version of an empty database is `0`. A snapshot of the state right
+/// after the merge with a certain version can be obtained with [`snapshot_at`]. Historical
+/// snapshots implement the ordinary `Snapshot` trait, so all indices can be used with them
+/// as is.
+///
+/// Versions older than a certain retention window can be removed with [`prune`].
+///
+/// The data is stored in the underlying database in the column families with the same names.
+/// Every key is accompanied by the version it was written at and deletions are recorded
+/// as tombstones, so the underlying database should be used exclusively through
+/// the `VersionedDatabase`.
+///
+/// [`merge`]: ../trait.Database.html#tymethod.merge
+/// [`snapshot_at`]: #method.snapshot_at
+/// [`prune`]: #method.prune
+///
+/// # Examples
+///
+/// ```
+/// use exonum::storage::{Database, Entry, MemoryDB, VersionedDatabase};
+///
+/// let db = VersionedDatabase::new(MemoryDB::new());
+/// for value in 1..4_u64 {
+///     let mut fork = db.fork();
+///     Entry::new("entry", &mut fork).set(value);
+///     db.merge(fork.into_patch()).unwrap();
+/// }
+/// assert_eq!(db.version(), 3);
+///
+/// let snapshot = db.snapshot_at(2).unwrap();
+/// let entry: Entry<_, u64> = Entry::new("entry", &snapshot);
+/// assert_eq!(entry.get(), Some(2));
+/// ```
+pub struct VersionedDatabase<D> {
+    db: D,
+    state: Mutex<VersionState>,
+}
+
+/// A snapshot of a `VersionedDatabase` as of a certain version.
+pub struct VersionedSnapshot {
+    snapshot: Box<Snapshot>,
+    version: u64,
+}
+
+struct VersionedIter<'a> {
+    inner: Iter<'a>,
+    version: u64,
+    last_key: Option<Vec<u8>>,
+    item: Option<(Vec<u8>, Vec<u8>)>,
+    peeked: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct VersionState {
+    current: u64,
+    oldest: u64,
+}
+
+impl<D: Database> VersionedDatabase<D> {
+    /// Wraps the database. The versions previously written to the database by
+    /// a `VersionedDatabase` are preserved.
+    pub fn new(db: D) -> Self {
+        let state = {
+            let snapshot = db.snapshot();
+            let read = |key| snapshot.get(META_CF, key).map_or(0, |v| BigEndian::read_u64(&v));
+            VersionState {
+                current: read(CURRENT_VERSION_KEY),
+                oldest: read(OLDEST_VERSION_KEY),
+            }
+        };
+        VersionedDatabase {
+            db,
+            state: Mutex::new(state),
+        }
+    }
+
+    /// Returns a reference to the underlying database.
+    pub fn inner(&self) -> &D {
+        &self.db
+    }
+
+    /// Returns the underlying database.
+    pub fn into_inner(self) -> D {
+        self.db
+    }
+
+    /// Returns the version of the latest merge, or `0` if the database is empty.
+    pub fn version(&self) -> u64 {
+        self.state.lock().unwrap().current
+    }
+
+    /// Returns the oldest version which a snapshot can be created for.
+    pub fn oldest_version(&self) -> u64 {
+        self.state.lock().unwrap().oldest
+    }
+
+    /// Creates a snapshot of the database state right after the merge with the given version.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the version is greater than the current version or if it
+    /// has been pruned.
+    pub fn snapshot_at(&self, version: u64) -> Result<Box<Snapshot>> {
+        let state = self.state.lock().unwrap();
+        if version > state.current {
+            return Err(Error::new(format!(
+                "Version {} is not committed yet, the current version is {}",
+                version, state.current
+            )));
+        }
+        if version < state.oldest {
+            return Err(Error::new(format!(
+                "Version {} is pruned, the oldest available version is {}",
+                version, state.oldest
+            )));
+        }
+        Ok(Box::new(VersionedSnapshot {
+            snapshot: self.db.snapshot(),
+            version,
+        }))
+    }
+
+    /// Removes the versions which are not needed to create snapshots for the last `retention`
+    /// versions, i.e. for the versions from `version() - retention` to `version()`.
+    ///
+    /// Returns the oldest available version after pruning.
+    pub fn prune(&self, retention: u64) -> Result<u64> {
+        let mut state = self.state.lock().unwrap();
+        let oldest = state.current.saturating_sub(retention);
+        if oldest <= state.oldest {
+            return Ok(state.oldest);
+        }
+
+        let snapshot = self.db.snapshot();
+        let mut fork = self.db.fork();
+        let mut names = snapshot.iter(NAMES_CF, &[]);
+        while let Some((name, _)) = names.next() {
+            let name = String::from_utf8(name.to_vec()).expect("Invalid column family name");
+            // Among the versions of a key not newer than `oldest` only the latest one
+            // is visible to the retained snapshots; tombstones are not needed at all.
+            let mut last_key: Option<Vec<u8>> = None;
+            let mut iter = snapshot.iter(&name, &[]);
+            while let Some((raw_key, raw_value)) = iter.next() {
+                let (key, version) = match decode_key(raw_key) {
+                    Some(decoded) => decoded,
+                    None => continue,
+                };
+                if version > oldest {
+                    continue;
+                }
+                if last_key.as_ref() == Some(&key) || raw_value.first() == Some(&TOMBSTONE) {
+                    fork.remove(&name, raw_key.to_vec());
+                }
+                last_key = Some(key);
+            }
+        }
+        fork.put(META_CF, OLDEST_VERSION_KEY.to_vec(), encode_version(oldest));
+        self.db.merge_sync(fork.into_raw_patch())?;
+        state.oldest = oldest;
+        Ok(oldest)
+    }
+
+    fn do_merge<F>(&self, patch: Patch, merge: F) -> Result<()>
+    where
+        F: FnOnce(&D, Patch) -> Result<()>,
+    {
+        let mut state = self.state.lock().unwrap();
+        if patch.has_reads() {
+            patch.check_reads(&VersionedSnapshot {
+                snapshot: self.db.snapshot(),
+                version: state.current,
+            })?;
+        }
+        let version = state.current + 1;
+        let mut fork = self.db.fork();
+        for (name, changes) in patch {
+            fork.put(NAMES_CF, name.as_bytes().to_vec(), Vec::new());
+            for (key, change) in changes {
+                let value = match change {
+                    Change::Put(value) => {
+                        let mut buf = Vec::with_capacity(value.len() + 1);
+                        buf.push(VALUE);
+                        buf.extend_from_slice(&value);
+                        buf
+                    }
+                    Change::Delete => vec![TOMBSTONE],
+                };
+                fork.put(&name, encode_key(&key, version), value);
+            }
+        }
+        fork.put(META_CF, CURRENT_VERSION_KEY.to_vec(), encode_version(version));
+        merge(&self.db, fork.into_raw_patch())?;
+        state.current = version;
+        Ok(())
+    }
+}
+
+impl<D: Database> Database for VersionedDatabase<D> {
+    fn snapshot(&self) -> Box<Snapshot> {
+        let state = self.state.lock().unwrap();
+        Box::new(VersionedSnapshot {
+            snapshot: self.db.snapshot(),
+            version: state.current,
+        })
+    }
+
+    fn merge(&self, patch: Patch) -> Result<()> {
+        self.do_merge(patch, |db, patch| db.merge(patch))
+    }
+
+    fn merge_sync(&self, patch: Patch) -> Result<()> {
+        self.do_merge(patch, |db, patch| db.merge_sync(patch))
+    }
+}
+
+impl Snapshot for VersionedSnapshot {
+    fn get(&self, name: &str, key: &[u8]) -> Option<Vec<u8>> {
+        let mut iter = self.snapshot.iter(name, &encode_key(key, self.version));
+        let value = match iter.next() {
+            Some((raw_key, raw_value)) => match decode_key(raw_key) {
+                Some((ref found, _)) if found.as_slice() == key => decode_value(raw_value),
+                _ => None,
+            },
+            _ => None,
+        };
+        value
+    }
+
+    fn iter<'a>(&'a self, name: &str, from: &[u8]) -> Iter<'a> {
+        Box::new(VersionedIter {
+            inner: self.snapshot.iter(name, &escape_key(from)),
+            version: self.version,
+            last_key: None,
+            item: None,
+            peeked: false,
+        })
+    }
+
+    fn column_families(&self) -> Vec<String> {
+        let mut names = Vec::new();
+        let mut iter = self.snapshot.iter_keys(NAMES_CF, &[]);
+        while let Some((name, _)) = iter.next() {
+            names.push(String::from_utf8_lossy(name).into_owned());
+        }
+        names
+    }
+}
+
+impl<'a> VersionedIter<'a> {
+    fn advance(&mut self) -> Option<(Vec<u8>, Vec<u8>)> {
+        loop {
+            let (key, version, value) = match self.inner.next() {
+                Some((raw_key, raw_value)) => match decode_key(raw_key) {
+                    Some((key, version)) => (key, version, decode_value(raw_value)),
+                    // Records not written by `VersionedDatabase` are skipped.
+                    None => continue,
+                },
+                None => return None,
+            };
+            // Versions of a key go from the newest to the oldest, so the first version
+            // not newer than the snapshot is the visible one.
+            if version > self.version || self.last_key.as_ref() == Some(&key) {
+                continue;
+            }
+            self.last_key = Some(key.clone());
+            if let Some(value) = value {
+                return Some((key, value));
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for VersionedIter<'a> {
+    fn next(&mut self) -> Option<(&[u8], &[u8])> {
+        if !self.peeked {
+            self.item = self.advance();
+        }
+        self.peeked = false;
+        self.item
+            .as_ref()
+            .map(|&(ref k, ref v)| (k.as_slice(), v.as_slice()))
+    }
+
+    fn peek(&mut self) -> Option<(&[u8], &[u8])> {
+        if !self.peeked {
+            self.item = self.advance();
+            self.peeked = true;
+        }
+        self.item
+            .as_ref()
+            .map(|&(ref k, ref v)| (k.as_slice(), v.as_slice()))
+    }
+}
+
+impl<D> fmt::Debug for VersionedDatabase<D> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let state = self.state.lock().unwrap();
+        f.debug_struct("VersionedDatabase")
+            .field("current", &state.current)
+            .field("oldest", &state.oldest)
+            .finish()
+    }
+}
+
+impl fmt::Debug for VersionedSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("VersionedSnapshot")
+            .field("version", &self.version)
+            .finish()
+    }
+}
+
+fn encode_version(version: u64) -> Vec<u8> {
+    let mut buf = vec![0; 8];
+    BigEndian::write_u64(&mut buf, version);
+    buf
+}
+
+/// Escapes zero bytes of the key as `00 ff`, so that the escaped keys compare in the same way
+/// as the original ones and none of them is a prefix of the `00 00` terminator.
+fn escape_key(key: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(key.len() + 10);
+    for &byte in key {
+        buf.push(byte);
+        if byte == 0 {
+            buf.push(0xff);
+        }
+    }
+    buf
+}
+
+/// Encodes the key together with its version. Versions are inverted, so that the versions
+/// of the same key are sorted from the newest to the oldest.
+fn encode_key(key: &[u8], version: u64) -> Vec<u8> {
+    let mut buf = escape_key(key);
+    buf.extend_from_slice(&[0, 0]);
+    buf.extend_from_slice(&encode_version(!version));
+    buf
+}
+
+/// Decodes a key encoded with `encode_key`, or returns `None` if the key is malformed.
+fn decode_key(raw: &[u8]) -> Option<(Vec<u8>, u64)> {
+    if raw.len() < 10 {
+        return None;
+    }
+    let (escaped, version) = raw.split_at(raw.len() - 10);
+    if version[..2] != [0, 0] {
+        return None;
+    }
+    let mut key = Vec::with_capacity(escaped.len());
+    let mut bytes = escaped.iter();
+    while let Some(&byte) = bytes.next() {
+        key.push(byte);
+        if byte == 0 {
+            bytes.next();
+        }
+    }
+    Some((key, !BigEndian::read_u64(&version[2..])))
+}
+
+fn decode_value(raw: &[u8]) -> Option<Vec<u8>> {
+    match raw.first() {
+        Some(&VALUE) => Some(raw[1..].to_vec()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use storage::{MapIndex, MemoryDB};
+
+    fn put_values(db: &VersionedDatabase<MemoryDB>, values: &[(u8, Option<u64>)]) {
+        let mut fork = db.fork();
+        {
+            let mut index = MapIndex::new("map", &mut fork);
+            for &(key, value) in values {
+                match value {
+                    Some(value) => index.put(&key, value),
+                    None => index.remove(&key),
+                }
+            }
+        }
+        db.merge(fork.into_patch()).unwrap();
+    }
+
+    fn map_at(db: &VersionedDatabase<MemoryDB>, version: u64) -> Vec<(u8, u64)> {
+        let snapshot = db.snapshot_at(version).unwrap();
+        let index: MapIndex<_, u8, u64> = MapIndex::new("map", &snapshot);
+        index.iter().collect()
+    }
+
+    #[test]
+    fn key_encoding_order() {
+        let keys = vec![
+            vec![],
+            vec![0],
+            vec![0, 0],
+            vec![0, 1],
+            vec![1],
+            vec![1, 0],
+            vec![0xff],
+        ];
+        for pair in keys.windows(2) {
+            assert!(encode_key(&pair[0], 0) < encode_key(&pair[1], 5));
+            assert!(encode_key(&pair[0], 5) < encode_key(&pair[1], 0));
+        }
+        for key in &keys {
+            assert!(encode_key(key, 2) < encode_key(key, 1));
+            assert_eq!(decode_key(&encode_key(key, 7)), Some((key.clone(), 7)));
+        }
+    }
+
+    #[test]
+    fn historical_snapshots() {
+        let db = VersionedDatabase::new(MemoryDB::new());
+        assert_eq!(db.version(), 0);
+        put_values(&db, &[(1, Some(10)), (2, Some(20))]);
+        put_values(&db, &[(1, Some(11)), (3, Some(30))]);
+        put_values(&db, &[(2, None)]);
+        assert_eq!(db.version(), 3);
+
+        assert_eq!(map_at(&db, 0), vec![]);
+        assert_eq!(map_at(&db, 1), vec![(1, 10), (2, 20)]);
+        assert_eq!(map_at(&db, 2), vec![(1, 11), (2, 20), (3, 30)]);
+        assert_eq!(map_at(&db, 3), vec![(1, 11), (3, 30)]);
+
+        let snapshot = db.snapshot_at(1).unwrap();
+        let index: MapIndex<_, u8, u64> = MapIndex::new("map", &snapshot);
+        assert_eq!(index.get(&1), Some(10));
+        assert!(!index.contains(&3));
+        assert_eq!(index.iter_from(&2).collect::<Vec<_>>(), vec![(2, 20)]);
+        assert!(db.snapshot_at(4).is_err());
+    }
+
+    #[test]
+    fn snapshot_is_isolated_from_merges() {
+        let db = VersionedDatabase::new(MemoryDB::new());
+        put_values(&db, &[(1, Some(1))]);
+        let snapshot = db.snapshot();
+        put_values(&db, &[(1, Some(2))]);
+        let index: MapIndex<_, u8, u64> = MapIndex::new("map", &snapshot);
+        assert_eq!(index.get(&1), Some(1));
+    }
+
+    #[test]
+    fn pruning() {
+        let db = VersionedDatabase::new(MemoryDB::new());
+        put_values(&db, &[(1, Some(10)), (2, Some(20))]);
+        put_values(&db, &[(1, Some(11)), (2, None)]);
+        put_values(&db, &[(1, Some(12))]);
+        put_values(&db, &[(3, Some(30))]);
+
+        assert_eq!(db.prune(2).unwrap(), 2);
+        assert_eq!(db.oldest_version(), 2);
+        assert!(db.snapshot_at(1).is_err());
+        assert_eq!(map_at(&db, 2), vec![(1, 11)]);
+        assert_eq!(map_at(&db, 3), vec![(1, 12)]);
+        assert_eq!(map_at(&db, 4), vec![(1, 12), (3, 30)]);
+
+        // Only the versions visible at version 2 and later remain.
+        let raw = db.inner().snapshot();
+        let mut iter = raw.iter("map", &[]);
+        let mut count = 0;
+        while iter.next().is_some() {
+            count += 1;
+        }
+        assert_eq!(count, 3);
+
+        // Pruning does not go back.
+        assert_eq!(db.prune(3).unwrap(), 2);
+    }
+
+    #[test]
+    fn malformed_keys_are_skipped() {
+        assert_eq!(decode_key(&[1, 2, 3]), None);
+        assert_eq!(decode_key(&[1; 12]), None);
+
+        let db = VersionedDatabase::new(MemoryDB::new());
+        put_values(&db, &[(1, Some(10))]);
+        let mut fork = db.inner().fork();
+        fork.put("map", vec![0], vec![]);
+        fork.put("map", vec![0xff; 3], vec![VALUE, 1]);
+        db.inner().merge(fork.into_patch()).unwrap();
+
+        assert_eq!(map_at(&db, 1), vec![(1, 10)]);
+        put_values(&db, &[(1, Some(11))]);
+        assert_eq!(db.prune(0).unwrap(), 2);
+        assert_eq!(map_at(&db, 2), vec![(1, 11)]);
+    }
+
+    #[test]
+    fn versions_survive_reopening() {
+        let db = VersionedDatabase::new(MemoryDB::new());
+        put_values(&db, &[(1, Some(10))]);
+        put_values(&db, &[(1, Some(11))]);
+        db.prune(1).unwrap();
+
+        let db = VersionedDatabase::new(db.into_inner());
+        assert_eq!(db.version(), 2);
+        assert_eq!(db.oldest_version(), 1);
+        assert_eq!(map_at(&db, 1), vec![(1, 10)]);
+        put_values(&db, &[(1, Some(12))]);
+        assert_eq!(map_at(&db, 3), vec![(1, 12)]);
+    }
+}